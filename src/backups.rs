@@ -0,0 +1,98 @@
+use crate::protocol;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Snapshots of keyboards taken before load overwrites them, file names start with keyboard uid.
+pub struct Backups {
+    pub dir: PathBuf,
+}
+
+/// Number of backups kept for each keyboard.
+const BACKUPS_KEPT: usize = 10;
+
+impl Backups {
+    /// Location of backups, $XDG_STATE_HOME/vitaly/backups or ~/.local/state/vitaly/backups.
+    pub fn default_dir() -> Option<PathBuf> {
+        let base = match env::var_os("XDG_STATE_HOME") {
+            Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => PathBuf::from(env::var_os("HOME")?)
+                .join(".local")
+                .join("state"),
+        };
+        Some(base.join("vitaly").join("backups"))
+    }
+
+    /// Writes snapshot into timestamped .vil file and removes the oldest backups of the keyboard.
+    pub fn save(
+        &self,
+        snapshot: &protocol::Snapshot,
+    ) -> Result<PathBuf, Box<dyn std::error::Error>> {
+        fs::create_dir_all(&self.dir)?;
+        let millis = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis();
+        let path = self
+            .dir
+            .join(format!("{:016x}-{:013}.vil", snapshot.uid, millis));
+        fs::write(&path, snapshot.to_config()?.to_vil_string()?)?;
+        let backups = self.list(snapshot.uid)?;
+        if backups.len() > BACKUPS_KEPT {
+            for old in &backups[..backups.len() - BACKUPS_KEPT] {
+                fs::remove_file(old)?;
+            }
+        }
+        Ok(path)
+    }
+
+    /// Backups of keyboard with given uid, the oldest first.
+    pub fn list(&self, uid: u64) -> Result<Vec<PathBuf>, Box<dyn std::error::Error>> {
+        let prefix = format!("{:016x}-", uid);
+        let mut result = Vec::new();
+        if !self.dir.exists() {
+            return Ok(result);
+        }
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if let Some(name) = path.file_name().and_then(|name| name.to_str())
+                && name.starts_with(&prefix)
+                && name.ends_with(".vil")
+            {
+                result.push(path);
+            }
+        }
+        result.sort();
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+
+    #[test]
+    fn test_backups() {
+        let meta = serde_json::json!({
+            "matrix": {"rows": 1, "cols": 2},
+            "layouts": {"keymap": [["0,0", "0,1"]]}
+        });
+        let simulator = protocol::Simulator::new(&meta).unwrap();
+        let capabilities = protocol::scan_capabilities(&simulator).unwrap();
+        let snapshot = protocol::Snapshot::load(&simulator, &capabilities, &meta).unwrap();
+        let dir = env::temp_dir().join(format!("vitaly-backups-{}", std::process::id()));
+        let backups = Backups { dir: dir.clone() };
+        assert!(backups.list(snapshot.uid).unwrap().is_empty());
+        let mut saved = Vec::new();
+        for _ in 0..BACKUPS_KEPT + 2 {
+            saved.push(backups.save(&snapshot).unwrap());
+            std::thread::sleep(std::time::Duration::from_millis(2));
+        }
+        let listed = backups.list(snapshot.uid).unwrap();
+        assert_eq!(listed, saved[2..]);
+        assert!(backups.list(snapshot.uid + 1).unwrap().is_empty());
+        let restored: Value =
+            serde_json::from_str(&fs::read_to_string(listed.last().unwrap()).unwrap()).unwrap();
+        assert_eq!(protocol::Snapshot::from_json(&restored).unwrap(), snapshot);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
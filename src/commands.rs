@@ -7,6 +7,32 @@ pub use crate::commands::save::run as save_run;
 mod load;
 pub use crate::commands::load::run as load_run;
 
+mod import;
+pub use crate::commands::import::run as import_run;
+
+mod qmk_keymap;
+
+mod remap;
+
+mod convert;
+pub use crate::commands::convert::needs_meta as convert_needs_meta;
+pub use crate::commands::convert::run as convert_run;
+
+mod reformat;
+pub use crate::commands::reformat::run as reformat_run;
+
+mod render_config;
+pub use crate::commands::render_config::run as render_config_run;
+
+mod migrate;
+pub use crate::commands::migrate::run as migrate_run;
+
+mod check;
+pub use crate::commands::check::run as check_run;
+
+mod rollback;
+pub use crate::commands::rollback::run as rollback_run;
+
 mod layers;
 pub use crate::commands::layers::run as layers_run;
 
@@ -42,3 +68,16 @@ pub use crate::commands::devices::run as devices_run;
 
 mod combos;
 pub use crate::commands::combos::run as combos_run;
+
+mod diff;
+pub use crate::commands::diff::{DEVICE_SOURCE, run as diff_run};
+
+mod export;
+pub use crate::commands::export::{
+    CommandExport, CommandExportQmkC, CommandExportZmk, ExportCommandEnum, run as export_run,
+};
+
+mod meta;
+pub use crate::commands::meta::{
+    CommandMeta, CommandMetaInfo, MetaCommandEnum, info_file as meta_info_file, run as meta_run,
+};
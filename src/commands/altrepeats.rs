@@ -1,21 +1,18 @@
 use crate::common;
 use crate::protocol;
-use hidapi::{DeviceInfo, HidApi};
+use serde_json::json;
 
 pub fn run(
-    api: &HidApi,
-    device: &DeviceInfo,
+    dev: &impl protocol::Transport,
     number: Option<u8>,
     value: &Option<String>,
+    output: &common::Output,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let device_path = device.path();
-    let dev = api.open_path(device_path)?;
-    let capabilities = protocol::scan_capabilities(&dev)?;
+    let capabilities = protocol::scan_capabilities(dev)?;
     if capabilities.alt_repeat_key_count == 0 {
-        return Err(common::CommandError(
-            format!("device {:?} has doesn't support alt repeat keys", device).to_string(),
-        )
-        .into());
+        return Err(
+            common::CommandError("device doesn't support alt repeat keys".to_string()).into(),
+        );
     }
     let n: u8 = match number {
         Some(num) => {
@@ -39,17 +36,26 @@ pub fn run(
                 0 => protocol::AltRepeat::empty(n),
                 _ => protocol::AltRepeat::from_string(n, value, capabilities.vial_version)?,
             };
-            protocol::set_alt_repeat(&dev, &alt_repeat)?;
-            println!("AltRepeat {} saved", alt_repeat.index);
+            protocol::set_alt_repeat(dev, &alt_repeat)?;
+            output.print_status(
+                &format!("AltRepeat {} saved", alt_repeat.index),
+                json!({"alt_repeat": alt_repeat.index}),
+            )?;
         }
         None => {
-            let altrepeats = protocol::load_alt_repeats(&dev, capabilities.alt_repeat_key_count)?;
+            let altrepeats = protocol::load_alt_repeats(dev, capabilities.alt_repeat_key_count)?;
+            if output.format == common::OutputFormat::Json {
+                return output.print_entries_json(
+                    protocol::alt_repeats_to_json(&altrepeats, capabilities.vial_version)?,
+                    number,
+                );
+            }
             if number.is_none() {
                 let altrepeat_count = altrepeats.len();
                 let mut first_empty = capabilities.alt_repeat_key_count;
                 for idxm in 1..=altrepeat_count {
                     let idx = altrepeat_count - idxm;
-                    if !altrepeats[idx as usize].is_empty() {
+                    if !altrepeats[idx].is_empty() {
                         break;
                     }
                     first_empty = idx as u8;
@@ -0,0 +1,58 @@
+use crate::common;
+use crate::meta_cache::MetaCache;
+use crate::protocol;
+use serde_json::Value;
+use std::fs;
+
+/// Validates layout file against keyboard, or against meta file only when device is None.
+pub fn run<T: protocol::Transport>(
+    dev: Option<&T>,
+    meta_file: &Option<String>,
+    meta_cache: Option<&MetaCache>,
+    file: &String,
+    output: &common::Output,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (capabilities, meta) = match dev {
+        Some(dev) => {
+            let capabilities = protocol::scan_capabilities(dev)?;
+            let meta = common::load_meta(dev, &capabilities, meta_file, meta_cache)?;
+            (Some(capabilities), meta)
+        }
+        None => {
+            let meta_file = meta_file.as_ref().ok_or(common::CommandError(
+                "meta file should be passed with -m to check without keyboard".to_string(),
+            ))?;
+            (None, serde_json::from_str(&fs::read_to_string(meta_file)?)?)
+        }
+    };
+    let config = common::read_layout_file(file, &meta)?;
+    let vial_version = match &capabilities {
+        Some(capabilities) => capabilities.vial_version,
+        None => config.vial_protocol.unwrap_or(0),
+    };
+    let cols = meta["matrix"]["cols"]
+        .as_u64()
+        .ok_or("matrix/cols not found in meta")? as u8;
+    let rows = meta["matrix"]["rows"]
+        .as_u64()
+        .ok_or("matrix/rows not found in meta")? as u8;
+
+    let issues = protocol::check_config(&config, vial_version, rows, cols, capabilities.as_ref());
+    let errors = issues
+        .iter()
+        .filter(|issue| issue.severity == protocol::Severity::Error)
+        .count();
+    if output.format == common::OutputFormat::Json {
+        let issues: Vec<Value> = issues.iter().map(|issue| issue.to_json()).collect();
+        output.print_json(&Value::Array(issues))?;
+    } else {
+        for issue in &issues {
+            println!("{}", issue);
+        }
+        println!("{} errors, {} warnings", errors, issues.len() - errors);
+    }
+    if errors > 0 {
+        return Err(common::CommandError(format!("{} is not valid for keyboard", file)).into());
+    }
+    Ok(())
+}
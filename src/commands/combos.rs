@@ -1,22 +1,17 @@
 use crate::common;
 use crate::protocol;
-use hidapi::{DeviceInfo, HidApi};
+use serde_json::json;
 
 pub fn run(
-    api: &HidApi,
-    device: &DeviceInfo,
+    dev: &impl protocol::Transport,
     number: Option<u8>,
     value: &Option<String>,
+    output: &common::Output,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let device_path = device.path();
-    let dev = api.open_path(device_path)?;
-    let capabilities = protocol::scan_capabilities(&dev)?;
+    let capabilities = protocol::scan_capabilities(dev)?;
 
     if capabilities.combo_count == 0 {
-        return Err(common::CommandError(
-            format!("device {:?} has doesn't support combos", device).to_string(),
-        )
-        .into());
+        return Err(common::CommandError("device doesn't support combos".to_string()).into());
     }
     let n: u8 = match number {
         Some(num) => {
@@ -32,13 +27,19 @@ pub fn run(
     };
     match value {
         None => {
-            let combos = protocol::load_combos(&dev, capabilities.combo_count)?;
+            let combos = protocol::load_combos(dev, capabilities.combo_count)?;
+            if output.format == common::OutputFormat::Json {
+                return output.print_entries_json(
+                    protocol::combos_to_json(&combos, capabilities.vial_version)?,
+                    number,
+                );
+            }
             if number.is_none() {
                 let combo_count = combos.len();
                 let mut first_empty = capabilities.combo_count;
                 for idxm in 1..=combo_count {
                     let idx = combo_count - idxm;
-                    if !combos[idx as usize].is_empty() {
+                    if !combos[idx].is_empty() {
                         break;
                     }
                     first_empty = idx as u8;
@@ -65,8 +66,11 @@ pub fn run(
                 0 => protocol::Combo::empty(n),
                 _ => protocol::Combo::from_string(n, value, capabilities.vial_version)?,
             };
-            protocol::set_combo(&dev, &combo)?;
-            println!("Combo {} saved", combo.index);
+            protocol::set_combo(dev, &combo)?;
+            output.print_status(
+                &format!("Combo {} saved", combo.index),
+                json!({"combo": combo.index}),
+            )?;
         }
     }
     Ok(())
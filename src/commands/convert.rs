@@ -0,0 +1,67 @@
+use crate::commands::remap;
+use crate::common;
+use crate::meta_cache::MetaCache;
+use crate::protocol;
+use serde_json::Value;
+
+/// Meta is needed only to read or write .toml files, .vil files are converted without it.
+pub fn needs_meta(from: &str, to: &str) -> bool {
+    common::is_toml_file(from) || common::is_toml_file(to)
+}
+
+pub fn run<T: protocol::Transport>(
+    dev: Option<&T>,
+    meta_file: &Option<String>,
+    meta_cache: Option<&MetaCache>,
+    from: &String,
+    to: &String,
+    from_protocol: Option<u32>,
+    to_protocol: u32,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let meta = match meta_file.is_some() || needs_meta(from, to) {
+        true => common::load_meta_or_file(dev, meta_file, meta_cache, "convert")?,
+        false => Value::Null,
+    };
+    let config = common::read_layout_file(from, &meta)?;
+    let from_protocol = match from_protocol.or(config.vial_protocol) {
+        Some(version) => version,
+        None => {
+            return Err(common::CommandError(format!(
+                "{} has no vial_protocol, pass it with --from-protocol",
+                from
+            ))
+            .into());
+        }
+    };
+    let mut snapshot = protocol::Snapshot::from_config(&config, from_protocol)?;
+    let missing = remap::convert_keycodes(&mut snapshot, to_protocol);
+    for name in &missing {
+        println!(
+            "{} has no equivalent in protocol {}, replaced with KC_NO",
+            name, to_protocol
+        );
+    }
+    let mut converted = snapshot.to_config()?;
+    // sections missing in original file stay missing
+    converted.retain(&config.sections());
+    converted.extra = config.extra;
+    // positions missing in layout are kept as Vial writes them
+    for (layer, rows) in config.layout.iter().enumerate() {
+        for (row, keys) in rows.iter().enumerate() {
+            for (col, key) in keys.iter().enumerate() {
+                if let protocol::LayoutKey::Number(_) = key {
+                    converted.layout[layer][row][col] = key.clone();
+                }
+            }
+        }
+    }
+    common::write_layout_file(to, &converted, &meta)?;
+    println!(
+        "Configuration converted from protocol {} to {}, {} keycodes without equivalent",
+        from_protocol,
+        to_protocol,
+        missing.len()
+    );
+    println!("Configuration saved to file {}", to);
+    Ok(())
+}
@@ -1,15 +1,21 @@
+use crate::common;
 use crate::protocol;
-use hidapi::{DeviceInfo, HidApi};
+use serde_json::json;
 
 pub fn run(
-    api: &HidApi,
-    device: &DeviceInfo,
+    dev: &impl protocol::Transport,
     capabilities: bool,
+    output: &common::Output,
 ) -> Result<(), Box<dyn std::error::Error>> {
+    if output.format == common::OutputFormat::Json {
+        let mut result = json!({});
+        if capabilities {
+            result["capabilities"] = protocol::scan_capabilities(dev)?.to_json();
+        }
+        return output.print_json(&result);
+    }
     if capabilities {
-        let device_path = device.path();
-        let dev = api.open_path(device_path)?;
-        let capabilities = protocol::scan_capabilities(&dev)?;
+        let capabilities = protocol::scan_capabilities(dev)?;
         println!("Capabilities:\n\tvia_version: {}", capabilities.via_version);
         println!("\tvial_version: {}", capabilities.vial_version);
         println!(
@@ -0,0 +1,462 @@
+use crate::common;
+use crate::keymap;
+use crate::meta_cache::MetaCache;
+use crate::protocol;
+use serde_json::Value;
+use std::cmp::max;
+use std::collections::{BTreeSet, HashSet};
+use std::fs;
+
+/// Source name which stands for connected keyboard instead of .vil file.
+pub const DEVICE_SOURCE: &str = "device";
+
+type ToJson<T> = fn(&Vec<T>, u32) -> Result<Vec<Value>, Box<dyn std::error::Error>>;
+
+/// Changed keys of layer as (row, col, from, to), None stands for key missing in keymap.
+type LayerChanges = Vec<(u8, u8, Option<String>, Option<String>)>;
+
+fn device(
+    dev: Option<&impl protocol::Transport>,
+) -> Result<&impl protocol::Transport, common::CommandError> {
+    dev.ok_or(common::CommandError("device is not available".to_string()))
+}
+
+/// Reads snapshot of keyboard or layout file, layout files are read the same way as load does.
+fn load_source<T: protocol::Transport>(
+    dev: Option<&T>,
+    meta: &Option<Value>,
+    source: &str,
+) -> Result<protocol::Snapshot, Box<dyn std::error::Error>> {
+    if source == DEVICE_SOURCE {
+        let dev = device(dev)?;
+        let capabilities = protocol::scan_capabilities(dev)?;
+        let meta = meta.as_ref().ok_or("meta is not loaded")?;
+        protocol::Snapshot::load(dev, &capabilities, meta)
+    } else {
+        if meta.is_none() && common::is_toml_file(source) {
+            return Err(common::CommandError(format!(
+                "meta is required to read {}, pass it with -m or compare with device",
+                source
+            ))
+            .into());
+        }
+        let config = common::read_layout_file(source, meta.as_ref().unwrap_or(&Value::Null))?;
+        protocol::Snapshot::from_config(&config, config.vial_protocol.unwrap_or(0))
+    }
+}
+
+fn show(value: Option<&Value>) -> String {
+    match value {
+        Some(value) => value.to_string(),
+        None => "-".to_string(),
+    }
+}
+
+/// Prints entries which differ by index, missing entry is equal to an empty one.
+fn diff_entries<T>(
+    title: &str,
+    from: &protocol::Snapshot,
+    from_entries: &Vec<T>,
+    to: &protocol::Snapshot,
+    to_entries: &Vec<T>,
+    to_json: ToJson<T>,
+    is_empty: fn(&T) -> bool,
+) -> Result<usize, Box<dyn std::error::Error>> {
+    let from_json = to_json(from_entries, from.vial_version)?;
+    let to_json = to_json(to_entries, to.vial_version)?;
+    let mut changes = 0;
+    for idx in 0..max(from_json.len(), to_json.len()) {
+        let changed = match (from_entries.get(idx), to_entries.get(idx)) {
+            (Some(_), Some(_)) => from_json[idx] != to_json[idx],
+            (Some(e), None) | (None, Some(e)) => !is_empty(e),
+            (None, None) => false,
+        };
+        if changed {
+            if changes == 0 {
+                println!("{}:", title);
+            }
+            println!(
+                "{}) {} -> {}",
+                idx,
+                show(from_json.get(idx)),
+                show(to_json.get(idx))
+            );
+            changes += 1;
+        }
+    }
+    Ok(changes)
+}
+
+fn diff_encoders(
+    from: &protocol::Snapshot,
+    to: &protocol::Snapshot,
+) -> Result<usize, Box<dyn std::error::Error>> {
+    let from_json = protocol::encoders_to_json(&from.encoders, from.vial_version)?;
+    let to_json = protocol::encoders_to_json(&to.encoders, to.vial_version)?;
+    let mut changes = 0;
+    for layer in 0..max(from_json.len(), to_json.len()) {
+        let from_layer = from_json.get(layer).and_then(|l| l.as_array());
+        let to_layer = to_json.get(layer).and_then(|l| l.as_array());
+        let count = max(
+            from_layer.map_or(0, |l| l.len()),
+            to_layer.map_or(0, |l| l.len()),
+        );
+        for idx in 0..count {
+            let from_value = from_layer.and_then(|l| l.get(idx));
+            let to_value = to_layer.and_then(|l| l.get(idx));
+            if from_value != to_value {
+                if changes == 0 {
+                    println!("Encoders:");
+                }
+                println!(
+                    "layer {}, encoder {}) {} -> {}",
+                    layer,
+                    idx,
+                    show(from_value),
+                    show(to_value)
+                );
+                changes += 1;
+            }
+        }
+    }
+    Ok(changes)
+}
+
+fn diff_settings(
+    from: &protocol::Snapshot,
+    to: &protocol::Snapshot,
+) -> Result<usize, Box<dyn std::error::Error>> {
+    let definitions = protocol::load_qmk_definitions()?;
+    let qsids: BTreeSet<&u16> = from
+        .qmk_settings
+        .keys()
+        .chain(to.qmk_settings.keys())
+        .collect();
+    let mut changes = 0;
+    for qsid in qsids {
+        let from_value = from.qmk_settings.get(qsid);
+        let to_value = to.qmk_settings.get(qsid);
+        if from_value == to_value {
+            continue;
+        }
+        if changes == 0 {
+            println!("Settings:");
+        }
+        changes += 1;
+        let mut described = false;
+        for group in definitions["tabs"]
+            .as_array()
+            .ok_or("tabs should be an array")?
+        {
+            for field in group["fields"]
+                .as_array()
+                .ok_or("fields should be an array")?
+            {
+                if field["qsid"].as_u64() != Some(*qsid as u64) {
+                    continue;
+                }
+                let title = field["title"].as_str().ok_or("title should be a string")?;
+                match field["bit"].as_u64() {
+                    Some(bit) => {
+                        let from_bit = from_value.map(|v| v.get_bool(bit as u8));
+                        let to_bit = to_value.map(|v| v.get_bool(bit as u8));
+                        if from_bit != to_bit {
+                            println!(
+                                "{}.{}) {}: {} -> {}",
+                                qsid,
+                                bit,
+                                title,
+                                from_bit.map_or("-".to_string(), |v| v.to_string()),
+                                to_bit.map_or("-".to_string(), |v| v.to_string()),
+                            );
+                        }
+                    }
+                    None => {
+                        println!(
+                            "{}) {}: {} -> {}",
+                            qsid,
+                            title,
+                            from_value.map_or("-".to_string(), |v| v.get().to_string()),
+                            to_value.map_or("-".to_string(), |v| v.get().to_string()),
+                        );
+                    }
+                }
+                described = true;
+            }
+        }
+        if !described {
+            println!(
+                "{}) {} -> {}",
+                qsid,
+                from_value.map_or("-".to_string(), |v| v.get().to_string()),
+                to_value.map_or("-".to_string(), |v| v.get().to_string()),
+            );
+        }
+    }
+    Ok(changes)
+}
+
+/// Keys which differ by position, layers without changes are skipped.
+fn changed_keys(from: &protocol::Snapshot, to: &protocol::Snapshot) -> Vec<(u8, LayerChanges)> {
+    let (from_layers, from_rows, from_cols) = from.keys.dimensions();
+    let (to_layers, to_rows, to_cols) = to.keys.dimensions();
+    let mut changed_keys = Vec::new();
+    for layer in 0..max(from_layers, to_layers) {
+        let mut layer_changes = Vec::new();
+        for row in 0..max(from_rows, to_rows) {
+            for col in 0..max(from_cols, to_cols) {
+                let from_key = from.keys.get_long(layer, row, col, from.vial_version).ok();
+                let to_key = to.keys.get_long(layer, row, col, to.vial_version).ok();
+                if from_key != to_key {
+                    layer_changes.push((row, col, from_key, to_key));
+                }
+            }
+        }
+        if !layer_changes.is_empty() {
+            changed_keys.push((layer, layer_changes));
+        }
+    }
+    changed_keys
+}
+
+pub fn run<T: protocol::Transport>(
+    dev: Option<&T>,
+    meta_file: &Option<String>,
+    meta_cache: Option<&MetaCache>,
+    from: &str,
+    to: &str,
+    render: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let meta: Option<Value> = match meta_file {
+        Some(meta_file) => Some(serde_json::from_str(&fs::read_to_string(meta_file)?)?),
+        None if from == DEVICE_SOURCE || to == DEVICE_SOURCE => {
+            let dev = device(dev)?;
+            let capabilities = protocol::scan_capabilities(dev)?;
+            Some(common::load_meta(
+                dev,
+                &capabilities,
+                meta_file,
+                meta_cache,
+            )?)
+        }
+        None => None,
+    };
+    let from_snapshot = load_source(dev, &meta, from)?;
+    let to_snapshot = load_source(dev, &meta, to)?;
+    println!("Comparing {} -> {}", from, to);
+
+    let changed_keys = changed_keys(&from_snapshot, &to_snapshot);
+
+    if render && !changed_keys.is_empty() {
+        let meta = meta.ok_or(common::CommandError(
+            "meta is required to render layers, pass it with -m or compare with device".to_string(),
+        ))?;
+        let state = max(to_snapshot.layout_options, 0) as u32;
+        let options = protocol::LayoutOptions::from_json(state, &meta["layouts"]["labels"])?;
+        let buttons = keymap::keymap_to_buttons(&meta["layouts"]["keymap"], &options)?;
+        for (layer, layer_changes) in &changed_keys {
+            let highlighted: HashSet<(u8, u8)> = layer_changes
+                .iter()
+                .map(|(row, col, _, _)| (*row, *col))
+                .collect();
+            let encoders = match to_snapshot.encoders.get(*layer as usize) {
+                Some(encoders) => encoders,
+                None => &Vec::new(),
+            };
+            common::render_layer(
+                &to_snapshot.keys,
+                encoders,
+                &buttons,
+                *layer,
+                to_snapshot.vial_version,
+                &meta.get("customKeycodes"),
+                &highlighted,
+            )?;
+        }
+    }
+
+    let mut keys = 0;
+    for (layer, layer_changes) in &changed_keys {
+        println!("Layer {}:", layer);
+        for (row, col, from_key, to_key) in layer_changes {
+            println!(
+                "{},{}) {} -> {}",
+                row,
+                col,
+                from_key.as_deref().unwrap_or("-"),
+                to_key.as_deref().unwrap_or("-")
+            );
+            keys += 1;
+        }
+    }
+
+    let encoders = diff_encoders(&from_snapshot, &to_snapshot)?;
+    let combos = diff_entries(
+        "Combos",
+        &from_snapshot,
+        &from_snapshot.combos,
+        &to_snapshot,
+        &to_snapshot.combos,
+        protocol::combos_to_json,
+        protocol::Combo::is_empty,
+    )?;
+    let tap_dances = diff_entries(
+        "Tap dances",
+        &from_snapshot,
+        &from_snapshot.tap_dances,
+        &to_snapshot,
+        &to_snapshot.tap_dances,
+        protocol::tap_dances_to_json,
+        protocol::TapDance::is_empty,
+    )?;
+    let key_overrides = diff_entries(
+        "Key overrides",
+        &from_snapshot,
+        &from_snapshot.key_overrides,
+        &to_snapshot,
+        &to_snapshot.key_overrides,
+        protocol::key_overrides_to_json,
+        protocol::KeyOverride::is_empty,
+    )?;
+    let alt_repeats = diff_entries(
+        "Alt repeat keys",
+        &from_snapshot,
+        &from_snapshot.alt_repeats,
+        &to_snapshot,
+        &to_snapshot.alt_repeats,
+        protocol::alt_repeats_to_json,
+        protocol::AltRepeat::is_empty,
+    )?;
+    let macros = diff_entries(
+        "Macros",
+        &from_snapshot,
+        &from_snapshot.macros,
+        &to_snapshot,
+        &to_snapshot.macros,
+        protocol::macros_to_json,
+        protocol::Macro::is_empty,
+    )?;
+    let settings = diff_settings(&from_snapshot, &to_snapshot)?;
+    let layout_options = if from_snapshot.layout_options != to_snapshot.layout_options {
+        println!(
+            "Layout options: {} -> {}",
+            from_snapshot.layout_options, to_snapshot.layout_options
+        );
+        1
+    } else {
+        0
+    };
+
+    println!(
+        "\n{} keys, {} encoders, {} combos, {} tap dances, {} key overrides, {} alt repeat keys, {} macros, {} settings, {} layout options differ",
+        keys,
+        encoders,
+        combos,
+        tap_dances,
+        key_overrides,
+        alt_repeats,
+        macros,
+        settings,
+        layout_options
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::env;
+
+    fn base() -> Value {
+        json!({
+            "vial_protocol": 6,
+            "layout": [[["KC_A", "KC_B"], ["KC_C", "KC_D"]]],
+            "combo": [["KC_A", "KC_B", "KC_NO", "KC_NO", "KC_ESCAPE"]],
+            "macro": [[["text", "hello"]], []]
+        })
+    }
+
+    #[test]
+    fn test_changed_keys() {
+        let from = protocol::Snapshot::from_json(&base()).unwrap();
+        assert!(changed_keys(&from, &from).is_empty());
+
+        let mut config = base();
+        config["layout"][0][1][0] = "KC_Z".into();
+        config["layout"]
+            .as_array_mut()
+            .unwrap()
+            .push(json!([["KC_1", "KC_TRNS"], ["KC_TRNS", "KC_TRNS"]]));
+        let to = protocol::Snapshot::from_json(&config).unwrap();
+        let changes = changed_keys(&from, &to);
+        assert_eq!(changes.len(), 2);
+        assert_eq!(
+            changes[0],
+            (
+                0,
+                vec![(1, 0, Some("KC_C".to_string()), Some("KC_Z".to_string()))]
+            )
+        );
+        assert_eq!(changes[1].1.len(), 4);
+        assert_eq!(changes[1].1[0], (0, 0, None, Some("KC_1".to_string())));
+    }
+
+    #[test]
+    fn test_diff_entries() {
+        let from = protocol::Snapshot::from_json(&base()).unwrap();
+        let mut config = base();
+        config["combo"][0][4] = "KC_TAB".into();
+        config["combo"]
+            .as_array_mut()
+            .unwrap()
+            .push(json!(["KC_NO", "KC_NO", "KC_NO", "KC_NO", "KC_NO"]));
+        config["macro"] = json!([[["text", "hello"]]]);
+        let to = protocol::Snapshot::from_json(&config).unwrap();
+        let combos = diff_entries(
+            "Combos",
+            &from,
+            &from.combos,
+            &to,
+            &to.combos,
+            protocol::combos_to_json,
+            protocol::Combo::is_empty,
+        )
+        .unwrap();
+        // empty combo added in `to` is not a change
+        assert_eq!(combos, 1);
+        let macros = diff_entries(
+            "Macros",
+            &from,
+            &from.macros,
+            &to,
+            &to.macros,
+            protocol::macros_to_json,
+            protocol::Macro::is_empty,
+        )
+        .unwrap();
+        assert_eq!(macros, 0);
+    }
+
+    #[test]
+    fn test_diff_files() {
+        let dir = env::temp_dir().join(format!("vitaly-diff-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let base_file = dir.join("base.vil").to_string_lossy().to_string();
+        fs::write(&base_file, base().to_string()).unwrap();
+        let mut ragged = base();
+        ragged["layout"][0][1] = json!(["KC_C"]);
+        let ragged_file = dir.join("ragged.vil").to_string_lossy().to_string();
+        fs::write(&ragged_file, ragged.to_string()).unwrap();
+
+        let no_device: Option<&protocol::Simulator> = None;
+        assert!(run(no_device, &None, None, &base_file, &base_file, false).is_ok());
+        let error = run(no_device, &None, None, &base_file, &ragged_file, false).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "layer 0 row 1 has 1 keys, row 0 of layer 0 has 2"
+        );
+        assert!(run(no_device, &None, None, DEVICE_SOURCE, &base_file, false).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,17 +1,16 @@
+use crate::common;
 use crate::keycodes;
 use crate::protocol;
-use hidapi::{DeviceInfo, HidApi};
+use serde_json::json;
 
 pub fn run(
-    api: &HidApi,
-    device: &DeviceInfo,
+    dev: &impl protocol::Transport,
     layer: u8,
     position: &str,
     value: &Option<String>,
+    output: &common::Output,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let device_path = device.path();
-    let dev = api.open_path(device_path)?;
-    let capabilities = protocol::scan_capabilities(&dev)?;
+    let capabilities = protocol::scan_capabilities(dev)?;
     let clean_position = position.replace(" ", "");
     let (index, direction) = clean_position
         .split_once(",")
@@ -27,17 +26,26 @@ pub fn run(
                 .into());
             }
             let keycode = keycodes::name_to_qid(value, capabilities.vial_version)?;
-            protocol::set_encoder(&dev, layer, index, direction, keycode)?;
-            println!(
-                "Encoder on layer={:?}, index={:?}, direction={:?} set to => {}, keycode = {:#x}",
-                layer, index, direction, value, keycode,
-            );
+            protocol::set_encoder(dev, layer, index, direction, keycode)?;
+            output.print_status(
+                &format!(
+                    "Encoder on layer={:?}, index={:?}, direction={:?} set to => {}, keycode = {:#x}",
+                    layer, index, direction, value, keycode,
+                ),
+                json!({
+                    "layer": layer,
+                    "index": index,
+                    "direction": direction,
+                    "keycode": value,
+                    "qid": keycode,
+                }),
+            )?;
         }
         None => {
-            let e = protocol::load_encoder(&dev, layer, index)?;
-            let value = match direction {
-                0 => keycodes::qid_to_name(e.ccw, capabilities.vial_version),
-                1 => keycodes::qid_to_name(e.cw, capabilities.vial_version),
+            let e = protocol::load_encoder(dev, layer, index)?;
+            let qid = match direction {
+                0 => e.ccw,
+                1 => e.cw,
                 _ => {
                     return Err(protocol::ProtocolError::General(
                         "direction should be 0 or 1".to_string(),
@@ -45,6 +53,16 @@ pub fn run(
                     .into());
                 }
             };
+            let value = keycodes::qid_to_name(qid, capabilities.vial_version);
+            if output.format == common::OutputFormat::Json {
+                return output.print_json(&json!({
+                    "layer": layer,
+                    "index": index,
+                    "direction": direction,
+                    "keycode": value,
+                    "qid": qid,
+                }));
+            }
 
             println!(
                 "Encoder on layer={:?}, index={:?}, direction={:?} => {}",
@@ -0,0 +1,173 @@
+use crate::commands::remap;
+use crate::common;
+use crate::export;
+use crate::keymap;
+use crate::meta_cache::MetaCache;
+use crate::protocol;
+use argh::FromArgs;
+use serde_json::Value;
+use std::cmp::max;
+use std::fs;
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// Export configuration as firmware source
+#[argh(subcommand, name = "export")]
+pub struct CommandExport {
+    #[argh(subcommand)]
+    pub command: ExportCommandEnum,
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand)]
+pub enum ExportCommandEnum {
+    QmkC(CommandExportQmkC),
+    Zmk(CommandExportZmk),
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// Export configuration as QMK keymap.c
+#[argh(subcommand, name = "qmk-c")]
+pub struct CommandExportQmkC {
+    /// meta file (to use instead of vial meta), keyboard isn't required with it and --from
+    #[argh(option, short = 'm')]
+    pub meta: Option<String>,
+
+    /// layout file to export, configuration of keyboard is exported without it
+    #[argh(option)]
+    pub from: Option<String>,
+
+    /// path to write keymap.c, it is printed without it
+    #[argh(option)]
+    pub to: Option<String>,
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// Export configuration as ZMK .keymap
+#[argh(subcommand, name = "zmk")]
+pub struct CommandExportZmk {
+    /// meta file (to use instead of vial meta), keyboard isn't required with it and --from
+    #[argh(option, short = 'm')]
+    pub meta: Option<String>,
+
+    /// layout file to export, configuration of keyboard is exported without it
+    #[argh(option)]
+    pub from: Option<String>,
+
+    /// path to write .keymap, it is printed without it
+    #[argh(option)]
+    pub to: Option<String>,
+}
+
+/// Loads snapshot from layout file or keyboard together with meta, keycodes are converted to
+/// Vial protocol 6 which uses QMK names.
+fn load<T: protocol::Transport>(
+    dev: Option<&T>,
+    meta_file: &Option<String>,
+    meta_cache: Option<&MetaCache>,
+    from: &Option<String>,
+) -> Result<(protocol::Snapshot, Value), Box<dyn std::error::Error>> {
+    let (mut snapshot, meta) = match (dev, from) {
+        (Some(dev), None) => {
+            let capabilities = protocol::scan_capabilities(dev)?;
+            let meta = common::load_meta(dev, &capabilities, meta_file, meta_cache)?;
+            (protocol::Snapshot::load(dev, &capabilities, &meta)?, meta)
+        }
+        (Some(dev), Some(from)) => {
+            let capabilities = protocol::scan_capabilities(dev)?;
+            let meta = common::load_meta(dev, &capabilities, meta_file, meta_cache)?;
+            let config = common::read_layout_file(from, &meta)?;
+            (
+                protocol::Snapshot::from_config(&config, capabilities.vial_version)?,
+                meta,
+            )
+        }
+        (None, Some(from)) => {
+            let meta_file = meta_file.as_ref().ok_or(common::CommandError(
+                "meta file should be passed with -m to export without keyboard".to_string(),
+            ))?;
+            let meta: Value = serde_json::from_str(&fs::read_to_string(meta_file)?)?;
+            let config = common::read_layout_file(from, &meta)?;
+            (
+                protocol::Snapshot::from_config(&config, config.vial_protocol.unwrap_or(0))?,
+                meta,
+            )
+        }
+        (None, None) => {
+            return Err(common::CommandError(
+                "layout file should be passed with --from to export without keyboard".to_string(),
+            )
+            .into());
+        }
+    };
+    if !matches!(snapshot.vial_version, 0 | 6) {
+        for name in remap::convert_keycodes(&mut snapshot, 6) {
+            eprintln!("{} has no QMK equivalent, replaced with KC_NO", name);
+        }
+    }
+    let cols = meta["matrix"]["cols"]
+        .as_u64()
+        .ok_or("matrix/cols not found in meta")? as u8;
+    let rows = meta["matrix"]["rows"]
+        .as_u64()
+        .ok_or("matrix/rows not found in meta")? as u8;
+    let (_, file_rows, file_cols) = snapshot.keys.dimensions();
+    if (file_rows, file_cols) != (rows, cols) {
+        return Err(common::CommandError(format!(
+            "layout has {}x{} keys, keyboard has {}x{} keys",
+            file_rows, file_cols, rows, cols
+        ))
+        .into());
+    }
+    Ok((snapshot, meta))
+}
+
+fn write_output(content: &str, to: &Option<String>) -> Result<(), Box<dyn std::error::Error>> {
+    match to {
+        Some(to) => {
+            fs::write(to, content)?;
+            println!("Exported to file {}", to);
+        }
+        None => print!("{}", content),
+    }
+    Ok(())
+}
+
+pub fn run<T: protocol::Transport>(
+    dev: Option<&T>,
+    meta_cache: Option<&MetaCache>,
+    cmd: &CommandExport,
+) -> Result<(), Box<dyn std::error::Error>> {
+    match &cmd.command {
+        ExportCommandEnum::QmkC(ops) => {
+            let (snapshot, meta) = load(dev, &ops.meta, meta_cache, &ops.from)?;
+            let options = protocol::LayoutOptions::from_json(
+                max(snapshot.layout_options, 0) as u32,
+                &meta["layouts"]["labels"],
+            )?;
+            let buttons = keymap::keymap_to_buttons(&meta["layouts"]["keymap"], &options)?;
+            let content = export::keymap_c(
+                &snapshot,
+                &buttons,
+                meta["name"].as_str().unwrap_or("keyboard"),
+            )?;
+            write_output(&content, &ops.to)
+        }
+        ExportCommandEnum::Zmk(ops) => {
+            let (snapshot, meta) = load(dev, &ops.meta, meta_cache, &ops.from)?;
+            let options = protocol::LayoutOptions::from_json(
+                max(snapshot.layout_options, 0) as u32,
+                &meta["layouts"]["labels"],
+            )?;
+            let buttons = keymap::keymap_to_buttons(&meta["layouts"]["keymap"], &options)?;
+            let (content, report) = export::zmk_keymap(
+                &snapshot,
+                &buttons,
+                meta["name"].as_str().unwrap_or("keyboard"),
+            )?;
+            for line in &report {
+                eprintln!("{}", line);
+            }
+            write_output(&content, &ops.to)
+        }
+    }
+}
@@ -0,0 +1,131 @@
+use crate::backups::Backups;
+use crate::commands::load;
+use crate::commands::qmk_keymap;
+use crate::common;
+use crate::keymap;
+use crate::meta_cache::MetaCache;
+use crate::protocol;
+use serde_json::Value;
+use std::collections::HashSet;
+use std::fs;
+
+/// Vial protocol of layout file written without keyboard.
+const DEFAULT_VIAL_PROTOCOL: u32 = 6;
+
+/// Imports QMK Configurator keymap.json, into layout file when `to` is passed or into keyboard
+/// otherwise. Keyboard isn't required when meta file and `to` are passed, dev is None then.
+#[allow(clippy::too_many_arguments)]
+pub fn run<T: protocol::Transport>(
+    dev: Option<&T>,
+    meta_file: &Option<String>,
+    meta_cache: Option<&MetaCache>,
+    file: &String,
+    to: &Option<String>,
+    vial_protocol: Option<u32>,
+    preview: bool,
+    backups: Option<&Backups>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let keymap: Value = serde_json::from_str(&fs::read_to_string(file)?)?;
+    let layers_count = keymap["layers"]
+        .as_array()
+        .ok_or("layers not found in keymap")?
+        .len() as u8;
+    let (capabilities, meta) = match dev {
+        Some(dev) => {
+            let capabilities = protocol::scan_capabilities(dev)?;
+            let meta = common::load_meta(dev, &capabilities, meta_file, meta_cache)?;
+            (Some(capabilities), meta)
+        }
+        None => {
+            let meta_file = meta_file.as_ref().ok_or(common::CommandError(
+                "meta file should be passed with -m to import without keyboard".to_string(),
+            ))?;
+            (None, serde_json::from_str(&fs::read_to_string(meta_file)?)?)
+        }
+    };
+    let cols = meta["matrix"]["cols"]
+        .as_u64()
+        .ok_or("matrix/cols not found in meta")? as u8;
+    let rows = meta["matrix"]["rows"]
+        .as_u64()
+        .ok_or("matrix/rows not found in meta")? as u8;
+
+    let (layers, vial_version, layout_options) = match (&capabilities, dev) {
+        (Some(capabilities), Some(dev)) => (
+            capabilities.layer_count,
+            capabilities.vial_version,
+            match &meta["layouts"]["labels"] {
+                Value::Null => -1,
+                _ => protocol::load_layout_options(dev)? as i64,
+            },
+        ),
+        _ => (
+            layers_count,
+            vial_protocol.unwrap_or(DEFAULT_VIAL_PROTOCOL),
+            match &meta["layouts"]["labels"] {
+                Value::Null => -1,
+                _ => 0,
+            },
+        ),
+    };
+    let options = protocol::LayoutOptions::from_json(
+        layout_options.max(0) as u32,
+        &meta["layouts"]["labels"],
+    )?;
+    let buttons = keymap::keymap_to_buttons(&meta["layouts"]["keymap"], &options)?;
+    let positions: Vec<(u8, u8)> = buttons
+        .iter()
+        .filter(|b| !b.encoder && !b.decal)
+        .map(|b| (b.wire_x, b.wire_y))
+        .collect();
+    let encoders = keymap::get_encoders_count(&meta["layouts"]["keymap"])?;
+
+    let (mut snapshot, unsupported) = qmk_keymap::snapshot_from_keymap(
+        &keymap,
+        &positions,
+        layers,
+        rows,
+        cols,
+        encoders,
+        vial_version,
+    )?;
+    snapshot.layout_options = layout_options;
+    for name in &unsupported {
+        println!("Keycode {} is not supported, replaced with KC_NO", name);
+    }
+
+    let sections = HashSet::from([
+        protocol::Section::Keys,
+        protocol::Section::Encoders,
+        protocol::Section::Layout,
+    ]);
+    match (to, dev, &capabilities) {
+        (Some(to), _, _) => {
+            if let Some(capabilities) = &capabilities {
+                snapshot.via_version = capabilities.via_version;
+            }
+            let mut config = snapshot.to_config()?;
+            config.retain(&sections);
+            common::write_layout_file(to, &config, &meta)?;
+            println!(
+                "{} layers imported, {} keycodes not supported",
+                layers_count,
+                unsupported.len()
+            );
+            println!("Configuration saved to file {}", to);
+            Ok(())
+        }
+        (None, Some(dev), Some(capabilities)) => load::apply(
+            dev,
+            capabilities,
+            &meta,
+            &snapshot,
+            &HashSet::from([protocol::Section::Keys, protocol::Section::Encoders]),
+            preview,
+            backups,
+        ),
+        _ => Err(
+            common::CommandError("keyboard is required to import without --to".to_string()).into(),
+        ),
+    }
+}
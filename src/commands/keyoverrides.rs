@@ -1,21 +1,16 @@
 use crate::common;
 use crate::protocol;
-use hidapi::{DeviceInfo, HidApi};
+use serde_json::json;
 
 pub fn run(
-    api: &HidApi,
-    device: &DeviceInfo,
+    dev: &impl protocol::Transport,
     number: Option<u8>,
     value: &Option<String>,
+    output: &common::Output,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let device_path = device.path();
-    let dev = api.open_path(device_path)?;
-    let capabilities = protocol::scan_capabilities(&dev)?;
+    let capabilities = protocol::scan_capabilities(dev)?;
     if capabilities.key_override_count == 0 {
-        return Err(common::CommandError(
-            format!("device {:?} has doesn't support key override", device).to_string(),
-        )
-        .into());
+        return Err(common::CommandError("device doesn't support key override".to_string()).into());
     }
     let n: u8 = match number {
         Some(num) => {
@@ -39,17 +34,26 @@ pub fn run(
                 0 => protocol::KeyOverride::empty(n),
                 _ => protocol::KeyOverride::from_string(n, value, capabilities.vial_version)?,
             };
-            protocol::set_key_override(&dev, &ko)?;
-            println!("KeyOverride {} saved", ko.index);
+            protocol::set_key_override(dev, &ko)?;
+            output.print_status(
+                &format!("KeyOverride {} saved", ko.index),
+                json!({"key_override": ko.index}),
+            )?;
         }
         None => {
-            let keyoverrides = protocol::load_key_overrides(&dev, capabilities.key_override_count)?;
+            let keyoverrides = protocol::load_key_overrides(dev, capabilities.key_override_count)?;
+            if output.format == common::OutputFormat::Json {
+                return output.print_entries_json(
+                    protocol::key_overrides_to_json(&keyoverrides, capabilities.vial_version)?,
+                    number,
+                );
+            }
             if number.is_none() {
                 let keyoverride_count = keyoverrides.len();
                 let mut first_empty = capabilities.key_override_count;
                 for idxm in 1..=keyoverride_count {
                     let idx = keyoverride_count - idxm;
-                    if !keyoverrides[idx as usize].is_empty() {
+                    if !keyoverrides[idx].is_empty() {
                         break;
                     }
                     first_empty = idx as u8;
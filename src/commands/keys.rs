@@ -1,20 +1,20 @@
 use crate::common;
 use crate::keycodes;
+use crate::meta_cache::MetaCache;
 use crate::protocol;
-use hidapi::{DeviceInfo, HidApi};
+use serde_json::json;
 
 pub fn run(
-    api: &HidApi,
-    device: &DeviceInfo,
+    dev: &impl protocol::Transport,
     meta_file: &Option<String>,
+    meta_cache: Option<&MetaCache>,
     layer: u8,
     position: &str,
     value: &Option<String>,
+    output: &common::Output,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let device_path = device.path();
-    let dev = api.open_path(device_path)?;
-    let capabilities = protocol::scan_capabilities(&dev)?;
-    let meta = common::load_meta(&dev, &capabilities, meta_file)?;
+    let capabilities = protocol::scan_capabilities(dev)?;
+    let meta = common::load_meta(dev, &capabilities, meta_file, meta_cache)?;
     let cols = meta["matrix"]["cols"]
         .as_u64()
         .ok_or("matrix/cols not found in meta")? as u8;
@@ -33,11 +33,20 @@ pub fn run(
     match value {
         Some(value) => match keycodes::name_to_qid(value, capabilities.vial_version) {
             Ok(keycode) => {
-                protocol::set_keycode(&dev, layer, row, col, keycode)?;
-                println!(
-                    "Key on layer={:?}, row={:?}, col={:?} set to => {}, keycode = {:#x}",
-                    layer, row, col, value, keycode,
-                );
+                protocol::set_keycode(dev, layer, row, col, keycode)?;
+                output.print_status(
+                    &format!(
+                        "Key on layer={:?}, row={:?}, col={:?} set to => {}, keycode = {:#x}",
+                        layer, row, col, value, keycode,
+                    ),
+                    json!({
+                        "layer": layer,
+                        "row": row,
+                        "col": col,
+                        "keycode": value,
+                        "qid": keycode,
+                    }),
+                )?;
             }
             Err(e) => {
                 return Err(common::CommandError(
@@ -47,8 +56,17 @@ pub fn run(
             }
         },
         None => {
-            let keys = protocol::load_layers_keys(&dev, capabilities.layer_count, rows, cols)?;
+            let keys = protocol::load_layers_keys(dev, capabilities.layer_count, rows, cols)?;
             let label = keys.get_long(layer, row, col, capabilities.vial_version)?;
+            if output.format == common::OutputFormat::Json {
+                return output.print_json(&json!({
+                    "layer": layer,
+                    "row": row,
+                    "col": col,
+                    "keycode": label,
+                    "qid": keys.get(layer, row, col),
+                }));
+            }
             println!(
                 "Key on layer={:?}, row={:?}, col={:?} => {}",
                 layer, row, col, label
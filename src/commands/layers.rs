@@ -1,20 +1,27 @@
 use crate::common;
+use crate::keycodes;
 use crate::keymap;
+use crate::meta_cache::MetaCache;
 use crate::protocol;
-use hidapi::{DeviceInfo, HidApi};
+use serde_json::{Value, json};
+use std::collections::HashSet;
+use std::fs;
 
+#[allow(clippy::too_many_arguments)]
 pub fn run(
-    api: &HidApi,
-    device: &DeviceInfo,
+    dev: &impl protocol::Transport,
     meta_file: &Option<String>,
+    meta_cache: Option<&MetaCache>,
     positions: bool,
     number: Option<u8>,
     layout_options: &Option<String>,
+    all: bool,
+    svg: &Option<String>,
+    titles: &[String],
+    output: &common::Output,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let device_path = device.path();
-    let dev = api.open_path(device_path)?;
-    let capabilities = protocol::scan_capabilities(&dev)?;
-    let meta = common::load_meta(&dev, &capabilities, meta_file)?;
+    let capabilities = protocol::scan_capabilities(dev)?;
+    let meta = common::load_meta(dev, &capabilities, meta_file, meta_cache)?;
     let options = if let Some(layout_options) = layout_options {
         let mut via_options = Vec::new();
         for group in layout_options.split(';') {
@@ -31,38 +38,162 @@ pub fn run(
         options
     } else {
         let layout_options = &meta["layouts"]["labels"];
-        let state = protocol::load_layout_options(&dev)?;
+        let state = protocol::load_layout_options(dev)?;
         protocol::LayoutOptions::from_json(state, layout_options)?
     };
     //println!("{:?}", &options);
     let buttons = keymap::keymap_to_buttons(&meta["layouts"]["keymap"], &options)?;
-    if positions {
+    if positions && let Some(svg) = svg {
+        let mut layer = keymap::svg::SvgLayer {
+            title: titles.first().filter(|t| !t.is_empty()).cloned(),
+            ..Default::default()
+        };
+        for button in &buttons {
+            layer.keys.insert(
+                (button.wire_x, button.wire_y),
+                format!("{},{}", button.wire_x, button.wire_y),
+            );
+        }
+        fs::write(svg, keymap::svg::render_svg(&buttons, &[layer]))?;
+        println!("Layers saved to file {}", svg);
+    } else if positions && output.format == common::OutputFormat::Json {
+        let mut result = Vec::new();
+        for button in &buttons {
+            if !button.decal {
+                result.push(json!({
+                    "row": button.wire_x,
+                    "col": button.wire_y,
+                    "x": button.x,
+                    "y": button.y,
+                    "w": button.w,
+                    "h": button.h,
+                    "encoder": button.encoder,
+                }));
+            }
+        }
+        output.print_json(&Value::Array(result))?;
+    } else if positions {
         keymap::render_and_dump(&buttons, None);
     } else {
-        let layer_number: u8 = number.unwrap_or_default();
+        let layer_numbers: Vec<u8> = if all {
+            (0..capabilities.layer_count).collect()
+        } else {
+            vec![number.unwrap_or_default()]
+        };
         let cols = meta["matrix"]["cols"]
             .as_u64()
             .ok_or("matrix/cols not found in meta")? as u8;
         let rows = meta["matrix"]["rows"]
             .as_u64()
             .ok_or("matrix/rows not found in meta")? as u8;
-        let keys = protocol::load_layers_keys(&dev, capabilities.layer_count, rows, cols)?;
-        let mut encoders = Vec::new();
-        for button in &buttons {
-            if button.encoder && button.wire_y == 1 {
-                let e = protocol::load_encoder(&dev, layer_number, button.wire_x)?;
-                encoders.push(e);
+        let keys = protocol::load_layers_keys(dev, capabilities.layer_count, rows, cols)?;
+        let mut layers_encoders = Vec::new();
+        for layer_number in &layer_numbers {
+            let mut encoders = Vec::new();
+            for button in &buttons {
+                if button.encoder && button.wire_y == 1 {
+                    let e = protocol::load_encoder(dev, *layer_number, button.wire_x)?;
+                    encoders.push(e);
+                }
+            }
+            encoders.sort_by_key(|e| e.index);
+            layers_encoders.push(encoders);
+        }
+        if let Some(svg) = svg {
+            let custom = custom_names(&meta)?;
+            let mut layers = Vec::new();
+            for (i, (layer_number, encoders)) in
+                layer_numbers.iter().zip(&layers_encoders).enumerate()
+            {
+                let title = match titles.get(i) {
+                    Some(title) if title.is_empty() => None,
+                    Some(title) => Some(title.clone()),
+                    None => Some(format!("Layer {}", layer_number)),
+                };
+                let mut layer = keymap::svg::SvgLayer {
+                    title,
+                    ..Default::default()
+                };
+                for button in &buttons {
+                    if !button.encoder && !button.decal {
+                        let label = if button.wire_x < rows && button.wire_y < cols {
+                            let keycode = keys.get(*layer_number, button.wire_x, button.wire_y);
+                            keycode_label(keycode, &custom, capabilities.vial_version)
+                        } else {
+                            String::new()
+                        };
+                        layer.keys.insert((button.wire_x, button.wire_y), label);
+                    }
+                }
+                for e in encoders {
+                    let ccw = keycode_label(e.ccw, &custom, capabilities.vial_version);
+                    let cw = keycode_label(e.cw, &custom, capabilities.vial_version);
+                    layer.encoders.insert((e.index, 0), ccw);
+                    layer.encoders.insert((e.index, 1), cw);
+                }
+                layers.push(layer);
+            }
+            fs::write(svg, keymap::svg::render_svg(&buttons, &layers))?;
+            println!("Layers saved to file {}", svg);
+        } else if output.format == common::OutputFormat::Json {
+            let json_layers = keys.to_json(capabilities.vial_version)?;
+            let mut result = Vec::new();
+            for (layer_number, encoders) in layer_numbers.iter().zip(layers_encoders) {
+                result.push(json!({
+                    "layer": layer_number,
+                    "keys": json_layers
+                        .get(*layer_number as usize)
+                        .ok_or(common::CommandError(format!(
+                            "layer {} is not defined",
+                            layer_number
+                        )))?,
+                    "encoders": protocol::encoders_to_json(&vec![encoders], capabilities.vial_version)?[0],
+                }));
+            }
+            if all {
+                return output.print_json(&Value::Array(result));
+            }
+            return output.print_json(&result[0]);
+        } else {
+            for (layer_number, encoders) in layer_numbers.iter().zip(&layers_encoders) {
+                common::render_layer(
+                    &keys,
+                    encoders,
+                    &buttons,
+                    *layer_number,
+                    capabilities.vial_version,
+                    &meta.get("customKeycodes"),
+                    &HashSet::new(),
+                )?
             }
         }
-        encoders.sort_by(|e1, e2| e1.index.cmp(&e2.index));
-        common::render_layer(
-            &keys,
-            &encoders,
-            &buttons,
-            layer_number,
-            capabilities.vial_version,
-            &meta.get("customKeycodes"),
-        )?
     }
     Ok(())
 }
+
+/// Names of custom keycodes from meta, multiline names are kept as they are drawn on keys.
+fn custom_names(meta: &Value) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let mut result = Vec::new();
+    // badly formatted json is ignored silently as in terminal rendering
+    if let Some(Value::Array(custom)) = meta.get("customKeycodes") {
+        for code in custom {
+            let name = code
+                .get("shortName")
+                .or_else(|| code.get("name"))
+                .and_then(|n| n.as_str())
+                .ok_or("customKeycodes elements should have shortName")?;
+            result.push(name.to_string());
+        }
+    }
+    Ok(result)
+}
+
+/// Full keycode name or name of custom keycode from meta.
+fn keycode_label(keycode: u16, custom: &[String], vial_version: u32) -> String {
+    if let Some(index) = keycodes::is_custom(keycode, vial_version)
+        && let Some(name) = custom.get(index as usize)
+    {
+        return name.clone();
+    }
+    keycodes::qid_to_name(keycode, vial_version)
+}
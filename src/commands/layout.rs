@@ -1,26 +1,24 @@
 use crate::common;
+use crate::meta_cache::MetaCache;
 use crate::protocol;
-use hidapi::{DeviceInfo, HidApi};
 
 pub fn run(
-    api: &HidApi,
-    device: &DeviceInfo,
+    dev: &impl protocol::Transport,
     meta_file: &Option<String>,
+    meta_cache: Option<&MetaCache>,
     option: &Option<u8>,
     value: &Option<u8>,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let device_path = device.path();
-    let dev = api.open_path(device_path)?;
-    let capabilities = protocol::scan_capabilities(&dev)?;
-    let meta = common::load_meta(&dev, &capabilities, meta_file)?;
+    let capabilities = protocol::scan_capabilities(dev)?;
+    let meta = common::load_meta(dev, &capabilities, meta_file, meta_cache)?;
     let layout_options = &meta["layouts"]["labels"];
-    let state = protocol::load_layout_options(&dev)?;
+    let state = protocol::load_layout_options(dev)?;
     let mut options = protocol::LayoutOptions::from_json(state, layout_options)?;
     match option {
         Some(o) => match value {
             Some(v) => {
                 options.set_via_options(vec![(*o, *v)])?;
-                protocol::set_layout_options(&dev, options.state)?;
+                protocol::set_layout_options(dev, options.state)?;
                 println!("Layout options has been updated");
             }
             None => {
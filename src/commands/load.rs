@@ -1,178 +1,124 @@
+use crate::backups::Backups;
 use crate::common;
 use crate::keymap;
+use crate::meta_cache::MetaCache;
 use crate::protocol;
-use hidapi::{DeviceInfo, HidApi};
 use serde_json::Value;
-use std::fs;
+use std::cmp::max;
+use std::collections::HashSet;
 
 pub fn run(
-    api: &HidApi,
-    device: &DeviceInfo,
+    dev: &impl protocol::Transport,
     meta_file: &Option<String>,
-    file: &String,
+    meta_cache: Option<&MetaCache>,
+    file: &str,
     preview: bool,
+    sections: &HashSet<protocol::Section>,
+    backups: Option<&Backups>,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let device_path = device.path();
-    let dev = api.open_path(device_path)?;
-    let capabilities = protocol::scan_capabilities(&dev)?;
-    let meta = common::load_meta(&dev, &capabilities, meta_file)?;
-    let cols = meta["matrix"]["cols"]
-        .as_u64()
-        .ok_or("matrix/cols not found in meta")? as u8;
-    let rows = meta["matrix"]["rows"]
-        .as_u64()
-        .ok_or("matrix/rows not found in meta")? as u8;
-
-    let layout_str = fs::read_to_string(file)?;
-    let root_json: Value = serde_json::from_str(&layout_str)?;
-    let root = root_json
-        .as_object()
-        .ok_or("config file root is not an object")?;
+    let capabilities = protocol::scan_capabilities(dev)?;
+    let meta = common::load_meta(dev, &capabilities, meta_file, meta_cache)?;
+
+    let config = common::read_layout_file(file, &meta)?;
+    // sections missing in file are skipped
+    let sections: HashSet<protocol::Section> =
+        sections.intersection(&config.sections()).copied().collect();
+    let mut snapshot = protocol::Snapshot::from_config(&config, capabilities.vial_version)?;
+    if common::is_toml_file(file) {
+        // .toml lists only defined entries
+        snapshot.fill_empty_entries(&capabilities);
+    }
+    apply(
+        dev,
+        &capabilities,
+        &meta,
+        &snapshot,
+        &sections,
+        preview,
+        backups,
+    )
+}
 
+/// Loads `sections` of snapshot into keyboard or shows them when `preview` is set.
+/// Keyboard configuration is saved into `backups` before it is overwritten, if they are given.
+pub fn apply(
+    dev: &impl protocol::Transport,
+    capabilities: &protocol::Capabilities,
+    meta: &Value,
+    snapshot: &protocol::Snapshot,
+    sections: &HashSet<protocol::Section>,
+    preview: bool,
+    backups: Option<&Backups>,
+) -> Result<(), Box<dyn std::error::Error>> {
     let layout_options = &meta["layouts"]["labels"];
-    let layout_state = match &root_json["layout_options"] {
-        Value::Null => 0,
-        Value::Number(num) => {
-            let n = num.as_i64().ok_or("layout_options should be a number")?;
-            if n == -1 { 0 } else { n as u32 }
-        }
-        _ => {
-            return Err(
-                common::CommandError("layout_option should be a number".to_string()).into(),
-            );
-        }
-    };
+    let layout_state = max(snapshot.layout_options, 0) as u32;
     let options = protocol::LayoutOptions::from_json(layout_state, layout_options)?;
 
     let buttons = keymap::keymap_to_buttons(&meta["layouts"]["keymap"], &options)?;
 
-    let layers = root
-        .get("layout")
-        .ok_or("config file has no layout defined")?
-        .as_array()
-        .ok_or("layout should be an array")?;
-
-    let keys = protocol::Keymap::from_json(
-        rows,
-        cols,
-        capabilities.layer_count,
-        layers,
-        capabilities.vial_version,
-    )?;
-    let encoder_layout = match &root.get("encoder_layout") {
-        Some(value) => protocol::load_encoders_from_json(value, capabilities.vial_version)?,
-        None => Vec::new(),
-    };
-    let combos = match capabilities.combo_count {
-        0 => Vec::new(),
-        _ => protocol::load_combos_from_json(
-            root.get("combo").ok_or("combo is not defined")?,
-            capabilities.vial_version,
-        )?,
-    };
-    let tap_dances = match capabilities.tap_dance_count {
-        0 => Vec::new(),
-        _ => protocol::load_tap_dances_from_json(
-            root.get("tap_dance").ok_or("tad_dance is not defined")?,
-            capabilities.vial_version,
-        )?,
-    };
-    let macros = protocol::load_macros_from_json(
-        root.get("macro").ok_or("macro is not defined")?,
-        capabilities.vial_version,
-    )?;
-    let key_overrides = match capabilities.key_override_count {
-        0 => Vec::new(),
-        _ => protocol::load_key_overrides_from_json(
-            root.get("key_override")
-                .ok_or("key_override are not defined")?,
-            capabilities.vial_version,
-        )?,
-    };
-
-    let alt_repeats = match capabilities.alt_repeat_key_count {
-        0 => Vec::new(),
-        _ => protocol::load_alt_repeats_from_json(
-            root.get("alt_repeat_key")
-                .ok_or("alt_repeat_key are not defined")?,
-            capabilities.vial_version,
-        )?,
-    };
-
     if !preview {
         println!();
-        if !macros.is_empty() && capabilities.vial_version > 0 {
-            let status = protocol::get_locked_status(&dev)?;
-            if status.locked {
+        let backup = match backups {
+            Some(backups) => {
+                let current = protocol::Snapshot::load(dev, capabilities, meta)?;
+                let path = backups.save(&current).map_err(|e| {
+                    common::CommandError(format!(
+                        "failed to save backup: {}, pass --no-backup to load without it",
+                        e
+                    ))
+                })?;
+                println!("Current configuration saved to {}", path.display());
+                Some(path)
+            }
+            None => None,
+        };
+        let report = match snapshot.apply_sections(dev, capabilities, meta, sections) {
+            Ok(report) => report,
+            Err(e)
+                if matches!(
+                    e.downcast_ref(),
+                    Some(protocol::ProtocolError::KeyboardLocked)
+                ) =>
+            {
                 return Err(common::CommandError("Keyboard is locked, macroses can't be updated, keyboard might be unlocked with subcommand 'lock -u'".to_string()).into());
             }
-        }
-
-        protocol::set_layout_options(&dev, layout_state)?;
-
-        protocol::set_macros(&dev, &capabilities, &macros)?;
-        println!("Macros restored");
-
-        for ko in key_overrides {
-            protocol::set_key_override(&dev, &ko)?;
-        }
-        println!("Key overrides restored");
-
-        for ar in alt_repeats {
-            protocol::set_alt_repeat(&dev, &ar)?;
-        }
-        println!("Alt repeat keys restored");
-
-        for combo in combos {
-            protocol::set_combo(&dev, &combo)?;
-        }
-        println!("Combos restored");
-
-        for td in tap_dances {
-            protocol::set_tap_dance(&dev, &td)?;
-        }
-        println!("TapDances restored");
-
-        for layer_number in 0..capabilities.layer_count {
-            let layer_encoders = if encoder_layout.len() > layer_number.into() {
-                &encoder_layout[layer_number as usize]
-            } else {
-                &Vec::new()
-            };
-            for encoder in layer_encoders {
-                protocol::set_encoder(&dev, layer_number, encoder.index, 0, encoder.ccw)?;
-                protocol::set_encoder(&dev, layer_number, encoder.index, 1, encoder.cw)?;
+            Err(e) if backup.is_some() => {
+                return Err(common::CommandError(format!(
+                    "{}, keyboard might be partially updated, previous configuration could be restored with subcommand 'rollback'",
+                    e
+                ))
+                .into());
             }
-        }
-        println!("Encoders restored");
-
-        protocol::set_keymap(&dev, &keys)?;
-        println!("Keys restored. All done!!!");
-
-        //
+            Err(e) => return Err(e),
+        };
+        common::print_apply_report(&report);
+        println!("All done!!!");
     } else {
-        if !options.is_empty() {
+        if sections.contains(&protocol::Section::Layout) && !options.is_empty() {
             println!("Layout options:\n{}", options);
         }
-        for layer_number in 0..capabilities.layer_count {
-            let encoders = if encoder_layout.len() > layer_number.into() {
-                &encoder_layout[layer_number as usize]
+        if sections.contains(&protocol::Section::Keys) {
+            let encoders = if sections.contains(&protocol::Section::Encoders) {
+                &snapshot.encoders
             } else {
                 &Vec::new()
             };
-            common::render_layer(
-                &keys,
-                encoders,
-                &buttons,
-                layer_number,
-                capabilities.vial_version,
-                &meta.get("customKeycodes"),
-            )?
+            for layer_number in 0..capabilities.layer_count {
+                common::render_layer(
+                    &snapshot.keys,
+                    encoders.get(layer_number as usize).unwrap_or(&Vec::new()),
+                    &buttons,
+                    layer_number,
+                    capabilities.vial_version,
+                    &meta.get("customKeycodes"),
+                    &HashSet::new(),
+                )?
+            }
         }
 
-        if !combos.is_empty() {
+        if sections.contains(&protocol::Section::Combos) && !snapshot.combos.is_empty() {
             println!("Combos:");
-            for combo in &combos {
+            for combo in &snapshot.combos {
                 if !combo.is_empty() {
                     combo.dump(capabilities.vial_version)?;
                     println!();
@@ -181,9 +127,9 @@ pub fn run(
             println!();
         }
 
-        if !macros.is_empty() {
+        if sections.contains(&protocol::Section::Macros) && !snapshot.macros.is_empty() {
             println!("Macros:");
-            for m in &macros {
+            for m in &snapshot.macros {
                 if !m.is_empty() {
                     m.dump(capabilities.vial_version)?;
                     println!();
@@ -192,9 +138,9 @@ pub fn run(
             println!();
         }
 
-        if !tap_dances.is_empty() {
+        if sections.contains(&protocol::Section::TapDances) && !snapshot.tap_dances.is_empty() {
             println!("TapDances:");
-            for tap_dance in &tap_dances {
+            for tap_dance in &snapshot.tap_dances {
                 if !tap_dance.is_empty() {
                     tap_dance.dump(capabilities.vial_version)?;
                     println!();
@@ -203,9 +149,10 @@ pub fn run(
             println!();
         }
 
-        if !key_overrides.is_empty() {
+        if sections.contains(&protocol::Section::KeyOverrides) && !snapshot.key_overrides.is_empty()
+        {
             println!("KeyOverrides:");
-            for key_override in &key_overrides {
+            for key_override in &snapshot.key_overrides {
                 if !key_override.is_empty() {
                     key_override.dump(capabilities.vial_version)?;
                     println!();
@@ -214,9 +161,9 @@ pub fn run(
             println!();
         }
 
-        if !alt_repeats.is_empty() {
+        if sections.contains(&protocol::Section::AltRepeats) && !snapshot.alt_repeats.is_empty() {
             println!("AltRepeatKeys:");
-            for alt_repeat in &alt_repeats {
+            for alt_repeat in &snapshot.alt_repeats {
                 if !alt_repeat.is_empty() {
                     alt_repeat.dump(capabilities.vial_version)?;
                     println!();
@@ -225,10 +172,10 @@ pub fn run(
             println!();
         }
 
-        if capabilities.vial_version >= protocol::VIAL_PROTOCOL_QMK_SETTINGS {
-            let qmk_settings = protocol::load_qmk_settings_from_json(
-                root.get("settings").ok_or("settings are not defined")?,
-            )?;
+        if sections.contains(&protocol::Section::Settings)
+            && capabilities.vial_version >= protocol::VIAL_PROTOCOL_QMK_SETTINGS
+        {
+            let qmk_settings = &snapshot.qmk_settings;
             let settings_defs = protocol::load_qmk_definitions()?;
             println!("Settings:");
             for group in settings_defs["tabs"]
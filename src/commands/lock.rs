@@ -1,31 +1,48 @@
 use crate::common;
 use crate::keymap;
+use crate::meta_cache::MetaCache;
 use crate::protocol;
-use hidapi::{DeviceInfo, HidApi};
+use serde_json::json;
 use std::collections::HashMap;
 use std::{thread, time};
 
 pub fn run(
-    api: &HidApi,
-    device: &DeviceInfo,
+    dev: &impl protocol::Transport,
+    meta_cache: Option<&MetaCache>,
     unlock: bool,
     lock: bool,
+    output: &common::Output,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let device_path = device.path();
-    let dev = api.open_path(device_path)?;
-    let capabilities = protocol::scan_capabilities(&dev)?;
-    let meta = common::load_meta(&dev, &capabilities, &None)?;
+    let capabilities = protocol::scan_capabilities(dev)?;
+    if output.format == common::OutputFormat::Json {
+        if unlock {
+            return Err(common::CommandError(
+                "unlock is interactive and can't be used with json output".to_string(),
+            )
+            .into());
+        }
+        if capabilities.vial_version == 0 {
+            return output.print_json(&json!({"supported": false}));
+        }
+        let mut status = protocol::get_locked_status(dev)?;
+        if !status.locked && lock {
+            protocol::set_locked(dev)?;
+            status = protocol::get_locked_status(dev)?;
+        }
+        return output.print_json(&status.to_json());
+    }
+    let meta = common::load_meta(dev, &capabilities, &None, meta_cache)?;
     if capabilities.vial_version == 0 {
         println!("Device doesn't support locking");
     } else {
-        let mut status = protocol::get_locked_status(&dev)?;
+        let mut status = protocol::get_locked_status(dev)?;
         // println!("{:?}", status);
         println!("Device is locked: {}", status.locked);
         if status.locked && unlock {
             println!("Starting unlock process... ");
             println!("Push marked buttons and keep then pushed to unlock...");
             let layout_options = &meta["layouts"]["labels"];
-            let state = protocol::load_layout_options(&dev)?;
+            let state = protocol::load_layout_options(dev)?;
             let options = protocol::LayoutOptions::from_json(state, layout_options)?;
             let buttons = keymap::keymap_to_buttons(&meta["layouts"]["keymap"], &options)?;
             let mut button_labels = HashMap::new();
@@ -34,25 +51,25 @@ pub fn run(
             }
             keymap::render_and_dump(&buttons, Some(button_labels));
             if !status.unlock_in_progress {
-                protocol::start_unlock(&dev)?;
+                protocol::start_unlock(dev)?;
             }
             let sleep_duration = time::Duration::from_millis(100);
             let mut unlocked = false;
             let mut polls_remaining: u8;
             while !unlocked {
                 thread::sleep(sleep_duration);
-                (unlocked, polls_remaining) = protocol::unlock_poll(&dev)?;
+                (unlocked, polls_remaining) = protocol::unlock_poll(dev)?;
                 println!(
                     "Seconds remaining: {} keep pushing...",
                     (polls_remaining as f64) / 10.0
                 );
             }
-            status = protocol::get_locked_status(&dev)?;
+            status = protocol::get_locked_status(dev)?;
             println!("Device is locked: {}", status.locked);
         } else if !status.locked && lock {
             println!("Locking keyboard...");
-            protocol::set_locked(&dev)?;
-            status = protocol::get_locked_status(&dev)?;
+            protocol::set_locked(dev)?;
+            status = protocol::get_locked_status(dev)?;
             println!("Device is locked: {}", status.locked);
         }
     }
@@ -1,22 +1,17 @@
 use crate::common;
 use crate::protocol;
-use hidapi::{DeviceInfo, HidApi};
+use serde_json::json;
 
 pub fn run(
-    api: &HidApi,
-    device: &DeviceInfo,
+    dev: &impl protocol::Transport,
     number: Option<u8>,
     value: &Option<String>,
+    output: &common::Output,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let device_path = device.path();
-    let dev = api.open_path(device_path)?;
-    let capabilities = protocol::scan_capabilities(&dev)?;
+    let capabilities = protocol::scan_capabilities(dev)?;
 
     if capabilities.macro_count == 0 {
-        return Err(common::CommandError(
-            format!("device {:?} has doesn't support macros", device).to_string(),
-        )
-        .into());
+        return Err(common::CommandError("device doesn't support macros".to_string()).into());
     }
     let n: u8 = match number {
         Some(num) => {
@@ -31,12 +26,18 @@ pub fn run(
         None => 0,
     };
     let mut macros = protocol::load_macros(
-        &dev,
+        dev,
         capabilities.macro_count,
         capabilities.macro_buffer_size,
     )?;
     match value {
         None => {
+            if output.format == common::OutputFormat::Json {
+                return output.print_entries_json(
+                    protocol::macros_to_json(&macros, capabilities.vial_version)?,
+                    number,
+                );
+            }
             if number.is_none() {
                 println!("Macros list:");
                 for m in macros {
@@ -69,19 +70,21 @@ pub fn run(
                 )
                 .into());
             }
-            println!("Updated macros list:");
-            for m in &macros {
-                m.dump(capabilities.vial_version)?;
-                println!()
+            if output.format == common::OutputFormat::Text {
+                println!("Updated macros list:");
+                for m in &macros {
+                    m.dump(capabilities.vial_version)?;
+                    println!()
+                }
             }
             if capabilities.vial_version > 0 {
-                let status = protocol::get_locked_status(&dev)?;
+                let status = protocol::get_locked_status(dev)?;
                 if status.locked {
                     return Err(common::CommandError("Keyboard is locked, macroses can't be updated, keyboard might be unlocked with subcommand 'lock -u'".to_string()).into());
                 }
             }
-            protocol::set_macros(&dev, &capabilities, &macros)?;
-            println!("Macros successfully updated");
+            protocol::set_macros(dev, &capabilities, &macros)?;
+            output.print_status("Macros successfully updated", json!({"macro": n}))?;
         }
     }
     Ok(())
@@ -0,0 +1,131 @@
+use crate::common;
+use crate::keymap;
+use crate::meta_cache::MetaCache;
+use crate::protocol;
+use argh::FromArgs;
+use serde_json::{Value, json};
+use std::fs;
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// Keyboard definition (vial.json) operations
+#[argh(subcommand, name = "meta")]
+pub struct CommandMeta {
+    #[argh(subcommand)]
+    pub command: MetaCommandEnum,
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand)]
+pub enum MetaCommandEnum {
+    Export(CommandMetaExport),
+    Info(CommandMetaInfo),
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// Export keyboard definition into file
+#[argh(subcommand, name = "export")]
+pub struct CommandMetaExport {
+    /// path to definition file
+    #[argh(option, short = 'f')]
+    pub file: String,
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// Show summary of keyboard definition
+#[argh(subcommand, name = "info")]
+pub struct CommandMetaInfo {
+    /// meta file (to use instead of vial meta)
+    #[argh(option, short = 'm')]
+    pub meta: Option<String>,
+}
+
+fn print_info(meta: &Value, output: &common::Output) -> Result<(), Box<dyn std::error::Error>> {
+    let options = protocol::LayoutOptions::from_json(0, &meta["layouts"]["labels"])?;
+    let encoders = keymap::get_encoders_count(&meta["layouts"]["keymap"])?;
+    let empty = Vec::new();
+    let custom_keycodes = match &meta["customKeycodes"] {
+        Value::Null => &empty,
+        keycodes => keycodes
+            .as_array()
+            .ok_or("customKeycodes should be an array")?,
+    };
+    if output.format == common::OutputFormat::Json {
+        let mut layout_options = Vec::new();
+        for (name, variants, _) in &options.options {
+            layout_options.push(json!({"name": name, "variants": variants}));
+        }
+        return output.print_json(&json!({
+            "name": meta["name"],
+            "vendor_id": meta["vendorId"],
+            "product_id": meta["productId"],
+            "rows": meta["matrix"]["rows"],
+            "cols": meta["matrix"]["cols"],
+            "encoders": encoders,
+            "layout_options": layout_options,
+            "custom_keycodes": custom_keycodes,
+        }));
+    }
+    println!("Name: {}", meta["name"].as_str().unwrap_or("unknown"));
+    println!(
+        "Vendor id: {}, product id: {}",
+        meta["vendorId"].as_str().unwrap_or("-"),
+        meta["productId"].as_str().unwrap_or("-")
+    );
+    println!(
+        "Matrix: {} rows, {} cols",
+        meta["matrix"]["rows"], meta["matrix"]["cols"]
+    );
+    println!("Encoders: {}", encoders);
+    if options.is_empty() {
+        println!("Layout options: none");
+    } else {
+        println!("Layout options:");
+        for (idx, (name, variants, _)) in options.options.iter().enumerate() {
+            println!("{}) {}: {}", idx, name, variants.join(", "));
+        }
+    }
+    if custom_keycodes.is_empty() {
+        println!("Custom keycodes: none");
+    } else {
+        println!("Custom keycodes:");
+        for (idx, keycode) in custom_keycodes.iter().enumerate() {
+            println!(
+                "{}) {} - {}",
+                idx,
+                keycode["name"].as_str().unwrap_or("-"),
+                keycode["title"].as_str().unwrap_or("-")
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Shows summary of definition file, doesn't require keyboard.
+pub fn info_file(
+    meta_file: &String,
+    output: &common::Output,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let meta: Value = serde_json::from_str(&fs::read_to_string(meta_file)?)?;
+    print_info(&meta, output)
+}
+
+pub fn run(
+    dev: &impl protocol::Transport,
+    meta_cache: Option<&MetaCache>,
+    cmd: &CommandMeta,
+    output: &common::Output,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let capabilities = protocol::scan_capabilities(dev)?;
+    match &cmd.command {
+        MetaCommandEnum::Export(ops) => {
+            let meta = common::load_meta(dev, &capabilities, &None, meta_cache)?;
+            fs::write(&ops.file, serde_json::to_string_pretty(&meta)?)?;
+            println!("Keyboard definition saved to file {}", ops.file);
+            Ok(())
+        }
+        MetaCommandEnum::Info(ops) => {
+            let meta = common::load_meta(dev, &capabilities, &ops.meta, meta_cache)?;
+            print_info(&meta, output)
+        }
+    }
+}
@@ -0,0 +1,264 @@
+use crate::commands::remap;
+use crate::common;
+use crate::keycodes;
+use crate::keymap;
+use crate::protocol;
+use serde_json::Value;
+use std::cmp::max;
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+
+/// Key position as (row, col).
+type Position = (u8, u8);
+
+fn parse_position(value: &str) -> Result<Position, Box<dyn std::error::Error>> {
+    let error = || common::CommandError(format!("position {:?} should be 'row,col'", value));
+    let (row, col) = value.trim().split_once(',').ok_or_else(error)?;
+    Ok((
+        row.trim().parse().map_err(|_| error())?,
+        col.trim().parse().map_err(|_| error())?,
+    ))
+}
+
+/// Parses explicit mapping rule 'row,col=row,col' from old keyboard position to new one,
+/// positions should fit into (rows, cols) matrices of old and new keyboards.
+fn parse_rule(
+    value: &str,
+    from_matrix: Position,
+    to_matrix: Position,
+) -> Result<(Position, Position), Box<dyn std::error::Error>> {
+    let (from, to) = value.split_once('=').ok_or(common::CommandError(format!(
+        "mapping rule {:?} should be 'row,col=row,col'",
+        value
+    )))?;
+    let (from, to) = (parse_position(from)?, parse_position(to)?);
+    for (position, (rows, cols), keyboard) in [
+        (from, from_matrix, "--from-meta"),
+        (to, to_matrix, "--to-meta"),
+    ] {
+        if position.0 >= rows || position.1 >= cols {
+            return Err(common::CommandError(format!(
+                "mapping rule {:?} has position {},{} outside of {}x{} matrix of {} keyboard",
+                value, position.0, position.1, rows, cols, keyboard
+            ))
+            .into());
+        }
+    }
+    Ok((from, to))
+}
+
+fn load_json(file: &String) -> Result<Value, Box<dyn std::error::Error>> {
+    Ok(serde_json::from_str(&fs::read_to_string(file)?)?)
+}
+
+/// Checks that layout file has matrix and encoders of keyboard described by `meta`, otherwise
+/// keys would be read from wrong positions.
+fn check_dimensions(
+    snapshot: &protocol::Snapshot,
+    meta: &Value,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let rows = meta["matrix"]["rows"]
+        .as_u64()
+        .ok_or("matrix/rows not found in meta")? as u8;
+    let cols = meta["matrix"]["cols"]
+        .as_u64()
+        .ok_or("matrix/cols not found in meta")? as u8;
+    let (layers, file_rows, file_cols) = snapshot.keys.dimensions();
+    if layers == 0 {
+        return Err(common::CommandError("layout file has no layers".to_string()).into());
+    }
+    if (file_rows, file_cols) != (rows, cols) {
+        return Err(common::CommandError(format!(
+            "layout file has {}x{} matrix, --from-meta keyboard has {}x{}",
+            file_rows, file_cols, rows, cols
+        ))
+        .into());
+    }
+    if snapshot.encoders.is_empty() {
+        return Ok(());
+    }
+    if snapshot.encoders.len() != layers as usize {
+        return Err(common::CommandError(format!(
+            "layout file has {} layers of keys and {} layers of encoders",
+            layers,
+            snapshot.encoders.len()
+        ))
+        .into());
+    }
+    let encoders = keymap::get_encoders_count(&meta["layouts"]["keymap"])?;
+    for (layer, layer_encoders) in snapshot.encoders.iter().enumerate() {
+        if layer_encoders.len() != encoders as usize {
+            return Err(common::CommandError(format!(
+                "layer {} of layout file has {} encoders, --from-meta keyboard has {}",
+                layer,
+                layer_encoders.len(),
+                encoders
+            ))
+            .into());
+        }
+    }
+    Ok(())
+}
+
+pub fn run(
+    from: &str,
+    from_meta_file: &String,
+    to_meta_file: &String,
+    to: &Option<String>,
+    rules: &[String],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let from_meta = load_json(from_meta_file)?;
+    let config = common::read_layout_file(from, &from_meta)?;
+    let snapshot = protocol::Snapshot::from_config(&config, config.vial_protocol.unwrap_or(0))?;
+    check_dimensions(&snapshot, &from_meta)?;
+    let (_, from_rows, from_cols) = snapshot.keys.dimensions();
+    let to_meta = load_json(to_meta_file)?;
+    let rows = to_meta["matrix"]["rows"]
+        .as_u64()
+        .ok_or("matrix/rows not found in meta")? as u8;
+    let cols = to_meta["matrix"]["cols"]
+        .as_u64()
+        .ok_or("matrix/cols not found in meta")? as u8;
+
+    let from_options = protocol::LayoutOptions::from_json(
+        max(snapshot.layout_options, 0) as u32,
+        &from_meta["layouts"]["labels"],
+    )?;
+    let from_buttons = keymap::keymap_to_buttons(&from_meta["layouts"]["keymap"], &from_options)?;
+    let to_options = protocol::LayoutOptions::from_json(0, &to_meta["layouts"]["labels"])?;
+    let to_buttons = keymap::keymap_to_buttons(&to_meta["layouts"]["keymap"], &to_options)?;
+
+    let mut positions: HashMap<Position, Position> =
+        keymap::match_positions(&from_buttons, &to_buttons)
+            .into_iter()
+            .filter(|(_, (row, col))| *row < rows && *col < cols)
+            .collect();
+    for rule in rules {
+        let (from_pos, to_pos) = parse_rule(rule, (from_rows, from_cols), (rows, cols))?;
+        positions.retain(|_, target| *target != to_pos);
+        positions.insert(from_pos, to_pos);
+    }
+
+    let from_keys: BTreeSet<Position> = from_buttons
+        .iter()
+        .filter(|b| !b.encoder && !b.decal)
+        .map(|b| (b.wire_x, b.wire_y))
+        .collect();
+    let (layers, _, _) = snapshot.keys.dimensions();
+    for (row, col) in &from_keys {
+        if positions.contains_key(&(*row, *col)) {
+            continue;
+        }
+        let names: Vec<String> = (0..layers)
+            .map(|layer| snapshot.keys.get(layer, *row, *col))
+            .filter(|keycode| *keycode > 1)
+            .map(|keycode| keycodes::qid_to_name(keycode, snapshot.vial_version))
+            .collect();
+        if !names.is_empty() {
+            println!(
+                "Key {},{} ({}) has no place on new keyboard",
+                row,
+                col,
+                names.join(", ")
+            );
+        }
+    }
+    let mapped: BTreeSet<&Position> = positions.values().collect();
+    let to_keys: BTreeSet<Position> = to_buttons
+        .iter()
+        .filter(|b| !b.encoder && !b.decal)
+        .map(|b| (b.wire_x, b.wire_y))
+        .collect();
+    for (row, col) in &to_keys {
+        if !mapped.contains(&(*row, *col)) {
+            println!("Key {},{} of new keyboard has no key mapped", row, col);
+        }
+    }
+    println!(
+        "{} of {} keys mapped",
+        positions.len(),
+        max(from_keys.len(), to_keys.len())
+    );
+
+    let encoders = keymap::get_encoders_count(&to_meta["layouts"]["keymap"])?;
+    let mut migrated = remap::remap_keys(snapshot, rows, cols, encoders, &positions);
+    // layout file belongs to another keyboard now
+    migrated.uid = 0;
+    migrated.layout_options = match &to_meta["layouts"]["labels"] {
+        Value::Null => -1,
+        _ => 0,
+    };
+    if let Some(to) = to {
+        fs::write(to, migrated.to_config()?.to_vil_string()?)?;
+        println!("Configuration saved to file {}", to);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_parse_rule() {
+        assert_eq!(
+            parse_rule(" 1,2 = 0,3", (2, 3), (1, 4)).unwrap(),
+            ((1, 2), (0, 3))
+        );
+        assert!(parse_rule("1,2", (2, 3), (1, 4)).is_err());
+        assert_eq!(
+            parse_rule("2,0=0,0", (2, 3), (1, 4))
+                .unwrap_err()
+                .to_string(),
+            "mapping rule \"2,0=0,0\" has position 2,0 outside of 2x3 matrix of --from-meta keyboard"
+        );
+        assert_eq!(
+            parse_rule("1,2=0,4", (2, 3), (1, 4))
+                .unwrap_err()
+                .to_string(),
+            "mapping rule \"1,2=0,4\" has position 0,4 outside of 1x4 matrix of --to-meta keyboard"
+        );
+    }
+
+    #[test]
+    fn test_check_dimensions() {
+        let meta = json!({
+            "matrix": {"rows": 2, "cols": 2},
+            "layouts": {"keymap": [["0,0", "0,1", "0,0\n\n\n\n\n\n\n\n\ne"], ["1,0", "1,1"]]}
+        });
+        let config = json!({
+            "layout": [[["KC_A", "KC_B"], ["KC_C", "KC_D"]], [["KC_1", "KC_2"], ["KC_3", "KC_4"]]],
+            "encoder_layout": [[["KC_VOLD", "KC_VOLU"]], [["KC_TRNS", "KC_TRNS"]]]
+        });
+        let snapshot = protocol::Snapshot::from_json(&config).unwrap();
+        assert!(check_dimensions(&snapshot, &meta).is_ok());
+
+        let mut other = meta.clone();
+        other["matrix"]["cols"] = 3.into();
+        assert_eq!(
+            check_dimensions(&snapshot, &other).unwrap_err().to_string(),
+            "layout file has 2x2 matrix, --from-meta keyboard has 2x3"
+        );
+        let mut other = meta.clone();
+        other["matrix"]["rows"] = 1.into();
+        assert!(check_dimensions(&snapshot, &other).is_err());
+
+        let mut fewer_encoders = config.clone();
+        fewer_encoders["encoder_layout"] = json!([[["KC_VOLD", "KC_VOLU"]]]);
+        let snapshot = protocol::Snapshot::from_json(&fewer_encoders).unwrap();
+        assert_eq!(
+            check_dimensions(&snapshot, &meta).unwrap_err().to_string(),
+            "layout file has 2 layers of keys and 1 layers of encoders"
+        );
+        let mut no_encoders = meta.clone();
+        no_encoders["layouts"]["keymap"] = json!([["0,0", "0,1"], ["1,0", "1,1"]]);
+        let snapshot = protocol::Snapshot::from_json(&config).unwrap();
+        assert_eq!(
+            check_dimensions(&snapshot, &no_encoders)
+                .unwrap_err()
+                .to_string(),
+            "layer 0 of layout file has 1 encoders, --from-meta keyboard has 0"
+        );
+    }
+}
@@ -0,0 +1,159 @@
+use crate::keycodes;
+use crate::protocol::{Encoder, Keymap, Snapshot};
+use serde_json::Value;
+use std::collections::{BTreeSet, HashMap};
+
+const KC_NO: u16 = 0x0000;
+const KC_TRNS: u16 = 0x0001;
+
+/// Builds snapshot from layers of QMK Configurator keymap.json. `positions` are matrix
+/// positions of keys in LAYOUT macro order, layers missing in keymap are transparent.
+/// Keycodes which can't be parsed are replaced with KC_NO and their names are returned.
+pub fn snapshot_from_keymap(
+    keymap: &Value,
+    positions: &[(u8, u8)],
+    layers: u8,
+    rows: u8,
+    cols: u8,
+    encoders: u8,
+    vial_version: u32,
+) -> Result<(Snapshot, BTreeSet<String>), Box<dyn std::error::Error>> {
+    let qmk_layers = keymap["layers"]
+        .as_array()
+        .ok_or("layers should be an array")?;
+    if qmk_layers.len() > layers as usize {
+        return Err(format!(
+            "keymap has {} layers, keyboard has only {}",
+            qmk_layers.len(),
+            layers
+        )
+        .into());
+    }
+    let mut unsupported = BTreeSet::new();
+    let mut parse = |name: &Value| -> Result<u16, Box<dyn std::error::Error>> {
+        let name = name.as_str().ok_or("keycode should be a string")?;
+        Ok(match keycodes::name_to_qid(name, vial_version) {
+            Ok(keycode) => keycode,
+            Err(_) => {
+                unsupported.insert(name.to_string());
+                KC_NO
+            }
+        })
+    };
+
+    let mut keys = Keymap::filled(layers, rows, cols, KC_TRNS);
+    for (layer, qmk_layer) in qmk_layers.iter().enumerate() {
+        let qmk_layer = qmk_layer.as_array().ok_or("layer should be an array")?;
+        if qmk_layer.len() != positions.len() {
+            return Err(format!(
+                "layer {} of keymap has {} keys, layout has {}",
+                layer,
+                qmk_layer.len(),
+                positions.len()
+            )
+            .into());
+        }
+        if layer == 0 {
+            for row in 0..rows {
+                for col in 0..cols {
+                    keys.set(0, row, col, KC_NO);
+                }
+            }
+        }
+        for (&(row, col), name) in positions.iter().zip(qmk_layer) {
+            let keycode = parse(name)?;
+            if row < rows && col < cols {
+                keys.set(layer as u8, row, col, keycode);
+            }
+        }
+    }
+
+    let mut all_encoders = Vec::new();
+    for layer in 0..layers as usize {
+        let qmk_encoders = match &keymap["encoders"][layer] {
+            Value::Null => &Vec::new(),
+            value => value.as_array().ok_or("encoders should be an array")?,
+        };
+        let mut layer_encoders = Vec::new();
+        for index in 0..encoders {
+            let encoder = match qmk_encoders.get(index as usize) {
+                Some(encoder) => Encoder {
+                    index,
+                    ccw: parse(&encoder["ccw"])?,
+                    cw: parse(&encoder["cw"])?,
+                },
+                None => Encoder {
+                    index,
+                    ccw: KC_TRNS,
+                    cw: KC_TRNS,
+                },
+            };
+            layer_encoders.push(encoder);
+        }
+        all_encoders.push(layer_encoders);
+    }
+
+    Ok((
+        Snapshot {
+            uid: 0,
+            via_version: 0,
+            vial_version,
+            keys,
+            encoders: all_encoders,
+            combos: Vec::new(),
+            tap_dances: Vec::new(),
+            macros: Vec::new(),
+            key_overrides: Vec::new(),
+            alt_repeats: Vec::new(),
+            qmk_settings: HashMap::new(),
+            layout_options: -1,
+        },
+        unsupported,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_snapshot_from_keymap() {
+        let keymap = json!({
+            "layout": "LAYOUT",
+            "layers": [
+                ["KC_A", "KC_B", "LT(1,KC_SPC)"],
+                ["KC_TRNS", "KC_FOO", "MO(2)"]
+            ],
+            "encoders": [[{"ccw": "KC_VOLD", "cw": "KC_VOLU"}]]
+        });
+        let positions = [(1, 1), (0, 0), (0, 1)];
+        let (snapshot, unsupported) =
+            snapshot_from_keymap(&keymap, &positions, 3, 2, 2, 1, 6).unwrap();
+        assert_eq!(unsupported, BTreeSet::from(["KC_FOO".to_string()]));
+        let json = snapshot.to_json().unwrap();
+        assert_eq!(
+            json["layout"],
+            json!([
+                [["KC_B", "LT(1,KC_SPACE)"], ["KC_NO", "KC_A"]],
+                [["KC_NO", "MO(2)"], ["KC_TRANSPARENT", "KC_TRANSPARENT"]],
+                [
+                    ["KC_TRANSPARENT", "KC_TRANSPARENT"],
+                    ["KC_TRANSPARENT", "KC_TRANSPARENT"]
+                ]
+            ])
+        );
+        assert_eq!(
+            json["encoder_layout"][0],
+            json!([["KC_AUDIO_VOL_DOWN", "KC_AUDIO_VOL_UP"]])
+        );
+        assert_eq!(
+            json["encoder_layout"][2],
+            json!([["KC_TRANSPARENT", "KC_TRANSPARENT"]])
+        );
+
+        let short = json!({"layers": [["KC_A", "KC_B"]]});
+        assert!(snapshot_from_keymap(&short, &positions, 3, 2, 2, 1, 6).is_err());
+        assert!(snapshot_from_keymap(&keymap, &positions, 1, 2, 2, 1, 6).is_err());
+    }
+}
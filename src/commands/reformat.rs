@@ -0,0 +1,23 @@
+use crate::common;
+use crate::meta_cache::MetaCache;
+use crate::protocol;
+
+/// Rewrites layout file between .vil and human editable .toml formats, chosen by extensions.
+/// Keyboard is used only to load meta, dev is None when meta file is passed.
+pub fn run<T: protocol::Transport>(
+    dev: Option<&T>,
+    meta_file: &Option<String>,
+    meta_cache: Option<&MetaCache>,
+    from: &str,
+    to: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let meta = common::load_meta_or_file(dev, meta_file, meta_cache, "reformat")?;
+    let config = common::read_layout_file(from, &meta)?;
+    if common::is_toml_file(to) && !config.extra.is_empty() {
+        let fields: Vec<&str> = config.extra.keys().map(|k| k.as_str()).collect();
+        println!("Fields {} are not kept in .toml file", fields.join(", "));
+    }
+    common::write_layout_file(to, &config, &meta)?;
+    println!("Configuration saved to file {}", to);
+    Ok(())
+}
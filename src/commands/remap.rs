@@ -0,0 +1,186 @@
+use crate::keycodes;
+use crate::protocol::{Encoder, Keymap, MacroStep, Snapshot};
+use std::collections::{BTreeSet, HashMap};
+
+const KC_NO: u16 = 0x0000;
+const KC_TRNS: u16 = 0x0001;
+
+/// Moves keys of `snapshot` into matrix of another keyboard, `positions` maps (row, col) of
+/// snapshot into (row, col) of the new matrix. Keys without mapping are KC_NO on base layer and
+/// KC_TRNS above, encoders are kept by index, other entries are carried over as is.
+pub fn remap_keys(
+    mut snapshot: Snapshot,
+    rows: u8,
+    cols: u8,
+    encoders: u8,
+    positions: &HashMap<(u8, u8), (u8, u8)>,
+) -> Snapshot {
+    let (layers, from_rows, from_cols) = snapshot.keys.dimensions();
+    let mut keys = Keymap::filled(layers, rows, cols, KC_TRNS);
+    for row in 0..rows {
+        for col in 0..cols {
+            keys.set(0, row, col, KC_NO);
+        }
+    }
+    for (&(from_row, from_col), &(to_row, to_col)) in positions {
+        if from_row < from_rows && from_col < from_cols && to_row < rows && to_col < cols {
+            for layer in 0..layers {
+                keys.set(
+                    layer,
+                    to_row,
+                    to_col,
+                    snapshot.keys.get(layer, from_row, from_col),
+                );
+            }
+        }
+    }
+    snapshot.keys = keys;
+    for layer_encoders in snapshot.encoders.iter_mut() {
+        layer_encoders.retain(|encoder| encoder.index < encoders);
+        for index in layer_encoders.len() as u8..encoders {
+            layer_encoders.push(Encoder {
+                index,
+                ccw: KC_TRNS,
+                cw: KC_TRNS,
+            });
+        }
+    }
+    snapshot
+}
+
+/// Re-encodes keycodes of `snapshot` for another Vial protocol version, keycodes without
+/// equivalent are replaced with KC_NO and their names are returned.
+pub fn convert_keycodes(snapshot: &mut Snapshot, vial_version: u32) -> BTreeSet<String> {
+    let from_version = snapshot.vial_version;
+    let mut missing = BTreeSet::new();
+    let mut convert =
+        |keycode: &mut u16| match keycodes::convert_qid(*keycode, from_version, vial_version) {
+            Ok(converted) => *keycode = converted,
+            Err(name) => {
+                missing.insert(name);
+                *keycode = KC_NO;
+            }
+        };
+    let (layers, rows, cols) = snapshot.keys.dimensions();
+    for layer in 0..layers {
+        for row in 0..rows {
+            for col in 0..cols {
+                let mut keycode = snapshot.keys.get(layer, row, col);
+                convert(&mut keycode);
+                snapshot.keys.set(layer, row, col, keycode);
+            }
+        }
+    }
+    for encoder in snapshot.encoders.iter_mut().flatten() {
+        convert(&mut encoder.ccw);
+        convert(&mut encoder.cw);
+    }
+    for combo in snapshot.combos.iter_mut() {
+        for keycode in [
+            &mut combo.key1,
+            &mut combo.key2,
+            &mut combo.key3,
+            &mut combo.key4,
+            &mut combo.output,
+        ] {
+            convert(keycode);
+        }
+    }
+    for td in snapshot.tap_dances.iter_mut() {
+        for keycode in [
+            &mut td.tap,
+            &mut td.hold,
+            &mut td.double_tap,
+            &mut td.tap_hold,
+        ] {
+            convert(keycode);
+        }
+    }
+    for ko in snapshot.key_overrides.iter_mut() {
+        convert(&mut ko.trigger);
+        convert(&mut ko.replacement);
+    }
+    for ar in snapshot.alt_repeats.iter_mut() {
+        convert(&mut ar.keycode);
+        convert(&mut ar.alt_keycode);
+    }
+    for step in snapshot.macros.iter_mut().flat_map(|m| m.steps.iter_mut()) {
+        match step {
+            MacroStep::Tap(keycode) | MacroStep::Down(keycode) | MacroStep::Up(keycode) => {
+                convert(keycode)
+            }
+            MacroStep::Delay(_) | MacroStep::Text(_) => {}
+        }
+    }
+    snapshot.vial_version = vial_version;
+    missing
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{Value, json};
+
+    fn config() -> Value {
+        json!({
+            "version": 1,
+            "via_protocol": 9,
+            "vial_protocol": 6,
+            "uid": 42,
+            "layout": [
+                [["KC_A", "KC_B", "KC_C"], ["KC_D", "KC_E", "KC_F"]],
+                [["KC_TRANSPARENT", "KC_1", "KC_2"], ["KC_3", "KC_4", "KC_5"]]
+            ],
+            "encoder_layout": [[["KC_AUDIO_VOL_DOWN", "KC_AUDIO_VOL_UP"]], [["KC_PAGE_DOWN", "KC_PAGE_UP"]]],
+            "combo": [["KC_A", "KC_B", "KC_NO", "KC_NO", "KC_ESCAPE"]],
+            "macro": [[["text", "hello"], ["tap", "KC_ENTER"]]]
+        })
+    }
+
+    #[test]
+    fn test_remap_keys() {
+        let snapshot = Snapshot::from_json(&config()).unwrap();
+        let positions = HashMap::from([((0, 0), (1, 1)), ((1, 2), (0, 0)), ((0, 1), (5, 5))]);
+        let remapped = remap_keys(snapshot, 2, 2, 2, &positions);
+        assert_eq!(remapped.keys.dimensions(), (2, 2, 2));
+        let json = remapped.to_json().unwrap();
+        assert_eq!(
+            json["layout"],
+            json!([
+                [["KC_F", "KC_NO"], ["KC_NO", "KC_A"]],
+                [
+                    ["KC_5", "KC_TRANSPARENT"],
+                    ["KC_TRANSPARENT", "KC_TRANSPARENT"]
+                ]
+            ])
+        );
+        assert_eq!(remapped.encoders[0].len(), 2);
+        assert_eq!(remapped.combos.len(), 1);
+        assert_eq!(json["macro"], config()["macro"]);
+    }
+
+    #[test]
+    fn test_convert_keycodes() {
+        let mut config = config();
+        config["vial_protocol"] = 5.into();
+        config["layout"][0][0][0] = "QK_MACRO_1".into();
+        config["macro"] = json!([[["tap", "QK_MACRO_2"]]]);
+        let mut snapshot = Snapshot::from_json(&config).unwrap();
+        assert_eq!(snapshot.keys.get(0, 0, 0), 0x5f13);
+        assert!(convert_keycodes(&mut snapshot, 6).is_empty());
+        assert_eq!(snapshot.vial_version, 6);
+        assert_eq!(snapshot.keys.get(0, 0, 0), 0x7701);
+        let json = snapshot.to_json().unwrap();
+        assert_eq!(json["layout"][0][0][0], "QK_MACRO_1");
+        assert_eq!(json["macro"], json!([[["tap", "QK_MACRO_2"]]]));
+        assert_eq!(json["combo"], config["combo"]);
+
+        snapshot.keys.set(1, 0, 0, 0x52E1);
+        assert_eq!(
+            convert_keycodes(&mut snapshot, 5),
+            BTreeSet::from(["PDF(1)".to_string()])
+        );
+        assert_eq!(snapshot.keys.get(1, 0, 0), KC_NO);
+        assert_eq!(snapshot.keys.get(0, 0, 0), 0x5f13);
+    }
+}
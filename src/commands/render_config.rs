@@ -0,0 +1,25 @@
+use crate::common;
+use crate::meta_cache::MetaCache;
+use crate::protocol;
+
+/// Prints layout file merged with files it extends, in format of the file, or writes it into `to`.
+/// Keyboard is used only to load meta, dev is None when meta file is passed.
+pub fn run<T: protocol::Transport>(
+    dev: Option<&T>,
+    meta_file: &Option<String>,
+    meta_cache: Option<&MetaCache>,
+    file: &str,
+    to: &Option<String>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let meta = common::load_meta_or_file(dev, meta_file, meta_cache, "render config")?;
+    let config = common::read_layout_file(file, &meta)?;
+    match to {
+        Some(to) => {
+            common::write_layout_file(to, &config, &meta)?;
+            println!("Configuration saved to file {}", to);
+        }
+        None if common::is_toml_file(file) => print!("{}", config.to_toml(&meta)?),
+        None => common::print_json(&config.to_json()?)?,
+    }
+    Ok(())
+}
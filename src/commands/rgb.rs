@@ -1,6 +1,7 @@
+use crate::common;
 use crate::protocol;
 use argh::FromArgs;
-use hidapi::{DeviceInfo, HidApi};
+use serde_json::json;
 
 #[derive(FromArgs, PartialEq, Debug)]
 /// RGB lighting
@@ -40,14 +41,11 @@ pub struct CommandRgb {
 }
 
 pub fn run(
-    api: &HidApi,
-    device: &DeviceInfo,
+    dev: &impl protocol::Transport,
     cmd: &CommandRgb,
+    output: &common::Output,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let device_path = device.path();
-    let dev = api.open_path(device_path)?;
-
-    let mut rgb_info = protocol::load_rgb_info(&dev)?;
+    let mut rgb_info = protocol::load_rgb_info(dev)?;
     let mut update = false;
     if let Some(s) = cmd.speed {
         rgb_info.effect_speed = s;
@@ -70,22 +68,43 @@ pub fn run(
         }
         update = true;
     }
-    if cmd.list {
-        rgb_info.dump_supported_effects();
-    }
-    if cmd.info {
-        println!("\n{}", rgb_info);
+    if output.format == common::OutputFormat::Text {
+        if cmd.list {
+            rgb_info.dump_supported_effects();
+        }
+        if cmd.info {
+            println!("\n{}", rgb_info);
+        }
     }
     if update {
-        protocol::set_rgb_mode(&dev, &rgb_info)?;
-        println!("RGB settings updated...");
+        protocol::set_rgb_mode(dev, &rgb_info)?;
+        if output.format == common::OutputFormat::Text {
+            println!("RGB settings updated...");
+        }
     }
     if cmd.persist {
-        protocol::persist_rgb(&dev)?;
-        println!("RGB settings persisted...");
+        protocol::persist_rgb(dev)?;
+        if output.format == common::OutputFormat::Text {
+            println!("RGB settings persisted...");
+        }
     }
     if let Some(command) = &cmd.direct {
-        protocol::set_leds_direct(&dev, command, rgb_info.max_brightness)?;
+        protocol::set_leds_direct(dev, command, rgb_info.max_brightness)?;
+    }
+    if output.format == common::OutputFormat::Json {
+        // info and status of writes are printed as one document
+        let written = update || cmd.persist || cmd.direct.is_some();
+        let mut result = if cmd.list || cmd.info || !written {
+            rgb_info.to_json()
+        } else {
+            json!({})
+        };
+        if written {
+            result["status"] = "ok".into();
+            result["updated"] = update.into();
+            result["persisted"] = cmd.persist.into();
+        }
+        output.print_json(&result)?;
     }
     Ok(())
 }
@@ -0,0 +1,60 @@
+use crate::backups::Backups;
+use crate::common;
+use crate::meta_cache::MetaCache;
+use crate::protocol;
+use serde_json::Value;
+use std::fs;
+
+pub fn run(
+    dev: &impl protocol::Transport,
+    meta_file: &Option<String>,
+    meta_cache: Option<&MetaCache>,
+    backups: Option<&Backups>,
+    file: &Option<String>,
+    list: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let backups = backups.ok_or(common::CommandError(
+        "backups are taken only for connected keyboards".to_string(),
+    ))?;
+    let uid = protocol::load_uid(dev)?;
+    let saved = backups.list(uid)?;
+    if list {
+        if saved.is_empty() {
+            println!("No backups found");
+        }
+        for path in &saved {
+            println!("{}", path.display());
+        }
+        return Ok(());
+    }
+    let path = match file {
+        Some(file) => file.into(),
+        None => saved.last().cloned().ok_or(common::CommandError(format!(
+            "no backups found for keyboard {:#018x} in {}",
+            uid,
+            backups.dir.display()
+        )))?,
+    };
+
+    let capabilities = protocol::scan_capabilities(dev)?;
+    let meta = common::load_meta(dev, &capabilities, meta_file, meta_cache)?;
+    let root: Value = serde_json::from_str(&fs::read_to_string(&path)?)?;
+    let config = protocol::VialConfig::from_json(&root)?;
+    let snapshot = protocol::Snapshot::from_config(&config, capabilities.vial_version)?;
+    println!();
+    let report = match snapshot.apply(dev, &capabilities, &meta) {
+        Ok(report) => report,
+        Err(e)
+            if matches!(
+                e.downcast_ref(),
+                Some(protocol::ProtocolError::KeyboardLocked)
+            ) =>
+        {
+            return Err(common::CommandError("Keyboard is locked, macroses can't be restored, keyboard might be unlocked with subcommand 'lock -u'".to_string()).into());
+        }
+        Err(e) => return Err(e),
+    };
+    common::print_apply_report(&report);
+    println!("Configuration restored from {}", path.display());
+    Ok(())
+}
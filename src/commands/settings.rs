@@ -1,19 +1,38 @@
 use crate::common;
 use crate::protocol;
-use hidapi::{DeviceInfo, HidApi};
-use serde_json::Value;
+use serde_json::{Value, json};
 use std::collections::HashMap;
 
+fn setting_json(group: &str, qsid: u16, bit: Option<u8>, title: &str, value: Value) -> Value {
+    let mut result = json!({
+        "group": group,
+        "qsid": qsid,
+        "title": title,
+        "value": value,
+    });
+    if let Some(bit) = bit {
+        result["bit"] = bit.into();
+    }
+    result
+}
+
+/// Value of setting field as it is shown in json output.
+fn field_json(value: &protocol::QmkValue, bool_field: bool, bit: Option<u8>) -> Value {
+    match bit {
+        Some(pos) if bool_field => value.get_bool(pos).into(),
+        _ if bool_field => (value.get() != 0).into(),
+        _ => value.get().into(),
+    }
+}
+
 pub fn run(
-    api: &HidApi,
-    device: &DeviceInfo,
+    dev: &impl protocol::Transport,
     qsid: &Option<f64>,
     value: &Option<String>,
     reset: bool,
+    output: &common::Output,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let device_path = device.path();
-    let dev = api.open_path(device_path)?;
-    let capabilities = protocol::scan_capabilities(&dev)?;
+    let capabilities = protocol::scan_capabilities(dev)?;
     if capabilities.vial_version < protocol::VIAL_PROTOCOL_QMK_SETTINGS {
         return Err(common::CommandError("Qmk settings are not supported".to_string()).into());
     }
@@ -25,10 +44,13 @@ pub fn run(
             )
             .into());
         }
-        protocol::reset_qmk_values(&dev)?;
+        protocol::reset_qmk_values(dev)?;
+        if output.format == common::OutputFormat::Json {
+            output.print_json(&json!({"status": "ok", "reset": true}))?;
+        }
         return Ok(());
     }
-    let qsids = protocol::load_qmk_qsids(&dev)?;
+    let qsids = protocol::load_qmk_qsids(dev)?;
     let settings = protocol::load_qmk_definitions()?;
     match qsid {
         Some(qsid_full) => {
@@ -42,15 +64,20 @@ pub fn run(
                 tsid = qsid_full_str.parse()?;
                 tbit = 0;
             }
+            // (qsid, bit) pairs are unique, so at most one field matches
+            let mut found = false;
             for group in settings["tabs"]
                 .as_array()
                 .ok_or("tabs should be an array")?
             {
-                //let group_name = group["name"].as_str().unwrap();
+                let group_name = group["name"].as_str().ok_or("name should be a string")?;
                 for field in group["fields"]
                     .as_array()
                     .ok_or("fields should be an array")?
                 {
+                    if found {
+                        break;
+                    }
                     let qsid = field["qsid"].as_u64().ok_or("qsid should be a number")? as u16;
                     let title = field["title"].as_str().ok_or("title should be a string")?;
                     let width: u8 = match &field["width"] {
@@ -64,10 +91,19 @@ pub fn run(
                         && (!with_bits
                             || (field["bit"].as_u64().ok_or("bit should be number")? as u8) == tbit)
                     {
+                        found = true;
                         match value {
                             None => {
-                                let value = protocol::get_qmk_value(&dev, qsid, width)?;
-                                if bool_field {
+                                let value = protocol::get_qmk_value(dev, qsid, width)?;
+                                if output.format == common::OutputFormat::Json {
+                                    output.print_json(&setting_json(
+                                        group_name,
+                                        qsid,
+                                        with_bits.then_some(tbit),
+                                        title,
+                                        field_json(&value, bool_field, with_bits.then_some(tbit)),
+                                    ))?;
+                                } else if bool_field {
                                     if with_bits {
                                         println!(
                                             "{}.{}) {} = {}",
@@ -86,40 +122,59 @@ pub fn run(
                             Some(v) => {
                                 if with_bits {
                                     let mut current_value =
-                                        protocol::get_qmk_value(&dev, qsid, width)?.get();
+                                        protocol::get_qmk_value(dev, qsid, width)?.get();
                                     let bw: bool = v.parse()?;
                                     if bw {
                                         current_value |= 1 << tbit;
                                     } else {
                                         current_value ^= 1 << tbit;
                                     }
-                                    protocol::set_qmk_value(&dev, qsid, current_value)?;
+                                    protocol::set_qmk_value(dev, qsid, current_value)?;
                                 } else if bool_field {
                                     let val: bool = v.parse()?;
                                     let int_val = match val {
                                         true => 1,
                                         false => 0,
                                     };
-                                    protocol::set_qmk_value(&dev, qsid, int_val)?;
+                                    protocol::set_qmk_value(dev, qsid, int_val)?;
                                 } else {
-                                    protocol::set_qmk_value(&dev, qsid, v.parse()?)?;
+                                    protocol::set_qmk_value(dev, qsid, v.parse()?)?;
+                                }
+                                if output.format == common::OutputFormat::Text {
+                                    println!("Option {:?} = {} now", title, v);
+                                } else {
+                                    let value = protocol::get_qmk_value(dev, qsid, width)?;
+                                    let mut status = setting_json(
+                                        group_name,
+                                        qsid,
+                                        with_bits.then_some(tbit),
+                                        title,
+                                        field_json(&value, bool_field, with_bits.then_some(tbit)),
+                                    );
+                                    status["status"] = "ok".into();
+                                    output.print_json(&status)?;
                                 }
-                                println!("Option {:?} = {} now", title, v);
                             }
                         }
                     }
                 }
             }
+            if !found {
+                return Err(common::CommandError(format!("unknown qsid {}", qsid_full_str)).into());
+            }
         }
         None => {
             let mut values_cache = HashMap::new();
+            let mut entries = Vec::new();
 
             for group in settings["tabs"]
                 .as_array()
                 .ok_or("tabs should be an array")?
             {
                 let group_name = group["name"].as_str().ok_or("name should be a string")?;
-                println!("\n{}:", group_name);
+                if output.format == common::OutputFormat::Text {
+                    println!("\n{}:", group_name);
+                }
                 for field in group["fields"]
                     .as_array()
                     .ok_or("fields should be an array")?
@@ -135,34 +190,41 @@ pub fn run(
                         if let std::collections::hash_map::Entry::Vacant(e) =
                             values_cache.entry(qsid)
                         {
-                            value = protocol::get_qmk_value(&dev, qsid, width)?;
+                            value = protocol::get_qmk_value(dev, qsid, width)?;
                             e.insert(value);
                         } else {
                             value = *values_cache.get(&qsid).ok_or("cache broken")?;
                         }
-                        match field["type"].as_str().ok_or("type should be a string")? {
-                            "boolean" => match field["bit"].as_number() {
-                                Some(n) => {
-                                    let pos = n.as_u64().ok_or("bit should be a number")? as u8;
-                                    println!(
-                                        "\t{}.{}) {} = {}",
-                                        qsid,
-                                        pos,
-                                        title,
-                                        value.get_bool(pos)
-                                    );
-                                }
-                                None => {
-                                    println!("\t{}) {} = {}", qsid, title, value.get() != 0);
-                                }
-                            },
-                            _ => {
-                                println!("\t{}) {} = {}", qsid, title, value.get());
+                        let bool_field =
+                            field["type"].as_str().ok_or("type should be a string")? == "boolean";
+                        let bit = match field["bit"].as_number() {
+                            Some(n) if bool_field => {
+                                Some(n.as_u64().ok_or("bit should be a number")? as u8)
+                            }
+                            _ => None,
+                        };
+                        let field_value = field_json(&value, bool_field, bit);
+                        match (output.format, bit) {
+                            (common::OutputFormat::Json, _) => entries.push(setting_json(
+                                group_name,
+                                qsid,
+                                bit,
+                                title,
+                                field_value,
+                            )),
+                            (common::OutputFormat::Text, Some(pos)) => {
+                                println!("\t{}.{}) {} = {}", qsid, pos, title, field_value);
+                            }
+                            (common::OutputFormat::Text, None) => {
+                                println!("\t{}) {} = {}", qsid, title, field_value);
                             }
                         }
                     }
                 }
             }
+            if output.format == common::OutputFormat::Json {
+                output.print_json(&Value::Array(entries))?;
+            }
         }
     }
     Ok(())
@@ -1,21 +1,16 @@
 use crate::common;
 use crate::protocol;
-use hidapi::{DeviceInfo, HidApi};
+use serde_json::json;
 
 pub fn run(
-    api: &HidApi,
-    device: &DeviceInfo,
+    dev: &impl protocol::Transport,
     number: Option<u8>,
     value: &Option<String>,
+    output: &common::Output,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let device_path = device.path();
-    let dev = api.open_path(device_path)?;
-    let capabilities = protocol::scan_capabilities(&dev)?;
+    let capabilities = protocol::scan_capabilities(dev)?;
     if capabilities.tap_dance_count == 0 {
-        return Err(common::CommandError(
-            format!("device {:?} has doesn't support tap dance", device).to_string(),
-        )
-        .into());
+        return Err(common::CommandError("device doesn't support tap dance".to_string()).into());
     }
     let n: u8;
     match number {
@@ -33,13 +28,19 @@ pub fn run(
     }
     match value {
         None => {
-            let tapdances = protocol::load_tap_dances(&dev, capabilities.tap_dance_count)?;
+            let tapdances = protocol::load_tap_dances(dev, capabilities.tap_dance_count)?;
+            if output.format == common::OutputFormat::Json {
+                return output.print_entries_json(
+                    protocol::tap_dances_to_json(&tapdances, capabilities.vial_version)?,
+                    number,
+                );
+            }
             if number.is_none() {
                 let tapdance_count = tapdances.len();
                 let mut first_empty = capabilities.tap_dance_count;
                 for idxm in 1..=tapdance_count {
                     let idx = tapdance_count - idxm;
-                    if !tapdances[idx as usize].is_empty() {
+                    if !tapdances[idx].is_empty() {
                         break;
                     }
                     first_empty = idx as u8;
@@ -66,8 +67,11 @@ pub fn run(
                 0 => protocol::TapDance::empty(n),
                 _ => protocol::TapDance::from_string(n, value, capabilities.vial_version)?,
             };
-            protocol::set_tap_dance(&dev, &tapdance)?;
-            println!("TapDance {} saved", tapdance.index);
+            protocol::set_tap_dance(dev, &tapdance)?;
+            output.print_status(
+                &format!("TapDance {} saved", tapdance.index),
+                json!({"tap_dance": tapdance.index}),
+            )?;
         }
     }
     Ok(())
@@ -1,10 +1,13 @@
 use crate::keycodes;
 use crate::keymap;
+use crate::meta_cache::MetaCache;
 use crate::protocol;
-use hidapi::HidDevice;
-use serde_json::Value;
-use std::collections::HashMap;
+use serde_json::{Map, Value};
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
 use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 use thiserror::Error;
 
 #[allow(dead_code)]
@@ -12,10 +15,374 @@ use thiserror::Error;
 #[error("{0}")]
 pub struct CommandError(pub String);
 
+/// Output format of read commands.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutputFormat {
+    Text,
+    Json,
+}
+
+impl FromStr for OutputFormat {
+    type Err = CommandError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "text" => Ok(OutputFormat::Text),
+            "json" => Ok(OutputFormat::Json),
+            _ => Err(CommandError(format!(
+                "unknown output format {:?}, expected text or json",
+                value
+            ))),
+        }
+    }
+}
+
+pub fn print_json(value: &Value) -> Result<(), Box<dyn std::error::Error>> {
+    println!("{}", serde_json::to_string_pretty(value)?);
+    Ok(())
+}
+
+/// Output of commands. Json documents are collected and printed at once by `finish`, so every
+/// invocation prints exactly one json document.
+pub struct Output {
+    pub format: OutputFormat,
+    documents: RefCell<Vec<Value>>,
+}
+
+impl Output {
+    pub fn new(format: OutputFormat) -> Output {
+        Output {
+            format,
+            documents: RefCell::new(Vec::new()),
+        }
+    }
+
+    pub fn is_json(&self) -> bool {
+        self.format == OutputFormat::Json
+    }
+
+    pub fn print_json(&self, value: &Value) -> Result<(), Box<dyn std::error::Error>> {
+        self.documents.borrow_mut().push(value.clone());
+        Ok(())
+    }
+
+    /// Prints all entries or only the one with passed number.
+    pub fn print_entries_json(
+        &self,
+        entries: Vec<Value>,
+        number: Option<u8>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        match number {
+            Some(n) => self.print_json(
+                entries
+                    .get(n as usize)
+                    .ok_or(CommandError(format!("entry {} is not defined", n)))?,
+            ),
+            None => self.print_json(&Value::Array(entries)),
+        }
+    }
+
+    /// Prints `message` of write command, or `status` object with json output.
+    pub fn print_status(
+        &self,
+        message: &str,
+        mut status: Value,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        match self.format {
+            OutputFormat::Text => println!("{}", message),
+            OutputFormat::Json => {
+                status["status"] = "ok".into();
+                self.print_json(&status)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Sets field of the last json document, if it is an object.
+    pub fn annotate(&self, key: &str, value: Value) {
+        if let Some(Value::Object(last)) = self.documents.borrow_mut().last_mut() {
+            last.insert(key.to_string(), value);
+        }
+    }
+
+    /// Removes collected documents, several documents are merged into array.
+    pub fn take(&self) -> Option<Value> {
+        let mut documents = self.documents.take();
+        match documents.len() {
+            0 => None,
+            1 => documents.pop(),
+            _ => Some(Value::Array(documents)),
+        }
+    }
+
+    /// Prints collected json document.
+    pub fn finish(&self) -> Result<(), Box<dyn std::error::Error>> {
+        match self.take() {
+            Some(document) => print_json(&document),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Device selection criteria, criteria which are not set match any device.
+#[derive(Debug, Default)]
+pub struct DeviceFilter {
+    pub product_id: Option<u16>,
+    pub vendor_id: Option<u16>,
+    pub serial: Option<String>,
+    pub path: Option<String>,
+    pub name: Option<String>,
+}
+
+impl DeviceFilter {
+    pub fn matches(&self, device: &hidapi::DeviceInfo) -> bool {
+        self.product_id.is_none_or(|id| id == device.product_id())
+            && self.vendor_id.is_none_or(|id| id == device.vendor_id())
+            && self
+                .serial
+                .as_ref()
+                .is_none_or(|serial| device.serial_number() == Some(serial.as_str()))
+            && self
+                .path
+                .as_ref()
+                .is_none_or(|path| device.path().to_string_lossy() == *path)
+            && self.name.as_ref().is_none_or(|name| {
+                device
+                    .product_string()
+                    .is_some_and(|product| glob_match(name, product))
+            })
+    }
+}
+
+/// Parses usb id in lsusb format, hex vendor id and product id separated by colon.
+pub fn parse_usb_id(value: &str) -> Result<(u16, u16), Box<dyn std::error::Error>> {
+    let error = || {
+        CommandError(format!(
+            "usb id {:?} should be in format vid:pid (hex)",
+            value
+        ))
+    };
+    let (vid, pid) = value.split_once(':').ok_or_else(error)?;
+    Ok((
+        u16::from_str_radix(vid, 16).map_err(|_| error())?,
+        u16::from_str_radix(pid, 16).map_err(|_| error())?,
+    ))
+}
+
+/// Parses Vial keyboard uid as decimal or 0x prefixed hex number.
+pub fn parse_uid(value: &str) -> Result<u64, Box<dyn std::error::Error>> {
+    let result = match value.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => value.parse(),
+    };
+    Ok(result.map_err(|_| CommandError(format!("uid {:?} should be a number", value)))?)
+}
+
+/// Matches value against pattern with * (any sequence) and ? (any char) wildcards.
+pub fn glob_match(pattern: &str, value: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let value: Vec<char> = value.chars().collect();
+    let (mut p, mut v) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+    while v < value.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == value[v]) {
+            p += 1;
+            v += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, v));
+            p += 1;
+        } else if let Some((star, matched)) = backtrack {
+            p = star + 1;
+            v = matched + 1;
+            backtrack = Some((star, matched + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// Prints numbers of entries written by load or rollback.
+pub fn print_apply_report(report: &protocol::ApplyReport) {
+    if report.layout_options {
+        println!("Layout options updated");
+    }
+    println!(
+        "{} keys, {} combos, {} macros, {} tap dances, {} key overrides, {} alt repeat keys, {} encoders, {} settings changed",
+        report.keys,
+        report.combos,
+        report.macros,
+        report.tap_dances,
+        report.key_overrides,
+        report.alt_repeats,
+        report.encoders,
+        report.settings,
+    );
+}
+
+/// Layout files with .toml extension are human editable, others are .vil JSON.
+pub fn is_toml_file(file: &str) -> bool {
+    file.to_lowercase().ends_with(".toml")
+}
+
+/// Content of layout file with `extends` already resolved.
+enum LayoutDocument {
+    Vil(Map<String, Value>),
+    Toml(toml::Table),
+}
+
+impl LayoutDocument {
+    fn into_vil(self, meta: &Value) -> Result<Map<String, Value>, Box<dyn std::error::Error>> {
+        match self {
+            LayoutDocument::Vil(root) => Ok(root),
+            LayoutDocument::Toml(table) => {
+                match protocol::VialConfig::from_toml(table, meta)?.to_json()? {
+                    Value::Object(root) => Ok(root),
+                    _ => Err("config should be an object".into()),
+                }
+            }
+        }
+    }
+
+    fn into_toml(self, meta: &Value) -> Result<toml::Table, Box<dyn std::error::Error>> {
+        match self {
+            LayoutDocument::Toml(table) => Ok(table),
+            LayoutDocument::Vil(root) => Ok(toml::from_str(
+                &protocol::VialConfig::from_json(&Value::Object(root))?.to_toml(meta)?,
+            )?),
+        }
+    }
+}
+
+/// Reads single layout file, `extends` is removed from it and returned separately.
+fn parse_layout_document(
+    file: &Path,
+) -> Result<(LayoutDocument, Option<String>), Box<dyn std::error::Error>> {
+    let content = fs::read_to_string(file)
+        .map_err(|e| CommandError(format!("failed to read {}: {}", file.display(), e)))?;
+    Ok(if is_toml_file(&file.to_string_lossy()) {
+        let mut table: toml::Table = toml::from_str(&content)?;
+        let extends = match table.remove("extends") {
+            Some(toml::Value::String(extends)) => Some(extends),
+            Some(_) => return Err(CommandError("extends should be a string".to_string()).into()),
+            None => None,
+        };
+        (LayoutDocument::Toml(table), extends)
+    } else {
+        let mut root = match serde_json::from_str(&content)? {
+            Value::Object(root) => root,
+            _ => return Err("config file root is not an object".into()),
+        };
+        let extends = match root.remove("extends") {
+            Some(Value::String(extends)) => Some(extends),
+            Some(_) => return Err(CommandError("extends should be a string".to_string()).into()),
+            None => None,
+        };
+        (LayoutDocument::Vil(root), extends)
+    })
+}
+
+/// Reads layout file and files it extends, `chain` holds files being read to detect cycles.
+fn read_layout_document(
+    file: &Path,
+    meta: &Value,
+    chain: &mut Vec<PathBuf>,
+) -> Result<LayoutDocument, Box<dyn std::error::Error>> {
+    let (document, extends) = parse_layout_document(file)?;
+    let path = fs::canonicalize(file)?;
+    if chain.contains(&path) {
+        let files: Vec<String> = chain
+            .iter()
+            .chain([&path])
+            .map(|p| p.display().to_string())
+            .collect();
+        return Err(CommandError(format!(
+            "layout files extend each other: {}",
+            files.join(" -> ")
+        ))
+        .into());
+    }
+    let Some(extends) = extends else {
+        return Ok(document);
+    };
+    // base file path is relative to the file extending it
+    let base_file = file.parent().unwrap_or(Path::new("")).join(extends);
+    chain.push(path);
+    let base = read_layout_document(&base_file, meta, chain)?;
+    chain.pop();
+    Ok(match document {
+        LayoutDocument::Vil(overlay) => {
+            let mut root = base.into_vil(meta)?;
+            protocol::merge_json(&mut root, overlay);
+            LayoutDocument::Vil(root)
+        }
+        LayoutDocument::Toml(overlay) => {
+            let mut table = base.into_toml(meta)?;
+            protocol::merge_toml(&mut table, overlay);
+            LayoutDocument::Toml(table)
+        }
+    })
+}
+
+/// Reads layout file, keys of .toml file are matched with layout of `meta`. File which has
+/// `extends` is merged over the file it extends.
+pub fn read_layout_file(
+    file: &str,
+    meta: &Value,
+) -> Result<protocol::VialConfig, Box<dyn std::error::Error>> {
+    match read_layout_document(Path::new(file), meta, &mut Vec::new())? {
+        LayoutDocument::Vil(root) => protocol::VialConfig::from_json(&Value::Object(root)),
+        LayoutDocument::Toml(table) => protocol::VialConfig::from_toml(table, meta),
+    }
+}
+
+/// Base file declared with `extends` in layout file, None if it extends nothing.
+pub fn layout_file_extends(file: &str) -> Result<Option<String>, Box<dyn std::error::Error>> {
+    Ok(parse_layout_document(Path::new(file))?.1)
+}
+
+/// Writes layout file in format chosen by extension, see `read_layout_file`.
+pub fn write_layout_file(
+    file: &str,
+    config: &protocol::VialConfig,
+    meta: &Value,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let content = match is_toml_file(file) {
+        true => config.to_toml(meta)?,
+        false => config.to_vil_string()?,
+    };
+    fs::write(file, content)?;
+    Ok(())
+}
+
+/// Meta from file when it is passed, from keyboard otherwise. `dev` is None when keyboard isn't
+/// opened, `command` is named in error.
+pub fn load_meta_or_file<T: protocol::Transport>(
+    dev: Option<&T>,
+    meta_file: &Option<String>,
+    meta_cache: Option<&MetaCache>,
+    command: &str,
+) -> Result<Value, Box<dyn std::error::Error>> {
+    match (dev, meta_file) {
+        (_, Some(meta_file)) => Ok(serde_json::from_str(&fs::read_to_string(meta_file)?)?),
+        (Some(dev), None) => {
+            let capabilities = protocol::scan_capabilities(dev)?;
+            load_meta(dev, &capabilities, meta_file, meta_cache)
+        }
+        (None, None) => Err(CommandError(format!(
+            "meta file should be passed with -m to {} without keyboard",
+            command
+        ))
+        .into()),
+    }
+}
+
+/// Meta from file when it is passed, from keyboard otherwise, cache is used if it is given.
 pub fn load_meta(
-    dev: &HidDevice,
+    dev: &impl protocol::Transport,
     capabilities: &protocol::Capabilities,
     meta_file: &Option<String>,
+    meta_cache: Option<&MetaCache>,
 ) -> Result<Value, Box<dyn std::error::Error>> {
     match meta_file {
         Some(meta_file) => {
@@ -32,7 +399,11 @@ pub fn load_meta(
                 )
                 .into());
             }
-            let meta_data = match protocol::load_vial_meta(dev) {
+            let loaded = match meta_cache {
+                Some(cache) => cache.load(dev),
+                None => protocol::load_vial_meta(dev),
+            };
+            let meta_data = match loaded {
                 Ok(meta_data) => meta_data,
                 Err(e) => {
                     return Err(CommandError(
@@ -53,6 +424,7 @@ pub fn render_layer(
     layer_number: u8,
     vial_version: u32,
     custom_keycodes: &Option<&Value>,
+    highlighted: &HashSet<(u8, u8)>,
 ) -> Result<(), Box<dyn std::error::Error>> {
     let mut button_labels = HashMap::new();
 
@@ -128,7 +500,7 @@ pub fn render_layer(
         }
     }
     println!("Layer: {}", layer_number);
-    keymap::render_and_dump(buttons, Some(button_labels));
+    keymap::render_and_dump_highlighted(buttons, Some(button_labels), highlighted);
     for (idx, fat) in fat_labels.into_iter().enumerate() {
         println!("*{} - {}", idx + 1, fat);
     }
@@ -143,3 +515,113 @@ pub fn render_layer(
     println!();
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn test_output_json() {
+        let output = Output::new(OutputFormat::Json);
+        assert_eq!(output.take(), None);
+        output
+            .print_status("Combo 1 saved", serde_json::json!({"combo": 1}))
+            .unwrap();
+        output.annotate("file", "my.vil".into());
+        assert_eq!(
+            output.take(),
+            Some(serde_json::json!({"combo": 1, "file": "my.vil", "status": "ok"}))
+        );
+        output.print_json(&Value::from(1)).unwrap();
+        output.print_json(&Value::from(2)).unwrap();
+        assert_eq!(output.take(), Some(serde_json::json!([1, 2])));
+        assert_eq!(output.take(), None);
+    }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("silakka54", "silakka54"));
+        assert!(glob_match("sil*", "silakka54"));
+        assert!(glob_match("*54", "silakka54"));
+        assert!(glob_match("s?lakka*", "silakka54"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("Corne*v4", "Corne keyboard v4"));
+        assert!(!glob_match("sil", "silakka54"));
+        assert!(!glob_match("*55", "silakka54"));
+        assert!(!glob_match("?", ""));
+    }
+
+    #[test]
+    fn test_parse_ids() {
+        assert_eq!(parse_usb_id("feed:1212").unwrap(), (0xfeed, 0x1212));
+        assert!(parse_usb_id("feed").is_err());
+        assert!(parse_usb_id("feed:xyz").is_err());
+        assert_eq!(parse_uid("42").unwrap(), 42);
+        assert_eq!(parse_uid("0x5649").unwrap(), 0x5649);
+        assert!(parse_uid("uid").is_err());
+    }
+
+    #[test]
+    fn test_read_layout_file_extends() {
+        let meta = serde_json::json!({
+            "matrix": {"rows": 1, "cols": 2},
+            "layouts": {"keymap": [["0,0", "0,1"]]}
+        });
+        let dir = env::temp_dir().join(format!("vitaly-extends-{}", std::process::id()));
+        fs::create_dir_all(dir.join("team")).unwrap();
+        fs::write(
+            dir.join("team").join("base.vil"),
+            r#"{"layout": [[["KC_A", "KC_B"]], [["KC_1", "KC_2"]]], "settings": {"7": 180, "2": 5000},
+               "tap_dance": [["KC_ESCAPE", "KC_NO", "KC_CAPS_LOCK", "KC_NO", 200]]}"#,
+        )
+        .unwrap();
+        fs::write(
+            dir.join("me.vil"),
+            r#"{"extends": "team/base.vil", "layout": [null, [[null, "KC_Z"]]], "settings": {"7": 150}}"#,
+        )
+        .unwrap();
+        fs::write(
+            dir.join("me.toml"),
+            "extends = \"team/base.vil\"\n\n[[layer]]\n[layer.set]\n\"0,1\" = \"TD(td0)\"\n",
+        )
+        .unwrap();
+        fs::write(dir.join("loop.vil"), r#"{"extends": "loop.vil"}"#).unwrap();
+
+        let name = |key: &str| protocol::LayoutKey::Name(key.to_string());
+        let config = read_layout_file(&dir.join("me.vil").to_string_lossy(), &meta).unwrap();
+        assert_eq!(
+            config.layout,
+            vec![
+                vec![vec![name("KC_A"), name("KC_B")]],
+                vec![vec![name("KC_1"), name("KC_Z")]]
+            ]
+        );
+        assert_eq!(
+            config.settings.unwrap(),
+            std::collections::BTreeMap::from([(2, 5000), (7, 150)])
+        );
+        assert!(config.extra.is_empty());
+
+        let config = read_layout_file(&dir.join("me.toml").to_string_lossy(), &meta).unwrap();
+        assert_eq!(config.layout[0], vec![vec![name("KC_A"), name("TD(0)")]]);
+        assert_eq!(config.layout[1], vec![vec![name("KC_1"), name("KC_2")]]);
+        assert_eq!(config.tap_dance.unwrap().len(), 1);
+
+        let error = read_layout_file(&dir.join("loop.vil").to_string_lossy(), &meta)
+            .unwrap_err()
+            .to_string();
+        assert!(error.starts_with("layout files extend each other"));
+        assert!(read_layout_file(&dir.join("missing.vil").to_string_lossy(), &meta).is_err());
+
+        assert_eq!(
+            layout_file_extends(&dir.join("me.toml").to_string_lossy()).unwrap(),
+            Some("team/base.vil".to_string())
+        );
+        assert_eq!(
+            layout_file_extends(&dir.join("team/base.vil").to_string_lossy()).unwrap(),
+            None
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
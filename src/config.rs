@@ -0,0 +1,188 @@
+use crate::common;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+/// Named device, selection criteria which are not set match any device.
+#[derive(Debug, Default, PartialEq)]
+pub struct DeviceAlias {
+    pub uid: Option<u64>,
+    pub vendor_id: Option<u16>,
+    pub product_id: Option<u16>,
+    pub serial: Option<String>,
+    pub path: Option<String>,
+    pub name: Option<String>,
+    /// meta file to use instead of vial meta
+    pub meta: Option<String>,
+    /// layout file for save and load
+    pub file: Option<String>,
+}
+
+#[derive(Debug, Default)]
+pub struct Config {
+    pub default_device: Option<String>,
+    pub output: Option<common::OutputFormat>,
+    pub devices: HashMap<String, DeviceAlias>,
+}
+
+fn optional_string(value: &Value, name: &str) -> Result<Option<String>, common::CommandError> {
+    match &value[name] {
+        Value::Null => Ok(None),
+        Value::String(s) => Ok(Some(s.to_string())),
+        _ => Err(common::CommandError(format!("{} should be a string", name))),
+    }
+}
+
+fn optional_u16(value: &Value, name: &str) -> Result<Option<u16>, common::CommandError> {
+    match &value[name] {
+        Value::Null => Ok(None),
+        v => match v.as_u64() {
+            Some(n) if n <= u16::MAX.into() => Ok(Some(n as u16)),
+            _ => Err(common::CommandError(format!(
+                "{} should be a number 0-65535",
+                name
+            ))),
+        },
+    }
+}
+
+impl DeviceAlias {
+    pub fn from_json(value: &Value) -> Result<DeviceAlias, Box<dyn std::error::Error>> {
+        if !value.is_object() {
+            return Err(common::CommandError("device should be an object".to_string()).into());
+        }
+        let uid = match &value["uid"] {
+            Value::Null => None,
+            Value::String(uid) => Some(common::parse_uid(uid)?),
+            uid => Some(uid.as_u64().ok_or("uid should be a number or a string")?),
+        };
+        Ok(DeviceAlias {
+            uid,
+            vendor_id: optional_u16(value, "vid")?,
+            product_id: optional_u16(value, "pid")?,
+            serial: optional_string(value, "serial")?,
+            path: optional_string(value, "path")?,
+            name: optional_string(value, "name")?,
+            meta: optional_string(value, "meta")?,
+            file: optional_string(value, "file")?,
+        })
+    }
+}
+
+impl Config {
+    /// Location of config file, $XDG_CONFIG_HOME/vitaly/config.json or ~/.config/vitaly/config.json.
+    pub fn path() -> Option<PathBuf> {
+        let base = match env::var_os("XDG_CONFIG_HOME") {
+            Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+        };
+        Some(base.join("vitaly").join("config.json"))
+    }
+
+    /// Loads config file, missing file means empty config.
+    pub fn load() -> Result<Config, Box<dyn std::error::Error>> {
+        match Config::path() {
+            Some(path) if path.exists() => {
+                let root: Value = serde_json::from_str(&fs::read_to_string(&path)?)
+                    .map_err(|e| format!("failed to parse {:?}: {}", path, e))?;
+                Config::from_json(&root).map_err(|e| format!("config {:?}: {}", path, e).into())
+            }
+            _ => Ok(Config::default()),
+        }
+    }
+
+    pub fn from_json(root: &Value) -> Result<Config, Box<dyn std::error::Error>> {
+        if !root.is_object() {
+            return Err(common::CommandError("config root should be an object".to_string()).into());
+        }
+        let output = match optional_string(root, "output")? {
+            Some(output) => Some(output.parse()?),
+            None => None,
+        };
+        let mut devices = HashMap::new();
+        match &root["devices"] {
+            Value::Null => {}
+            Value::Object(aliases) => {
+                for (name, device) in aliases {
+                    devices.insert(
+                        name.to_string(),
+                        DeviceAlias::from_json(device).map_err(|e| format!("{}: {}", name, e))?,
+                    );
+                }
+            }
+            _ => {
+                return Err(common::CommandError("devices should be an object".to_string()).into());
+            }
+        }
+        let default_device = optional_string(root, "default_device")?;
+        if let Some(name) = &default_device
+            && !devices.contains_key(name)
+        {
+            return Err(
+                common::CommandError(format!("default device {:?} is not defined", name)).into(),
+            );
+        }
+        Ok(Config {
+            default_device,
+            output,
+            devices,
+        })
+    }
+
+    pub fn device(&self, name: &str) -> Result<&DeviceAlias, common::CommandError> {
+        self.devices.get(name).ok_or(common::CommandError(format!(
+            "device alias {:?} is not defined in config file",
+            name
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_config_from_json() {
+        let config = Config::from_json(&json!({
+            "default_device": "lily",
+            "output": "json",
+            "devices": {
+                "lily": {"uid": "0x10", "vid": 65261, "pid": 4626, "meta": "lily.json", "file": "lily.vil"},
+                "corne": {"uid": 42, "serial": "vial:f64c2b3c", "name": "Corne*"}
+            }
+        }))
+        .unwrap();
+        assert_eq!(config.default_device.as_deref(), Some("lily"));
+        assert_eq!(config.output, Some(common::OutputFormat::Json));
+        assert_eq!(
+            config.device("lily").unwrap(),
+            &DeviceAlias {
+                uid: Some(16),
+                vendor_id: Some(65261),
+                product_id: Some(4626),
+                meta: Some("lily.json".to_string()),
+                file: Some("lily.vil".to_string()),
+                ..Default::default()
+            }
+        );
+        let corne = config.device("corne").unwrap();
+        assert_eq!(corne.uid, Some(42));
+        assert_eq!(corne.serial.as_deref(), Some("vial:f64c2b3c"));
+        assert_eq!(corne.name.as_deref(), Some("Corne*"));
+        assert!(config.device("unknown").is_err());
+    }
+
+    #[test]
+    fn test_config_errors() {
+        assert!(Config::from_json(&json!({})).is_ok());
+        assert!(Config::from_json(&json!([])).is_err());
+        assert!(Config::from_json(&json!({"output": "xml"})).is_err());
+        assert!(Config::from_json(&json!({"default_device": "lily"})).is_err());
+        assert!(Config::from_json(&json!({"devices": {"lily": {"vid": 70000}}})).is_err());
+        assert!(Config::from_json(&json!({"devices": {"lily": {"uid": true}}})).is_err());
+        assert!(Config::from_json(&json!({"devices": {"lily": {"meta": 1}}})).is_err());
+    }
+}
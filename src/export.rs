@@ -0,0 +1,18 @@
+use crate::keymap::Button;
+use crate::protocol::Snapshot;
+
+mod qmk;
+pub use crate::export::qmk::keymap_c;
+
+mod zmk;
+pub use crate::export::zmk::zmk_keymap;
+
+/// Keycode of key on layer, KC_NO for keys outside of snapshot matrix.
+fn keycode_at(snapshot: &Snapshot, layer: u8, button: &Button) -> u16 {
+    let (_, rows, cols) = snapshot.keys.dimensions();
+    if button.wire_x < rows && button.wire_y < cols {
+        snapshot.keys.get(layer, button.wire_x, button.wire_y)
+    } else {
+        0
+    }
+}
@@ -0,0 +1,515 @@
+use crate::export::keycode_at;
+use crate::keycodes;
+use crate::keymap::{Button, physical_rows};
+use crate::protocol::{AltRepeat, MacroStep, Snapshot};
+use std::fmt::Write;
+
+const KEY_OVERRIDE_OPTIONS: [&str; 6] = [
+    "ko_option_activation_trigger_down",
+    "ko_option_activation_required_mod_down",
+    "ko_option_activation_negative_mod_up",
+    "ko_option_one_mod",
+    "ko_option_no_reregister_trigger",
+    "ko_option_no_unregister_on_other_key_down",
+];
+
+const TAP_DANCE_HANDLERS: &str = r#"typedef struct {
+    uint16_t tap;
+    uint16_t hold;
+    uint16_t double_tap;
+    uint16_t tap_hold;
+    uint16_t tapping_term;
+    uint16_t active;
+} vitaly_tap_dance_t;
+
+static void vitaly_tap_dance_finished(tap_dance_state_t *state, void *user_data) {
+    vitaly_tap_dance_t *td = (vitaly_tap_dance_t *)user_data;
+    bool held = state->pressed && !state->interrupted;
+    td->active = td->tap;
+    if (state->count == 1) {
+        if (held && td->hold != KC_NO) {
+            td->active = td->hold;
+        }
+    } else if (state->count == 2 && held && td->tap_hold != KC_NO) {
+        td->active = td->tap_hold;
+    } else if (state->count == 2 && td->double_tap != KC_NO) {
+        td->active = td->double_tap;
+    } else {
+        for (uint8_t i = 1; i < state->count; i++) {
+            tap_code16(td->tap);
+        }
+    }
+    register_code16(td->active);
+}
+
+static void vitaly_tap_dance_reset(tap_dance_state_t *state, void *user_data) {
+    vitaly_tap_dance_t *td = (vitaly_tap_dance_t *)user_data;
+    unregister_code16(td->active);
+    td->active = KC_NO;
+}
+
+#define VITALY_TAP_DANCE(index) \
+    { .fn = {NULL, vitaly_tap_dance_finished, vitaly_tap_dance_reset}, .user_data = &vitaly_tap_dances[index] }
+"#;
+
+/// C string literal, bytes outside of printable ascii are written as octal escapes.
+fn c_string(text: &str) -> String {
+    let mut result = String::from("\"");
+    for byte in text.bytes() {
+        match byte {
+            b'"' => result.push_str("\\\""),
+            b'\\' => result.push_str("\\\\"),
+            b'\n' => result.push_str("\\n"),
+            b'\t' => result.push_str("\\t"),
+            0x20..=0x7E => result.push(byte as char),
+            _ => result.push_str(&format!("\\{:03o}", byte)),
+        }
+    }
+    result.push('"');
+    result
+}
+
+/// 8 bit mods mask as C expression.
+fn mods(mask: u8) -> String {
+    match mask {
+        0 => "0".to_string(),
+        _ => keycodes::bitmod_to_name(mask).replace('|', " | "),
+    }
+}
+
+/// Name of basic keycode for SEND_STRING (X_ prefix instead of KC_), None for other keycodes.
+fn send_string_name(keycode: u16, vial_version: u32) -> Option<String> {
+    if keycode > 0xFF {
+        return None;
+    }
+    keycodes::qid_to_name(keycode, vial_version)
+        .strip_prefix("KC_")
+        .map(|name| format!("X_{}", name))
+}
+
+/// Statements of macro body, runs of basic keycodes and text are joined into SEND_STRING.
+fn macro_body(steps: &[MacroStep], vial_version: u32) -> Vec<String> {
+    let mut statements = Vec::new();
+    let mut parts: Vec<String> = Vec::new();
+    for step in steps {
+        let part = match step {
+            MacroStep::Text(text) => Some(c_string(text)),
+            MacroStep::Delay(ms) => Some(format!("SS_DELAY({})", ms)),
+            MacroStep::Tap(keycode) | MacroStep::Down(keycode) | MacroStep::Up(keycode) => {
+                send_string_name(*keycode, vial_version).map(|name| match step {
+                    MacroStep::Tap(_) => format!("SS_TAP({})", name),
+                    MacroStep::Down(_) => format!("SS_DOWN({})", name),
+                    _ => format!("SS_UP({})", name),
+                })
+            }
+        };
+        match part {
+            Some(part) => parts.push(part),
+            None => {
+                if !parts.is_empty() {
+                    statements.push(format!("SEND_STRING({});", parts.join(" ")));
+                    parts.clear();
+                }
+                let (function, keycode) = match step {
+                    MacroStep::Tap(keycode) => ("tap_code16", keycode),
+                    MacroStep::Down(keycode) => ("register_code16", keycode),
+                    MacroStep::Up(keycode) => ("unregister_code16", keycode),
+                    _ => unreachable!("only keycode steps lack SEND_STRING form"),
+                };
+                statements.push(format!(
+                    "{}({});",
+                    function,
+                    keycodes::qid_to_name(*keycode, vial_version)
+                ));
+            }
+        }
+    }
+    if !parts.is_empty() {
+        statements.push(format!("SEND_STRING({});", parts.join(" ")));
+    }
+    statements
+}
+
+/// Generates QMK keymap.c from snapshot. Layers are written as LAYOUT arguments in order of
+/// `buttons` (keymap_to_buttons output), one line per physical row. Keycode names should be
+/// QMK ones, so snapshot is expected to use Vial protocol 6.
+pub fn keymap_c(
+    snapshot: &Snapshot,
+    buttons: &[Button],
+    keyboard_name: &str,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let vial_version = snapshot.vial_version;
+    let name = |keycode: u16| keycodes::qid_to_name(keycode, vial_version);
+    let combos: Vec<_> = snapshot.combos.iter().filter(|c| !c.is_empty()).collect();
+    let tap_dances: Vec<_> = snapshot
+        .tap_dances
+        .iter()
+        .filter(|t| !t.is_empty())
+        .collect();
+    let key_overrides: Vec<_> = snapshot
+        .key_overrides
+        .iter()
+        .filter(|k| !k.is_empty() && k.ko_enabled)
+        .collect();
+    let alt_repeats: Vec<_> = snapshot
+        .alt_repeats
+        .iter()
+        .filter(|a| !a.is_empty() && a.arep_enabled)
+        .collect();
+    let macros: Vec<_> = snapshot.macros.iter().filter(|m| !m.is_empty()).collect();
+    let has_encoders = snapshot.encoders.iter().any(|layer| !layer.is_empty());
+
+    let mut features = Vec::new();
+    if has_encoders {
+        features.push("ENCODER_MAP_ENABLE");
+    }
+    if !combos.is_empty() {
+        features.push("COMBO_ENABLE");
+    }
+    if !tap_dances.is_empty() {
+        features.push("TAP_DANCE_ENABLE");
+    }
+    if !key_overrides.is_empty() {
+        features.push("KEY_OVERRIDE_ENABLE");
+    }
+    if !alt_repeats.is_empty() {
+        features.push("REPEAT_KEY_ENABLE");
+    }
+
+    let mut out = String::new();
+    writeln!(out, "// keymap.c for {} generated by vitaly", keyboard_name)?;
+    if !features.is_empty() {
+        writeln!(
+            out,
+            "// rules.mk should have: {}",
+            features
+                .iter()
+                .map(|feature| format!("{} = yes", feature))
+                .collect::<Vec<_>>()
+                .join(", ")
+        )?;
+    }
+    writeln!(out)?;
+    writeln!(out, "#include QMK_KEYBOARD_H")?;
+    writeln!(out)?;
+
+    let (layers, _, _) = snapshot.keys.dimensions();
+    writeln!(
+        out,
+        "const uint16_t PROGMEM keymaps[][MATRIX_ROWS][MATRIX_COLS] = {{"
+    )?;
+    for layer in 0..layers {
+        writeln!(out, "    [{}] = LAYOUT(", layer)?;
+        let lines: Vec<String> = physical_rows(buttons)
+            .iter()
+            .map(|row| {
+                let names: Vec<String> = row
+                    .iter()
+                    .map(|button| name(keycode_at(snapshot, layer, button)))
+                    .collect();
+                format!("        {}", names.join(", "))
+            })
+            .collect();
+        writeln!(out, "{}", lines.join(",\n"))?;
+        writeln!(out, "    ),")?;
+    }
+    writeln!(out, "}};")?;
+
+    if has_encoders {
+        writeln!(out)?;
+        writeln!(out, "#ifdef ENCODER_MAP_ENABLE")?;
+        writeln!(
+            out,
+            "const uint16_t PROGMEM encoder_map[][NUM_ENCODERS][NUM_DIRECTIONS] = {{"
+        )?;
+        for (layer, encoders) in snapshot.encoders.iter().enumerate() {
+            let encoders: Vec<String> = encoders
+                .iter()
+                .map(|e| format!("ENCODER_CCW_CW({}, {})", name(e.ccw), name(e.cw)))
+                .collect();
+            writeln!(out, "    [{}] = {{{}}},", layer, encoders.join(", "))?;
+        }
+        writeln!(out, "}};")?;
+        writeln!(out, "#endif")?;
+    }
+
+    if !combos.is_empty() {
+        writeln!(out)?;
+        writeln!(out, "#ifdef COMBO_ENABLE")?;
+        for combo in &combos {
+            let keys: Vec<String> = [combo.key1, combo.key2, combo.key3, combo.key4]
+                .iter()
+                .filter(|keycode| **keycode != 0)
+                .map(|keycode| name(*keycode))
+                .collect();
+            writeln!(
+                out,
+                "const uint16_t PROGMEM combo_{}[] = {{{}, COMBO_END}};",
+                combo.index,
+                keys.join(", ")
+            )?;
+        }
+        writeln!(out, "combo_t key_combos[] = {{")?;
+        for combo in &combos {
+            writeln!(
+                out,
+                "    COMBO(combo_{}, {}),",
+                combo.index,
+                name(combo.output)
+            )?;
+        }
+        writeln!(out, "}};")?;
+        writeln!(out, "#endif")?;
+    }
+
+    if !tap_dances.is_empty() {
+        writeln!(out)?;
+        writeln!(out, "#ifdef TAP_DANCE_ENABLE")?;
+        out.push_str(TAP_DANCE_HANDLERS);
+        writeln!(out)?;
+        writeln!(out, "static vitaly_tap_dance_t vitaly_tap_dances[] = {{")?;
+        for td in &tap_dances {
+            writeln!(
+                out,
+                "    [{}] = {{{}, {}, {}, {}, {}, KC_NO}},",
+                td.index,
+                name(td.tap),
+                name(td.hold),
+                name(td.double_tap),
+                name(td.tap_hold),
+                td.tapping_term
+            )?;
+        }
+        writeln!(out, "}};")?;
+        writeln!(out)?;
+        writeln!(out, "tap_dance_action_t tap_dance_actions[] = {{")?;
+        for td in &tap_dances {
+            writeln!(out, "    [{}] = VITALY_TAP_DANCE({}),", td.index, td.index)?;
+        }
+        writeln!(out, "}};")?;
+        writeln!(out)?;
+        writeln!(out, "#ifdef TAPPING_TERM_PER_KEY")?;
+        writeln!(
+            out,
+            "uint16_t get_tapping_term(uint16_t keycode, keyrecord_t *record) {{"
+        )?;
+        writeln!(
+            out,
+            "    if (IS_QK_TAP_DANCE(keycode) && QK_TAP_DANCE_GET_INDEX(keycode) < ARRAY_SIZE(vitaly_tap_dances)) {{"
+        )?;
+        writeln!(
+            out,
+            "        return vitaly_tap_dances[QK_TAP_DANCE_GET_INDEX(keycode)].tapping_term;"
+        )?;
+        writeln!(out, "    }}")?;
+        writeln!(out, "    return TAPPING_TERM;")?;
+        writeln!(out, "}}")?;
+        writeln!(out, "#endif")?;
+        writeln!(out, "#endif")?;
+    }
+
+    if !key_overrides.is_empty() {
+        writeln!(out)?;
+        writeln!(out, "#ifdef KEY_OVERRIDE_ENABLE")?;
+        for ko in &key_overrides {
+            let options: Vec<&str> = KEY_OVERRIDE_OPTIONS
+                .iter()
+                .enumerate()
+                .filter(|(bit, _)| ko.options() & (1 << bit) != 0)
+                .map(|(_, option)| *option)
+                .collect();
+            writeln!(out, "const key_override_t key_override_{} = {{", ko.index)?;
+            writeln!(out, "    .trigger_mods = {},", mods(ko.trigger_mods))?;
+            writeln!(out, "    .layers = {:#06x},", ko.layers)?;
+            writeln!(out, "    .suppressed_mods = {},", mods(ko.suppressed_mods))?;
+            writeln!(
+                out,
+                "    .options = {},",
+                match options.is_empty() {
+                    true => "0".to_string(),
+                    false => options.join(" | "),
+                }
+            )?;
+            writeln!(
+                out,
+                "    .negative_mod_mask = {},",
+                mods(ko.negative_mod_mask)
+            )?;
+            writeln!(out, "    .trigger = {},", name(ko.trigger))?;
+            writeln!(out, "    .replacement = {},", name(ko.replacement))?;
+            writeln!(out, "}};")?;
+        }
+        writeln!(out, "const key_override_t *key_overrides[] = {{")?;
+        for ko in &key_overrides {
+            writeln!(out, "    &key_override_{},", ko.index)?;
+        }
+        writeln!(out, "}};")?;
+        writeln!(out, "#endif")?;
+    }
+
+    if !alt_repeats.is_empty() {
+        writeln!(out)?;
+        writeln!(out, "#ifdef REPEAT_KEY_ENABLE")?;
+        writeln!(
+            out,
+            "uint16_t get_alt_repeat_key_keycode_user(uint16_t keycode, uint8_t mods) {{"
+        )?;
+        let condition = |ar: &AltRepeat| {
+            if ar.arep_option_ignore_mod_handedness {
+                let allowed = (ar.allowed_mods | ar.allowed_mods >> 4) & 0x0F;
+                format!("(((mods | mods >> 4) & 0x0f) & ~{:#04x}) == 0", allowed)
+            } else if ar.allowed_mods == 0 {
+                "mods == 0".to_string()
+            } else {
+                format!("(mods & ~({})) == 0", mods(ar.allowed_mods))
+            }
+        };
+        for ar in &alt_repeats {
+            writeln!(
+                out,
+                "    if (keycode == {} && {}) {{",
+                name(ar.keycode),
+                condition(ar)
+            )?;
+            writeln!(out, "        return {};", name(ar.alt_keycode))?;
+            writeln!(out, "    }}")?;
+            if ar.arep_option_bidirectional {
+                writeln!(
+                    out,
+                    "    if (keycode == {} && {}) {{",
+                    name(ar.alt_keycode),
+                    condition(ar)
+                )?;
+                writeln!(out, "        return {};", name(ar.keycode))?;
+                writeln!(out, "    }}")?;
+            }
+        }
+        for ar in alt_repeats
+            .iter()
+            .filter(|ar| ar.arep_option_default_to_this_alt_key)
+        {
+            writeln!(out, "    if ({}) {{", condition(ar))?;
+            writeln!(out, "        return {};", name(ar.alt_keycode))?;
+            writeln!(out, "    }}")?;
+        }
+        writeln!(out, "    return KC_TRANSPARENT;")?;
+        writeln!(out, "}}")?;
+        writeln!(out, "#endif")?;
+    }
+
+    if !macros.is_empty() {
+        writeln!(out)?;
+        writeln!(
+            out,
+            "bool process_record_user(uint16_t keycode, keyrecord_t *record) {{"
+        )?;
+        writeln!(out, "    if (!record->event.pressed) {{")?;
+        writeln!(out, "        return true;")?;
+        writeln!(out, "    }}")?;
+        writeln!(out, "    switch (keycode) {{")?;
+        for m in &macros {
+            writeln!(out, "        case QK_MACRO_{}:", m.index)?;
+            for statement in macro_body(&m.steps, vial_version) {
+                writeln!(out, "            {}", statement)?;
+            }
+            writeln!(out, "            return false;")?;
+        }
+        writeln!(out, "    }}")?;
+        writeln!(out, "    return true;")?;
+        writeln!(out, "}}")?;
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keymap;
+    use crate::protocol::LayoutOptions;
+    use serde_json::json;
+
+    fn buttons() -> Vec<Button> {
+        let meta = json!([["0,0", "0,1"], ["1,0", "1,1"]]);
+        let options = LayoutOptions::from_json(0, &serde_json::Value::Null).unwrap();
+        keymap::keymap_to_buttons(&meta, &options).unwrap()
+    }
+
+    #[test]
+    fn test_c_string() {
+        assert_eq!(c_string("a\"b\\c\n"), "\"a\\\"b\\\\c\\n\"");
+        assert_eq!(c_string("é"), "\"\\303\\251\"");
+    }
+
+    #[test]
+    fn test_macro_body() {
+        let steps = vec![
+            MacroStep::Text("hi".to_string()),
+            MacroStep::Tap(0x0028),
+            MacroStep::Delay(100),
+            MacroStep::Tap(0x0106),
+            MacroStep::Down(0x00E1),
+            MacroStep::Up(0x00E1),
+        ];
+        assert_eq!(
+            macro_body(&steps, 6),
+            vec![
+                "SEND_STRING(\"hi\" SS_TAP(X_ENTER) SS_DELAY(100));",
+                "tap_code16(LCTL(KC_C));",
+                "SEND_STRING(SS_DOWN(X_LEFT_SHIFT) SS_UP(X_LEFT_SHIFT));",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_keymap_c() {
+        let snapshot = Snapshot::from_json(&json!({
+            "version": 1,
+            "uid": 1,
+            "vial_protocol": 6,
+            "layout": [
+                [["KC_A", "KC_B"], ["LT(1,KC_SPACE)", "TD(0)"]],
+                [["KC_TRNS", "QK_MACRO_0"], ["KC_NO", "KC_1"]]
+            ],
+            "combo": [["KC_A", "KC_B", "KC_NO", "KC_NO", "KC_ESCAPE"]],
+            "tap_dance": [["KC_A", "KC_LEFT_CTRL", "KC_NO", "KC_NO", 200]],
+            "key_override": [{
+                "trigger": "KC_BACKSPACE",
+                "replacement": "KC_DELETE",
+                "layers": 65535,
+                "trigger_mods": 2,
+                "negative_mod_mask": 0,
+                "suppressed_mods": 2,
+                "options": 135
+            }],
+            "alt_repeat_key": [{
+                "keycode": "KC_LEFT",
+                "alt_keycode": "KC_RIGHT",
+                "allowed_mods": 0,
+                "options": 10
+            }],
+            "macro": [[["text", "hi"], ["tap", "KC_ENTER"]]]
+        }))
+        .unwrap();
+        let c = keymap_c(&snapshot, &buttons(), "Test").unwrap();
+        assert!(c.starts_with("// keymap.c for Test generated by vitaly\n"));
+        assert!(c.contains(
+            "    [0] = LAYOUT(\n        KC_A, KC_B,\n        LT(1,KC_SPACE), TD(0)\n    ),\n"
+        ));
+        assert!(c.contains("    [1] = LAYOUT(\n        KC_TRANSPARENT, QK_MACRO_0,\n"));
+        assert!(!c.contains("ENCODER_MAP_ENABLE"));
+        assert!(c.contains("const uint16_t PROGMEM combo_0[] = {KC_A, KC_B, COMBO_END};"));
+        assert!(c.contains("    COMBO(combo_0, KC_ESCAPE),"));
+        assert!(c.contains("    [0] = {KC_A, KC_LEFT_CTRL, KC_NO, KC_NO, 200, KC_NO},"));
+        assert!(c.contains("    [0] = VITALY_TAP_DANCE(0),"));
+        assert!(c.contains("    .trigger_mods = MOD_BIT_LSHIFT,"));
+        assert!(c.contains(
+            "    .options = ko_option_activation_trigger_down | ko_option_activation_required_mod_down | ko_option_activation_negative_mod_up,"
+        ));
+        assert!(c.contains("    &key_override_0,"));
+        assert!(c.contains("    if (keycode == KC_LEFT && mods == 0) {\n        return KC_RIGHT;"));
+        assert!(c.contains("    if (keycode == KC_RIGHT && mods == 0) {\n        return KC_LEFT;"));
+        assert!(c.contains(
+            "        case QK_MACRO_0:\n            SEND_STRING(\"hi\" SS_TAP(X_ENTER));\n            return false;"
+        ));
+    }
+}
@@ -0,0 +1,517 @@
+use crate::export::keycode_at;
+use crate::keycodes;
+use crate::keymap::{Button, physical_rows};
+use crate::protocol::{MacroStep, Snapshot};
+use std::collections::BTreeSet;
+use std::fmt::Write;
+
+const KC_TRNS: u16 = 0x0001;
+
+/// Default wait between macro bindings, restored after delay steps.
+const MACRO_WAIT_MS: u16 = 15;
+
+const LETTERS: [&str; 26] = [
+    "A", "B", "C", "D", "E", "F", "G", "H", "I", "J", "K", "L", "M", "N", "O", "P", "Q", "R", "S",
+    "T", "U", "V", "W", "X", "Y", "Z",
+];
+
+/// ZMK name of basic (HID usage) keycode.
+fn key_name(keycode: u16) -> Option<String> {
+    let name = match keycode {
+        0x04..=0x1D => LETTERS[(keycode - 0x04) as usize],
+        0x1E..=0x26 => return Some(format!("N{}", keycode - 0x1D)),
+        0x27 => "N0",
+        0x28 => "RET",
+        0x29 => "ESC",
+        0x2A => "BSPC",
+        0x2B => "TAB",
+        0x2C => "SPACE",
+        0x2D => "MINUS",
+        0x2E => "EQUAL",
+        0x2F => "LBKT",
+        0x30 => "RBKT",
+        0x31 => "BSLH",
+        0x32 => "NON_US_HASH",
+        0x33 => "SEMI",
+        0x34 => "SQT",
+        0x35 => "GRAVE",
+        0x36 => "COMMA",
+        0x37 => "DOT",
+        0x38 => "FSLH",
+        0x39 => "CAPS",
+        0x3A..=0x45 => return Some(format!("F{}", keycode - 0x39)),
+        0x46 => "PSCRN",
+        0x47 => "SLCK",
+        0x48 => "PAUSE_BREAK",
+        0x49 => "INS",
+        0x4A => "HOME",
+        0x4B => "PG_UP",
+        0x4C => "DEL",
+        0x4D => "END",
+        0x4E => "PG_DN",
+        0x4F => "RIGHT",
+        0x50 => "LEFT",
+        0x51 => "DOWN",
+        0x52 => "UP",
+        0x53 => "KP_NUM",
+        0x54 => "KP_DIVIDE",
+        0x55 => "KP_MULTIPLY",
+        0x56 => "KP_MINUS",
+        0x57 => "KP_PLUS",
+        0x58 => "KP_ENTER",
+        0x59..=0x61 => return Some(format!("KP_N{}", keycode - 0x58)),
+        0x62 => "KP_N0",
+        0x63 => "KP_DOT",
+        0x64 => "NON_US_BSLH",
+        0x65 => "K_APP",
+        0x67 => "KP_EQUAL",
+        0x68..=0x73 => return Some(format!("F{}", keycode - 0x5B)),
+        0x7A => "K_UNDO",
+        0x7B => "K_CUT",
+        0x7C => "K_COPY",
+        0x7D => "K_PASTE",
+        0x7E => "K_FIND",
+        0x85 => "KP_COMMA",
+        0x87..=0x8F => return Some(format!("INT{}", keycode - 0x86)),
+        0x90..=0x98 => return Some(format!("LANG{}", keycode - 0x8F)),
+        0xA8 => "C_MUTE",
+        0xA9 => "C_VOL_UP",
+        0xAA => "C_VOL_DN",
+        0xAB => "C_NEXT",
+        0xAC => "C_PREV",
+        0xAD => "C_STOP",
+        0xAE => "C_PP",
+        0xB0 => "C_EJECT",
+        0xB2 => "C_AL_CALC",
+        0xB4 => "C_AC_SEARCH",
+        0xB5 => "C_AC_HOME",
+        0xB6 => "C_AC_BACK",
+        0xB7 => "C_AC_FORWARD",
+        0xB8 => "C_AC_STOP",
+        0xB9 => "C_AC_REFRESH",
+        0xBA => "C_AC_BOOKMARKS",
+        0xBB => "C_FF",
+        0xBC => "C_RW",
+        0xBD => "C_BRI_UP",
+        0xBE => "C_BRI_DN",
+        0xE0 => "LCTRL",
+        0xE1 => "LSHFT",
+        0xE2 => "LALT",
+        0xE3 => "LGUI",
+        0xE4 => "RCTRL",
+        0xE5 => "RSHFT",
+        0xE6 => "RALT",
+        0xE7 => "RGUI",
+        _ => return None,
+    };
+    Some(name.to_string())
+}
+
+/// Applies 5 bit QMK mods (ctrl, shift, alt, gui, right hand flag) to ZMK key as modifier
+/// functions, e.g. LC(LS(A)).
+fn with_mods(mods: u8, key: String) -> String {
+    let hand = if mods & 0x10 != 0 { "R" } else { "L" };
+    ["C", "S", "A", "G"]
+        .iter()
+        .enumerate()
+        .rev()
+        .filter(|(bit, _)| mods & (1 << bit) != 0)
+        .fold(key, |key, (_, function)| {
+            format!("{}{}({})", hand, function, key)
+        })
+}
+
+/// ZMK modifier for 5 bit QMK mods, e.g. LC(LSHFT) for ctrl and shift.
+fn mods_name(mods: u8) -> Option<String> {
+    let hand = if mods & 0x10 != 0 { 4 } else { 0 };
+    let last = (0..4).rev().find(|bit| mods & (1 << bit) != 0)?;
+    let key = key_name(0xE0 + hand + last as u16)?;
+    Some(with_mods(mods & !(1 << last), key))
+}
+
+/// Parameter of &kp, &lt and &mt: basic keycode optionally wrapped into modifiers.
+fn kp_param(keycode: u16) -> Option<String> {
+    match keycode {
+        0x0000..=0x00FF => key_name(keycode),
+        0x0100..=0x1FFF => Some(with_mods(
+            (keycode >> 8) as u8 & 0x1F,
+            key_name(keycode & 0xFF)?,
+        )),
+        _ => None,
+    }
+}
+
+/// ZMK behavior binding for Vial protocol 6 keycode. `macros` are indexes of macros which are
+/// defined in keymap.
+fn binding(keycode: u16, macros: &BTreeSet<u8>) -> Option<String> {
+    match keycode {
+        0x0000 => Some("&none".to_string()),
+        0x0001 => Some("&trans".to_string()),
+        0x0002..=0x1FFF => kp_param(keycode).map(|key| format!("&kp {}", key)),
+        0x2000..=0x3FFF => Some(format!(
+            "&mt {} {}",
+            mods_name((keycode >> 8) as u8 & 0x1F)?,
+            key_name(keycode & 0xFF)?
+        )),
+        0x4000..=0x4FFF => Some(format!(
+            "&lt {} {}",
+            (keycode >> 8) & 0x0F,
+            key_name(keycode & 0xFF)?
+        )),
+        0x5200..=0x521F => Some(format!("&to {}", keycode & 0x1F)),
+        0x5220..=0x523F => Some(format!("&mo {}", keycode & 0x1F)),
+        0x5260..=0x527F => Some(format!("&tog {}", keycode & 0x1F)),
+        0x5280..=0x529F => Some(format!("&sl {}", keycode & 0x1F)),
+        0x52A0..=0x52BF => Some(format!("&sk {}", mods_name(keycode as u8 & 0x1F)?)),
+        0x7700..=0x777F if macros.contains(&((keycode & 0x7F) as u8)) => {
+            Some(format!("&macro_{}", keycode & 0x7F))
+        }
+        0x7C00 => Some("&bootloader".to_string()),
+        0x7C01 => Some("&sys_reset".to_string()),
+        0x7C73 => Some("&caps_word".to_string()),
+        _ => None,
+    }
+}
+
+/// The same as binding, but keycodes which can't be translated are added to `missing` and
+/// replaced with &none.
+fn bind(keycode: u16, macros: &BTreeSet<u8>, missing: &mut BTreeSet<String>) -> String {
+    binding(keycode, macros).unwrap_or_else(|| {
+        missing.insert(keycodes::qid_to_name(keycode, 6));
+        "&none".to_string()
+    })
+}
+
+/// &kp parameter typing character on US layout.
+fn char_param(c: char) -> Option<String> {
+    let name = match c {
+        'a'..='z' => return Some(LETTERS[c as usize - 'a' as usize].to_string()),
+        'A'..='Z' => return Some(format!("LS({})", LETTERS[c as usize - 'A' as usize])),
+        '1'..='9' => return Some(format!("N{}", c)),
+        '0' => "N0",
+        ' ' => "SPACE",
+        '\n' => "RET",
+        '\t' => "TAB",
+        '-' => "MINUS",
+        '=' => "EQUAL",
+        '[' => "LBKT",
+        ']' => "RBKT",
+        '\\' => "BSLH",
+        ';' => "SEMI",
+        '\'' => "SQT",
+        '`' => "GRAVE",
+        ',' => "COMMA",
+        '.' => "DOT",
+        '/' => "FSLH",
+        '!' => "EXCL",
+        '@' => "AT",
+        '#' => "HASH",
+        '$' => "DOLLAR",
+        '%' => "PRCNT",
+        '^' => "CARET",
+        '&' => "AMPS",
+        '*' => "STAR",
+        '(' => "LPAR",
+        ')' => "RPAR",
+        '_' => "UNDER",
+        '+' => "PLUS",
+        '{' => "LBRC",
+        '}' => "RBRC",
+        '|' => "PIPE",
+        ':' => "COLON",
+        '"' => "DQT",
+        '~' => "TILDE",
+        '<' => "LT",
+        '>' => "GT",
+        '?' => "QMARK",
+        _ => return None,
+    };
+    Some(name.to_string())
+}
+
+/// Bindings of ZMK macro, steps which can't be translated are added to `missing`.
+fn macro_bindings(steps: &[MacroStep], missing: &mut BTreeSet<String>) -> Vec<String> {
+    let mut bindings = Vec::new();
+    for step in steps {
+        match step {
+            MacroStep::Text(text) => {
+                for c in text.chars() {
+                    match char_param(c) {
+                        Some(param) => bindings.push(format!("&kp {}", param)),
+                        None => {
+                            missing.insert(format!("{:?}", c));
+                        }
+                    }
+                }
+            }
+            MacroStep::Delay(ms) => {
+                bindings.push(format!("&macro_wait_time {} &none", ms));
+                bindings.push(format!("&macro_wait_time {}", MACRO_WAIT_MS));
+            }
+            MacroStep::Tap(keycode) | MacroStep::Down(keycode) | MacroStep::Up(keycode) => {
+                match kp_param(*keycode) {
+                    Some(param) => bindings.push(match step {
+                        MacroStep::Tap(_) => format!("&kp {}", param),
+                        MacroStep::Down(_) => format!("&macro_press &kp {} &macro_tap", param),
+                        _ => format!("&macro_release &kp {} &macro_tap", param),
+                    }),
+                    None => {
+                        missing.insert(keycodes::qid_to_name(*keycode, 6));
+                    }
+                }
+            }
+        }
+    }
+    bindings
+}
+
+/// Generates ZMK .keymap devicetree from snapshot with Vial protocol 6 keycodes. Layer bindings
+/// follow order of keys in `buttons` (keymap_to_buttons output), combos use positions of their
+/// keys on layer 0. Returns keymap and report of what can't be translated, such entries are
+/// replaced with &none or skipped.
+pub fn zmk_keymap(
+    snapshot: &Snapshot,
+    buttons: &[Button],
+    keyboard_name: &str,
+) -> Result<(String, Vec<String>), Box<dyn std::error::Error>> {
+    let rows = physical_rows(buttons);
+    let keys: Vec<&Button> = rows.iter().flatten().copied().collect();
+    let macros: Vec<_> = snapshot.macros.iter().filter(|m| !m.is_empty()).collect();
+    let macro_indexes: BTreeSet<u8> = macros.iter().map(|m| m.index).collect();
+    let mut missing = BTreeSet::new();
+    let mut report = Vec::new();
+    let mut out = String::new();
+    writeln!(out, "/*")?;
+    writeln!(out, " * {} keymap generated by vitaly", keyboard_name)?;
+    writeln!(out, " */")?;
+    writeln!(out)?;
+    writeln!(out, "#include <behaviors.dtsi>")?;
+    writeln!(out, "#include <dt-bindings/zmk/keys.h>")?;
+    writeln!(out)?;
+    writeln!(out, "/ {{")?;
+
+    let mut combos = Vec::new();
+    for combo in snapshot.combos.iter().filter(|c| !c.is_empty()) {
+        let mut positions = Vec::new();
+        for keycode in [combo.key1, combo.key2, combo.key3, combo.key4] {
+            if keycode == 0 {
+                continue;
+            }
+            match keys
+                .iter()
+                .position(|button| keycode_at(snapshot, 0, button) == keycode)
+            {
+                Some(position) => positions.push(position.to_string()),
+                None => {
+                    report.push(format!(
+                        "Combo {} skipped, {} is not on layer 0",
+                        combo.index,
+                        keycodes::qid_to_name(keycode, 6)
+                    ));
+                    positions.clear();
+                    break;
+                }
+            }
+        }
+        if !positions.is_empty() {
+            combos.push((
+                combo.index,
+                positions,
+                bind(combo.output, &macro_indexes, &mut missing),
+            ));
+        }
+    }
+    if !combos.is_empty() {
+        writeln!(out, "    combos {{")?;
+        writeln!(out, "        compatible = \"zmk,combos\";")?;
+        for (index, positions, output) in &combos {
+            writeln!(out)?;
+            writeln!(out, "        combo_{} {{", index)?;
+            writeln!(
+                out,
+                "            key-positions = <{}>;",
+                positions.join(" ")
+            )?;
+            writeln!(out, "            bindings = <{}>;", output)?;
+            writeln!(out, "        }};")?;
+        }
+        writeln!(out, "    }};")?;
+        writeln!(out)?;
+    }
+
+    if !macros.is_empty() {
+        writeln!(out, "    macros {{")?;
+        for m in &macros {
+            writeln!(out, "        macro_{}: macro_{} {{", m.index, m.index)?;
+            writeln!(out, "            compatible = \"zmk,behavior-macro\";")?;
+            writeln!(out, "            #binding-cells = <0>;")?;
+            writeln!(out, "            wait-ms = <{}>;", MACRO_WAIT_MS)?;
+            writeln!(
+                out,
+                "            bindings = <{}>;",
+                macro_bindings(&m.steps, &mut missing).join(" ")
+            )?;
+            writeln!(out, "        }};")?;
+        }
+        writeln!(out, "    }};")?;
+        writeln!(out)?;
+    }
+
+    writeln!(out, "    keymap {{")?;
+    writeln!(out, "        compatible = \"zmk,keymap\";")?;
+    let (layers, _, _) = snapshot.keys.dimensions();
+    for layer in 0..layers {
+        writeln!(out)?;
+        writeln!(out, "        layer_{} {{", layer)?;
+        writeln!(out, "            bindings = <")?;
+        for row in &rows {
+            let bindings: Vec<String> = row
+                .iter()
+                .map(|button| {
+                    bind(
+                        keycode_at(snapshot, layer, button),
+                        &macro_indexes,
+                        &mut missing,
+                    )
+                })
+                .collect();
+            writeln!(out, "                {}", bindings.join("  "))?;
+        }
+        writeln!(out, "            >;")?;
+        // transparent layers are left without sensor bindings, ZMK falls through them anyway
+        if let Some(encoders) = snapshot.encoders.get(layer as usize)
+            && encoders.iter().any(|e| e.cw != KC_TRNS || e.ccw != KC_TRNS)
+        {
+            let mut sensors = Vec::new();
+            for e in encoders {
+                match (kp_param(e.cw), kp_param(e.ccw)) {
+                    (Some(cw), Some(ccw)) => sensors.push(format!("&inc_dec_kp {} {}", cw, ccw)),
+                    _ => report.push(format!(
+                        "Sensor bindings of layer {} skipped, encoder {} has {} and {} without ZMK equivalent",
+                        layer,
+                        e.index,
+                        keycodes::qid_to_name(e.ccw, 6),
+                        keycodes::qid_to_name(e.cw, 6)
+                    )),
+                }
+            }
+            if sensors.len() == encoders.len() {
+                writeln!(
+                    out,
+                    "            sensor-bindings = <{}>;",
+                    sensors.join(" ")
+                )?;
+            }
+        }
+        writeln!(out, "        }};")?;
+    }
+    writeln!(out, "    }};")?;
+    writeln!(out, "}};")?;
+
+    for name in missing {
+        report.push(format!("{} has no ZMK equivalent", name));
+    }
+    Ok((out, report))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keymap;
+    use crate::protocol::LayoutOptions;
+    use serde_json::json;
+
+    #[test]
+    fn test_binding() {
+        let macros = BTreeSet::from([0]);
+        let cases = [
+            (0x0000, "&none"),
+            (0x0001, "&trans"),
+            (0x0004, "&kp A"),
+            (0x0027, "&kp N0"),
+            (0x0045, "&kp F12"),
+            (0x0068, "&kp F13"),
+            (0x00A9, "&kp C_VOL_UP"),
+            (0x0306, "&kp LC(LS(C))"),
+            (0x1204, "&kp RS(A)"),
+            (0x2204, "&mt LSHFT A"),
+            (0x2304, "&mt LC(LSHFT) A"),
+            (0x412C, "&lt 1 SPACE"),
+            (0x5202, "&to 2"),
+            (0x5221, "&mo 1"),
+            (0x5263, "&tog 3"),
+            (0x5281, "&sl 1"),
+            (0x52A2, "&sk LSHFT"),
+            (0x7700, "&macro_0"),
+            (0x7C00, "&bootloader"),
+        ];
+        for (keycode, expected) in cases {
+            assert_eq!(binding(keycode, &macros).as_deref(), Some(expected));
+        }
+        assert_eq!(binding(0x7701, &macros), None);
+        assert_eq!(binding(0x5700, &macros), None);
+        assert_eq!(binding(0x52E1, &macros), None);
+    }
+
+    #[test]
+    fn test_macro_bindings() {
+        let mut missing = BTreeSet::new();
+        let steps = vec![
+            MacroStep::Text("Hi!é".to_string()),
+            MacroStep::Down(0x00E1),
+            MacroStep::Delay(100),
+            MacroStep::Up(0x00E1),
+            MacroStep::Tap(0x7C03),
+        ];
+        assert_eq!(
+            macro_bindings(&steps, &mut missing).join(" "),
+            "&kp LS(H) &kp I &kp EXCL &macro_press &kp LSHFT &macro_tap \
+             &macro_wait_time 100 &none &macro_wait_time 15 \
+             &macro_release &kp LSHFT &macro_tap"
+        );
+        assert_eq!(
+            missing,
+            BTreeSet::from(["'é'".to_string(), "QK_CLEAR_EEPROM".to_string()])
+        );
+    }
+
+    #[test]
+    fn test_zmk_keymap() {
+        let meta = json!([["0,0", "0,1"], ["1,0", "1,1"]]);
+        let options = LayoutOptions::from_json(0, &serde_json::Value::Null).unwrap();
+        let buttons = keymap::keymap_to_buttons(&meta, &options).unwrap();
+        let snapshot = Snapshot::from_json(&json!({
+            "version": 1,
+            "uid": 1,
+            "vial_protocol": 6,
+            "layout": [
+                [["KC_A", "KC_B"], ["LT(1,KC_SPACE)", "TD(0)"]],
+                [["KC_TRNS", "QK_MACRO_0"], ["MO(2)", "MT(MOD_LCTL,KC_ESCAPE)"]]
+            ],
+            "combo": [
+                ["KC_A", "KC_B", "KC_NO", "KC_NO", "KC_ESCAPE"],
+                ["KC_A", "KC_C", "KC_NO", "KC_NO", "KC_TAB"]
+            ],
+            "macro": [[["text", "hi"], ["tap", "KC_ENTER"]]]
+        }))
+        .unwrap();
+        let (keymap, report) = zmk_keymap(&snapshot, &buttons, "Test").unwrap();
+        assert!(keymap.contains(
+            "        combo_0 {\n            key-positions = <0 1>;\n            bindings = <&kp ESC>;"
+        ));
+        assert!(!keymap.contains("combo_1"));
+        assert!(keymap.contains("            bindings = <&kp H &kp I &kp RET>;"));
+        assert!(keymap.contains(
+            "        layer_0 {\n            bindings = <\n                &kp A  &kp B\n                &lt 1 SPACE  &none\n            >;"
+        ));
+        assert!(keymap.contains("                &mo 2  &mt LCTRL ESC\n"));
+        assert!(keymap.contains("                &trans  &macro_0\n"));
+        assert_eq!(
+            report,
+            vec![
+                "Combo 1 skipped, KC_C is not on layer 0",
+                "TD(0) has no ZMK equivalent"
+            ]
+        );
+    }
+}
@@ -0,0 +1,314 @@
+use crate::keycodes;
+use crate::keymap;
+use crate::protocol::{
+    self, AltRepeat, ApplyReport, Capabilities, Combo, Encoder, KeyOverride, Keymap, LayoutOptions,
+    Macro, ProtocolError, QmkValue, RGBInfo, Snapshot, TapDance, Transport,
+};
+use hidapi::{DeviceInfo, HidApi, HidDevice};
+use serde_json::Value;
+use std::collections::HashMap;
+
+/// Session with single keyboard, capabilities and meta are loaded once on open.
+pub struct Keyboard<T: Transport> {
+    device: T,
+    capabilities: Capabilities,
+    meta: Value,
+    rows: u8,
+    cols: u8,
+}
+
+/// Connected devices which talk VIA/Vial protocol.
+pub fn list_devices(api: &HidApi) -> Vec<&DeviceInfo> {
+    api.device_list()
+        .filter(|d| d.usage_page() == protocol::USAGE_PAGE && d.usage() == protocol::USAGE_ID)
+        .collect()
+}
+
+impl Keyboard<HidDevice> {
+    pub fn open(api: &HidApi, info: &DeviceInfo) -> Result<Self, Box<dyn std::error::Error>> {
+        Keyboard::new(info.open_device(api)?)
+    }
+}
+
+impl<T: Transport> Keyboard<T> {
+    /// Scans capabilities and loads meta from keyboard.
+    pub fn new(device: T) -> Result<Self, Box<dyn std::error::Error>> {
+        let capabilities = protocol::scan_capabilities(&device)?;
+        if capabilities.vial_version == 0 {
+            return Err(
+                ProtocolError::General("device doesn't support vial protocol".to_string()).into(),
+            );
+        }
+        let meta = protocol::load_vial_meta(&device)?;
+        Keyboard::init(device, capabilities, meta)
+    }
+
+    /// Uses provided meta instead of loading it from keyboard.
+    pub fn with_meta(device: T, meta: Value) -> Result<Self, Box<dyn std::error::Error>> {
+        let capabilities = protocol::scan_capabilities(&device)?;
+        Keyboard::init(device, capabilities, meta)
+    }
+
+    fn init(
+        device: T,
+        capabilities: Capabilities,
+        meta: Value,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let rows = meta["matrix"]["rows"]
+            .as_u64()
+            .ok_or("matrix/rows not found in meta")? as u8;
+        let cols = meta["matrix"]["cols"]
+            .as_u64()
+            .ok_or("matrix/cols not found in meta")? as u8;
+        Ok(Keyboard {
+            device,
+            capabilities,
+            meta,
+            rows,
+            cols,
+        })
+    }
+
+    pub fn device(&self) -> &T {
+        &self.device
+    }
+
+    pub fn capabilities(&self) -> &Capabilities {
+        &self.capabilities
+    }
+
+    pub fn meta(&self) -> &Value {
+        &self.meta
+    }
+
+    /// Matrix size as (rows, cols).
+    pub fn matrix(&self) -> (u8, u8) {
+        (self.rows, self.cols)
+    }
+
+    pub fn uid(&self) -> Result<u64, Box<dyn std::error::Error>> {
+        protocol::load_uid(&self.device)
+    }
+
+    pub fn keymap(&self) -> Result<Keymap, Box<dyn std::error::Error>> {
+        protocol::load_layers_keys(
+            &self.device,
+            self.capabilities.layer_count,
+            self.rows,
+            self.cols,
+        )
+    }
+
+    /// Assigns keycode given by name, e.g. KC_A or LCTL(KC_C).
+    pub fn set_key(
+        &self,
+        layer: u8,
+        row: u8,
+        col: u8,
+        keycode: &str,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if layer >= self.capabilities.layer_count || row >= self.rows || col >= self.cols {
+            return Err(ProtocolError::General(format!(
+                "key {},{} on layer {} is out of keyboard bounds",
+                row, col, layer
+            ))
+            .into());
+        }
+        let qid = keycodes::name_to_qid(keycode, self.capabilities.vial_version)?;
+        protocol::set_keycode(&self.device, layer, row, col, qid)
+    }
+
+    /// Encoders of every layer.
+    pub fn encoders(&self) -> Result<Vec<Vec<Encoder>>, Box<dyn std::error::Error>> {
+        let count = keymap::get_encoders_count(&self.meta["layouts"]["keymap"])?;
+        let mut result = Vec::new();
+        for layer in 0..self.capabilities.layer_count {
+            let mut encoders = Vec::new();
+            for index in 0..count {
+                encoders.push(protocol::load_encoder(&self.device, layer, index)?);
+            }
+            result.push(encoders);
+        }
+        Ok(result)
+    }
+
+    pub fn set_encoder(
+        &self,
+        layer: u8,
+        encoder: &Encoder,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        protocol::set_encoder(&self.device, layer, encoder.index, 0, encoder.ccw)?;
+        protocol::set_encoder(&self.device, layer, encoder.index, 1, encoder.cw)
+    }
+
+    pub fn combos(&self) -> Result<Vec<Combo>, Box<dyn std::error::Error>> {
+        match self.capabilities.combo_count {
+            0 => Ok(Vec::new()),
+            count => protocol::load_combos(&self.device, count),
+        }
+    }
+
+    pub fn set_combo(&self, combo: &Combo) -> Result<(), Box<dyn std::error::Error>> {
+        protocol::set_combo(&self.device, combo)
+    }
+
+    pub fn tap_dances(&self) -> Result<Vec<TapDance>, Box<dyn std::error::Error>> {
+        match self.capabilities.tap_dance_count {
+            0 => Ok(Vec::new()),
+            count => protocol::load_tap_dances(&self.device, count),
+        }
+    }
+
+    pub fn set_tap_dance(&self, tap_dance: &TapDance) -> Result<(), Box<dyn std::error::Error>> {
+        protocol::set_tap_dance(&self.device, tap_dance)
+    }
+
+    pub fn macros(&self) -> Result<Vec<Macro>, Box<dyn std::error::Error>> {
+        protocol::load_macros(
+            &self.device,
+            self.capabilities.macro_count,
+            self.capabilities.macro_buffer_size,
+        )
+    }
+
+    /// Macros are stored in single buffer, so all of them are written at once.
+    pub fn set_macros(&self, macros: &Vec<Macro>) -> Result<(), Box<dyn std::error::Error>> {
+        if protocol::get_locked_status(&self.device)?.locked {
+            return Err(ProtocolError::KeyboardLocked.into());
+        }
+        protocol::set_macros(&self.device, &self.capabilities, macros)
+    }
+
+    pub fn key_overrides(&self) -> Result<Vec<KeyOverride>, Box<dyn std::error::Error>> {
+        match self.capabilities.key_override_count {
+            0 => Ok(Vec::new()),
+            count => protocol::load_key_overrides(&self.device, count),
+        }
+    }
+
+    pub fn set_key_override(
+        &self,
+        key_override: &KeyOverride,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        protocol::set_key_override(&self.device, key_override)
+    }
+
+    pub fn alt_repeats(&self) -> Result<Vec<AltRepeat>, Box<dyn std::error::Error>> {
+        match self.capabilities.alt_repeat_key_count {
+            0 => Ok(Vec::new()),
+            count => protocol::load_alt_repeats(&self.device, count),
+        }
+    }
+
+    pub fn set_alt_repeat(&self, alt_repeat: &AltRepeat) -> Result<(), Box<dyn std::error::Error>> {
+        protocol::set_alt_repeat(&self.device, alt_repeat)
+    }
+
+    /// QMK settings by qsid, empty if keyboard doesn't support them.
+    pub fn settings(&self) -> Result<HashMap<u16, QmkValue>, Box<dyn std::error::Error>> {
+        if self.capabilities.vial_version < protocol::VIAL_PROTOCOL_QMK_SETTINGS {
+            return Ok(HashMap::new());
+        }
+        protocol::load_qmk_settings(&self.device)
+    }
+
+    pub fn set_setting(&self, qsid: u16, value: u32) -> Result<(), Box<dyn std::error::Error>> {
+        protocol::set_qmk_value(&self.device, qsid, value)
+    }
+
+    pub fn rgb(&self) -> Result<RGBInfo, Box<dyn std::error::Error>> {
+        protocol::load_rgb_info(&self.device)
+    }
+
+    /// Applies effect, speed and color of `rgb`, persist makes it survive restart.
+    pub fn set_rgb(&self, rgb: &RGBInfo, persist: bool) -> Result<(), Box<dyn std::error::Error>> {
+        protocol::set_rgb_mode(&self.device, rgb)?;
+        if persist {
+            protocol::persist_rgb(&self.device)?;
+        }
+        Ok(())
+    }
+
+    pub fn layout_options(&self) -> Result<LayoutOptions<'_>, Box<dyn std::error::Error>> {
+        let state = match &self.meta["layouts"]["labels"] {
+            Value::Null => 0,
+            _ => protocol::load_layout_options(&self.device)?,
+        };
+        LayoutOptions::from_json(state, &self.meta["layouts"]["labels"])
+    }
+
+    pub fn set_layout_options(&self, state: u32) -> Result<(), Box<dyn std::error::Error>> {
+        protocol::set_layout_options(&self.device, state)
+    }
+
+    /// Whole configuration, the same content as .vil file has.
+    pub fn snapshot(&self) -> Result<Snapshot, Box<dyn std::error::Error>> {
+        Snapshot::load(&self.device, &self.capabilities, &self.meta)
+    }
+
+    /// Writes configuration, only entries which differ from keyboard state are written.
+    pub fn apply(&self, snapshot: &Snapshot) -> Result<ApplyReport, Box<dyn std::error::Error>> {
+        snapshot.apply(&self.device, &self.capabilities, &self.meta)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::Simulator;
+    use serde_json::json;
+
+    fn meta() -> Value {
+        json!({
+            "name": "test",
+            "matrix": {"rows": 2, "cols": 3},
+            "layouts": {
+                "labels": ["Split Backspace"],
+                "keymap": [
+                    ["0,0", "0,1", "0,2"],
+                    ["1,0", "1,1", "1,2", "0,0\n\n\n\n\n\n\n\n\ne", "0,1\n\n\n\n\n\n\n\n\ne"]
+                ]
+            }
+        })
+    }
+
+    #[test]
+    fn test_keyboard_keys() {
+        let keyboard = Keyboard::new(Simulator::new(&meta()).unwrap()).unwrap();
+        assert_eq!(keyboard.matrix(), (2, 3));
+        assert_eq!(keyboard.meta()["name"], "test");
+        keyboard.set_key(1, 1, 2, "KC_ESC").unwrap();
+        let keymap = keyboard.keymap().unwrap();
+        assert_eq!(
+            keymap
+                .get_long(1, 1, 2, keyboard.capabilities().vial_version)
+                .unwrap(),
+            "KC_ESCAPE"
+        );
+        assert!(keyboard.set_key(1, 2, 0, "KC_ESC").is_err());
+        assert!(keyboard.set_key(0, 0, 0, "KC_UNKNOWN").is_err());
+        assert_eq!(keyboard.encoders().unwrap()[0].len(), 1);
+        assert_eq!(keyboard.layout_options().unwrap().options.len(), 1);
+    }
+
+    #[test]
+    fn test_keyboard_apply_snapshot() {
+        let source = Keyboard::new(Simulator::new(&meta()).unwrap()).unwrap();
+        source.set_key(0, 0, 0, "KC_Q").unwrap();
+        source.set_key(2, 1, 1, "KC_W").unwrap();
+        let mut combos = source.combos().unwrap();
+        combos[0] = Combo::from_string(0, "KC_A+KC_B=KC_ESC", 6).unwrap();
+        source.set_combo(&combos[0]).unwrap();
+        source.set_layout_options(1).unwrap();
+        let snapshot = source.snapshot().unwrap();
+
+        let target = Keyboard::with_meta(Simulator::new(&meta()).unwrap(), meta()).unwrap();
+        let report = target.apply(&snapshot).unwrap();
+        assert_eq!(report.keys, 2);
+        assert_eq!(report.combos, 1);
+        assert!(report.layout_options);
+        assert_eq!(target.snapshot().unwrap().keys, snapshot.keys);
+        assert_eq!(target.combos().unwrap(), combos);
+        assert_eq!(target.apply(&snapshot).unwrap(), ApplyReport::default());
+    }
+}
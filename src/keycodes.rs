@@ -236,6 +236,17 @@ pub fn qid_to_name(keycode: u16, vial_version: u32) -> String {
     }
 }
 
+/// Re-encodes keycode of one Vial protocol version for another one through its name,
+/// returns the name as error if there is no equivalent.
+pub fn convert_qid(keycode: u16, from_version: u32, to_version: u32) -> Result<u16, String> {
+    let name = qid_to_name(keycode, from_version);
+    // unknown keycodes are rendered as hex numbers which mean another keycode in other version
+    if name.contains("0x") {
+        return Err(name);
+    }
+    name_to_qid(&name, to_version).map_err(|_| name)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -319,4 +330,16 @@ mod tests {
         assert_eq!(qid_to_name(0xE1, 5), "KC_LEFT_SHIFT");
         assert_eq!(qid_to_name(0xE5, 5), "KC_RIGHT_SHIFT");
     }
+
+    #[test]
+    fn test_convert_qid() {
+        assert_eq!(convert_qid(0x5f1c, 5, 6), Ok(0x770A));
+        assert_eq!(convert_qid(0x770A, 6, 5), Ok(0x5f1c));
+        assert_eq!(convert_qid(0x0004, 5, 6), Ok(0x0004));
+        assert_eq!(
+            convert_qid(0x7228, 5, 6),
+            Ok(name_to_qid("RSFT_T(KC_ENTER)", 6).unwrap())
+        );
+        assert_eq!(convert_qid(0x52E1, 6, 5), Err("PDF(1)".to_string()));
+    }
 }
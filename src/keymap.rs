@@ -1,11 +1,12 @@
 pub mod buffer;
+pub mod svg;
 
 use crate::protocol;
 use buffer::Buffer;
 use serde_json::Value;
 use std::cmp::max;
-use std::collections::HashMap;
 use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -99,7 +100,6 @@ pub fn keymap_to_buttons(
     let mut y = 0f64;
     let mut x = 0f64;
     let mut decal = false;
-    let mut cluster: (f64, f64) = (0.0, 0.0);
 
     for row in rows.iter() {
         match row.as_array() {
@@ -120,18 +120,8 @@ pub fn keymap_to_buttons(
                                     "w" => w = value.as_f64().ok_or("w should be a number")?,
                                     "h" => h = value.as_f64().ok_or("h should be a number")?,
                                     "r" => r = value.as_f64().ok_or("r should be a number")?,
-                                    "rx" => {
-                                        rx = value.as_f64().ok_or("rx should be a number")?;
-                                        cluster.0 = rx;
-                                        //x = cluster.0;
-                                        //y = cluster.1;
-                                    }
-                                    "ry" => {
-                                        ry = value.as_f64().ok_or("ry should be a number")?;
-                                        cluster.1 = ry;
-                                        //x = cluster.0;
-                                        //y = cluster.1;
-                                    }
+                                    "rx" => rx = value.as_f64().ok_or("rx should be a number")?,
+                                    "ry" => ry = value.as_f64().ok_or("ry should be a number")?,
                                     "d" => decal = value.as_bool().ok_or("d should be bool")?,
                                     &_ => {
                                         // println!("warning ignored value {:?} = {:?}", key, value)
@@ -269,29 +259,25 @@ pub fn keymap_to_buttons(
     let mut deltas = HashMap::new();
     for button in &mut buttons {
         match button.layout_options {
-            Some(option) => {
-                if matches(&via_options, Some(option)) {
-                    match deltas.entry(option.0) {
-                        Entry::Vacant(v) => {
-                            if let Some((def_x, def_y)) = option_groups.get(&option.0) {
-                                //println!("{:?} => {:?}", button, (def_x, def_y));
-                                let dx = button.x - *def_x;
-                                let dy = button.y - *def_y;
-                                button.x = *def_x;
-                                button.y = *def_y;
-                                v.insert_entry((dx, dy));
-                            }
-                        }
-                        Entry::Occupied(o) => {
-                            let (dx, dy) = o.get();
-                            //println!("{:?}", (dx, dy));
-                            button.x -= dx;
-                            button.y -= dy;
-                        }
+            Some(option) if matches(&via_options, Some(option)) => match deltas.entry(option.0) {
+                Entry::Vacant(v) => {
+                    if let Some((def_x, def_y)) = option_groups.get(&option.0) {
+                        //println!("{:?} => {:?}", button, (def_x, def_y));
+                        let dx = button.x - *def_x;
+                        let dy = button.y - *def_y;
+                        button.x = *def_x;
+                        button.y = *def_y;
+                        v.insert_entry((dx, dy));
                     }
                 }
-            }
-            None => {
+                Entry::Occupied(o) => {
+                    let (dx, dy) = o.get();
+                    //println!("{:?}", (dx, dy));
+                    button.x -= dx;
+                    button.y -= dy;
+                }
+            },
+            _ => {
                 //do nothing
             }
         }
@@ -299,7 +285,107 @@ pub fn keymap_to_buttons(
     Ok(buttons)
 }
 
+/// Largest distance in key units between keys considered to be at the same place.
+const SAME_PLACE_DISTANCE: f64 = 0.6;
+
+/// Key centers by wire position, x is measured from the inner edge of the half for split keyboards.
+/// The flag is set for keys of the right half.
+fn key_places(buttons: &[Button], split: bool) -> Vec<((u8, u8), bool, f64, f64)> {
+    let keys: Vec<&Button> = buttons.iter().filter(|b| !b.encoder && !b.decal).collect();
+    let min_y = keys.iter().map(|b| b.y).fold(f64::MAX, f64::min);
+    let min_x = keys.iter().map(|b| b.x).fold(f64::MAX, f64::min);
+    let gap = if split { split_gap(&keys) } else { None };
+    keys.iter()
+        .map(|b| {
+            let cx = b.x + b.w / 2.0;
+            let cy = b.y + b.h / 2.0 - min_y;
+            match gap {
+                Some((_, right_inner)) if b.x >= right_inner => {
+                    ((b.wire_x, b.wire_y), true, cx - right_inner, cy)
+                }
+                Some((left_inner, _)) => ((b.wire_x, b.wire_y), false, cx - left_inner, cy),
+                None => ((b.wire_x, b.wire_y), false, cx - min_x, cy),
+            }
+        })
+        .collect()
+}
+
+/// Inner edges (right edge of left half, left edge of right half) of the widest gap between
+/// key columns, None if there is no gap of at least one key wide.
+fn split_gap(keys: &[&Button]) -> Option<(f64, f64)> {
+    let mut sorted: Vec<&&Button> = keys.iter().collect();
+    sorted.sort_by(|a, b| a.x.total_cmp(&b.x));
+    let mut right_edge = f64::MIN;
+    let mut result: Option<(f64, f64)> = None;
+    for b in sorted {
+        if right_edge > f64::MIN
+            && b.x - right_edge >= 1.0
+            && result.is_none_or(|(left, right)| b.x - right_edge > right - left)
+        {
+            result = Some((right_edge, b.x));
+        }
+        right_edge = right_edge.max(b.x + b.w);
+    }
+    result
+}
+
+/// Keys of layout (without encoders and decals) grouped into physical rows.
+pub fn physical_rows(buttons: &[Button]) -> Vec<Vec<&Button>> {
+    let mut rows: Vec<Vec<&Button>> = Vec::new();
+    let mut last_y = None;
+    for button in buttons.iter().filter(|b| !b.encoder && !b.decal) {
+        if last_y.is_none_or(|y: f64| (button.y - y).abs() > 0.25) {
+            rows.push(Vec::new());
+            last_y = Some(button.y);
+        }
+        if let Some(row) = rows.last_mut() {
+            row.push(button);
+        }
+    }
+    rows
+}
+
+/// Maps wire positions of `from` keyboard to wire positions of `to` keyboard by physical place of keys,
+/// halves of split keyboards are aligned by their inner edges.
+pub fn match_positions(from: &[Button], to: &[Button]) -> HashMap<(u8, u8), (u8, u8)> {
+    let split = {
+        let from_keys: Vec<&Button> = from.iter().filter(|b| !b.encoder && !b.decal).collect();
+        let to_keys: Vec<&Button> = to.iter().filter(|b| !b.encoder && !b.decal).collect();
+        split_gap(&from_keys).is_some() && split_gap(&to_keys).is_some()
+    };
+    let from_places = key_places(from, split);
+    let to_places = key_places(to, split);
+    let mut candidates = Vec::new();
+    for (from_pos, from_right, from_x, from_y) in &from_places {
+        for (to_pos, to_right, to_x, to_y) in &to_places {
+            let distance = (from_x - to_x).hypot(from_y - to_y);
+            if from_right == to_right && distance <= SAME_PLACE_DISTANCE {
+                candidates.push((distance, *from_pos, *to_pos));
+            }
+        }
+    }
+    candidates.sort_by(|a, b| a.0.total_cmp(&b.0));
+    let mut result = HashMap::new();
+    let mut taken = HashSet::new();
+    for (_, from_pos, to_pos) in candidates {
+        if !result.contains_key(&from_pos) && !taken.contains(&to_pos) {
+            result.insert(from_pos, to_pos);
+            taken.insert(to_pos);
+        }
+    }
+    result
+}
+
 pub fn render_and_dump(buttons: &Vec<Button>, labels: Option<HashMap<(u8, u8), String>>) {
+    render_and_dump_highlighted(buttons, labels, &HashSet::new());
+}
+
+/// Same as render_and_dump but keys at wire positions from `highlighted` are drawn with heavy box.
+pub fn render_and_dump_highlighted(
+    buttons: &Vec<Button>,
+    labels: Option<HashMap<(u8, u8), String>>,
+    highlighted: &HashSet<(u8, u8)>,
+) {
     let mut buff = Buffer::new();
     for button in buttons {
         if !button.decal {
@@ -313,22 +399,28 @@ pub fn render_and_dump(buttons: &Vec<Button>, labels: Option<HashMap<(u8, u8), S
                 (b.y + b.h - 1.0).round() as usize,
             );
             if !b.encoder {
-                buff.put(lu.0, lu.1, '╔');
+                let (h, v, tl, tr, bl, br) =
+                    if highlighted.contains(&(button.wire_x, button.wire_y)) {
+                        ('━', '┃', '┏', '┓', '┗', '┛')
+                    } else {
+                        ('═', '║', '╔', '╗', '╚', '╝')
+                    };
+                buff.put(lu.0, lu.1, tl);
                 for x in (lu.0 + 1)..ru.0 {
-                    buff.put(x, lu.1, '═');
+                    buff.put(x, lu.1, h);
                 }
-                buff.put(ru.0, ru.1, '╗');
+                buff.put(ru.0, ru.1, tr);
                 for y in (lu.1 + 1)..lb.1 {
-                    buff.put(lu.0, y, '║');
+                    buff.put(lu.0, y, v);
                 }
                 for y in (ru.1 + 1)..rb.1 {
-                    buff.put(ru.0, y, '║');
+                    buff.put(ru.0, y, v);
                 }
-                buff.put(lb.0, lb.1, '╚');
+                buff.put(lb.0, lb.1, bl);
                 for x in (lb.0 + 1)..rb.0 {
-                    buff.put(x, lb.1, '═');
+                    buff.put(x, lb.1, h);
                 }
-                buff.put(rb.0, rb.1, '╝');
+                buff.put(rb.0, rb.1, br);
             } else {
                 buff.put(lu.0, lu.1, '╭');
                 for x in (lu.0 + 1)..ru.0 {
@@ -413,3 +505,38 @@ pub fn render_and_dump(buttons: &Vec<Button>, labels: Option<HashMap<(u8, u8), S
     }
     buff.dump();
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn buttons(keymap: Value) -> Vec<Button> {
+        keymap_to_buttons(&keymap, &protocol::LayoutOptions::empty()).unwrap()
+    }
+
+    #[test]
+    fn test_match_positions() {
+        // the same physical keys with different wiring
+        let from = buttons(json!([["0,0", "0,1", "0,2"], ["1,0", "1,1", "1,2"]]));
+        let to = buttons(json!([["1,2", "1,1", "1,0"], [{"x": 1}, "0,1", "0,0"]]));
+        let positions = match_positions(&from, &to);
+        assert_eq!(positions[&(0, 0)], (1, 2));
+        assert_eq!(positions[&(0, 2)], (1, 0));
+        assert_eq!(positions[&(1, 1)], (0, 1));
+        assert_eq!(positions[&(1, 2)], (0, 0));
+        assert!(!positions.contains_key(&(1, 0)));
+    }
+
+    #[test]
+    fn test_match_positions_split() {
+        // halves are 1 and 2 keys apart, right half of target has extra outer column
+        let from = buttons(json!([["0,0", "0,1", {"x": 1}, "0,2", "0,3"]]));
+        let to = buttons(json!([["0,0", "0,1", {"x": 2}, "0,2", "0,3", "0,4"]]));
+        let positions = match_positions(&from, &to);
+        assert_eq!(positions.len(), 4);
+        assert_eq!(positions[&(0, 1)], (0, 1));
+        assert_eq!(positions[&(0, 2)], (0, 2));
+        assert_eq!(positions[&(0, 3)], (0, 3));
+    }
+}
@@ -0,0 +1,256 @@
+use super::Button;
+use std::collections::HashMap;
+use std::fmt::Write;
+
+/// Size of 1u key in pixels.
+const UNIT: f64 = 56.0;
+/// Space between neighbour keys.
+const GAP: f64 = 4.0;
+const MARGIN: f64 = 16.0;
+const TITLE_HEIGHT: f64 = 28.0;
+const FONT_SIZE: f64 = 11.0;
+const MIN_FONT_SIZE: f64 = 6.0;
+/// Approximate width of monospace glyph relative to font size.
+const CHAR_WIDTH: f64 = 0.6;
+const LINE_HEIGHT: f64 = 1.2;
+
+const STYLE: &str = "
+    text { font-family: monospace; fill: #222; text-anchor: middle; }
+    .title { font-size: 16px; font-weight: bold; text-anchor: start; }
+    .key { fill: #f4f4f4; stroke: #555; stroke-width: 1; }
+    .encoder { fill: #e4ecf4; stroke: #555; stroke-width: 1; }
+    .decal { fill: none; stroke: #aaa; stroke-width: 1; stroke-dasharray: 3 3; }
+";
+
+/// Labels of one layer drawn on SVG sheet.
+#[derive(Debug, Default)]
+pub struct SvgLayer {
+    pub title: Option<String>,
+    /// Key labels by wire position, lines are separated with '\n'.
+    pub keys: HashMap<(u8, u8), String>,
+    /// Encoder labels by index and direction (0 is counter clockwise, 1 is clockwise).
+    pub encoders: HashMap<(u8, u8), String>,
+}
+
+fn escape(text: &str) -> String {
+    let mut result = String::new();
+    for c in text.chars() {
+        match c {
+            '&' => result.push_str("&amp;"),
+            '<' => result.push_str("&lt;"),
+            '>' => result.push_str("&gt;"),
+            '"' => result.push_str("&quot;"),
+            _ => result.push(c),
+        }
+    }
+    result
+}
+
+/// Splits line into parts not longer than max_chars, breaking after '_', ',', '(' or space
+/// where possible, so KC_ names and keycode functions stay readable.
+fn wrap(line: &str, max_chars: usize) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut token = String::new();
+    for c in line.chars() {
+        token.push(c);
+        if matches!(c, '_' | ',' | '(' | ' ') {
+            tokens.push(std::mem::take(&mut token));
+        }
+    }
+    if !token.is_empty() {
+        tokens.push(token);
+    }
+    let mut result: Vec<String> = Vec::new();
+    let mut current = String::new();
+    for token in tokens {
+        if !current.is_empty() && current.chars().count() + token.chars().count() > max_chars {
+            result.push(std::mem::take(&mut current));
+        }
+        current.push_str(&token);
+    }
+    if !current.is_empty() || result.is_empty() {
+        result.push(current);
+    }
+    result
+}
+
+/// Writes label centered in box, wraps and shrinks it to fit.
+fn write_label(out: &mut String, label: &str, x: f64, y: f64, w: f64, h: f64) {
+    let max_chars = ((w - 6.0) / (FONT_SIZE * CHAR_WIDTH)).floor().max(1.0) as usize;
+    let lines: Vec<String> = label
+        .lines()
+        .flat_map(|line| wrap(line.trim(), max_chars))
+        .collect();
+    let longest = lines.iter().map(|l| l.chars().count()).max().unwrap_or(0);
+    if longest == 0 {
+        return;
+    }
+    let font_size = FONT_SIZE
+        .min((w - 6.0) / (longest as f64 * CHAR_WIDTH))
+        .min((h - 6.0) / (lines.len() as f64 * LINE_HEIGHT))
+        .max(MIN_FONT_SIZE);
+    let line_height = font_size * LINE_HEIGHT;
+    let cx = x + w / 2.0;
+    // baseline of first line, text block is centered vertically
+    let top = y + h / 2.0 - line_height * lines.len() as f64 / 2.0 + font_size;
+    let _ = write!(
+        out,
+        "<text x=\"{:.1}\" y=\"{:.1}\" font-size=\"{:.1}\">",
+        cx, top, font_size
+    );
+    for (i, line) in lines.iter().enumerate() {
+        let _ = write!(
+            out,
+            "<tspan x=\"{:.1}\" y=\"{:.1}\">{}</tspan>",
+            cx,
+            top + i as f64 * line_height,
+            escape(line)
+        );
+    }
+    out.push_str("</text>\n");
+}
+
+/// Renders layers one under another as single SVG document. Buttons are drawn with
+/// their layout geometry, keys without label are left blank, decals are drawn as
+/// dashed outlines without labels.
+pub fn render_svg(buttons: &[Button], layers: &[SvgLayer]) -> String {
+    let (mut min_x, mut min_y) = (f64::MAX, f64::MAX);
+    let (mut max_x, mut max_y) = (0f64, 0f64);
+    for button in buttons {
+        min_x = min_x.min(button.x);
+        min_y = min_y.min(button.y);
+        max_x = max_x.max(button.x + button.w);
+        max_y = max_y.max(button.y + button.h);
+    }
+    if buttons.is_empty() {
+        (min_x, min_y) = (0.0, 0.0);
+    }
+    let board_width = (max_x - min_x) * UNIT;
+    let board_height = (max_y - min_y) * UNIT;
+
+    let mut body = String::new();
+    let mut top = MARGIN;
+    for layer in layers {
+        if let Some(title) = &layer.title {
+            let _ = writeln!(
+                body,
+                "<text class=\"title\" x=\"{:.1}\" y=\"{:.1}\">{}</text>",
+                MARGIN,
+                top + 18.0,
+                escape(title)
+            );
+            top += TITLE_HEIGHT;
+        }
+        for button in buttons {
+            let x = MARGIN + (button.x - min_x) * UNIT + GAP / 2.0;
+            let y = top + (button.y - min_y) * UNIT + GAP / 2.0;
+            let w = button.w * UNIT - GAP;
+            let h = button.h * UNIT - GAP;
+            let (class, radius) = if button.decal {
+                ("decal", 6.0)
+            } else if button.encoder {
+                ("encoder", w.min(h) / 2.0)
+            } else {
+                ("key", 6.0)
+            };
+            let _ = writeln!(
+                body,
+                "<rect class=\"{}\" x=\"{:.1}\" y=\"{:.1}\" width=\"{:.1}\" height=\"{:.1}\" rx=\"{:.1}\"/>",
+                class, x, y, w, h, radius
+            );
+            if button.decal {
+                continue;
+            }
+            let label = if button.encoder {
+                let arrow = match button.wire_y {
+                    0 => '↺',
+                    1 => '↻',
+                    _ => 'x',
+                };
+                let name = layer
+                    .encoders
+                    .get(&(button.wire_x, button.wire_y))
+                    .map(|l| l.as_str())
+                    .unwrap_or_default();
+                format!("{}{}\n{}", button.wire_x, arrow, name)
+            } else {
+                layer
+                    .keys
+                    .get(&(button.wire_x, button.wire_y))
+                    .cloned()
+                    .unwrap_or_default()
+            };
+            write_label(&mut body, &label, x, y, w, h);
+        }
+        top += board_height + MARGIN;
+    }
+
+    let width = board_width + 2.0 * MARGIN;
+    let height = top;
+    let mut out = String::new();
+    let _ = writeln!(
+        out,
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{0:.0}\" height=\"{1:.0}\" viewBox=\"0 0 {0:.0} {1:.0}\">",
+        width, height
+    );
+    let _ = writeln!(out, "<style>{}</style>", STYLE);
+    let _ = writeln!(out, "<rect width=\"100%\" height=\"100%\" fill=\"white\"/>");
+    out.push_str(&body);
+    out.push_str("</svg>\n");
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keymap::keymap_to_buttons;
+    use crate::protocol;
+    use serde_json::json;
+
+    #[test]
+    fn test_wrap() {
+        assert_eq!(wrap("KC_A", 7), vec!["KC_A"]);
+        assert_eq!(
+            wrap("QK_BACKLIGHT_STEP", 7),
+            vec!["QK_", "BACKLIGHT_", "STEP"]
+        );
+        assert_eq!(wrap("LT(1,KC_SPACE)", 7), vec!["LT(1,", "KC_", "SPACE)"]);
+        assert_eq!(wrap("", 7), vec![""]);
+    }
+
+    #[test]
+    fn test_render_svg() {
+        let buttons = keymap_to_buttons(
+            &json!([
+                ["0,0", {"w": 2}, "0,1", {"d": true}, "0,2"],
+                ["0,0\n\n\n\n\n\n\n\n\ne", "0,1\n\n\n\n\n\n\n\n\ne"]
+            ]),
+            &protocol::LayoutOptions::empty(),
+        )
+        .unwrap();
+        let mut base = SvgLayer {
+            title: Some("Base & <fn>".to_string()),
+            ..Default::default()
+        };
+        base.keys.insert((0, 0), "KC_ESCAPE".to_string());
+        base.keys.insert((0, 1), "Custom\nname".to_string());
+        base.encoders.insert((0, 0), "KC_VOLD".to_string());
+        base.encoders.insert((0, 1), "KC_VOLU".to_string());
+        let svg = render_svg(&buttons, &[base, SvgLayer::default()]);
+        assert!(
+            svg.starts_with(
+                "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"256\" height=\"300\""
+            )
+        );
+        assert!(svg.ends_with("</svg>\n"));
+        assert!(svg.contains(">Base &amp; &lt;fn&gt;</text>"));
+        assert_eq!(svg.matches("class=\"title\"").count(), 1);
+        assert_eq!(svg.matches("class=\"key\"").count(), 4);
+        assert_eq!(svg.matches("class=\"encoder\"").count(), 4);
+        assert_eq!(svg.matches("class=\"decal\"").count(), 2);
+        assert!(svg.contains(">KC_</tspan><tspan x=\"44.0\" y=\"83.0\">ESCAPE</tspan>"));
+        assert!(svg.contains(">Custom</tspan><tspan"));
+        assert!(svg.contains(">0↺</tspan><tspan x=\"44.0\" y=\"132.4\">KC_</tspan>"));
+        assert!(svg.contains(">0↻</tspan>"));
+    }
+}
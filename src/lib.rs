@@ -1,3 +1,5 @@
+pub mod export;
+pub mod keyboard;
 pub mod keycodes;
 pub mod keymap;
 pub mod protocol;
@@ -2,7 +2,11 @@ extern crate hidapi;
 
 use argh::FromArgs;
 use hidapi::HidApi;
+use serde_json::{Value, json};
+use std::fs::{self, File};
+use std::io::BufReader;
 
+mod export;
 mod keycodes;
 mod keymap;
 mod protocol;
@@ -11,17 +15,79 @@ mod commands;
 
 mod common;
 
+mod backups;
+
+mod config;
+
+mod meta_cache;
+
 /// VIA/Vial HID USB cli tool
 #[derive(FromArgs)]
 struct VialClient {
+    /// device alias defined in config file
+    #[argh(option, short = 'd')]
+    device: Option<String>,
+
     /// device product id
     #[argh(option, short = 'i')]
     id: Option<u16>,
 
+    /// device vendor id
+    #[argh(option)]
+    vid: Option<u16>,
+
+    /// device usb id in lsusb format vid:pid (hex)
+    #[argh(option)]
+    usb_id: Option<String>,
+
+    /// device serial number
+    #[argh(option)]
+    serial: Option<String>,
+
+    /// device HID path
+    #[argh(option)]
+    path: Option<String>,
+
+    /// device product name, * and ? wildcards are allowed
+    #[argh(option)]
+    name: Option<String>,
+
+    /// keyboard Vial uid (decimal or 0x prefixed hex)
+    #[argh(option)]
+    uid: Option<String>,
+
+    /// run command on the first matching device only, otherwise write commands refuse to run if several devices match
+    #[argh(switch)]
+    first: bool,
+
     /// show vitaly version
     #[argh(switch, short = 'v')]
     version: bool,
 
+    /// simulate keyboard described by vial.json file instead of using connected devices
+    #[argh(option)]
+    simulate: Option<String>,
+
+    /// layout file (.vil) to seed simulated keyboard with
+    #[argh(option)]
+    simulate_config: Option<String>,
+
+    /// write every request and response into trace file (json lines)
+    #[argh(option)]
+    record: Option<String>,
+
+    /// replay trace file written with --record instead of using connected devices
+    #[argh(option)]
+    replay: Option<String>,
+
+    /// ignore cached keyboard definition and load it from keyboard again
+    #[argh(switch)]
+    refresh_meta: bool,
+
+    /// output format of read commands: text or json
+    #[argh(option)]
+    output: Option<common::OutputFormat>,
+
     /// command to run
     #[argh(subcommand)]
     command: Option<CommandEnum>,
@@ -42,9 +108,19 @@ enum CommandEnum {
     KeyOverrides(CommandKeyOverrides),
     AltRepeats(CommandAltRepeats),
     Load(CommandLoad),
+    Check(CommandCheck),
+    Migrate(CommandMigrate),
+    Convert(CommandConvert),
+    Reformat(CommandReformat),
+    RenderConfig(CommandRenderConfig),
+    Import(CommandImport),
+    Rollback(CommandRollback),
     Save(CommandSave),
     Rgb(commands::CommandRgb),
     Layout(CommandLayout),
+    Diff(CommandDiff),
+    Meta(commands::CommandMeta),
+    Export(commands::CommandExport),
 }
 
 #[derive(FromArgs, PartialEq, Debug)]
@@ -73,6 +149,10 @@ struct CommandLock {
 /// Combos operations
 #[argh(subcommand, name = "combos")]
 struct CommandCombos {
+    /// meta file (required to edit layout file)
+    #[argh(option, short = 'm')]
+    meta: Option<String>,
+
     /// combo number
     #[argh(option, short = 'n')]
     number: Option<u8>,
@@ -80,12 +160,20 @@ struct CommandCombos {
     /// value expression in format KEY_1 + KEY_2 + KEY_3 + KEY_4 = KEY_5
     #[argh(option, short = 'v')]
     value: Option<String>,
+
+    /// layout file (.vil) to edit instead of connected keyboard
+    #[argh(option, short = 'f')]
+    file: Option<String>,
 }
 
 #[derive(FromArgs, PartialEq, Debug)]
 /// Macros operations
 #[argh(subcommand, name = "macros")]
 struct CommandMacros {
+    /// meta file (required to edit layout file)
+    #[argh(option, short = 'm')]
+    meta: Option<String>,
+
     /// macro number
     #[argh(option, short = 'n')]
     number: Option<u8>,
@@ -93,12 +181,20 @@ struct CommandMacros {
     /// value expression in format Text(some text); Tap(KC_1); Down(KC_D); Up(KC_D)
     #[argh(option, short = 'v')]
     value: Option<String>,
+
+    /// layout file (.vil) to edit instead of connected keyboard
+    #[argh(option, short = 'f')]
+    file: Option<String>,
 }
 
 #[derive(FromArgs, PartialEq, Debug)]
 /// TapDance operations
 #[argh(subcommand, name = "tapdances")]
 struct CommandTapDances {
+    /// meta file (required to edit layout file)
+    #[argh(option, short = 'm')]
+    meta: Option<String>,
+
     /// tap dance number
     #[argh(option, short = 'n')]
     number: Option<u8>,
@@ -106,12 +202,20 @@ struct CommandTapDances {
     /// value expression in format TAP_KEY + HOLD_KEY + DOUBLE_TAP_KEY + TAPHOLD_KEY ~ TAPPING_TERM_MS
     #[argh(option, short = 'v')]
     value: Option<String>,
+
+    /// layout file (.vil) to edit instead of connected keyboard
+    #[argh(option, short = 'f')]
+    file: Option<String>,
 }
 
 #[derive(FromArgs, PartialEq, Debug)]
 /// KeyOverride operations
 #[argh(subcommand, name = "keyoverrides")]
 struct CommandKeyOverrides {
+    /// meta file (required to edit layout file)
+    #[argh(option, short = 'm')]
+    meta: Option<String>,
+
     /// tap dance number
     #[argh(option, short = 'n')]
     number: Option<u8>,
@@ -119,12 +223,20 @@ struct CommandKeyOverrides {
     /// value expression in format trigger=KC_1; replacement=KC_2; layers=1|2|3; trigger_mods=LS|RS; negative_mod_mask=LC|RC; suppressed_mods =LGUI|RGUI; options=ko_enabled|ko_option_activation_trigger_down
     #[argh(option, short = 'v')]
     value: Option<String>,
+
+    /// layout file (.vil) to edit instead of connected keyboard
+    #[argh(option, short = 'f')]
+    file: Option<String>,
 }
 
 #[derive(FromArgs, PartialEq, Debug)]
 /// AltRepeat operations
 #[argh(subcommand, name = "altrepeats")]
 struct CommandAltRepeats {
+    /// meta file (required to edit layout file)
+    #[argh(option, short = 'm')]
+    meta: Option<String>,
+
     /// alt repeat number
     #[argh(option, short = 'n')]
     number: Option<u8>,
@@ -132,6 +244,10 @@ struct CommandAltRepeats {
     /// value expression in format keycode=KC_1; alt_keycode=KC_2; allowed_mods=LS; options=arep_enabled   
     #[argh(option, short = 'v')]
     value: Option<String>,
+
+    /// layout file (.vil) to edit instead of connected keyboard
+    #[argh(option, short = 'f')]
+    file: Option<String>,
 }
 
 #[derive(FromArgs, PartialEq, Debug)]
@@ -153,6 +269,22 @@ struct CommandLayers {
     /// override layout options
     #[argh(option, short = 'o')]
     options: Option<String>,
+
+    /// layout file (.vil) to edit instead of connected keyboard
+    #[argh(option, short = 'f')]
+    file: Option<String>,
+
+    /// show all layers
+    #[argh(switch, short = 'a')]
+    all: bool,
+
+    /// write layers into svg file
+    #[argh(option)]
+    svg: Option<String>,
+
+    /// title of layer in svg file, repeated for each layer, empty title is not drawn
+    #[argh(option)]
+    title: Vec<String>,
 }
 
 #[derive(FromArgs, PartialEq, Debug)]
@@ -174,12 +306,20 @@ struct CommandKeys {
     /// key value
     #[argh(option, short = 'v')]
     value: Option<String>,
+
+    /// layout file (.vil) to edit instead of connected keyboard
+    #[argh(option, short = 'f')]
+    file: Option<String>,
 }
 
 #[derive(FromArgs, PartialEq, Debug)]
 /// Settings operations
 #[argh(subcommand, name = "settings")]
 struct CommandSettings {
+    /// meta file (required to edit layout file)
+    #[argh(option, short = 'm')]
+    meta: Option<String>,
+
     /// setting identifier
     #[argh(option, short = 'q')]
     qsid: Option<f64>,
@@ -191,6 +331,10 @@ struct CommandSettings {
     /// reset all settings into default values
     #[argh(switch, short = 'r')]
     reset: bool,
+
+    /// layout file (.vil) to edit instead of connected keyboard
+    #[argh(option, short = 'f')]
+    file: Option<String>,
 }
 
 #[derive(FromArgs, PartialEq, Debug)]
@@ -203,11 +347,166 @@ struct CommandLoad {
 
     /// path to layout file
     #[argh(option, short = 'f')]
-    file: String,
+    file: Option<String>,
 
     /// preview content of layout file instead of loading into keyboard
     #[argh(switch, short = 'p')]
     preview: bool,
+
+    /// load only listed sections, comma separated: keys, layout, encoders, combos, macros, tapdances, keyoverrides, altrepeats, settings; settings are loaded only when listed here
+    #[argh(option)]
+    only: Option<String>,
+
+    /// skip listed sections, comma separated
+    #[argh(option)]
+    except: Option<String>,
+
+    /// don't save current configuration before loading
+    #[argh(switch)]
+    no_backup: bool,
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// Check configuration file against keyboard without loading it
+#[argh(subcommand, name = "check")]
+struct CommandCheck {
+    /// meta file, keyboard isn't required when it is passed
+    #[argh(option, short = 'm')]
+    meta: Option<String>,
+
+    /// path to layout file
+    #[argh(option, short = 'f')]
+    file: Option<String>,
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// Move layout file to keyboard with another matrix
+#[argh(subcommand, name = "migrate")]
+struct CommandMigrate {
+    /// layout file of old keyboard
+    #[argh(option)]
+    from: String,
+
+    /// meta file of old keyboard
+    #[argh(option)]
+    from_meta: String,
+
+    /// meta file of new keyboard
+    #[argh(option)]
+    to_meta: String,
+
+    /// path to write layout file for new keyboard, only report is shown without it
+    #[argh(option)]
+    to: Option<String>,
+
+    /// explicit mapping of old key position to new one as 'row,col=row,col', could be repeated
+    #[argh(option)]
+    map: Vec<String>,
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// Convert layout file keycodes to another Vial protocol version
+#[argh(subcommand, name = "convert")]
+struct CommandConvert {
+    /// meta file (to use instead of vial meta), it is needed only for .toml files
+    #[argh(option, short = 'm')]
+    meta: Option<String>,
+
+    /// layout file to convert
+    #[argh(option)]
+    from: String,
+
+    /// path to write converted layout file
+    #[argh(option)]
+    to: String,
+
+    /// vial protocol of layout file, vial_protocol from file is used by default
+    #[argh(option)]
+    from_protocol: Option<u32>,
+
+    /// vial protocol to convert keycodes to
+    #[argh(option)]
+    protocol: u32,
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// Convert layout file between .vil and human editable .toml formats
+#[argh(subcommand, name = "reformat")]
+struct CommandReformat {
+    /// meta file (to use instead of vial meta), keyboard isn't required with it
+    #[argh(option, short = 'm')]
+    meta: Option<String>,
+
+    /// layout file to convert, format is chosen by extension
+    #[argh(option)]
+    from: String,
+
+    /// path to write converted layout file, .toml for human editable format
+    #[argh(option)]
+    to: String,
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// Print layout file merged with files it extends
+#[argh(subcommand, name = "render-config")]
+struct CommandRenderConfig {
+    /// meta file (to use instead of vial meta), keyboard isn't required with it
+    #[argh(option, short = 'm')]
+    meta: Option<String>,
+
+    /// path to layout file
+    #[argh(option, short = 'f')]
+    file: Option<String>,
+
+    /// path to write merged layout file instead of printing it
+    #[argh(option)]
+    to: Option<String>,
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// Import QMK Configurator keymap.json
+#[argh(subcommand, name = "import")]
+struct CommandImport {
+    /// meta file (to use instead of vial meta), keyboard isn't required with it and --to
+    #[argh(option, short = 'm')]
+    meta: Option<String>,
+
+    /// path to keymap.json
+    #[argh(option, short = 'f')]
+    file: String,
+
+    /// path to write layout file instead of loading into keyboard
+    #[argh(option)]
+    to: Option<String>,
+
+    /// vial protocol of layout file written without keyboard, 6 by default
+    #[argh(option)]
+    protocol: Option<u32>,
+
+    /// preview imported layout instead of loading into keyboard
+    #[argh(switch, short = 'p')]
+    preview: bool,
+
+    /// don't save current configuration before loading
+    #[argh(switch)]
+    no_backup: bool,
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// Restore configuration saved before the last load
+#[argh(subcommand, name = "rollback")]
+struct CommandRollback {
+    /// meta file (to use instead of vial meta)
+    #[argh(option, short = 'm')]
+    meta: Option<String>,
+
+    /// backup file to restore instead of the latest one
+    #[argh(option, short = 'f')]
+    file: Option<String>,
+
+    /// list backups of keyboard
+    #[argh(switch, short = 'l')]
+    list: bool,
 }
 
 #[derive(FromArgs, PartialEq, Debug)]
@@ -220,7 +519,15 @@ struct CommandSave {
 
     /// path to layout file
     #[argh(option, short = 'f')]
-    file: String,
+    file: Option<String>,
+
+    /// save only listed sections, comma separated: keys, layout, encoders, combos, macros, tapdances, keyoverrides, altrepeats, settings
+    #[argh(option)]
+    only: Option<String>,
+
+    /// skip listed sections, comma separated
+    #[argh(option)]
+    except: Option<String>,
 }
 
 #[derive(FromArgs, PartialEq, Debug)]
@@ -240,10 +547,35 @@ struct CommandLayout {
     value: Option<u8>,
 }
 
+#[derive(FromArgs, PartialEq, Debug)]
+/// Compare configurations of device and layout files
+#[argh(subcommand, name = "diff")]
+struct CommandDiff {
+    /// meta file (to use instead of vial meta)
+    #[argh(option, short = 'm')]
+    meta: Option<String>,
+
+    /// render changed layers with changed keys highlighted
+    #[argh(switch, short = 'r')]
+    render: bool,
+
+    /// source to compare from, path to layout file or "device"
+    #[argh(positional)]
+    from: String,
+
+    /// source to compare to, path to layout file or "device"
+    #[argh(positional)]
+    to: String,
+}
+
 #[derive(FromArgs, PartialEq, Debug)]
 /// Encoders operations
 #[argh(subcommand, name = "encoders")]
 struct CommandEncoders {
+    /// meta file (required to edit layout file)
+    #[argh(option, short = 'm')]
+    meta: Option<String>,
+
     /// elncoder layer
     #[argh(option, short = 'l')]
     layer: u8,
@@ -255,113 +587,760 @@ struct CommandEncoders {
     /// encoder value
     #[argh(option, short = 'v')]
     value: Option<String>,
+
+    /// layout file (.vil) to edit instead of connected keyboard
+    #[argh(option, short = 'f')]
+    file: Option<String>,
+}
+
+fn required_file(file: &Option<String>) -> Result<&String, common::CommandError> {
+    file.as_ref().ok_or(common::CommandError(
+        "layout file should be passed with -f or set for device in config file".to_string(),
+    ))
+}
+
+fn run_command(
+    dev: &impl protocol::Transport,
+    meta_cache: Option<&meta_cache::MetaCache>,
+    backups: Option<&backups::Backups>,
+    command: &CommandEnum,
+    output: &common::Output,
+) -> Result<(), Box<dyn std::error::Error>> {
+    match command {
+        CommandEnum::Devices(ops) => commands::devices_run(dev, ops.capabilities, output),
+        CommandEnum::Lock(ops) => commands::lock_run(dev, meta_cache, ops.unlock, ops.lock, output),
+        CommandEnum::Combos(ops) => commands::combos_run(dev, ops.number, &ops.value, output),
+        CommandEnum::Macros(ops) => commands::macros_run(dev, ops.number, &ops.value, output),
+        CommandEnum::TapDances(ops) => commands::tapdances_run(dev, ops.number, &ops.value, output),
+        CommandEnum::KeyOverrides(ops) => {
+            commands::keyoverrides_run(dev, ops.number, &ops.value, output)
+        }
+        CommandEnum::AltRepeats(ops) => {
+            commands::altrepeats_run(dev, ops.number, &ops.value, output)
+        }
+        CommandEnum::Layers(ops) => commands::layers_run(
+            dev,
+            &ops.meta,
+            meta_cache,
+            ops.positions,
+            ops.number,
+            &ops.options,
+            ops.all,
+            &ops.svg,
+            &ops.title,
+            output,
+        ),
+        CommandEnum::Keys(ops) => commands::keys_run(
+            dev,
+            &ops.meta,
+            meta_cache,
+            ops.layer,
+            &ops.position,
+            &ops.value,
+            output,
+        ),
+        CommandEnum::Encoders(ops) => {
+            commands::encoders_run(dev, ops.layer, &ops.position, &ops.value, output)
+        }
+        CommandEnum::Settings(ops) => {
+            commands::settings_run(dev, &ops.qsid, &ops.value, ops.reset, output)
+        }
+        CommandEnum::Load(ops) => {
+            let mut sections = protocol::Section::select(&ops.only, &ops.except)?;
+            if ops.only.is_none() {
+                // QMK settings are written only on explicit request
+                sections.remove(&protocol::Section::Settings);
+            }
+            commands::load_run(
+                dev,
+                &ops.meta,
+                meta_cache,
+                required_file(&ops.file)?,
+                ops.preview,
+                &sections,
+                backups.filter(|_| !ops.no_backup),
+            )
+        }
+        CommandEnum::Check(ops) => commands::check_run(
+            Some(dev),
+            &ops.meta,
+            meta_cache,
+            required_file(&ops.file)?,
+            output,
+        ),
+        CommandEnum::Rollback(ops) => {
+            commands::rollback_run(dev, &ops.meta, meta_cache, backups, &ops.file, ops.list)
+        }
+        CommandEnum::Migrate(ops) => {
+            commands::migrate_run(&ops.from, &ops.from_meta, &ops.to_meta, &ops.to, &ops.map)
+        }
+        CommandEnum::Convert(ops) => commands::convert_run(
+            Some(dev),
+            &ops.meta,
+            meta_cache,
+            &ops.from,
+            &ops.to,
+            ops.from_protocol,
+            ops.protocol,
+        ),
+        CommandEnum::Reformat(ops) => {
+            commands::reformat_run(Some(dev), &ops.meta, meta_cache, &ops.from, &ops.to)
+        }
+        CommandEnum::RenderConfig(ops) => commands::render_config_run(
+            Some(dev),
+            &ops.meta,
+            meta_cache,
+            required_file(&ops.file)?,
+            &ops.to,
+        ),
+        CommandEnum::Import(ops) => commands::import_run(
+            Some(dev),
+            &ops.meta,
+            meta_cache,
+            &ops.file,
+            &ops.to,
+            ops.protocol,
+            ops.preview,
+            backups.filter(|_| !ops.no_backup),
+        ),
+        CommandEnum::Save(ops) => commands::save_run(
+            dev,
+            &ops.meta,
+            meta_cache,
+            required_file(&ops.file)?,
+            &protocol::Section::select(&ops.only, &ops.except)?,
+        ),
+        CommandEnum::Rgb(ops) => commands::rgb_run(dev, ops, output),
+        CommandEnum::Layout(ops) => {
+            commands::layout_run(dev, &ops.meta, meta_cache, &ops.option, &ops.value)
+        }
+        CommandEnum::Diff(ops) => commands::diff_run(
+            Some(dev),
+            &ops.meta,
+            meta_cache,
+            &ops.from,
+            &ops.to,
+            ops.render,
+        ),
+        CommandEnum::Meta(ops) => commands::meta_run(dev, meta_cache, ops, output),
+        CommandEnum::Export(ops) => commands::export_run(Some(dev), meta_cache, ops),
+    }
 }
 
-fn command_for_devices(id: Option<u16>, command: &CommandEnum) {
-    match HidApi::new() {
-        Ok(api) => {
-            let mut found = false;
-            for device in api.device_list() {
-                if device.usage_page() == protocol::USAGE_PAGE
-                    && device.usage() == protocol::USAGE_ID
-                    && (id.is_none() || id.unwrap() == device.product_id())
-                {
-                    found = true;
-                    println!(
-                        "Product name: {:?} id: {:?},\nManufacturer name: {:?}, id: {:?},\nRelease: {:?}, Serial: {:?}, Path: {:?}",
-                        device.product_string().unwrap(),
-                        device.product_id(),
-                        device.manufacturer_string().unwrap(),
-                        device.vendor_id(),
-                        device.release_number(),
-                        device.serial_number().unwrap(),
-                        device.path(),
-                    );
-                    let result = match command {
-                        CommandEnum::Devices(ops) => {
-                            commands::devices_run(&api, device, ops.capabilities)
-                        }
-                        CommandEnum::Lock(ops) => {
-                            commands::lock_run(&api, device, ops.unlock, ops.lock)
-                        }
-                        CommandEnum::Combos(ops) => {
-                            commands::combos_run(&api, device, ops.number, &ops.value)
-                        }
-                        CommandEnum::Macros(ops) => {
-                            commands::macros_run(&api, device, ops.number, &ops.value)
-                        }
-                        CommandEnum::TapDances(ops) => {
-                            commands::tapdances_run(&api, device, ops.number, &ops.value)
-                        }
-                        CommandEnum::KeyOverrides(ops) => {
-                            commands::keyoverrides_run(&api, device, ops.number, &ops.value)
-                        }
-                        CommandEnum::AltRepeats(ops) => {
-                            commands::altrepeats_run(&api, device, ops.number, &ops.value)
-                        }
-                        CommandEnum::Layers(ops) => commands::layers_run(
-                            &api,
-                            device,
-                            &ops.meta,
-                            ops.positions,
-                            ops.number,
-                            &ops.options,
-                        ),
-                        CommandEnum::Keys(ops) => commands::keys_run(
-                            &api,
-                            device,
-                            &ops.meta,
-                            ops.layer,
-                            &ops.position,
-                            &ops.value,
-                        ),
-                        CommandEnum::Encoders(ops) => commands::encoders_run(
-                            &api,
-                            device,
-                            ops.layer,
-                            &ops.position,
-                            &ops.value,
-                        ),
-                        CommandEnum::Settings(ops) => {
-                            commands::settings_run(&api, device, &ops.qsid, &ops.value, ops.reset)
-                        }
-                        CommandEnum::Load(ops) => {
-                            commands::load_run(&api, device, &ops.meta, &ops.file, ops.preview)
-                        }
-                        CommandEnum::Save(ops) => {
-                            commands::save_run(&api, device, &ops.meta, &ops.file)
-                        }
-                        CommandEnum::Rgb(ops) => commands::rgb_run(&api, device, ops),
-                        CommandEnum::Layout(ops) => {
-                            commands::layout_run(&api, device, &ops.meta, &ops.option, &ops.value)
-                        }
-                    };
-                    match result {
-                        Ok(_) => {
-                            // nothing here
-                        }
-                        Err(e) => {
-                            eprintln!("Error: {}", e)
-                        }
-                    }
+fn run_traced(
+    dev: &impl protocol::Transport,
+    meta_cache: Option<&meta_cache::MetaCache>,
+    backups: Option<&backups::Backups>,
+    trace: &Option<File>,
+    command: &CommandEnum,
+    output: &common::Output,
+) -> Result<(), Box<dyn std::error::Error>> {
+    match trace {
+        Some(trace) => run_command(
+            &protocol::Recorder::new(dev, trace),
+            meta_cache,
+            backups,
+            command,
+            output,
+        ),
+        None => run_command(dev, meta_cache, backups, command, output),
+    }
+}
+
+/// Runs commands which don't touch keyboard at all, returns None if device is required.
+fn command_without_device(
+    command: &CommandEnum,
+    output: &common::Output,
+) -> Option<Result<(), Box<dyn std::error::Error>>> {
+    match command {
+        CommandEnum::Meta(commands::CommandMeta {
+            command:
+                commands::MetaCommandEnum::Info(commands::CommandMetaInfo {
+                    meta: Some(meta_file),
+                }),
+        }) => Some(commands::meta_info_file(meta_file, output)),
+        CommandEnum::Export(
+            ops @ commands::CommandExport {
+                command:
+                    commands::ExportCommandEnum::QmkC(commands::CommandExportQmkC {
+                        meta: Some(_),
+                        from: Some(_),
+                        ..
+                    })
+                    | commands::ExportCommandEnum::Zmk(commands::CommandExportZmk {
+                        meta: Some(_),
+                        from: Some(_),
+                        ..
+                    }),
+            },
+        ) => Some(commands::export_run::<hidapi::HidDevice>(None, None, ops)),
+        CommandEnum::Check(CommandCheck {
+            meta: meta @ Some(_),
+            file,
+        }) => Some(match required_file(file) {
+            Ok(file) => commands::check_run::<hidapi::HidDevice>(None, meta, None, file, output),
+            Err(e) => Err(e.into()),
+        }),
+        CommandEnum::Import(ops) if ops.meta.is_some() && ops.to.is_some() => {
+            Some(commands::import_run::<hidapi::HidDevice>(
+                None,
+                &ops.meta,
+                None,
+                &ops.file,
+                &ops.to,
+                ops.protocol,
+                ops.preview,
+                None,
+            ))
+        }
+        CommandEnum::Reformat(ops) if ops.meta.is_some() => {
+            Some(commands::reformat_run::<hidapi::HidDevice>(
+                None, &ops.meta, None, &ops.from, &ops.to,
+            ))
+        }
+        CommandEnum::RenderConfig(ops) if ops.meta.is_some() => {
+            Some(match required_file(&ops.file) {
+                Ok(file) => commands::render_config_run::<hidapi::HidDevice>(
+                    None, &ops.meta, None, file, &ops.to,
+                ),
+                Err(e) => Err(e.into()),
+            })
+        }
+        CommandEnum::Convert(ops)
+            if ops.meta.is_some() || !commands::convert_needs_meta(&ops.from, &ops.to) =>
+        {
+            Some(commands::convert_run::<hidapi::HidDevice>(
+                None,
+                &ops.meta,
+                None,
+                &ops.from,
+                &ops.to,
+                ops.from_protocol,
+                ops.protocol,
+            ))
+        }
+        CommandEnum::Migrate(ops) => Some(commands::migrate_run(
+            &ops.from,
+            &ops.from_meta,
+            &ops.to_meta,
+            &ops.to,
+            &ops.map,
+        )),
+        CommandEnum::Diff(ops)
+            if ops.from != commands::DEVICE_SOURCE && ops.to != commands::DEVICE_SOURCE =>
+        {
+            Some(commands::diff_run::<hidapi::HidDevice>(
+                None, &ops.meta, None, &ops.from, &ops.to, ops.render,
+            ))
+        }
+        _ => None,
+    }
+}
+
+/// Vial keyboard uid of device, None if device can't be opened or doesn't report uid.
+fn device_uid(api: &HidApi, device: &hidapi::DeviceInfo) -> Option<u64> {
+    match api.open_path(device.path()) {
+        Ok(dev) => protocol::load_uid(&dev).ok(),
+        Err(_) => None,
+    }
+}
+
+/// Commands which change keyboard state.
+fn is_write(command: &CommandEnum) -> bool {
+    match command {
+        CommandEnum::Devices(_)
+        | CommandEnum::Layers(_)
+        | CommandEnum::Save(_)
+        | CommandEnum::Check(_)
+        | CommandEnum::Migrate(_)
+        | CommandEnum::Convert(_)
+        | CommandEnum::Reformat(_)
+        | CommandEnum::RenderConfig(_)
+        | CommandEnum::Diff(_)
+        | CommandEnum::Meta(_)
+        | CommandEnum::Export(_) => false,
+        CommandEnum::Lock(ops) => ops.unlock || ops.lock,
+        CommandEnum::Keys(ops) => ops.value.is_some(),
+        CommandEnum::Encoders(ops) => ops.value.is_some(),
+        CommandEnum::Combos(ops) => ops.value.is_some(),
+        CommandEnum::Macros(ops) => ops.value.is_some(),
+        CommandEnum::TapDances(ops) => ops.value.is_some(),
+        CommandEnum::KeyOverrides(ops) => ops.value.is_some(),
+        CommandEnum::AltRepeats(ops) => ops.value.is_some(),
+        CommandEnum::Settings(ops) => ops.value.is_some() || ops.reset,
+        CommandEnum::Load(ops) => !ops.preview,
+        CommandEnum::Import(ops) => ops.to.is_none() && !ops.preview,
+        CommandEnum::Rollback(ops) => !ops.list,
+        CommandEnum::Rgb(ops) => {
+            ops.effect.is_some()
+                || ops.speed.is_some()
+                || ops.brightness.is_some()
+                || ops.color.is_some()
+                || ops.persist
+                || ops.direct.is_some()
+        }
+        CommandEnum::Layout(ops) => ops.value.is_some(),
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn command_for_devices(
+    filter: &common::DeviceFilter,
+    uid: Option<u64>,
+    first: bool,
+    meta_cache: Option<&meta_cache::MetaCache>,
+    backups: Option<&backups::Backups>,
+    trace: &Option<File>,
+    command: &CommandEnum,
+    output: &common::Output,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let api = HidApi::new()?;
+    let mut devices = Vec::new();
+    for device in api.device_list() {
+        if device.usage_page() == protocol::USAGE_PAGE
+            && device.usage() == protocol::USAGE_ID
+            && filter.matches(device)
+            && uid.is_none_or(|uid| device_uid(&api, device) == Some(uid))
+        {
+            devices.push(device);
+        }
+    }
+    if devices.is_empty() {
+        return Err(common::CommandError("No matching devices found".to_string()).into());
+    }
+    if first {
+        devices.truncate(1);
+    } else if devices.len() > 1 && is_write(command) {
+        let mut names = Vec::new();
+        for device in &devices {
+            names.push(format!(
+                "{:?} ({:04x}:{:04x}, {:?})",
+                device.product_string().unwrap_or_default(),
+                device.vendor_id(),
+                device.product_id(),
+                device.path(),
+            ));
+        }
+        return Err(common::CommandError(format!(
+            "{} devices match: {}, select one device or pass --first",
+            devices.len(),
+            names.join(", ")
+        ))
+        .into());
+    }
+
+    // with json output results of several devices are printed as one array
+    let several = devices.len() > 1;
+    let mut listing = Vec::new();
+    for device in devices {
+        let mut entry = json!({
+            "product_name": device.product_string(),
+            "product_id": device.product_id(),
+            "manufacturer_name": device.manufacturer_string(),
+            "vendor_id": device.vendor_id(),
+            "release": device.release_number(),
+            "serial": device.serial_number(),
+            "path": device.path().to_string_lossy(),
+        });
+        if let (CommandEnum::Devices(ops), common::OutputFormat::Json) = (command, output.format) {
+            if ops.capabilities {
+                match api.open_path(device.path()) {
+                    Ok(dev) => match protocol::scan_capabilities(&dev) {
+                        Ok(capabilities) => entry["capabilities"] = capabilities.to_json(),
+                        Err(e) => eprintln!("Error: {}", e),
+                    },
+                    Err(e) => eprintln!("Error: {}", e),
                 }
             }
-            if !found {
-                eprintln!("No matching devices found");
+            listing.push(entry);
+            continue;
+        }
+        if output.format == common::OutputFormat::Text {
+            println!(
+                "Product name: {:?} id: {:?},\nManufacturer name: {:?}, id: {:?},\nRelease: {:?}, Serial: {:?}, Path: {:?}",
+                device.product_string().unwrap(),
+                device.product_id(),
+                device.manufacturer_string().unwrap(),
+                device.vendor_id(),
+                device.release_number(),
+                device.serial_number().unwrap(),
+                device.path(),
+            );
+        }
+        let result = match command {
+            CommandEnum::Devices(ops) if !ops.capabilities => {
+                // plain listing doesn't require device to be opened
+                println!();
+                Ok(())
+            }
+            _ => match api.open_path(device.path()) {
+                Ok(dev) => run_traced(&dev, meta_cache, backups, trace, command, output),
+                Err(e) => Err(e.into()),
+            },
+        };
+        match result {
+            Ok(_) if output.is_json() && several => {
+                entry["result"] = output.take().unwrap_or_default();
+                listing.push(entry);
+            }
+            Ok(_) => {
+                // nothing here
+            }
+            Err(e) if output.is_json() && several => {
+                entry["error"] = e.to_string().into();
+                listing.push(entry);
+            }
+            Err(e) => {
+                eprintln!("Error: {}", e)
+            }
+        }
+    }
+    if output.is_json() && (several || matches!(command, CommandEnum::Devices(_))) {
+        output.print_json(&Value::Array(listing))?;
+    }
+    Ok(())
+}
+
+fn command_for_simulator(
+    meta_file: &String,
+    config_file: &Option<String>,
+    trace: &Option<File>,
+    command: &CommandEnum,
+    output: &common::Output,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let meta: Value = serde_json::from_str(&fs::read_to_string(meta_file)?)?;
+    let simulator = protocol::Simulator::new(&meta)?;
+    if let Some(config_file) = config_file {
+        let config: Value = serde_json::from_str(&fs::read_to_string(config_file)?)?;
+        simulator.load_config(&config)?;
+    }
+    if output.format == common::OutputFormat::Text {
+        println!(
+            "Product name: {:?} (simulated),\nMeta: {:?}",
+            meta["name"].as_str().unwrap_or("unknown"),
+            meta_file,
+        );
+    }
+    run_traced(&simulator, None, None, trace, command, output)
+}
+
+/// Layout file and meta file of commands which are able to edit layout file offline.
+fn layout_file(command: &CommandEnum) -> Option<(&String, &Option<String>)> {
+    match command {
+        CommandEnum::Keys(CommandKeys {
+            file: Some(file),
+            meta,
+            ..
+        })
+        | CommandEnum::Layers(CommandLayers {
+            file: Some(file),
+            meta,
+            ..
+        })
+        | CommandEnum::Combos(CommandCombos {
+            file: Some(file),
+            meta,
+            ..
+        })
+        | CommandEnum::Macros(CommandMacros {
+            file: Some(file),
+            meta,
+            ..
+        })
+        | CommandEnum::TapDances(CommandTapDances {
+            file: Some(file),
+            meta,
+            ..
+        })
+        | CommandEnum::KeyOverrides(CommandKeyOverrides {
+            file: Some(file),
+            meta,
+            ..
+        })
+        | CommandEnum::AltRepeats(CommandAltRepeats {
+            file: Some(file),
+            meta,
+            ..
+        })
+        | CommandEnum::Encoders(CommandEncoders {
+            file: Some(file),
+            meta,
+            ..
+        })
+        | CommandEnum::Settings(CommandSettings {
+            file: Some(file),
+            meta,
+            ..
+        }) => Some((file, meta)),
+        _ => None,
+    }
+}
+
+/// Runs command against simulator seeded with layout file and writes file back if it was changed.
+fn command_for_file(
+    file: &String,
+    meta_file: &Option<String>,
+    trace: &Option<File>,
+    command: &CommandEnum,
+    output: &common::Output,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let meta_file = meta_file.as_ref().ok_or(common::CommandError(
+        "meta file should be passed with -m to edit layout file".to_string(),
+    ))?;
+    let meta: Value = serde_json::from_str(&fs::read_to_string(meta_file)?)?;
+    let config = common::read_layout_file(file, &meta)?;
+    let simulator = protocol::Simulator::new(&meta)?;
+    simulator.load_config(&config.to_json()?)?;
+    if output.format == common::OutputFormat::Text {
+        println!("Layout file: {:?},\nMeta: {:?}", file, meta_file);
+    }
+
+    let capabilities = protocol::scan_capabilities(&simulator)?;
+    let before = protocol::Snapshot::load(&simulator, &capabilities, &meta)?;
+    run_traced(&simulator, None, None, trace, command, output)?;
+    let after = protocol::Snapshot::load(&simulator, &capabilities, &meta)?;
+    if after != before {
+        // merged content of file and its base can't be written back into file
+        if let Some(extends) = common::layout_file_extends(file)? {
+            return Err(common::CommandError(format!(
+                "{} extends {}, changes are not saved, edit the file it extends instead",
+                file, extends
+            ))
+            .into());
+        }
+        // only changed sections are written, the rest of file is kept as is
+        let mut updated = config;
+        after.update_config(&before, &mut updated)?;
+        common::write_layout_file(file, &updated, &meta)?;
+        if output.is_json() {
+            output.annotate("file", file.as_str().into());
+        } else {
+            println!("\nConfiguration saved to file {}", file);
+        }
+    }
+    Ok(())
+}
+
+fn command_for_replay(
+    trace_file: &String,
+    command: &CommandEnum,
+    output: &common::Output,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let replayer = protocol::Replayer::new(BufReader::new(File::open(trace_file)?))?;
+    if output.format == common::OutputFormat::Text {
+        println!("Replaying trace: {:?}", trace_file);
+    }
+    run_command(&replayer, None, None, command, output)?;
+    if replayer.remaining() > 0 {
+        eprintln!(
+            "Warning: {} trace records were not replayed",
+            replayer.remaining()
+        );
+    }
+    Ok(())
+}
+
+fn device_selection(
+    options: &VialClient,
+    alias: Option<&config::DeviceAlias>,
+) -> Result<(common::DeviceFilter, Option<u64>), Box<dyn std::error::Error>> {
+    let mut filter = common::DeviceFilter {
+        product_id: options.id,
+        vendor_id: options.vid,
+        serial: options.serial.clone(),
+        path: options.path.clone(),
+        name: options.name.clone(),
+    };
+    if let Some(usb_id) = &options.usb_id {
+        if options.id.is_some() || options.vid.is_some() {
+            return Err(common::CommandError(
+                "--usb-id can't be combined with --id or --vid".to_string(),
+            )
+            .into());
+        }
+        let (vendor_id, product_id) = common::parse_usb_id(usb_id)?;
+        filter.vendor_id = Some(vendor_id);
+        filter.product_id = Some(product_id);
+    }
+    let mut uid = match &options.uid {
+        Some(uid) => Some(common::parse_uid(uid)?),
+        None => None,
+    };
+    // command line selectors take precedence over alias
+    if let Some(alias) = alias {
+        filter.product_id = filter.product_id.or(alias.product_id);
+        filter.vendor_id = filter.vendor_id.or(alias.vendor_id);
+        filter.serial = filter.serial.or(alias.serial.clone());
+        filter.path = filter.path.or(alias.path.clone());
+        filter.name = filter.name.or(alias.name.clone());
+        uid = uid.or(alias.uid);
+    }
+    Ok((filter, uid))
+}
+
+/// Device alias to use, explicit one or default device if no other selector is passed.
+/// Default device isn't applied to commands listing all connected devices.
+fn device_alias<'a>(
+    options: &VialClient,
+    command: &CommandEnum,
+    config: &'a config::Config,
+) -> Result<Option<&'a config::DeviceAlias>, common::CommandError> {
+    let name = match &options.device {
+        Some(name) => Some(name),
+        None if !matches!(command, CommandEnum::Devices(_))
+            && options.id.is_none()
+            && options.vid.is_none()
+            && options.usb_id.is_none()
+            && options.serial.is_none()
+            && options.path.is_none()
+            && options.name.is_none()
+            && options.uid.is_none()
+            && options.simulate.is_none()
+            && options.replay.is_none() =>
+        {
+            config.default_device.as_ref()
+        }
+        None => None,
+    };
+    match name {
+        Some(name) => Ok(Some(config.device(name)?)),
+        None => Ok(None),
+    }
+}
+
+/// Fills meta and layout file options which are not passed with values from device alias.
+fn apply_alias(command: &mut CommandEnum, alias: &config::DeviceAlias) {
+    match command {
+        CommandEnum::Combos(CommandCombos { meta, .. })
+        | CommandEnum::Macros(CommandMacros { meta, .. })
+        | CommandEnum::TapDances(CommandTapDances { meta, .. })
+        | CommandEnum::KeyOverrides(CommandKeyOverrides { meta, .. })
+        | CommandEnum::AltRepeats(CommandAltRepeats { meta, .. })
+        | CommandEnum::Layers(CommandLayers { meta, .. })
+        | CommandEnum::Keys(CommandKeys { meta, .. })
+        | CommandEnum::Encoders(CommandEncoders { meta, .. })
+        | CommandEnum::Settings(CommandSettings { meta, .. })
+        | CommandEnum::Layout(CommandLayout { meta, .. })
+        | CommandEnum::Rollback(CommandRollback { meta, .. })
+        | CommandEnum::Import(CommandImport { meta, .. })
+        | CommandEnum::Reformat(CommandReformat { meta, .. })
+        | CommandEnum::Diff(CommandDiff { meta, .. }) => {
+            if meta.is_none() {
+                meta.clone_from(&alias.meta);
+            }
+        }
+        CommandEnum::Load(CommandLoad { meta, file, .. })
+        | CommandEnum::Check(CommandCheck { meta, file })
+        | CommandEnum::RenderConfig(CommandRenderConfig { meta, file, .. })
+        | CommandEnum::Save(CommandSave { meta, file, .. }) => {
+            if meta.is_none() {
+                meta.clone_from(&alias.meta);
+            }
+            if file.is_none() {
+                file.clone_from(&alias.file);
             }
         }
-        Err(e) => {
-            eprintln!("Error: {:?}", e);
+        CommandEnum::Meta(commands::CommandMeta {
+            command: commands::MetaCommandEnum::Info(commands::CommandMetaInfo { meta }),
+        })
+        | CommandEnum::Export(commands::CommandExport {
+            command: commands::ExportCommandEnum::QmkC(commands::CommandExportQmkC { meta, .. }),
+        })
+        | CommandEnum::Export(commands::CommandExport {
+            command: commands::ExportCommandEnum::Zmk(commands::CommandExportZmk { meta, .. }),
+        }) => {
+            if meta.is_none() {
+                meta.clone_from(&alias.meta);
+            }
         }
+        CommandEnum::Devices(_)
+        | CommandEnum::Lock(_)
+        | CommandEnum::Rgb(_)
+        | CommandEnum::Migrate(_)
+        | CommandEnum::Convert(_)
+        | CommandEnum::Meta(_) => {}
     }
 }
 
 fn main() {
-    let options: VialClient = argh::from_env();
+    let mut options: VialClient = argh::from_env();
     if options.version {
         println!("vitaly {}", env!("CARGO_PKG_VERSION"))
-    } else if let Some(command) = options.command {
-        command_for_devices(options.id, &command);
+    } else if let Some(mut command) = options.command.take() {
+        let config = match config::Config::load() {
+            Ok(config) => config,
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                return;
+            }
+        };
+        let alias = match device_alias(&options, &command, &config) {
+            Ok(alias) => alias,
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                return;
+            }
+        };
+        if let Some(alias) = alias {
+            apply_alias(&mut command, alias);
+        }
+        let output = common::Output::new(
+            options
+                .output
+                .or(config.output)
+                .unwrap_or(common::OutputFormat::Text),
+        );
+        let output = &output;
+        let command = &command;
+        let trace = match &options.record {
+            Some(trace_file) => match File::create(trace_file) {
+                Ok(trace) => Some(trace),
+                Err(e) => {
+                    eprintln!("Error: failed to create trace file {:?}: {}", trace_file, e);
+                    return;
+                }
+            },
+            None => None,
+        };
+        let result = match (&options.replay, &options.simulate) {
+            _ if let Some((file, meta_file)) = layout_file(command) => {
+                command_for_file(file, meta_file, &trace, command, output)
+            }
+            _ if let Some(result) = command_without_device(command, output) => result,
+            (Some(trace_file), _) => command_for_replay(trace_file, command, output),
+            (None, Some(meta_file)) => {
+                command_for_simulator(meta_file, &options.simulate_config, &trace, command, output)
+            }
+            (None, None) => {
+                let backups = backups::Backups::default_dir().map(|dir| backups::Backups { dir });
+                // recorded trace should contain definition, so cache isn't used while recording
+                let meta_cache = match trace {
+                    None => meta_cache::MetaCache::default_dir().map(|dir| meta_cache::MetaCache {
+                        dir,
+                        refresh: options.refresh_meta,
+                    }),
+                    Some(_) => None,
+                };
+                match device_selection(&options, alias) {
+                    Ok((filter, uid)) => command_for_devices(
+                        &filter,
+                        uid,
+                        options.first,
+                        meta_cache.as_ref(),
+                        backups.as_ref(),
+                        &trace,
+                        command,
+                        output,
+                    ),
+                    Err(e) => Err(e),
+                }
+            }
+        };
+        // documents collected before failure are printed as well
+        let finished = output.finish();
+        if let Err(e) = result.and(finished) {
+            eprintln!("Error: {}", e)
+        }
     } else {
         println!(
             "{0} {1}\nRun {0} --help for more information.",
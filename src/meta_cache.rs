@@ -0,0 +1,87 @@
+use crate::protocol;
+use serde_json::Value;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+/// On-disk cache of vial definitions keyed by keyboard uid and definition size.
+pub struct MetaCache {
+    pub dir: PathBuf,
+    /// ignore cached definitions and load them from keyboard again
+    pub refresh: bool,
+}
+
+impl MetaCache {
+    /// Location of cache, $XDG_CACHE_HOME/vitaly/meta or ~/.cache/vitaly/meta.
+    pub fn default_dir() -> Option<PathBuf> {
+        let base = match env::var_os("XDG_CACHE_HOME") {
+            Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => PathBuf::from(env::var_os("HOME")?).join(".cache"),
+        };
+        Some(base.join("vitaly").join("meta"))
+    }
+
+    pub fn load(
+        &self,
+        dev: &impl protocol::Transport,
+    ) -> Result<Value, Box<dyn std::error::Error>> {
+        let uid = protocol::load_uid(dev)?;
+        let size = protocol::load_vial_meta_size(dev)?;
+        let path = self.dir.join(format!("{:016x}-{}.json", uid, size));
+        if !self.refresh
+            && let Ok(cached) = fs::read_to_string(&path)
+            && let Ok(meta) = serde_json::from_str(&cached)
+        {
+            return Ok(meta);
+        }
+        let meta = protocol::load_vial_meta(dev)?;
+        // failing to write cache shouldn't fail command
+        if fs::create_dir_all(&self.dir).is_ok() {
+            let tmp = path.with_extension("tmp");
+            if fs::write(&tmp, meta.to_string()).is_ok() {
+                let _ = fs::rename(&tmp, &path);
+            }
+        }
+        Ok(meta)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_meta_cache() {
+        let meta = serde_json::json!({
+            "name": "cached",
+            "matrix": {"rows": 1, "cols": 2},
+            "layouts": {"keymap": [["0,0", "0,1"]]}
+        });
+        let simulator = protocol::Simulator::new(&meta).unwrap();
+        let dir = env::temp_dir().join(format!("vitaly-meta-cache-{}", std::process::id()));
+        let cache = MetaCache {
+            dir: dir.clone(),
+            refresh: false,
+        };
+        assert_eq!(cache.load(&simulator).unwrap(), meta);
+        let entries: Vec<PathBuf> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().path())
+            .collect();
+        assert_eq!(entries.len(), 1);
+
+        // cached definition is used instead of the one from keyboard
+        let mut cached = meta.clone();
+        cached["name"] = "edited".into();
+        fs::write(&entries[0], cached.to_string()).unwrap();
+        assert_eq!(cache.load(&simulator).unwrap(), cached);
+
+        let refresh = MetaCache {
+            dir: dir.clone(),
+            refresh: true,
+        };
+        assert_eq!(refresh.load(&simulator).unwrap(), meta);
+        assert_eq!(cache.load(&simulator).unwrap(), meta);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,6 +1,6 @@
-use hidapi::{HidDevice, HidError, HidResult};
+use hidapi::{HidError, HidResult};
 use lzma::LzmaError;
-use serde_json::Value;
+use serde_json::{Value, json};
 use std::cmp::min;
 use std::string::FromUtf8Error;
 use thiserror::Error;
@@ -9,47 +9,84 @@ use crate::keycodes;
 
 mod key_override;
 pub use crate::protocol::key_override::{
-    KeyOverride, key_overrides_to_json, load_key_overrides, load_key_overrides_from_json,
-    set_key_override,
+    KeyOverride, key_overrides_to_config, key_overrides_to_json, load_key_overrides,
+    load_key_overrides_from_config, load_key_overrides_from_json, set_key_override,
 };
 
 mod alt_repeat;
 pub use crate::protocol::alt_repeat::{
-    AltRepeat, alt_repeats_to_json, load_alt_repeats, load_alt_repeats_from_json, set_alt_repeat,
+    AltRepeat, alt_repeats_to_config, alt_repeats_to_json, load_alt_repeats,
+    load_alt_repeats_from_config, load_alt_repeats_from_json, set_alt_repeat,
 };
 
 mod tap_dance;
 pub use crate::protocol::tap_dance::{
-    TapDance, load_tap_dances, load_tap_dances_from_json, set_tap_dance, tap_dances_to_json,
+    TapDance, load_tap_dances, load_tap_dances_from_config, load_tap_dances_from_json,
+    set_tap_dance, tap_dances_to_config, tap_dances_to_json,
 };
 
 mod combo;
 pub use crate::protocol::combo::{
-    Combo, combos_to_json, load_combos, load_combos_from_json, set_combo,
+    Combo, combos_to_config, combos_to_json, load_combos, load_combos_from_config,
+    load_combos_from_json, set_combo,
 };
 
 mod r#macro;
 pub use crate::protocol::r#macro::{
-    Macro, load_macros, load_macros_from_json, macros_to_json, set_macros,
+    Macro, MacroStep, load_macros, load_macros_from_config, load_macros_from_json,
+    macros_to_config, macros_to_json, set_macros,
 };
 
 mod qmk_settings;
+#[allow(unused_imports)]
 pub use crate::protocol::qmk_settings::{
-    get_qmk_value, load_qmk_definitions, load_qmk_qsids, load_qmk_settings,
-    load_qmk_settings_from_json, qmk_settings_to_json, reset_qmk_values, set_qmk_value,
+    QmkValue, get_qmk_value, load_qmk_definitions, load_qmk_qsids, load_qmk_settings,
+    load_qmk_settings_from_config, load_qmk_settings_from_json, qmk_settings_to_config,
+    qmk_settings_to_json, reset_qmk_values, set_qmk_value,
 };
 
 mod rgb;
-pub use crate::protocol::rgb::{load_rgb_info, persist_rgb, set_leds_direct, set_rgb_mode};
+#[allow(unused_imports)]
+pub use crate::protocol::rgb::{
+    RGBInfo, load_rgb_info, persist_rgb, set_leds_direct, set_rgb_mode,
+};
 
 mod layout;
 pub use crate::protocol::layout::{LayoutOptions, load_layout_options, set_layout_options};
 
 mod encoder;
 pub use crate::protocol::encoder::{
-    Encoder, encoders_to_json, load_encoder, load_encoders_from_json, set_encoder,
+    Encoder, encoders_to_config, encoders_to_json, load_encoder, load_encoders_from_config,
+    load_encoders_from_json, set_encoder,
+};
+
+mod transport;
+pub use crate::protocol::transport::Transport;
+
+mod simulator;
+pub use crate::protocol::simulator::Simulator;
+
+mod trace;
+pub use crate::protocol::trace::{Recorder, Replayer};
+
+mod vial_config;
+#[allow(unused_imports)]
+pub use crate::protocol::vial_config::{
+    AltRepeatConfig, KeyOverrideConfig, LayoutKey, MacroAction, Section, TapDanceConfig,
+    VialConfig, merge_json,
 };
 
+mod toml_config;
+pub use crate::protocol::toml_config::merge_toml;
+
+mod snapshot;
+#[allow(unused_imports)]
+pub use crate::protocol::snapshot::{ApplyReport, Snapshot};
+
+mod check;
+#[allow(unused_imports)]
+pub use crate::protocol::check::{Issue, Severity, check_config};
+
 pub const USAGE_PAGE: u16 = 0xFF60;
 pub const USAGE_ID: u16 = 0x61;
 
@@ -72,6 +109,7 @@ pub const CMD_VIAL_GET_DEFINITION: u8 = 0x02;
 pub const CMD_VIA_SET_KEYCODE: u8 = 0x05;
 pub const CMD_VIA_GET_LAYER_COUNT: u8 = 0x11;
 pub const CMD_VIA_KEYMAP_GET_BUFFER: u8 = 0x12;
+pub const CMD_VIA_KEYMAP_SET_BUFFER: u8 = 0x13;
 
 pub const VIA_UNHANDLED: u8 = 0xFF;
 pub const VIA_LAYOUT_OPTIONS: u8 = 0x02;
@@ -137,15 +175,17 @@ pub enum ProtocolError {
     SerdeJsonError(#[from] serde_json::Error),
     #[error("Error {0}")]
     General(String),
+    #[error("Keyboard is locked")]
+    KeyboardLocked,
 }
 
-pub fn send(device: &HidDevice, data: &[u8]) -> HidResult<usize> {
+pub fn send(device: &impl Transport, data: &[u8]) -> HidResult<usize> {
     let mut buff: [u8; MESSAGE_LENGTH + 1] = [0u8; MESSAGE_LENGTH + 1];
     buff[1..(data.len() + 1)].copy_from_slice(data);
     device.write(&buff)
 }
 
-pub fn recv(device: &HidDevice) -> HidResult<[u8; MESSAGE_LENGTH]> {
+pub fn recv(device: &impl Transport) -> HidResult<[u8; MESSAGE_LENGTH]> {
     let mut buff: [u8; MESSAGE_LENGTH] = [0u8; MESSAGE_LENGTH];
     match device.read_timeout(&mut buff, 500) {
         Ok(_size) => Ok(buff),
@@ -154,7 +194,7 @@ pub fn recv(device: &HidDevice) -> HidResult<[u8; MESSAGE_LENGTH]> {
 }
 
 pub fn send_recv(
-    device: &HidDevice,
+    device: &impl Transport,
     data_out: &[u8],
 ) -> Result<[u8; MESSAGE_LENGTH], Box<dyn std::error::Error>> {
     let mut attempts = 5;
@@ -200,7 +240,28 @@ pub struct Capabilities {
     pub layer_lock: bool,
 }
 
-pub fn scan_capabilities(device: &HidDevice) -> Result<Capabilities, Box<dyn std::error::Error>> {
+impl Capabilities {
+    pub fn to_json(&self) -> Value {
+        json!({
+            "via_version": self.via_version,
+            "vial_version": self.vial_version,
+            "companion_hid_version": self.companion_hid_version,
+            "layer_count": self.layer_count,
+            "macro_count": self.macro_count,
+            "macro_buffer_size": self.macro_buffer_size,
+            "tap_dance_count": self.tap_dance_count,
+            "combo_count": self.combo_count,
+            "key_override_count": self.key_override_count,
+            "alt_repeat_key_count": self.alt_repeat_key_count,
+            "caps_word": self.caps_word,
+            "layer_lock": self.layer_lock,
+        })
+    }
+}
+
+pub fn scan_capabilities(
+    device: &impl Transport,
+) -> Result<Capabilities, Box<dyn std::error::Error>> {
     let vial_version;
     let companion_hid_version;
     let layer_count;
@@ -325,23 +386,27 @@ pub fn scan_capabilities(device: &HidDevice) -> Result<Capabilities, Box<dyn std
     }
 }
 
-pub fn load_vial_meta(device: &HidDevice) -> Result<Value, Box<dyn std::error::Error>> {
-    let meta_size: u32;
-    let mut block: u32;
-    let mut remaining_size: i64;
+/// Size of compressed vial definition in bytes.
+pub fn load_vial_meta_size(device: &impl Transport) -> Result<u32, Box<dyn std::error::Error>> {
     match send_recv(device, &[CMD_VIA_VIAL_PREFIX, CMD_VIAL_GET_SIZE]) {
         Ok(buff) => {
             if buff[0] != VIA_UNHANDLED {
-                meta_size = ((buff[3] as u32) << 24)
+                Ok(((buff[3] as u32) << 24)
                     + ((buff[2] as u32) << 16)
                     + ((buff[1] as u32) << 8)
-                    + buff[0] as u32;
+                    + buff[0] as u32)
             } else {
-                return Err(ProtocolError::ViaUnhandledError.into());
+                Err(ProtocolError::ViaUnhandledError.into())
             }
         }
-        Err(e) => return Err(e),
+        Err(e) => Err(e),
     }
+}
+
+pub fn load_vial_meta(device: &impl Transport) -> Result<Value, Box<dyn std::error::Error>> {
+    let meta_size = load_vial_meta_size(device)?;
+    let mut block: u32;
+    let mut remaining_size: i64;
     remaining_size = meta_size as i64;
     block = 0;
     let mut raw_meta = Vec::new();
@@ -380,7 +445,7 @@ pub fn load_vial_meta(device: &HidDevice) -> Result<Value, Box<dyn std::error::E
     Ok(meta)
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub struct Keymap {
     rows: u8,
     cols: u8,
@@ -442,6 +507,83 @@ impl Keymap {
         })
     }
 
+    /// Keymap from .vil layout, positions missing in layout (-1) are KC_NO. All layers should
+    /// have the same number of rows and all rows the same number of keys.
+    pub fn from_config(
+        layout: &[Vec<Vec<LayoutKey>>],
+        vial_version: u32,
+    ) -> Result<Keymap, Box<dyn std::error::Error>> {
+        let rows = layout.first().map_or(0, |layer| layer.len());
+        let cols = layout
+            .first()
+            .and_then(|layer| layer.first())
+            .map_or(0, |row| row.len());
+        for (layer_num, layer) in layout.iter().enumerate() {
+            if layer.len() != rows {
+                return Err(KeymapError(format!(
+                    "layer {} has {} rows, layer 0 has {}",
+                    layer_num,
+                    layer.len(),
+                    rows
+                ))
+                .into());
+            }
+            for (row_num, row) in layer.iter().enumerate() {
+                if row.len() != cols {
+                    return Err(KeymapError(format!(
+                        "layer {} row {} has {} keys, row 0 of layer 0 has {}",
+                        layer_num,
+                        row_num,
+                        row.len(),
+                        cols
+                    ))
+                    .into());
+                }
+            }
+        }
+        let mut keys = Vec::<u8>::new();
+        for key in layout.iter().flatten().flatten() {
+            let keycode: u16 = match key {
+                LayoutKey::Number(_) => 0,
+                LayoutKey::Name(value) => {
+                    if value.starts_with("0x") {
+                        let (_, hex) = value.split_once("x").ok_or("Incorrect hex encoding")?;
+                        u16::from_str_radix(hex, 16)?
+                    } else {
+                        keycodes::name_to_qid(value, vial_version)?
+                    }
+                }
+            };
+            keys.push((keycode >> 8) as u8);
+            keys.push((keycode & 0xFF) as u8);
+        }
+        Ok(Keymap {
+            rows: rows as u8,
+            cols: cols as u8,
+            layers: layout.len() as u8,
+            keys,
+        })
+    }
+
+    pub fn to_config(&self, vial_version: u32) -> Vec<Vec<Vec<LayoutKey>>> {
+        (0..self.layers)
+            .map(|layer| {
+                (0..self.rows)
+                    .map(|row| {
+                        (0..self.cols)
+                            .map(|col| {
+                                LayoutKey::Name(keycodes::qid_to_name(
+                                    self.get(layer, row, col),
+                                    vial_version,
+                                ))
+                            })
+                            .collect()
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
     pub fn to_json(&self, vial_version: u32) -> Result<Value, Box<dyn std::error::Error>> {
         let mut result = Vec::new();
         for layer_num in 0..self.layers {
@@ -511,6 +653,37 @@ impl Keymap {
         }
     }
 
+    /// Keymap size as (layers, rows, cols).
+    pub fn dimensions(&self) -> (u8, u8, u8) {
+        (self.layers, self.rows, self.cols)
+    }
+
+    /// Positions (layer, row, col) of keys which differ in `other` keymap, both keymaps should
+    /// have the same dimensions.
+    pub fn diff(&self, other: &Keymap) -> Result<Vec<(u8, u8, u8)>, KeymapError> {
+        if self.dimensions() != other.dimensions() {
+            let (layers, rows, cols) = other.dimensions();
+            return Err(KeymapError(format!(
+                "keymap has {} layers of {}x{} keys, expected {} layers of {}x{} keys",
+                layers, rows, cols, self.layers, self.rows, self.cols
+            )));
+        }
+        let mut result = Vec::new();
+        for layer in 0..self.layers {
+            for row in 0..self.rows {
+                for col in 0..self.cols {
+                    let offset = (layer as usize * self.rows as usize * self.cols as usize * 2)
+                        + (row as usize * self.cols as usize * 2)
+                        + (col as usize * 2);
+                    if other.keys.get(offset..offset + 2) != self.keys.get(offset..offset + 2) {
+                        result.push((layer, row, col));
+                    }
+                }
+            }
+        }
+        Ok(result)
+    }
+
     pub fn get(&self, layer: u8, row: u8, col: u8) -> u16 {
         let offset = (layer as usize * self.rows as usize * self.cols as usize * 2)
             + (row as usize * self.cols as usize * 2)
@@ -519,10 +692,33 @@ impl Keymap {
         let v2 = self.keys[offset + 1];
         ((v1 as u16) << 8) + (v2 as u16)
     }
+
+    /// Keymap with all keys set to `keycode`.
+    pub fn filled(layers: u8, rows: u8, cols: u8, keycode: u16) -> Keymap {
+        let mut keys = Vec::new();
+        for _ in 0..layers as usize * rows as usize * cols as usize {
+            keys.push((keycode >> 8) as u8);
+            keys.push((keycode & 0xFF) as u8);
+        }
+        Keymap {
+            rows,
+            cols,
+            layers,
+            keys,
+        }
+    }
+
+    pub fn set(&mut self, layer: u8, row: u8, col: u8, keycode: u16) {
+        let offset = (layer as usize * self.rows as usize * self.cols as usize * 2)
+            + (row as usize * self.cols as usize * 2)
+            + (col as usize * 2);
+        self.keys[offset] = (keycode >> 8) as u8;
+        self.keys[offset + 1] = (keycode & 0xFF) as u8;
+    }
 }
 
 pub fn load_layers_keys(
-    device: &HidDevice,
+    device: &impl Transport,
     layers: u8,
     rows: u8,
     cols: u8,
@@ -560,7 +756,7 @@ pub fn load_layers_keys(
 }
 
 pub fn set_keycode(
-    device: &HidDevice,
+    device: &impl Transport,
     layer: u8,
     row: u8,
     col: u8,
@@ -574,14 +770,90 @@ pub fn set_keycode(
     }
 }
 
-pub fn set_keymap(device: &HidDevice, keymap: &Keymap) -> Result<(), Box<dyn std::error::Error>> {
-    for layer_num in 0..keymap.layers {
-        for row_num in 0..keymap.rows {
-            for col_num in 0..keymap.cols {
-                let kk = keymap.get(layer_num, row_num, col_num);
-                set_keycode(device, layer_num, row_num, col_num, kk)?;
+pub fn set_keymap(
+    device: &impl Transport,
+    keymap: &Keymap,
+) -> Result<(), Box<dyn std::error::Error>> {
+    write_keymap(device, keymap, None)
+}
+
+/// Writes only chunks of `keymap` which differ from `current` content of device.
+pub fn update_keymap(
+    device: &impl Transport,
+    current: &Keymap,
+    keymap: &Keymap,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if current.dimensions() != keymap.dimensions() {
+        return Err(KeymapError(format!(
+            "keymap has {} layers of {}x{} keys, keyboard has {} layers of {}x{} keys",
+            keymap.layers, keymap.rows, keymap.cols, current.layers, current.rows, current.cols
+        ))
+        .into());
+    }
+    write_keymap(device, keymap, Some(current))
+}
+
+fn write_keymap(
+    device: &impl Transport,
+    keymap: &Keymap,
+    current: Option<&Keymap>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let size = keymap.keys.len() as u16;
+    let mut offset: u16 = 0;
+    let mut written_any = false;
+    while offset < size {
+        let write_size: u8 = min(size - offset, BUFFER_FETCH_CHUNK as u16) as u8;
+        let chunk = offset as usize..(offset + write_size as u16) as usize;
+        if let Some(current) = current
+            && current.keys.get(chunk.clone()) == Some(&keymap.keys[chunk.clone()])
+        {
+            offset += write_size as u16;
+            continue;
+        }
+        written_any = true;
+        let mut msg: [u8; MESSAGE_LENGTH] = [0u8; MESSAGE_LENGTH];
+        msg[0] = CMD_VIA_KEYMAP_SET_BUFFER;
+        msg[1] = ((offset >> 8) & 0xFF) as u8;
+        msg[2] = (offset & 0xFF) as u8;
+        msg[3] = write_size;
+        msg[4..(write_size + 4) as usize].copy_from_slice(&keymap.keys[chunk]);
+        match send_recv(device, &msg) {
+            Ok(buff) => {
+                if buff[0] == VIA_UNHANDLED {
+                    return Err(ProtocolError::ViaUnhandledError.into());
+                }
+                // device acknowledges write by echoing request back
+                if buff[..(write_size + 4) as usize] != msg[..(write_size + 4) as usize] {
+                    return Err(KeymapError(format!(
+                        "keymap write at offset {} is not acknowledged",
+                        offset
+                    ))
+                    .into());
+                }
             }
+            Err(e) => return Err(e),
         }
+        offset += write_size as u16;
+    }
+    if !written_any {
+        return Ok(());
+    }
+    let written = load_layers_keys(device, keymap.layers, keymap.rows, keymap.cols)?;
+    if let Some(position) = written
+        .keys
+        .iter()
+        .zip(keymap.keys.iter())
+        .position(|(w, k)| w != k)
+    {
+        let key = position / 2;
+        let per_layer = keymap.rows as usize * keymap.cols as usize;
+        return Err(KeymapError(format!(
+            "keymap verification failed at layer={}, row={}, col={}",
+            key / per_layer,
+            key % per_layer / keymap.cols as usize,
+            key % keymap.cols as usize
+        ))
+        .into());
     }
     Ok(())
 }
@@ -593,7 +865,19 @@ pub struct LockedStatus {
     pub unlock_buttons: Vec<(u8, u8)>,
 }
 
-pub fn get_locked_status(device: &HidDevice) -> Result<LockedStatus, Box<dyn std::error::Error>> {
+impl LockedStatus {
+    pub fn to_json(&self) -> Value {
+        json!({
+            "locked": self.locked,
+            "unlock_in_progress": self.unlock_in_progress,
+            "unlock_buttons": self.unlock_buttons,
+        })
+    }
+}
+
+pub fn get_locked_status(
+    device: &impl Transport,
+) -> Result<LockedStatus, Box<dyn std::error::Error>> {
     match send_recv(device, &[CMD_VIA_VIAL_PREFIX, CMD_VIAL_GET_UNLOCK_STATUS]) {
         Ok(data) => {
             // println!("{:?}", data);
@@ -617,7 +901,7 @@ pub fn get_locked_status(device: &HidDevice) -> Result<LockedStatus, Box<dyn std
     }
 }
 
-pub fn start_unlock(device: &HidDevice) -> Result<(), Box<dyn std::error::Error>> {
+pub fn start_unlock(device: &impl Transport) -> Result<(), Box<dyn std::error::Error>> {
     match send_recv(device, &[CMD_VIA_VIAL_PREFIX, CMD_VIAL_UNLOCK_START]) {
         Ok(_) => {
             //println!("start_unlock {:?}", data);
@@ -627,7 +911,7 @@ pub fn start_unlock(device: &HidDevice) -> Result<(), Box<dyn std::error::Error>
     }
 }
 
-pub fn unlock_poll(device: &HidDevice) -> Result<(bool, u8), Box<dyn std::error::Error>> {
+pub fn unlock_poll(device: &impl Transport) -> Result<(bool, u8), Box<dyn std::error::Error>> {
     match send_recv(device, &[CMD_VIA_VIAL_PREFIX, CMD_VIAL_UNLOCK_POLL]) {
         Ok(data) => {
             //println!("unlock poll{:?}", data);
@@ -639,14 +923,14 @@ pub fn unlock_poll(device: &HidDevice) -> Result<(bool, u8), Box<dyn std::error:
     }
 }
 
-pub fn set_locked(device: &HidDevice) -> Result<(), Box<dyn std::error::Error>> {
+pub fn set_locked(device: &impl Transport) -> Result<(), Box<dyn std::error::Error>> {
     match send_recv(device, &[CMD_VIA_VIAL_PREFIX, CMD_VIAL_LOCK]) {
         Ok(_) => Ok(()),
         Err(e) => Err(e),
     }
 }
 
-pub fn load_uid(device: &HidDevice) -> Result<u64, Box<dyn std::error::Error>> {
+pub fn load_uid(device: &impl Transport) -> Result<u64, Box<dyn std::error::Error>> {
     match send_recv(device, &[CMD_VIA_VIAL_PREFIX]) {
         Ok(data) => {
             let mut uid_bytes: [u8; 8] = [0; 8];
@@ -1,12 +1,12 @@
 use crate::keycodes;
+use crate::protocol::vial_config::AltRepeatConfig;
 use crate::protocol::{
     CMD_VIA_VIAL_PREFIX, CMD_VIAL_DYNAMIC_ENTRY_OP, DYNAMIC_VIAL_ALT_REPEAT_KEY_GET,
-    DYNAMIC_VIAL_ALT_REPEAT_KEY_SET, ProtocolError, VIA_UNHANDLED, send, send_recv,
+    DYNAMIC_VIAL_ALT_REPEAT_KEY_SET, ProtocolError, Transport, VIA_UNHANDLED, send, send_recv,
 };
-use hidapi::HidDevice;
 use serde_json::{Value, json};
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub struct AltRepeat {
     pub index: u8,
     pub keycode: u16,
@@ -225,7 +225,7 @@ impl AltRepeat {
 }
 
 pub fn load_alt_repeats(
-    device: &HidDevice,
+    device: &impl Transport,
     count: u8,
 ) -> Result<Vec<AltRepeat>, Box<dyn std::error::Error>> {
     let mut altrepeats: Vec<AltRepeat> = vec![];
@@ -276,8 +276,29 @@ pub fn load_alt_repeats_from_json(
     Ok(result)
 }
 
+pub fn load_alt_repeats_from_config(
+    alt_repeats: &[AltRepeatConfig],
+    vial_version: u32,
+) -> Result<Vec<AltRepeat>, Box<dyn std::error::Error>> {
+    let mut result = Vec::new();
+    for (i, alt_repeat) in alt_repeats.iter().enumerate() {
+        let options = alt_repeat.options;
+        result.push(AltRepeat {
+            index: i as u8,
+            keycode: keycodes::name_to_qid(&alt_repeat.keycode, vial_version)?,
+            alt_keycode: keycodes::name_to_qid(&alt_repeat.alt_keycode, vial_version)?,
+            allowed_mods: alt_repeat.allowed_mods,
+            arep_option_default_to_this_alt_key: options & (1 << 0) == (1 << 0),
+            arep_option_bidirectional: options & (1 << 1) == (1 << 1),
+            arep_option_ignore_mod_handedness: options & (1 << 2) == (1 << 2),
+            arep_enabled: options & (1 << 3) == (1 << 3),
+        });
+    }
+    Ok(result)
+}
+
 pub fn set_alt_repeat(
-    device: &HidDevice,
+    device: &impl Transport,
     altrepeat: &AltRepeat,
 ) -> Result<(), Box<dyn std::error::Error>> {
     match send(
@@ -316,6 +337,18 @@ pub fn alt_repeats_to_json(
     Ok(result)
 }
 
+pub fn alt_repeats_to_config(alt_repeats: &[AltRepeat], vial_version: u32) -> Vec<AltRepeatConfig> {
+    alt_repeats
+        .iter()
+        .map(|alt_repeat| AltRepeatConfig {
+            keycode: keycodes::qid_to_name(alt_repeat.keycode, vial_version),
+            alt_keycode: keycodes::qid_to_name(alt_repeat.alt_keycode, vial_version),
+            allowed_mods: alt_repeat.allowed_mods,
+            options: alt_repeat.options(),
+        })
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -325,7 +358,7 @@ mod tests {
     fn test_from_string_two_buttons() {
         let altrepeat = AltRepeat::from_string(
             3,
-            &"keycode = KC_3; alt_keycode= KC_5; options= arep_enabled;".to_string(),
+            "keycode = KC_3; alt_keycode= KC_5; options= arep_enabled;",
             6,
         )
         .unwrap();
@@ -333,17 +366,13 @@ mod tests {
         assert_eq!(keycodes::qid_to_name(altrepeat.keycode, 6), "KC_3");
         assert_eq!(keycodes::qid_to_name(altrepeat.alt_keycode, 6), "KC_5");
         assert_eq!(altrepeat.allowed_mods, 0);
-        assert_eq!(altrepeat.arep_enabled, true);
+        assert!(altrepeat.arep_enabled);
     }
 
     #[test]
     fn test_from_string_full() {
-        let ar = AltRepeat::from_string(
-            0,
-            &"k=KC_A; a=KC_B; m=LCTL; o=enabled|bidirectional".to_string(),
-            6,
-        )
-        .unwrap();
+        let ar = AltRepeat::from_string(0, "k=KC_A; a=KC_B; m=LCTL; o=enabled|bidirectional", 6)
+            .unwrap();
         assert_eq!(keycodes::qid_to_name(ar.keycode, 6), "KC_A");
         assert_eq!(keycodes::qid_to_name(ar.alt_keycode, 6), "KC_B");
         assert_eq!(ar.allowed_mods, 0b00000001); // MOD_LCTL
@@ -355,19 +384,19 @@ mod tests {
     #[test]
     fn test_from_string_errors() {
         assert!(
-            AltRepeat::from_string(0, &"k=KC_A; a".to_string(), 6).is_err(),
+            AltRepeat::from_string(0, "k=KC_A; a", 6).is_err(),
             "Missing ="
         );
         assert!(
-            AltRepeat::from_string(0, &"foo=bar".to_string(), 6).is_err(),
+            AltRepeat::from_string(0, "foo=bar", 6).is_err(),
             "Unknown key"
         );
         assert!(
-            AltRepeat::from_string(0, &"o=invalid_option".to_string(), 6).is_err(),
+            AltRepeat::from_string(0, "o=invalid_option", 6).is_err(),
             "Unknown option"
         );
         assert!(
-            AltRepeat::from_string(0, &"k=INVALID".to_string(), 6).is_err(),
+            AltRepeat::from_string(0, "k=INVALID", 6).is_err(),
             "Invalid keycode"
         );
     }
@@ -419,7 +448,7 @@ mod tests {
         assert!(empty_ar.is_empty());
 
         let mut non_empty = AltRepeat::empty(1);
-        non_empty.keycode = keycodes::name_to_qid(&"KC_A".to_string(), 6).unwrap();
+        non_empty.keycode = keycodes::name_to_qid("KC_A", 6).unwrap();
         assert!(!non_empty.is_empty());
 
         let mut non_empty2 = AltRepeat::empty(2);
@@ -430,13 +459,13 @@ mod tests {
     #[test]
     fn test_json_round_trip() {
         let mut ar1 = AltRepeat::empty(0);
-        ar1.keycode = keycodes::name_to_qid(&"KC_A".to_string(), 6).unwrap();
+        ar1.keycode = keycodes::name_to_qid("KC_A", 6).unwrap();
         ar1.arep_enabled = true;
         ar1.arep_option_bidirectional = true;
 
         let mut ar2 = AltRepeat::empty(1);
-        ar2.keycode = keycodes::name_to_qid(&"KC_X".to_string(), 6).unwrap();
-        ar2.alt_keycode = keycodes::name_to_qid(&"KC_Y".to_string(), 6).unwrap();
+        ar2.keycode = keycodes::name_to_qid("KC_X", 6).unwrap();
+        ar2.alt_keycode = keycodes::name_to_qid("KC_Y", 6).unwrap();
         ar2.allowed_mods = 1; // LCTL
 
         let alt_repeats = vec![ar1, ar2];
@@ -0,0 +1,428 @@
+use crate::keycodes;
+use crate::protocol::r#macro::serialize;
+use crate::protocol::{Capabilities, Snapshot, VialConfig};
+use serde_json::{Value, json};
+use std::collections::BTreeSet;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    /// configuration can't be loaded as is
+    Error,
+    /// configuration can be loaded, but probably doesn't work as intended
+    Warning,
+}
+
+/// Problem found by Snapshot::check.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Issue {
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl Issue {
+    fn error(message: String) -> Issue {
+        Issue {
+            severity: Severity::Error,
+            message,
+        }
+    }
+
+    fn warning(message: String) -> Issue {
+        Issue {
+            severity: Severity::Warning,
+            message,
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "severity": match self.severity {
+                Severity::Error => "error",
+                Severity::Warning => "warning",
+            },
+            "message": self.message,
+        })
+    }
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.severity {
+            Severity::Error => write!(f, "error: {}", self.message),
+            Severity::Warning => write!(f, "warning: {}", self.message),
+        }
+    }
+}
+
+/// Keycode used somewhere in configuration, layer is None for entries active on every layer.
+struct KeycodeUse {
+    location: String,
+    layer: Option<u8>,
+    keycode: u16,
+}
+
+/// Function and its first argument of keycode name, e.g. ("LT", "1") for LT(1,KC_A).
+fn keycode_call(name: &str) -> Option<(&str, &str)> {
+    let (function, args) = name.split_once('(')?;
+    let args = args.strip_suffix(')')?;
+    Some((function, args.split(',').next()?.trim()))
+}
+
+/// Layer activated or switched to by keycode.
+fn target_layer(name: &str) -> Option<u8> {
+    match keycode_call(name)? {
+        ("TO" | "MO" | "DF" | "PDF" | "TG" | "OSL" | "TT" | "LT" | "LM", layer) => {
+            layer.parse().ok()
+        }
+        _ => None,
+    }
+}
+
+/// Non-empty entries beyond number supported by keyboard.
+fn check_count(issues: &mut Vec<Issue>, title: &str, empty: &[bool], count: u8) {
+    let extra = empty
+        .iter()
+        .skip(count as usize)
+        .filter(|empty| !**empty)
+        .count();
+    if extra > 0 {
+        issues.push(Issue::error(format!(
+            "{} {} defined beyond {} supported by keyboard",
+            extra, title, count
+        )));
+    }
+}
+
+/// Validates .vil content the same way as Snapshot::check, configuration which can't be
+/// parsed at all, e.g. with ragged layout, is reported as single error.
+pub fn check_config(
+    config: &VialConfig,
+    vial_version: u32,
+    rows: u8,
+    cols: u8,
+    capabilities: Option<&Capabilities>,
+) -> Vec<Issue> {
+    match Snapshot::from_config(config, vial_version) {
+        Ok(snapshot) => snapshot.check(rows, cols, capabilities),
+        Err(e) => vec![Issue::error(e.to_string())],
+    }
+}
+
+impl Snapshot {
+    fn keycode_uses(&self) -> Vec<KeycodeUse> {
+        let mut result = Vec::new();
+        let (layers, rows, cols) = self.keys.dimensions();
+        for layer in 0..layers {
+            for row in 0..rows {
+                for col in 0..cols {
+                    result.push(KeycodeUse {
+                        location: format!("key {},{} on layer {}", row, col, layer),
+                        layer: Some(layer),
+                        keycode: self.keys.get(layer, row, col),
+                    });
+                }
+            }
+        }
+        for (layer, encoders) in self.encoders.iter().enumerate() {
+            for encoder in encoders {
+                for keycode in [encoder.ccw, encoder.cw] {
+                    result.push(KeycodeUse {
+                        location: format!("encoder {} on layer {}", encoder.index, layer),
+                        layer: Some(layer as u8),
+                        keycode,
+                    });
+                }
+            }
+        }
+        let mut global = |location: String, keycodes: &[u16]| {
+            for keycode in keycodes {
+                result.push(KeycodeUse {
+                    location: location.clone(),
+                    layer: None,
+                    keycode: *keycode,
+                });
+            }
+        };
+        for combo in self.combos.iter().filter(|c| !c.is_empty()) {
+            global(
+                format!("combo {}", combo.index),
+                &[combo.key1, combo.key2, combo.key3, combo.key4, combo.output],
+            );
+        }
+        for td in self.tap_dances.iter().filter(|td| !td.is_empty()) {
+            global(
+                format!("tap dance {}", td.index),
+                &[td.tap, td.hold, td.double_tap, td.tap_hold],
+            );
+        }
+        for ko in self.key_overrides.iter().filter(|ko| !ko.is_empty()) {
+            global(
+                format!("key override {}", ko.index),
+                &[ko.trigger, ko.replacement],
+            );
+        }
+        for ar in self.alt_repeats.iter().filter(|ar| !ar.is_empty()) {
+            global(
+                format!("alt repeat key {}", ar.index),
+                &[ar.keycode, ar.alt_keycode],
+            );
+        }
+        result
+    }
+
+    /// Validates configuration against keyboard matrix and capabilities without touching keyboard,
+    /// checks which need capabilities are skipped when they are not known.
+    pub fn check(&self, rows: u8, cols: u8, capabilities: Option<&Capabilities>) -> Vec<Issue> {
+        let mut issues = Vec::new();
+        let (layers, layout_rows, layout_cols) = self.keys.dimensions();
+        if (layout_rows, layout_cols) != (rows, cols) {
+            issues.push(Issue::error(format!(
+                "layout has {}x{} matrix, keyboard has {}x{}",
+                layout_rows, layout_cols, rows, cols
+            )));
+        }
+
+        if let Some(capabilities) = capabilities {
+            if layers != capabilities.layer_count {
+                issues.push(Issue::error(format!(
+                    "layout has {} layers, keyboard has {}",
+                    layers, capabilities.layer_count
+                )));
+            }
+            let empty: Vec<bool> = self.macros.iter().map(|m| m.is_empty()).collect();
+            check_count(&mut issues, "macros", &empty, capabilities.macro_count);
+            let size = serialize(&self.macros).len();
+            if size > capabilities.macro_buffer_size as usize {
+                issues.push(Issue::error(format!(
+                    "macros take {} bytes, keyboard macro buffer has {}",
+                    size, capabilities.macro_buffer_size
+                )));
+            }
+            let empty: Vec<bool> = self.combos.iter().map(|e| e.is_empty()).collect();
+            check_count(&mut issues, "combos", &empty, capabilities.combo_count);
+            let empty: Vec<bool> = self.tap_dances.iter().map(|e| e.is_empty()).collect();
+            check_count(
+                &mut issues,
+                "tap dances",
+                &empty,
+                capabilities.tap_dance_count,
+            );
+            let empty: Vec<bool> = self.key_overrides.iter().map(|e| e.is_empty()).collect();
+            check_count(
+                &mut issues,
+                "key overrides",
+                &empty,
+                capabilities.key_override_count,
+            );
+            let empty: Vec<bool> = self.alt_repeats.iter().map(|e| e.is_empty()).collect();
+            check_count(
+                &mut issues,
+                "alt repeat keys",
+                &empty,
+                capabilities.alt_repeat_key_count,
+            );
+        }
+
+        // layers reachable from base layer, entries active on every layer are reachable too
+        let uses = self.keycode_uses();
+        let mut reachable = BTreeSet::from([0u8]);
+        loop {
+            let before = reachable.len();
+            for keycode_use in &uses {
+                if keycode_use
+                    .layer
+                    .is_none_or(|layer| reachable.contains(&layer))
+                    && let Some(target) = target_layer(&keycodes::qid_to_name(
+                        keycode_use.keycode,
+                        self.vial_version,
+                    ))
+                    && target < layers
+                {
+                    reachable.insert(target);
+                }
+            }
+            if reachable.len() == before {
+                break;
+            }
+        }
+
+        for keycode_use in &uses {
+            let name = keycodes::qid_to_name(keycode_use.keycode, self.vial_version);
+            let location = &keycode_use.location;
+            if let Some(target) = target_layer(&name)
+                && target >= layers
+            {
+                issues.push(Issue::error(format!(
+                    "{} uses {}, but layout has only {} layers",
+                    location, name, layers
+                )));
+            }
+            if let Some(("TD", index)) = keycode_call(&name)
+                && let Ok(index) = index.parse::<usize>()
+            {
+                if capabilities.is_some_and(|c| index >= c.tap_dance_count as usize) {
+                    issues.push(Issue::error(format!(
+                        "{} uses {}, but keyboard has only {} tap dances",
+                        location,
+                        name,
+                        capabilities.map_or(0, |c| c.tap_dance_count)
+                    )));
+                } else if self.tap_dances.get(index).is_none_or(|td| td.is_empty()) {
+                    issues.push(Issue::warning(format!(
+                        "{} uses {}, but tap dance {} is empty",
+                        location, name, index
+                    )));
+                }
+            }
+            if let Some(index) = name.strip_prefix("QK_MACRO_")
+                && let Ok(index) = index.parse::<usize>()
+            {
+                if capabilities.is_some_and(|c| index >= c.macro_count as usize) {
+                    issues.push(Issue::error(format!(
+                        "{} uses {}, but keyboard has only {} macros",
+                        location,
+                        name,
+                        capabilities.map_or(0, |c| c.macro_count)
+                    )));
+                } else if self.macros.get(index).is_none_or(|m| m.is_empty()) {
+                    issues.push(Issue::warning(format!(
+                        "{} uses {}, but macro {} is empty",
+                        location, name, index
+                    )));
+                }
+            }
+            if let Some(capabilities) = capabilities {
+                if name == "QK_CAPS_WORD_TOGGLE" && !capabilities.caps_word {
+                    issues.push(Issue::warning(format!(
+                        "{} uses {}, but keyboard firmware has no caps word",
+                        location, name
+                    )));
+                }
+                if name == "QK_LAYER_LOCK" && !capabilities.layer_lock {
+                    issues.push(Issue::warning(format!(
+                        "{} uses {}, but keyboard firmware has no layer lock",
+                        location, name
+                    )));
+                }
+            }
+        }
+
+        for layer in 0..layers {
+            // layers with transparent or empty keys only are fine to be unreachable
+            let used = uses.iter().any(|u| u.layer == Some(layer) && u.keycode > 1);
+            if used && !reachable.contains(&layer) {
+                issues.push(Issue::warning(format!(
+                    "layer {} is not reachable from layer 0 by any layer key",
+                    layer
+                )));
+            }
+        }
+        issues
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn capabilities() -> Capabilities {
+        Capabilities {
+            via_version: 9,
+            vial_version: 6,
+            companion_hid_version: 1,
+            layer_count: 3,
+            tap_dance_count: 2,
+            combo_count: 1,
+            key_override_count: 1,
+            alt_repeat_key_count: 1,
+            macro_count: 2,
+            macro_buffer_size: 16,
+            caps_word: false,
+            layer_lock: true,
+        }
+    }
+
+    fn messages(issues: &[Issue]) -> Vec<String> {
+        issues.iter().map(|issue| issue.to_string()).collect()
+    }
+
+    #[test]
+    fn test_check_clean() {
+        let snapshot = Snapshot::from_json(&json!({
+            "vial_protocol": 6,
+            "layout": [
+                [["KC_A", "MO(1)"], ["TD(0)", "QK_MACRO_0"]],
+                [["KC_TRNS", "KC_TRNS"], ["LT(2,KC_B)", "QK_LAYER_LOCK"]],
+                [["KC_NO", "KC_TRNS"], ["KC_1", "KC_TRNS"]]
+            ],
+            "tap_dance": [["KC_A", "KC_B", "KC_NO", "KC_NO", 200]],
+            "macro": [[["tap", "KC_A"]]]
+        }))
+        .unwrap();
+        assert_eq!(
+            messages(&snapshot.check(2, 2, Some(&capabilities()))),
+            Vec::<String>::new()
+        );
+    }
+
+    #[test]
+    fn test_check_issues() {
+        let snapshot = Snapshot::from_json(&json!({
+            "vial_protocol": 6,
+            "layout": [
+                [["KC_A", "MO(3)"], ["TD(1)", "QK_MACRO_5"]],
+                [["KC_B", "CW_TOGG"], ["KC_TRNS", "KC_TRNS"]]
+            ],
+            "combo": [
+                ["KC_A", "KC_B", "KC_NO", "KC_NO", "KC_C"],
+                ["KC_A", "KC_C", "KC_NO", "KC_NO", "KC_D"]
+            ],
+            "macro": [[["text", "a very long macro text"]]]
+        }))
+        .unwrap();
+        let issues = messages(&snapshot.check(2, 3, Some(&capabilities())));
+        assert_eq!(
+            issues,
+            vec![
+                "error: layout has 2x2 matrix, keyboard has 2x3",
+                "error: layout has 2 layers, keyboard has 3",
+                "error: macros take 23 bytes, keyboard macro buffer has 16",
+                "error: 1 combos defined beyond 1 supported by keyboard",
+                "error: key 0,1 on layer 0 uses MO(3), but layout has only 2 layers",
+                "warning: key 1,0 on layer 0 uses TD(1), but tap dance 1 is empty",
+                "error: key 1,1 on layer 0 uses QK_MACRO_5, but keyboard has only 2 macros",
+                "warning: key 0,1 on layer 1 uses QK_CAPS_WORD_TOGGLE, but keyboard firmware has no caps word",
+                "warning: layer 1 is not reachable from layer 0 by any layer key",
+            ]
+        );
+
+        // without capabilities only layout itself is checked
+        let issues = messages(&snapshot.check(2, 2, None));
+        assert_eq!(
+            issues,
+            vec![
+                "error: key 0,1 on layer 0 uses MO(3), but layout has only 2 layers",
+                "warning: key 1,0 on layer 0 uses TD(1), but tap dance 1 is empty",
+                "warning: key 1,1 on layer 0 uses QK_MACRO_5, but macro 5 is empty",
+                "warning: layer 1 is not reachable from layer 0 by any layer key",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_check_config_ragged() {
+        let config = VialConfig::from_json(&json!({
+            "vial_protocol": 6,
+            "layout": [
+                [["KC_A", "MO(1)"], ["KC_B", "KC_C"]],
+                [["KC_TRNS", "KC_TRNS"], ["KC_TRNS"]]
+            ]
+        }))
+        .unwrap();
+        assert_eq!(
+            messages(&check_config(&config, 6, 2, 2, Some(&capabilities()))),
+            vec!["error: layer 1 row 1 has 1 keys, row 0 of layer 0 has 2"]
+        );
+    }
+}
@@ -1,9 +1,8 @@
 use crate::keycodes;
 use crate::protocol::{
     CMD_VIA_VIAL_PREFIX, CMD_VIAL_DYNAMIC_ENTRY_OP, DYNAMIC_VIAL_COMBO_GET, DYNAMIC_VIAL_COMBO_SET,
-    ProtocolError, VIA_UNHANDLED, send, send_recv,
+    ProtocolError, Transport, VIA_UNHANDLED, send, send_recv,
 };
-use hidapi::HidDevice;
 use serde_json::{Value, json};
 use thiserror::Error;
 
@@ -11,7 +10,7 @@ use thiserror::Error;
 #[error("{0}")]
 pub struct ComboFormatError(pub String);
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub struct Combo {
     pub index: u8,
     pub key1: u16,
@@ -117,7 +116,7 @@ impl Combo {
 }
 
 pub fn load_combos(
-    device: &HidDevice,
+    device: &impl Transport,
     count: u8,
 ) -> Result<Vec<Combo>, Box<dyn std::error::Error>> {
     let mut combos: Vec<Combo> = vec![];
@@ -166,7 +165,30 @@ pub fn load_combos_from_json(
     Ok(result)
 }
 
-pub fn set_combo(device: &HidDevice, combo: &Combo) -> Result<(), Box<dyn std::error::Error>> {
+/// Combos of .vil file as [key1, key2, key3, key4, output].
+pub fn load_combos_from_config(
+    combos: &[[String; 5]],
+    vial_version: u32,
+) -> Result<Vec<Combo>, Box<dyn std::error::Error>> {
+    let mut result = Vec::new();
+    for (i, combo) in combos.iter().enumerate() {
+        let mut ks: [u16; 5] = [0x0; 5];
+        for (pos, name) in combo.iter().enumerate() {
+            ks[pos] = keycodes::name_to_qid(name, vial_version)?;
+        }
+        result.push(Combo {
+            index: i as u8,
+            key1: ks[0],
+            key2: ks[1],
+            key3: ks[2],
+            key4: ks[3],
+            output: ks[4],
+        })
+    }
+    Ok(result)
+}
+
+pub fn set_combo(device: &impl Transport, combo: &Combo) -> Result<(), Box<dyn std::error::Error>> {
     match send(
         device,
         &[
@@ -208,13 +230,23 @@ pub fn combos_to_json(
     Ok(result)
 }
 
+pub fn combos_to_config(combos: &[Combo], vial_version: u32) -> Vec<[String; 5]> {
+    combos
+        .iter()
+        .map(|combo| {
+            [combo.key1, combo.key2, combo.key3, combo.key4, combo.output]
+                .map(|keycode| keycodes::qid_to_name(keycode, vial_version))
+        })
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     #[test]
     fn test_from_string_two_buttons() {
-        let combo = Combo::from_string(0, &"KC_V + KC_B = KC_Z".to_string(), 6).unwrap();
+        let combo = Combo::from_string(0, "KC_V + KC_B = KC_Z", 6).unwrap();
         assert_eq!(combo.index, 0);
         assert_eq!(keycodes::qid_to_name(combo.key1, 6), "KC_V");
         assert_eq!(keycodes::qid_to_name(combo.key2, 6), "KC_B");
@@ -225,7 +257,7 @@ mod tests {
 
     #[test]
     fn test_from_string_one_button() {
-        let combo = Combo::from_string(0, &"KC_A = KC_B".to_string(), 6).unwrap();
+        let combo = Combo::from_string(0, "KC_A = KC_B", 6).unwrap();
         assert_eq!(keycodes::qid_to_name(combo.key1, 6), "KC_A");
         assert_eq!(combo.key2, 0);
         assert_eq!(keycodes::qid_to_name(combo.output, 6), "KC_B");
@@ -233,7 +265,7 @@ mod tests {
 
     #[test]
     fn test_from_string_three_buttons() {
-        let combo = Combo::from_string(0, &"KC_A + KC_B + KC_C = KC_D".to_string(), 6).unwrap();
+        let combo = Combo::from_string(0, "KC_A + KC_B + KC_C = KC_D", 6).unwrap();
         assert_eq!(keycodes::qid_to_name(combo.key1, 6), "KC_A");
         assert_eq!(keycodes::qid_to_name(combo.key2, 6), "KC_B");
         assert_eq!(keycodes::qid_to_name(combo.key3, 6), "KC_C");
@@ -243,8 +275,7 @@ mod tests {
 
     #[test]
     fn test_from_string_four_buttons() {
-        let combo =
-            Combo::from_string(0, &"KC_A + KC_B + KC_C + KC_D = KC_E".to_string(), 6).unwrap();
+        let combo = Combo::from_string(0, "KC_A + KC_B + KC_C + KC_D = KC_E", 6).unwrap();
         assert_eq!(keycodes::qid_to_name(combo.key1, 6), "KC_A");
         assert_eq!(keycodes::qid_to_name(combo.key2, 6), "KC_B");
         assert_eq!(keycodes::qid_to_name(combo.key3, 6), "KC_C");
@@ -254,7 +285,7 @@ mod tests {
 
     #[test]
     fn test_from_string_invalid_format() {
-        let result = Combo::from_string(0, &"KC_A + KC_B KC_Z".to_string(), 6);
+        let result = Combo::from_string(0, "KC_A + KC_B KC_Z", 6);
         assert!(result.is_err());
         assert_eq!(
             result.unwrap_err().to_string(),
@@ -264,7 +295,7 @@ mod tests {
 
     #[test]
     fn test_from_string_invalid_keycode() {
-        let result = Combo::from_string(0, &"KC_A + INVALID_KEY = KC_Z".to_string(), 6);
+        let result = Combo::from_string(0, "KC_A + INVALID_KEY = KC_Z", 6);
         assert!(result.is_err());
     }
 
@@ -285,7 +316,7 @@ mod tests {
         let empty_combo = Combo::empty(0);
         assert!(empty_combo.is_empty());
 
-        let non_empty_combo = Combo::from_string(0, &"KC_A = KC_B".to_string(), 6).unwrap();
+        let non_empty_combo = Combo::from_string(0, "KC_A = KC_B", 6).unwrap();
         assert!(!non_empty_combo.is_empty());
     }
 
@@ -336,7 +367,7 @@ mod tests {
 
     #[test]
     fn test_combos_to_json_single_combo() {
-        let combo = Combo::from_string(0, &"KC_V = KC_Z".to_string(), 6).unwrap();
+        let combo = Combo::from_string(0, "KC_V = KC_Z", 6).unwrap();
         let combos = vec![combo];
         let json_values = combos_to_json(&combos, 6).unwrap();
         assert_eq!(json_values.len(), 1);
@@ -348,8 +379,8 @@ mod tests {
 
     #[test]
     fn test_combos_to_json_multiple_combos() {
-        let combo1 = Combo::from_string(0, &"KC_A = KC_B".to_string(), 6).unwrap();
-        let combo2 = Combo::from_string(1, &"KC_C + KC_D = KC_E".to_string(), 6).unwrap();
+        let combo1 = Combo::from_string(0, "KC_A = KC_B", 6).unwrap();
+        let combo2 = Combo::from_string(1, "KC_C + KC_D = KC_E", 6).unwrap();
         let combos = vec![combo1, combo2];
         let json_values = combos_to_json(&combos, 6).unwrap();
         assert_eq!(json_values.len(), 2);
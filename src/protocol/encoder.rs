@@ -1,11 +1,11 @@
 use crate::keycodes;
 use crate::protocol::{
-    CMD_VIA_VIAL_PREFIX, CMD_VIAL_GET_ENCODER, CMD_VIAL_SET_ENCODER, ProtocolError, send_recv,
+    CMD_VIA_VIAL_PREFIX, CMD_VIAL_GET_ENCODER, CMD_VIAL_SET_ENCODER, ProtocolError, Transport,
+    send_recv,
 };
-use hidapi::HidDevice;
 use serde_json::{Value, json};
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub struct Encoder {
     pub index: u8,
     pub ccw: u16,
@@ -13,7 +13,7 @@ pub struct Encoder {
 }
 
 pub fn load_encoder(
-    device: &HidDevice,
+    device: &impl Transport,
     layer: u8,
     index: u8,
 ) -> Result<Encoder, Box<dyn std::error::Error>> {
@@ -31,7 +31,7 @@ pub fn load_encoder(
 }
 
 pub fn set_encoder(
-    device: &HidDevice,
+    device: &impl Transport,
     layer: u8,
     index: u8,
     direction: u8,
@@ -101,6 +101,46 @@ pub fn load_encoders_from_json(
     Ok(result)
 }
 
+/// Encoders of .vil file as [ccw, cw] for every layer.
+pub fn load_encoders_from_config(
+    encoder_layout: &[Vec<[String; 2]>],
+    vial_version: u32,
+) -> Result<Vec<Vec<Encoder>>, Box<dyn std::error::Error>> {
+    let mut result = Vec::new();
+    for layer in encoder_layout {
+        let mut layer_encoders = Vec::new();
+        for (idx, [ccw, cw]) in layer.iter().enumerate() {
+            layer_encoders.push(Encoder {
+                index: idx as u8,
+                ccw: keycodes::name_to_qid(ccw, vial_version)?,
+                cw: keycodes::name_to_qid(cw, vial_version)?,
+            });
+        }
+        result.push(layer_encoders);
+    }
+    Ok(result)
+}
+
+pub fn encoders_to_config(
+    layers_encoders: &[Vec<Encoder>],
+    vial_version: u32,
+) -> Vec<Vec<[String; 2]>> {
+    layers_encoders
+        .iter()
+        .map(|layer| {
+            layer
+                .iter()
+                .map(|encoder| {
+                    [
+                        keycodes::qid_to_name(encoder.ccw, vial_version),
+                        keycodes::qid_to_name(encoder.cw, vial_version),
+                    ]
+                })
+                .collect()
+        })
+        .collect()
+}
+
 pub fn encoders_to_json(
     layers_encoders: &Vec<Vec<Encoder>>,
     vial_version: u32,
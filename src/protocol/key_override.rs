@@ -1,12 +1,12 @@
 use crate::keycodes;
+use crate::protocol::vial_config::KeyOverrideConfig;
 use crate::protocol::{
     CMD_VIA_VIAL_PREFIX, CMD_VIAL_DYNAMIC_ENTRY_OP, DYNAMIC_VIAL_KEY_OVERRIDE_GET,
-    DYNAMIC_VIAL_KEY_OVERRIDE_SET, ProtocolError, VIA_UNHANDLED, send, send_recv,
+    DYNAMIC_VIAL_KEY_OVERRIDE_SET, ProtocolError, Transport, VIA_UNHANDLED, send, send_recv,
 };
-use hidapi::HidDevice;
 use serde_json::{Value, json};
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub struct KeyOverride {
     pub index: u8,
     pub trigger: u16,
@@ -357,8 +357,35 @@ pub fn load_key_overrides_from_json(
     Ok(result)
 }
 
+pub fn load_key_overrides_from_config(
+    key_overrides: &[KeyOverrideConfig],
+    vial_version: u32,
+) -> Result<Vec<KeyOverride>, Box<dyn std::error::Error>> {
+    let mut result = Vec::new();
+    for (i, key_override) in key_overrides.iter().enumerate() {
+        let options = key_override.options;
+        result.push(KeyOverride {
+            index: i as u8,
+            trigger: keycodes::name_to_qid(&key_override.trigger, vial_version)?,
+            replacement: keycodes::name_to_qid(&key_override.replacement, vial_version)?,
+            layers: key_override.layers,
+            trigger_mods: key_override.trigger_mods,
+            negative_mod_mask: key_override.negative_mod_mask,
+            suppressed_mods: key_override.suppressed_mods,
+            ko_option_activation_trigger_down: options & (1 << 0) == (1 << 0),
+            ko_option_activation_required_mod_down: options & (1 << 1) == (1 << 1),
+            ko_option_activation_negative_mod_up: options & (1 << 2) == (1 << 2),
+            ko_option_one_mod: options & (1 << 3) == (1 << 3),
+            ko_option_no_reregister_trigger: options & (1 << 4) == (1 << 4),
+            ko_option_no_unregister_on_other_key_down: options & (1 << 5) == (1 << 5),
+            ko_enabled: options & (1 << 7) == (1 << 7),
+        });
+    }
+    Ok(result)
+}
+
 pub fn load_key_overrides(
-    device: &HidDevice,
+    device: &impl Transport,
     count: u8,
 ) -> Result<Vec<KeyOverride>, Box<dyn std::error::Error>> {
     let mut keyoverrides: Vec<KeyOverride> = vec![];
@@ -402,7 +429,7 @@ pub fn load_key_overrides(
 }
 
 pub fn set_key_override(
-    device: &HidDevice,
+    device: &impl Transport,
     keyoverride: &KeyOverride,
 ) -> Result<(), Box<dyn std::error::Error>> {
     match send(
@@ -447,6 +474,24 @@ pub fn key_overrides_to_json(
     Ok(result)
 }
 
+pub fn key_overrides_to_config(
+    key_overrides: &[KeyOverride],
+    vial_version: u32,
+) -> Vec<KeyOverrideConfig> {
+    key_overrides
+        .iter()
+        .map(|key_override| KeyOverrideConfig {
+            trigger: keycodes::qid_to_name(key_override.trigger, vial_version),
+            replacement: keycodes::qid_to_name(key_override.replacement, vial_version),
+            layers: key_override.layers,
+            trigger_mods: key_override.trigger_mods,
+            negative_mod_mask: key_override.negative_mod_mask,
+            suppressed_mods: key_override.suppressed_mods,
+            options: key_override.options(),
+        })
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -456,7 +501,7 @@ mod tests {
     fn test_positive() {
         let keyoverride = KeyOverride::from_string(
             9,
-            &"trigger=KC_1; replacement=KC_2; layers=1; options=ko_enabled|ko_option_no_reregister_trigger;".to_string(),
+            "trigger=KC_1; replacement=KC_2; layers=1; options=ko_enabled|ko_option_no_reregister_trigger;",
             6,
         )
         .unwrap();
@@ -464,8 +509,8 @@ mod tests {
         assert_eq!(keycodes::qid_to_name(keyoverride.trigger, 6), "KC_1");
         assert_eq!(keycodes::qid_to_name(keyoverride.replacement, 6), "KC_2");
         assert_eq!(keyoverride.layers, 2);
-        assert_eq!(keyoverride.ko_enabled, true);
-        assert_eq!(keyoverride.ko_option_no_reregister_trigger, true);
+        assert!(keyoverride.ko_enabled);
+        assert!(keyoverride.ko_option_no_reregister_trigger);
     }
 
     #[test]
@@ -485,23 +530,23 @@ mod tests {
     #[test]
     fn test_from_string_errors() {
         assert!(
-            KeyOverride::from_string(0, &"t=KC_A; r".to_string(), 6).is_err(),
+            KeyOverride::from_string(0, "t=KC_A; r", 6).is_err(),
             "Missing ="
         );
         assert!(
-            KeyOverride::from_string(0, &"foo=bar".to_string(), 6).is_err(),
+            KeyOverride::from_string(0, "foo=bar", 6).is_err(),
             "Unknown key"
         );
         assert!(
-            KeyOverride::from_string(0, &"t=INVALID".to_string(), 6).is_err(),
+            KeyOverride::from_string(0, "t=INVALID", 6).is_err(),
             "Invalid keycode"
         );
         assert!(
-            KeyOverride::from_string(0, &"l=abc".to_string(), 6).is_err(),
+            KeyOverride::from_string(0, "l=abc", 6).is_err(),
             "Invalid layer"
         );
         assert!(
-            KeyOverride::from_string(0, &"o=invalid_option".to_string(), 6).is_err(),
+            KeyOverride::from_string(0, "o=invalid_option", 6).is_err(),
             "Unknown option"
         );
     }
@@ -554,7 +599,7 @@ mod tests {
         assert!(empty_ko.is_empty());
 
         let mut non_empty = KeyOverride::empty(1);
-        non_empty.trigger = keycodes::name_to_qid(&"KC_A".to_string(), 6).unwrap();
+        non_empty.trigger = keycodes::name_to_qid("KC_A", 6).unwrap();
         assert!(!non_empty.is_empty());
 
         let mut non_empty2 = KeyOverride::empty(2);
@@ -565,8 +610,8 @@ mod tests {
     #[test]
     fn test_json_round_trip() {
         let mut ko1 = KeyOverride::empty(0);
-        ko1.trigger = keycodes::name_to_qid(&"KC_A".to_string(), 6).unwrap();
-        ko1.replacement = keycodes::name_to_qid(&"KC_B".to_string(), 6).unwrap();
+        ko1.trigger = keycodes::name_to_qid("KC_A", 6).unwrap();
+        ko1.replacement = keycodes::name_to_qid("KC_B", 6).unwrap();
         ko1.layers = 1;
         ko1.ko_enabled = true;
 
@@ -1,8 +1,7 @@
 use crate::protocol::{
-    CMD_VIA_GET_KEYBOARD_VALUE, CMD_VIA_SET_KEYBOARD_VALUE, ProtocolError, VIA_LAYOUT_OPTIONS,
-    VIA_UNHANDLED, send_recv,
+    CMD_VIA_GET_KEYBOARD_VALUE, CMD_VIA_SET_KEYBOARD_VALUE, ProtocolError, Transport,
+    VIA_LAYOUT_OPTIONS, VIA_UNHANDLED, send_recv,
 };
-use hidapi::HidDevice;
 use serde_json::Value;
 use std::fmt;
 
@@ -147,7 +146,7 @@ impl fmt::Display for LayoutOptions<'_> {
     }
 }
 
-pub fn load_layout_options(device: &HidDevice) -> Result<u32, Box<dyn std::error::Error>> {
+pub fn load_layout_options(device: &impl Transport) -> Result<u32, Box<dyn std::error::Error>> {
     match send_recv(device, &[CMD_VIA_GET_KEYBOARD_VALUE, VIA_LAYOUT_OPTIONS]) {
         Ok(data) => {
             if data[0] != VIA_UNHANDLED {
@@ -165,7 +164,7 @@ pub fn load_layout_options(device: &HidDevice) -> Result<u32, Box<dyn std::error
 }
 
 pub fn set_layout_options(
-    device: &HidDevice,
+    device: &impl Transport,
     options: u32,
 ) -> Result<(), Box<dyn std::error::Error>> {
     match send_recv(
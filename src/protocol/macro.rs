@@ -1,9 +1,9 @@
 use crate::keycodes;
+use crate::protocol::vial_config::MacroAction;
 use crate::protocol::{
     BUFFER_FETCH_CHUNK, CMD_VIA_MACRO_GET_BUFFER, CMD_VIA_MACRO_SET_BUFFER, Capabilities,
-    MESSAGE_LENGTH, ProtocolError, VIA_UNHANDLED, send_recv,
+    MESSAGE_LENGTH, ProtocolError, Transport, VIA_UNHANDLED, send_recv,
 };
-use hidapi::HidDevice;
 use serde_json::{Value, json};
 use std::cmp::min;
 use thiserror::Error;
@@ -25,7 +25,7 @@ pub struct MacroParsingError(String);
 #[error("{0}")]
 pub struct MacroSavingError(String);
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub enum MacroStep {
     Tap(u16),
     Down(u16),
@@ -176,7 +176,7 @@ impl MacroStep {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub struct Macro {
     pub index: u8,
     pub steps: Vec<MacroStep>,
@@ -261,6 +261,42 @@ pub fn load_macros_from_json(
     Ok(result)
 }
 
+/// Macros of .vil file, actions with several arguments are split into one step per argument.
+pub fn load_macros_from_config(
+    macros: &[Vec<MacroAction>],
+    vial_version: u32,
+) -> Result<Vec<Macro>, Box<dyn std::error::Error>> {
+    let mut result = Vec::new();
+    for (i, actions) in macros.iter().enumerate() {
+        let mut steps = Vec::new();
+        for action in actions {
+            match action {
+                MacroAction::Text(args) => {
+                    steps.extend(args.iter().map(|arg| MacroStep::Text(arg.clone())))
+                }
+                MacroAction::Delay(args) => {
+                    steps.extend(args.iter().map(|&ms| MacroStep::Delay(ms)))
+                }
+                MacroAction::Tap(args) | MacroAction::Down(args) | MacroAction::Up(args) => {
+                    for arg in args {
+                        let keycode = keycodes::name_to_qid(arg, vial_version)?;
+                        steps.push(match action {
+                            MacroAction::Tap(_) => MacroStep::Tap(keycode),
+                            MacroAction::Down(_) => MacroStep::Down(keycode),
+                            _ => MacroStep::Up(keycode),
+                        });
+                    }
+                }
+            }
+        }
+        result.push(Macro {
+            index: i as u8,
+            steps,
+        });
+    }
+    Ok(result)
+}
+
 // State machine here
 enum MacroParsingState {
     Start,
@@ -379,7 +415,7 @@ pub fn serialize(macros: &Vec<Macro>) -> Vec<u8> {
 }
 
 pub fn load_macros(
-    device: &HidDevice,
+    device: &impl Transport,
     count: u8,
     buffer_size: u16,
 ) -> Result<Vec<Macro>, Box<dyn std::error::Error>> {
@@ -435,7 +471,7 @@ pub fn load_macros(
 }
 
 pub fn set_macros(
-    device: &HidDevice,
+    device: &impl Transport,
     capabilities: &Capabilities,
     macros: &Vec<Macro>,
 ) -> Result<(), Box<dyn std::error::Error>> {
@@ -527,6 +563,30 @@ pub fn macros_to_json(
     Ok(result)
 }
 
+pub fn macros_to_config(macros: &[Macro], vial_version: u32) -> Vec<Vec<MacroAction>> {
+    macros
+        .iter()
+        .map(|m| {
+            m.steps
+                .iter()
+                .map(|step| match step {
+                    MacroStep::Tap(v) => {
+                        MacroAction::Tap(vec![keycodes::qid_to_name(*v, vial_version)])
+                    }
+                    MacroStep::Down(v) => {
+                        MacroAction::Down(vec![keycodes::qid_to_name(*v, vial_version)])
+                    }
+                    MacroStep::Up(v) => {
+                        MacroAction::Up(vec![keycodes::qid_to_name(*v, vial_version)])
+                    }
+                    MacroStep::Delay(v) => MacroAction::Delay(vec![*v]),
+                    MacroStep::Text(v) => MacroAction::Text(vec![v.clone()]),
+                })
+                .collect()
+        })
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -549,7 +609,7 @@ mod tests {
 
     #[test]
     fn test_from_string() -> Result<(), Box<dyn std::error::Error>> {
-        let m = Macro::from_string(12, &"Text(example); Tap(KC_1)".to_string(), 6)?;
+        let m = Macro::from_string(12, "Text(example); Tap(KC_1)", 6)?;
         assert_eq!(12, m.index);
         assert_eq!(2, m.steps.len());
         Ok(())
@@ -568,17 +628,11 @@ mod tests {
 
     #[test]
     fn test_step_serde_round_trip() {
-        step_round_trip(MacroStep::Tap(name_to_qid(&"KC_A".to_string(), 6).unwrap()));
-        step_round_trip(MacroStep::Down(
-            name_to_qid(&"KC_B".to_string(), 6).unwrap(),
-        ));
-        step_round_trip(MacroStep::Up(name_to_qid(&"KC_C".to_string(), 6).unwrap()));
-        step_round_trip(MacroStep::Tap(
-            name_to_qid(&"KC_LCTL".to_string(), 6).unwrap(),
-        ));
-        step_round_trip(MacroStep::Tap(
-            name_to_qid(&"LCTL(KC_C)".to_string(), 6).unwrap(),
-        ));
+        step_round_trip(MacroStep::Tap(name_to_qid("KC_A", 6).unwrap()));
+        step_round_trip(MacroStep::Down(name_to_qid("KC_B", 6).unwrap()));
+        step_round_trip(MacroStep::Up(name_to_qid("KC_C", 6).unwrap()));
+        step_round_trip(MacroStep::Tap(name_to_qid("KC_LCTL", 6).unwrap()));
+        step_round_trip(MacroStep::Tap(name_to_qid("LCTL(KC_C)", 6).unwrap()));
         step_round_trip(MacroStep::Delay(100));
         step_round_trip(MacroStep::Text("Hello".to_string()));
     }
@@ -650,8 +704,7 @@ mod tests {
 
     #[test]
     fn test_json_round_trip() -> Result<(), Box<dyn std::error::Error>> {
-        let original_macro =
-            Macro::from_string(0, &"Tap(KC_A); Delay(100); Text(test)".to_string(), 6)?;
+        let original_macro = Macro::from_string(0, "Tap(KC_A); Delay(100); Text(test)", 6)?;
         let macros_vec = vec![original_macro];
         let json_val = macros_to_json(&macros_vec, 6)?;
         let loaded_macros = load_macros_from_json(&serde_json::Value::Array(json_val), 6)?;
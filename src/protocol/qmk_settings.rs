@@ -1,12 +1,11 @@
 use crate::protocol::{
     CMD_VIA_VIAL_PREFIX, CMD_VIAL_QMK_SETTINGS_GET, CMD_VIAL_QMK_SETTINGS_QUERY,
     CMD_VIAL_QMK_SETTINGS_RESET, CMD_VIAL_QMK_SETTINGS_SET, MESSAGE_LENGTH, ProtocolError,
-    send_recv,
+    Transport, send_recv,
 };
-use hidapi::HidDevice;
 use serde_json::{Map, Value};
 use std::cmp::max;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
 pub fn load_qmk_definitions() -> serde_json::Result<serde_json::Value> {
     let qmk_settings_json = include_str!("qmk_settings.json");
@@ -14,7 +13,7 @@ pub fn load_qmk_definitions() -> serde_json::Result<serde_json::Value> {
     Ok(qmk_settings)
 }
 
-pub fn load_qmk_qsids(device: &HidDevice) -> Result<Vec<u16>, Box<dyn std::error::Error>> {
+pub fn load_qmk_qsids(device: &impl Transport) -> Result<Vec<u16>, Box<dyn std::error::Error>> {
     let mut cur = 0u16;
     let mut qsids = Vec::new();
     'o: loop {
@@ -43,7 +42,7 @@ pub fn load_qmk_qsids(device: &HidDevice) -> Result<Vec<u16>, Box<dyn std::error
     Ok(qsids)
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub struct QmkValue {
     value: u32,
 }
@@ -59,7 +58,7 @@ impl QmkValue {
 }
 
 pub fn get_qmk_value(
-    device: &HidDevice,
+    device: &impl Transport,
     qsid: u16,
     width: u8,
 ) -> Result<QmkValue, Box<dyn std::error::Error>> {
@@ -94,7 +93,7 @@ pub fn get_qmk_value(
 }
 
 pub fn set_qmk_value(
-    device: &HidDevice,
+    device: &impl Transport,
     qsid: u16,
     value: u32,
 ) -> Result<(), Box<dyn std::error::Error>> {
@@ -122,7 +121,7 @@ pub fn set_qmk_value(
 }
 
 pub fn load_qmk_settings(
-    device: &HidDevice,
+    device: &impl Transport,
 ) -> Result<HashMap<u16, QmkValue>, Box<dyn std::error::Error>> {
     let mut result = HashMap::new();
     let sids = load_qmk_qsids(device)?;
@@ -149,7 +148,7 @@ pub fn load_qmk_settings(
     Ok(result)
 }
 
-pub fn reset_qmk_values(device: &HidDevice) -> Result<(), Box<dyn std::error::Error>> {
+pub fn reset_qmk_values(device: &impl Transport) -> Result<(), Box<dyn std::error::Error>> {
     let buff: [u8; 2] = [CMD_VIA_VIAL_PREFIX, CMD_VIAL_QMK_SETTINGS_RESET];
     match send_recv(device, &buff) {
         Ok(buff) => {
@@ -179,6 +178,22 @@ pub fn load_qmk_settings_from_json(
     Ok(result)
 }
 
+pub fn load_qmk_settings_from_config(settings: &BTreeMap<u16, u32>) -> HashMap<u16, QmkValue> {
+    settings
+        .iter()
+        .map(|(&qsid, &value)| (qsid, QmkValue { value }))
+        .collect()
+}
+
+/// Settings ordered by qsid, the same way as Vial GUI writes them.
+pub fn qmk_settings_to_config(values: &HashMap<u16, QmkValue>) -> BTreeMap<u16, u32> {
+    values
+        .iter()
+        .map(|(&qsid, value)| (qsid, value.get()))
+        .collect()
+}
+
+#[allow(dead_code)]
 pub fn qmk_settings_to_json(
     values: &HashMap<u16, QmkValue>,
 ) -> Result<Value, Box<dyn std::error::Error>> {
@@ -1,14 +1,14 @@
 use palette::FromColor;
 use palette::{Hsv, Srgb};
+use serde_json::{Value, json};
 use std::cmp::min;
 use std::fmt;
 
 use crate::protocol::{
     CMD_VIA_LIGHTING_GET_VALUE, CMD_VIA_LIGHTING_SAVE, CMD_VIA_LIGHTING_SET_VALUE, MESSAGE_LENGTH,
-    VIA_UNHANDLED, VIALRGB_DIRECT_FASTSET, VIALRGB_GET_INFO, VIALRGB_GET_MODE,
+    Transport, VIA_UNHANDLED, VIALRGB_DIRECT_FASTSET, VIALRGB_GET_INFO, VIALRGB_GET_MODE,
     VIALRGB_GET_NUMBER_LEDS, VIALRGB_GET_SUPPORTED, VIALRGB_SET_MODE, send_recv,
 };
-use hidapi::HidDevice;
 
 pub fn rgb_to_hsv(
     color: &str,
@@ -97,6 +97,24 @@ impl RGBInfo {
         }
     }
 
+    pub fn to_json(&self) -> Value {
+        let mut effects = Vec::new();
+        for effect in &self.effects {
+            if let Ok(name) = RGBInfo::effect_name(*effect) {
+                effects.push(json!({"id": effect, "name": name}));
+            }
+        }
+        json!({
+            "version": self.version,
+            "leds_count": self.leds_count,
+            "max_brightness": self.max_brightness,
+            "effect": self.effect,
+            "effect_speed": self.effect_speed,
+            "color_hsv": [self.color_h, self.color_s, self.color_v],
+            "supported_effects": effects,
+        })
+    }
+
     pub fn set_color(&mut self, color: &str) -> Result<(), Box<dyn std::error::Error>> {
         let (h, s, v) = rgb_to_hsv(color, self.max_brightness)?;
         self.color_h = h;
@@ -127,7 +145,7 @@ impl fmt::Display for RGBInfo {
     }
 }
 
-pub fn load_rgb_info(device: &HidDevice) -> Result<RGBInfo, Box<dyn std::error::Error>> {
+pub fn load_rgb_info(device: &impl Transport) -> Result<RGBInfo, Box<dyn std::error::Error>> {
     let version: u16;
     let max_brightness: u8;
     let mut effect: u16 = 0;
@@ -210,7 +228,7 @@ pub fn load_rgb_info(device: &HidDevice) -> Result<RGBInfo, Box<dyn std::error::
 }
 
 pub fn set_rgb_mode(
-    device: &HidDevice,
+    device: &impl Transport,
     rgb_info: &RGBInfo,
 ) -> Result<(), Box<dyn std::error::Error>> {
     let e1 = (rgb_info.effect & 0xFF) as u8;
@@ -235,7 +253,7 @@ pub fn set_rgb_mode(
     Ok(())
 }
 
-pub fn persist_rgb(device: &HidDevice) -> Result<(), Box<dyn std::error::Error>> {
+pub fn persist_rgb(device: &impl Transport) -> Result<(), Box<dyn std::error::Error>> {
     match send_recv(device, &[CMD_VIA_LIGHTING_SAVE]) {
         Ok(_) => {}
         Err(e) => return Err(e),
@@ -247,7 +265,7 @@ pub fn persist_rgb(device: &HidDevice) -> Result<(), Box<dyn std::error::Error>>
 const LEDS_PER_REQ: u16 = 8;
 
 fn set_leds_range(
-    device: &HidDevice,
+    device: &impl Transport,
     from: u16,
     to: u16,
     color: &str,
@@ -292,7 +310,7 @@ fn set_leds_range(
 }
 
 pub fn set_leds_direct(
-    device: &HidDevice,
+    device: &impl Transport,
     command: &str,
     max_brightness: u8,
 ) -> Result<(), Box<dyn std::error::Error>> {
@@ -0,0 +1,773 @@
+use crate::protocol::r#macro::serialize;
+use crate::protocol::{
+    CMD_VIA_GET_KEYBOARD_VALUE, CMD_VIA_GET_LAYER_COUNT, CMD_VIA_GET_PROTOCOL_VERSION,
+    CMD_VIA_KEYMAP_GET_BUFFER, CMD_VIA_KEYMAP_SET_BUFFER, CMD_VIA_LIGHTING_GET_VALUE,
+    CMD_VIA_LIGHTING_SAVE, CMD_VIA_LIGHTING_SET_VALUE, CMD_VIA_MACRO_GET_BUFFER,
+    CMD_VIA_MACRO_GET_BUFFER_SIZE, CMD_VIA_MACRO_GET_COUNT, CMD_VIA_MACRO_SET_BUFFER,
+    CMD_VIA_SET_KEYBOARD_VALUE, CMD_VIA_SET_KEYCODE, CMD_VIA_VIAL_PREFIX,
+    CMD_VIAL_DYNAMIC_ENTRY_OP, CMD_VIAL_GET_DEFINITION, CMD_VIAL_GET_ENCODER,
+    CMD_VIAL_GET_KEYBOARD_ID, CMD_VIAL_GET_SIZE, CMD_VIAL_GET_UNLOCK_STATUS, CMD_VIAL_LOCK,
+    CMD_VIAL_QMK_SETTINGS_GET, CMD_VIAL_QMK_SETTINGS_QUERY, CMD_VIAL_QMK_SETTINGS_RESET,
+    CMD_VIAL_QMK_SETTINGS_SET, CMD_VIAL_SET_ENCODER, CMD_VIAL_UNLOCK_POLL, CMD_VIAL_UNLOCK_START,
+    DYNAMIC_VIAL_ALT_REPEAT_KEY_GET, DYNAMIC_VIAL_ALT_REPEAT_KEY_SET, DYNAMIC_VIAL_COMBO_GET,
+    DYNAMIC_VIAL_COMBO_SET, DYNAMIC_VIAL_GET_NUMBER_OF_ENTRIES, DYNAMIC_VIAL_KEY_OVERRIDE_GET,
+    DYNAMIC_VIAL_KEY_OVERRIDE_SET, DYNAMIC_VIAL_TAP_DANCE_GET, DYNAMIC_VIAL_TAP_DANCE_SET, Keymap,
+    MESSAGE_LENGTH, Transport, VIA_LAYOUT_OPTIONS, VIA_UNHANDLED, VIALRGB_DIRECT_FASTSET,
+    VIALRGB_GET_INFO, VIALRGB_GET_MODE, VIALRGB_GET_NUMBER_LEDS, VIALRGB_GET_SUPPORTED,
+    VIALRGB_SET_MODE, load_alt_repeats_from_json, load_combos_from_json, load_encoders_from_json,
+    load_key_overrides_from_json, load_macros_from_json, load_qmk_definitions,
+    load_qmk_settings_from_json, load_tap_dances_from_json, scan_capabilities, set_alt_repeat,
+    set_combo, set_encoder, set_key_override, set_keymap, set_layout_options, set_macros,
+    set_qmk_value, set_tap_dance,
+};
+use hidapi::HidResult;
+use serde_json::Value;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+
+const SIMULATOR_UID: u64 = 0x56495441_4C590001;
+const DEFAULT_VIA_VERSION: u8 = 9;
+const DEFAULT_VIAL_VERSION: u32 = 6;
+const DEFAULT_LAYERS: u8 = 4;
+const DEFAULT_DYNAMIC_ENTRIES: u8 = 16;
+const DEFAULT_MACRO_COUNT: u8 = 16;
+const DEFAULT_MACRO_BUFFER_SIZE: u16 = 1024;
+const DEFAULT_LEDS_COUNT: u16 = 16;
+const DYNAMIC_ENTRY_SIZE: usize = 10;
+const UNLOCK_POLLS: u8 = 3;
+const RGB_MAX_BRIGHTNESS: u8 = 255;
+const RGB_EFFECTS_COUNT: u16 = 44;
+
+type DynamicEntry = [u8; DYNAMIC_ENTRY_SIZE];
+
+#[derive(Debug)]
+struct RgbState {
+    effect: u16,
+    effect_speed: u8,
+    color_h: u8,
+    color_s: u8,
+    color_v: u8,
+    leds: Vec<(u8, u8, u8)>,
+}
+
+#[derive(Debug)]
+struct SimulatorState {
+    definition: Vec<u8>,
+    uid: u64,
+    via_version: u8,
+    vial_version: u32,
+    rows: u8,
+    cols: u8,
+    layers: u8,
+    keys: Vec<u8>,
+    encoders: HashMap<(u8, u8), [u16; 2]>,
+    tap_dances: Vec<DynamicEntry>,
+    combos: Vec<DynamicEntry>,
+    key_overrides: Vec<DynamicEntry>,
+    alt_repeats: Vec<DynamicEntry>,
+    macro_count: u8,
+    macro_buffer: Vec<u8>,
+    qmk_settings: BTreeMap<u16, u32>,
+    layout_options: u32,
+    locked: bool,
+    unlock_polls: Option<u8>,
+    unlock_buttons: Vec<(u8, u8)>,
+    rgb: Option<RgbState>,
+    response: Option<[u8; MESSAGE_LENGTH]>,
+}
+
+/// In-memory keyboard answering VIA/Vial requests, usable in place of a `HidDevice`.
+///
+/// Only the reply to the most recent report is kept, so fire-and-forget writes
+/// never shift replies read by later requests.
+#[derive(Debug)]
+pub struct Simulator {
+    state: RefCell<SimulatorState>,
+}
+
+fn keymap_size(layers: u8, rows: u8, cols: u8) -> usize {
+    layers as usize * rows as usize * cols as usize * 2
+}
+
+fn entries(count: u8) -> Vec<DynamicEntry> {
+    vec![[0u8; DYNAMIC_ENTRY_SIZE]; count as usize]
+}
+
+fn entries_count(config: &Value, section: &str, default: usize) -> u8 {
+    match config[section].as_array() {
+        Some(items) => items.len() as u8,
+        None => default as u8,
+    }
+}
+
+impl Simulator {
+    /// Creates simulator of a keyboard described by vial.json `meta` with an empty keymap.
+    pub fn new(meta: &Value) -> Result<Simulator, Box<dyn std::error::Error>> {
+        let rows = meta["matrix"]["rows"]
+            .as_u64()
+            .ok_or("matrix/rows not found in meta")? as u8;
+        let cols = meta["matrix"]["cols"]
+            .as_u64()
+            .ok_or("matrix/cols not found in meta")? as u8;
+        let definition = lzma::compress(serde_json::to_string(meta)?.as_bytes(), 6)?;
+
+        let mut qmk_settings = BTreeMap::new();
+        for group in load_qmk_definitions()?["tabs"]
+            .as_array()
+            .ok_or("tabs should be an array")?
+        {
+            for field in group["fields"]
+                .as_array()
+                .ok_or("fields should be an array")?
+            {
+                let qsid = field["qsid"].as_u64().ok_or("qsid should be a number")? as u16;
+                qmk_settings.insert(qsid, 0);
+            }
+        }
+
+        let rgb = match meta["lighting"].as_str() {
+            Some("vialrgb") => Some(RgbState {
+                effect: 0,
+                effect_speed: 0,
+                color_h: 0,
+                color_s: 0,
+                color_v: 0,
+                leds: vec![(0, 0, 0); DEFAULT_LEDS_COUNT as usize],
+            }),
+            _ => None,
+        };
+
+        Ok(Simulator {
+            state: RefCell::new(SimulatorState {
+                definition,
+                uid: SIMULATOR_UID,
+                via_version: DEFAULT_VIA_VERSION,
+                vial_version: DEFAULT_VIAL_VERSION,
+                rows,
+                cols,
+                layers: DEFAULT_LAYERS,
+                keys: vec![0u8; keymap_size(DEFAULT_LAYERS, rows, cols)],
+                encoders: HashMap::new(),
+                tap_dances: entries(DEFAULT_DYNAMIC_ENTRIES),
+                combos: entries(DEFAULT_DYNAMIC_ENTRIES),
+                key_overrides: entries(DEFAULT_DYNAMIC_ENTRIES),
+                alt_repeats: entries(DEFAULT_DYNAMIC_ENTRIES),
+                macro_count: DEFAULT_MACRO_COUNT,
+                macro_buffer: vec![0u8; DEFAULT_MACRO_BUFFER_SIZE as usize],
+                qmk_settings,
+                layout_options: 0,
+                locked: false,
+                unlock_polls: None,
+                unlock_buttons: vec![(0, 0)],
+                rgb,
+                response: None,
+            }),
+        })
+    }
+
+    /// Seeds simulator with content of .vil `config` file.
+    ///
+    /// Layer and entry counts are taken from the file, then content is written
+    /// through regular protocol calls exactly as `load` subcommand does.
+    pub fn load_config(&self, config: &Value) -> Result<(), Box<dyn std::error::Error>> {
+        let layers = config["layout"]
+            .as_array()
+            .ok_or("config file has no layout defined")?;
+        {
+            let mut state = self.state.borrow_mut();
+            if let Some(uid) = config["uid"].as_u64() {
+                state.uid = uid;
+            }
+            if let Some(via_version) = config["via_protocol"].as_u64() {
+                state.via_version = via_version as u8;
+            }
+            if let Some(vial_version) = config["vial_protocol"].as_u64() {
+                state.vial_version = vial_version as u32;
+            }
+            state.layers = layers.len() as u8;
+            state.keys = vec![0u8; keymap_size(state.layers, state.rows, state.cols)];
+            state.encoders.clear();
+            let td_count = entries_count(config, "tap_dance", state.tap_dances.len());
+            state.tap_dances = entries(td_count);
+            let combo_count = entries_count(config, "combo", state.combos.len());
+            state.combos = entries(combo_count);
+            let ko_count = entries_count(config, "key_override", state.key_overrides.len());
+            state.key_overrides = entries(ko_count);
+            let ar_count = entries_count(config, "alt_repeat_key", state.alt_repeats.len());
+            state.alt_repeats = entries(ar_count);
+        }
+
+        let vial_version = self.state.borrow().vial_version;
+        let macros = match config.get("macro") {
+            Some(macros) => load_macros_from_json(macros, vial_version)?,
+            None => Vec::new(),
+        };
+        {
+            let mut state = self.state.borrow_mut();
+            // trailing empty macros are not saved, so file tells only lower bound
+            state.macro_count = state.macro_count.max(macros.len() as u8);
+            let buffer_size = serialize(&macros)
+                .len()
+                .max(DEFAULT_MACRO_BUFFER_SIZE.into());
+            state.macro_buffer = vec![0u8; buffer_size];
+        }
+
+        let capabilities = scan_capabilities(self)?;
+        if let Some(layout_options) = config["layout_options"].as_i64()
+            && layout_options >= 0
+        {
+            set_layout_options(self, layout_options as u32)?;
+        }
+        set_macros(self, &capabilities, &macros)?;
+        if let Some(tap_dances) = config.get("tap_dance") {
+            for td in load_tap_dances_from_json(tap_dances, vial_version)? {
+                set_tap_dance(self, &td)?;
+            }
+        }
+        if let Some(combos) = config.get("combo") {
+            for combo in load_combos_from_json(combos, vial_version)? {
+                set_combo(self, &combo)?;
+            }
+        }
+        if let Some(key_overrides) = config.get("key_override") {
+            for ko in load_key_overrides_from_json(key_overrides, vial_version)? {
+                set_key_override(self, &ko)?;
+            }
+        }
+        if let Some(alt_repeats) = config.get("alt_repeat_key") {
+            for ar in load_alt_repeats_from_json(alt_repeats, vial_version)? {
+                set_alt_repeat(self, &ar)?;
+            }
+        }
+        for (layer_number, layer_encoders) in
+            load_encoders_from_json(&config["encoder_layout"], vial_version)?
+                .iter()
+                .enumerate()
+        {
+            for encoder in layer_encoders {
+                set_encoder(self, layer_number as u8, encoder.index, 0, encoder.ccw)?;
+                set_encoder(self, layer_number as u8, encoder.index, 1, encoder.cw)?;
+            }
+        }
+        if let Some(settings) = config.get("settings") {
+            for (qsid, value) in load_qmk_settings_from_json(settings)? {
+                set_qmk_value(self, qsid, value.get())?;
+            }
+        }
+        let (rows, cols) = {
+            let state = self.state.borrow();
+            (state.rows, state.cols)
+        };
+        let keys = Keymap::from_json(rows, cols, capabilities.layer_count, layers, vial_version)?;
+        set_keymap(self, &keys)?;
+        Ok(())
+    }
+}
+
+impl SimulatorState {
+    fn handle(&mut self, msg: &[u8]) -> [u8; MESSAGE_LENGTH] {
+        let mut resp = [0u8; MESSAGE_LENGTH];
+        resp.copy_from_slice(msg);
+        let handled = match msg[0] {
+            CMD_VIA_GET_PROTOCOL_VERSION => {
+                resp[1] = 0;
+                resp[2] = self.via_version;
+                true
+            }
+            CMD_VIA_GET_KEYBOARD_VALUE if msg[1] == VIA_LAYOUT_OPTIONS => {
+                resp[2..6].copy_from_slice(&self.layout_options.to_be_bytes());
+                true
+            }
+            CMD_VIA_SET_KEYBOARD_VALUE if msg[1] == VIA_LAYOUT_OPTIONS => {
+                self.layout_options = u32::from_be_bytes([msg[2], msg[3], msg[4], msg[5]]);
+                true
+            }
+            CMD_VIA_SET_KEYCODE => match self.key_offset(msg[1], msg[2], msg[3]) {
+                Some(offset) => {
+                    self.keys[offset] = msg[4];
+                    self.keys[offset + 1] = msg[5];
+                    true
+                }
+                None => false,
+            },
+            CMD_VIA_GET_LAYER_COUNT => {
+                resp[1] = self.layers;
+                true
+            }
+            CMD_VIA_KEYMAP_GET_BUFFER => {
+                let offset = u16::from_be_bytes([msg[1], msg[2]]) as usize;
+                let size = msg[3] as usize;
+                if size <= MESSAGE_LENGTH - 4 && offset + size <= self.keys.len() {
+                    resp[4..4 + size].copy_from_slice(&self.keys[offset..offset + size]);
+                    true
+                } else {
+                    false
+                }
+            }
+            CMD_VIA_KEYMAP_SET_BUFFER => {
+                let offset = u16::from_be_bytes([msg[1], msg[2]]) as usize;
+                let size = msg[3] as usize;
+                if size <= MESSAGE_LENGTH - 4 && offset + size <= self.keys.len() {
+                    self.keys[offset..offset + size].copy_from_slice(&msg[4..4 + size]);
+                    true
+                } else {
+                    false
+                }
+            }
+            CMD_VIA_MACRO_GET_COUNT => {
+                resp[1] = self.macro_count;
+                true
+            }
+            CMD_VIA_MACRO_GET_BUFFER_SIZE => {
+                resp[1..3].copy_from_slice(&(self.macro_buffer.len() as u16).to_be_bytes());
+                true
+            }
+            CMD_VIA_MACRO_GET_BUFFER => {
+                let offset = u16::from_be_bytes([msg[1], msg[2]]) as usize;
+                let size = msg[3] as usize;
+                if size <= MESSAGE_LENGTH - 4 && offset + size <= self.macro_buffer.len() {
+                    resp[4..4 + size].copy_from_slice(&self.macro_buffer[offset..offset + size]);
+                    true
+                } else {
+                    false
+                }
+            }
+            CMD_VIA_MACRO_SET_BUFFER => {
+                let offset = u16::from_be_bytes([msg[1], msg[2]]) as usize;
+                let size = msg[3] as usize;
+                if !self.locked
+                    && size <= MESSAGE_LENGTH - 4
+                    && offset + size <= self.macro_buffer.len()
+                {
+                    self.macro_buffer[offset..offset + size].copy_from_slice(&msg[4..4 + size]);
+                    true
+                } else {
+                    false
+                }
+            }
+            CMD_VIA_LIGHTING_GET_VALUE | CMD_VIA_LIGHTING_SET_VALUE | CMD_VIA_LIGHTING_SAVE => {
+                self.handle_rgb(msg, &mut resp)
+            }
+            CMD_VIA_VIAL_PREFIX => self.handle_vial(msg, &mut resp),
+            _ => false,
+        };
+        if !handled {
+            resp[0] = VIA_UNHANDLED;
+        }
+        resp
+    }
+
+    fn key_offset(&self, layer: u8, row: u8, col: u8) -> Option<usize> {
+        if layer < self.layers && row < self.rows && col < self.cols {
+            Some(
+                (layer as usize * self.rows as usize * self.cols as usize * 2)
+                    + (row as usize * self.cols as usize * 2)
+                    + (col as usize * 2),
+            )
+        } else {
+            None
+        }
+    }
+
+    fn handle_rgb(&mut self, msg: &[u8], resp: &mut [u8; MESSAGE_LENGTH]) -> bool {
+        let Some(rgb) = &mut self.rgb else {
+            return false;
+        };
+        match (msg[0], msg[1]) {
+            (CMD_VIA_LIGHTING_GET_VALUE, VIALRGB_GET_INFO) => {
+                resp[2..4].copy_from_slice(&1u16.to_le_bytes());
+                resp[4] = RGB_MAX_BRIGHTNESS;
+            }
+            (CMD_VIA_LIGHTING_GET_VALUE, VIALRGB_GET_MODE) => {
+                resp[2..4].copy_from_slice(&rgb.effect.to_le_bytes());
+                resp[4] = rgb.effect_speed;
+                resp[5] = rgb.color_h;
+                resp[6] = rgb.color_s;
+                resp[7] = rgb.color_v;
+            }
+            (CMD_VIA_LIGHTING_GET_VALUE, VIALRGB_GET_SUPPORTED) => {
+                let after = u16::from_le_bytes([msg[2], msg[3]]);
+                resp[2..].fill(0xFF);
+                for (i, effect) in ((after + 1)..=RGB_EFFECTS_COUNT).take(15).enumerate() {
+                    resp[2 + i * 2..4 + i * 2].copy_from_slice(&effect.to_le_bytes());
+                }
+            }
+            (CMD_VIA_LIGHTING_GET_VALUE, VIALRGB_GET_NUMBER_LEDS) => {
+                resp[2..4].copy_from_slice(&(rgb.leds.len() as u16).to_le_bytes());
+            }
+            (CMD_VIA_LIGHTING_SET_VALUE, VIALRGB_SET_MODE) => {
+                rgb.effect = u16::from_le_bytes([msg[2], msg[3]]);
+                rgb.effect_speed = msg[4];
+                rgb.color_h = msg[5];
+                rgb.color_s = msg[6];
+                rgb.color_v = msg[7];
+            }
+            (CMD_VIA_LIGHTING_SET_VALUE, VIALRGB_DIRECT_FASTSET) => {
+                let first = u16::from_le_bytes([msg[2], msg[3]]) as usize;
+                for i in 0..msg[4] as usize {
+                    let start = 5 + i * 3;
+                    if start + 2 >= MESSAGE_LENGTH || first + i >= rgb.leds.len() {
+                        return false;
+                    }
+                    rgb.leds[first + i] = (msg[start], msg[start + 1], msg[start + 2]);
+                }
+            }
+            (CMD_VIA_LIGHTING_SAVE, _) => {}
+            _ => return false,
+        }
+        true
+    }
+
+    fn handle_vial(&mut self, msg: &[u8], resp: &mut [u8; MESSAGE_LENGTH]) -> bool {
+        match msg[1] {
+            CMD_VIAL_GET_KEYBOARD_ID => {
+                resp[0..4].copy_from_slice(&self.vial_version.to_le_bytes());
+                resp[4..12].copy_from_slice(&self.uid.to_le_bytes());
+            }
+            CMD_VIAL_GET_SIZE => {
+                resp[0..4].copy_from_slice(&(self.definition.len() as u32).to_le_bytes());
+            }
+            CMD_VIAL_GET_DEFINITION => {
+                let block = u32::from_le_bytes([msg[2], msg[3], msg[4], msg[5]]) as usize;
+                let start = block * MESSAGE_LENGTH;
+                resp.fill(0);
+                if start < self.definition.len() {
+                    let end = (start + MESSAGE_LENGTH).min(self.definition.len());
+                    resp[0..end - start].copy_from_slice(&self.definition[start..end]);
+                }
+            }
+            CMD_VIAL_GET_ENCODER => {
+                let [ccw, cw] = self
+                    .encoders
+                    .get(&(msg[2], msg[3]))
+                    .copied()
+                    .unwrap_or_default();
+                resp[0..2].copy_from_slice(&ccw.to_be_bytes());
+                resp[2..4].copy_from_slice(&cw.to_be_bytes());
+            }
+            CMD_VIAL_SET_ENCODER => {
+                if msg[2] >= self.layers || msg[4] > 1 {
+                    return false;
+                }
+                let encoder = self.encoders.entry((msg[2], msg[3])).or_default();
+                encoder[msg[4] as usize] = u16::from_be_bytes([msg[5], msg[6]]);
+            }
+            CMD_VIAL_GET_UNLOCK_STATUS => {
+                resp[0] = !self.locked as u8;
+                resp[1] = self.unlock_polls.is_some() as u8;
+                resp[2..].fill(0xFF);
+                for (i, (row, col)) in self.unlock_buttons.iter().take(15).enumerate() {
+                    resp[2 + i * 2] = *row;
+                    resp[3 + i * 2] = *col;
+                }
+            }
+            CMD_VIAL_UNLOCK_START => {
+                self.unlock_polls = Some(UNLOCK_POLLS);
+            }
+            CMD_VIAL_UNLOCK_POLL => {
+                // unlock buttons are considered to be held all the time
+                if let Some(polls) = self.unlock_polls {
+                    if polls <= 1 {
+                        self.unlock_polls = None;
+                        self.locked = false;
+                    } else {
+                        self.unlock_polls = Some(polls - 1);
+                    }
+                }
+                resp[0] = !self.locked as u8;
+                resp[1] = self.unlock_polls.is_some() as u8;
+                resp[2] = self.unlock_polls.unwrap_or(0);
+            }
+            CMD_VIAL_LOCK => {
+                self.locked = true;
+                self.unlock_polls = None;
+            }
+            CMD_VIAL_QMK_SETTINGS_QUERY => {
+                let after = u16::from_le_bytes([msg[2], msg[3]]);
+                resp.fill(0xFF);
+                for (i, qsid) in self
+                    .qmk_settings
+                    .keys()
+                    .filter(|qsid| **qsid > after)
+                    .take(MESSAGE_LENGTH / 2)
+                    .enumerate()
+                {
+                    resp[i * 2..i * 2 + 2].copy_from_slice(&qsid.to_le_bytes());
+                }
+            }
+            CMD_VIAL_QMK_SETTINGS_GET => {
+                let qsid = u16::from_le_bytes([msg[2], msg[3]]);
+                resp.fill(0);
+                match self.qmk_settings.get(&qsid) {
+                    Some(value) => resp[1..5].copy_from_slice(&value.to_le_bytes()),
+                    None => resp[0] = 1,
+                }
+            }
+            CMD_VIAL_QMK_SETTINGS_SET => {
+                let qsid = u16::from_le_bytes([msg[2], msg[3]]);
+                resp.fill(0);
+                match self.qmk_settings.get_mut(&qsid) {
+                    Some(value) => *value = u32::from_le_bytes([msg[4], msg[5], msg[6], msg[7]]),
+                    None => resp[0] = 1,
+                }
+            }
+            CMD_VIAL_QMK_SETTINGS_RESET => {
+                resp.fill(0);
+                self.qmk_settings.values_mut().for_each(|value| *value = 0);
+            }
+            CMD_VIAL_DYNAMIC_ENTRY_OP => return self.handle_dynamic_entry(msg, resp),
+            _ => return false,
+        }
+        true
+    }
+
+    fn handle_dynamic_entry(&mut self, msg: &[u8], resp: &mut [u8; MESSAGE_LENGTH]) -> bool {
+        let (entries, set) = match msg[2] {
+            DYNAMIC_VIAL_GET_NUMBER_OF_ENTRIES => {
+                resp.fill(0);
+                resp[0] = self.tap_dances.len() as u8;
+                resp[1] = self.combos.len() as u8;
+                resp[2] = self.key_overrides.len() as u8;
+                resp[3] = self.alt_repeats.len() as u8;
+                return true;
+            }
+            DYNAMIC_VIAL_TAP_DANCE_GET => (&mut self.tap_dances, false),
+            DYNAMIC_VIAL_TAP_DANCE_SET => (&mut self.tap_dances, true),
+            DYNAMIC_VIAL_COMBO_GET => (&mut self.combos, false),
+            DYNAMIC_VIAL_COMBO_SET => (&mut self.combos, true),
+            DYNAMIC_VIAL_KEY_OVERRIDE_GET => (&mut self.key_overrides, false),
+            DYNAMIC_VIAL_KEY_OVERRIDE_SET => (&mut self.key_overrides, true),
+            DYNAMIC_VIAL_ALT_REPEAT_KEY_GET => (&mut self.alt_repeats, false),
+            DYNAMIC_VIAL_ALT_REPEAT_KEY_SET => (&mut self.alt_repeats, true),
+            _ => return false,
+        };
+        let Some(entry) = entries.get_mut(msg[3] as usize) else {
+            return false;
+        };
+        resp.fill(0);
+        if set {
+            entry.copy_from_slice(&msg[4..4 + DYNAMIC_ENTRY_SIZE]);
+        } else {
+            resp[1..1 + DYNAMIC_ENTRY_SIZE].copy_from_slice(entry);
+        }
+        true
+    }
+}
+
+impl Transport for Simulator {
+    fn write(&self, data: &[u8]) -> HidResult<usize> {
+        let mut msg = [0u8; MESSAGE_LENGTH];
+        let payload = &data[1.min(data.len())..];
+        let size = payload.len().min(MESSAGE_LENGTH);
+        msg[..size].copy_from_slice(&payload[..size]);
+        let mut state = self.state.borrow_mut();
+        let response = state.handle(&msg);
+        state.response = Some(response);
+        Ok(data.len())
+    }
+
+    fn read_timeout(&self, buf: &mut [u8], _timeout: i32) -> HidResult<usize> {
+        match self.state.borrow_mut().response.take() {
+            Some(response) => {
+                let size = buf.len().min(MESSAGE_LENGTH);
+                buf[..size].copy_from_slice(&response[..size]);
+                Ok(size)
+            }
+            // same as HidDevice when nothing arrives before timeout
+            None => Ok(0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{Recorder, update_keymap};
+    use crate::protocol::{
+        combos_to_json, encoders_to_json, get_locked_status, load_combos, load_encoder,
+        load_layers_keys, load_layout_options, load_macros, load_qmk_settings, load_rgb_info,
+        load_tap_dances, load_uid, load_vial_meta, macros_to_json, reset_qmk_values, set_keycode,
+        set_locked, set_rgb_mode, start_unlock, tap_dances_to_json, unlock_poll,
+    };
+    use serde_json::json;
+
+    fn meta() -> Value {
+        json!({
+            "name": "Simulated",
+            "lighting": "vialrgb",
+            "matrix": {"rows": 2, "cols": 3},
+            "layouts": {
+                "labels": ["Split Backspace"],
+                "keymap": [["0,0", "0,1", "0,2"], ["1,0", "1,1", "1,2\n\n\n\n\n\n\n\n\ne"]]
+            }
+        })
+    }
+
+    fn config() -> Value {
+        json!({
+            "version": 1,
+            "uid": 42,
+            "layout": [
+                [["KC_A", "KC_B", "KC_C"], ["KC_D", "KC_E", "KC_F"]],
+                [["KC_TRANSPARENT", "KC_1", "KC_2"], ["KC_3", "KC_4", "KC_5"]]
+            ],
+            "layout_options": 1,
+            "encoder_layout": [[["KC_AUDIO_VOL_DOWN", "KC_AUDIO_VOL_UP"]], [["KC_PAGE_DOWN", "KC_PAGE_UP"]]],
+            "combo": [["KC_A", "KC_B", "KC_NO", "KC_NO", "KC_ESCAPE"], ["KC_NO", "KC_NO", "KC_NO", "KC_NO", "KC_NO"]],
+            "tap_dance": [["KC_A", "KC_LEFT_SHIFT", "KC_NO", "KC_NO", 200]],
+            "macro": [[["text", "hello"], ["tap", "KC_ENTER"]]],
+            "key_override": [],
+            "alt_repeat_key": [],
+            "settings": {"7": 180}
+        })
+    }
+
+    #[test]
+    fn test_simulator_capabilities_and_meta() {
+        let simulator = Simulator::new(&meta()).unwrap();
+        let capabilities = scan_capabilities(&simulator).unwrap();
+        assert_eq!(capabilities.via_version, DEFAULT_VIA_VERSION);
+        assert_eq!(capabilities.vial_version, DEFAULT_VIAL_VERSION);
+        assert_eq!(capabilities.layer_count, DEFAULT_LAYERS);
+        assert_eq!(capabilities.combo_count, DEFAULT_DYNAMIC_ENTRIES);
+        assert_eq!(capabilities.macro_count, DEFAULT_MACRO_COUNT);
+        assert_eq!(capabilities.macro_buffer_size, DEFAULT_MACRO_BUFFER_SIZE);
+        assert_eq!(load_vial_meta(&simulator).unwrap(), meta());
+        assert_eq!(load_uid(&simulator).unwrap(), SIMULATOR_UID);
+    }
+
+    #[test]
+    fn test_simulator_seeded_from_config() {
+        let config = config();
+        let simulator = Simulator::new(&meta()).unwrap();
+        simulator.load_config(&config).unwrap();
+        let capabilities = scan_capabilities(&simulator).unwrap();
+        assert_eq!(capabilities.layer_count, 2);
+        assert_eq!(capabilities.combo_count, 2);
+        assert_eq!(capabilities.tap_dance_count, 1);
+        assert_eq!(capabilities.key_override_count, 0);
+        assert_eq!(capabilities.macro_count, DEFAULT_MACRO_COUNT);
+        assert_eq!(load_uid(&simulator).unwrap(), 42);
+
+        let keys = load_layers_keys(&simulator, 2, 2, 3).unwrap();
+        assert_eq!(keys.to_json(6).unwrap(), config["layout"]);
+        let combos = load_combos(&simulator, 2).unwrap();
+        assert_eq!(json!(combos_to_json(&combos, 6).unwrap()), config["combo"]);
+        let tap_dances = load_tap_dances(&simulator, 1).unwrap();
+        assert_eq!(
+            json!(tap_dances_to_json(&tap_dances, 6).unwrap()),
+            config["tap_dance"]
+        );
+        let macros = load_macros(&simulator, 1, capabilities.macro_buffer_size).unwrap();
+        assert_eq!(json!(macros_to_json(&macros, 6).unwrap()), config["macro"]);
+        let encoders = vec![
+            vec![load_encoder(&simulator, 0, 0).unwrap()],
+            vec![load_encoder(&simulator, 1, 0).unwrap()],
+        ];
+        assert_eq!(
+            json!(encoders_to_json(&encoders, 6).unwrap()),
+            config["encoder_layout"]
+        );
+        assert_eq!(load_layout_options(&simulator).unwrap(), 1);
+        assert_eq!(load_qmk_settings(&simulator).unwrap()[&7].get(), 180);
+    }
+
+    #[test]
+    fn test_simulator_updates() {
+        let simulator = Simulator::new(&meta()).unwrap();
+        set_keycode(&simulator, 3, 1, 2, 0x0004).unwrap();
+        let keys = load_layers_keys(&simulator, DEFAULT_LAYERS, 2, 3).unwrap();
+        assert_eq!(keys.get(3, 1, 2), 0x0004);
+        assert_eq!(keys.get(3, 1, 1), 0x0000);
+
+        set_qmk_value(&simulator, 7, 250).unwrap();
+        assert_eq!(load_qmk_settings(&simulator).unwrap()[&7].get(), 250);
+        reset_qmk_values(&simulator).unwrap();
+        assert_eq!(load_qmk_settings(&simulator).unwrap()[&7].get(), 0);
+
+        let mut rgb = load_rgb_info(&simulator).unwrap();
+        assert_eq!(rgb.version, 1);
+        assert_eq!(rgb.effects.len(), RGB_EFFECTS_COUNT as usize + 1);
+        assert_eq!(rgb.leds_count, DEFAULT_LEDS_COUNT);
+        rgb.effect = 2;
+        rgb.set_color("#00FF00").unwrap();
+        set_rgb_mode(&simulator, &rgb).unwrap();
+        let updated = load_rgb_info(&simulator).unwrap();
+        assert_eq!(updated.effect, 2);
+        assert_eq!(updated.color_h, rgb.color_h);
+
+        // out of range writes are rejected like on real firmware
+        let unhandled =
+            crate::protocol::send_recv(&simulator, &[CMD_VIA_SET_KEYCODE, 9, 0, 0, 0, 4]);
+        assert_eq!(unhandled.unwrap()[0], VIA_UNHANDLED);
+    }
+
+    #[test]
+    fn test_simulator_keymap_set_buffer() {
+        let simulator = Simulator::new(&json!({"matrix": {"rows": 6, "cols": 7}})).unwrap();
+        let mut layers = Vec::new();
+        for layer in 0..DEFAULT_LAYERS {
+            let mut rows = Vec::new();
+            for row in 0..6 {
+                let cols: Vec<_> = (0..7)
+                    .map(|col| json!(format!("0x{:04x}", layer as u16 * 100 + row * 10 + col)))
+                    .collect();
+                rows.push(Value::Array(cols));
+            }
+            layers.push(Value::Array(rows));
+        }
+        let keymap = Keymap::from_json(6, 7, DEFAULT_LAYERS, &layers, 6).unwrap();
+        set_keymap(&simulator, &keymap).unwrap();
+        let keys = load_layers_keys(&simulator, DEFAULT_LAYERS, 6, 7).unwrap();
+        assert_eq!(keys.get(0, 0, 0), 0);
+        assert_eq!(keys.get(3, 5, 6), 356);
+        assert_eq!(keys.get(2, 4, 1), 241);
+    }
+
+    #[test]
+    fn test_simulator_update_keymap() {
+        let simulator = Simulator::new(&json!({"matrix": {"rows": 6, "cols": 7}})).unwrap();
+        let current = load_layers_keys(&simulator, DEFAULT_LAYERS, 6, 7).unwrap();
+        let mut layers = current.to_json(6).unwrap();
+        layers[1][2][3] = json!("KC_A");
+        layers[3][5][6] = json!("KC_B");
+        let keymap =
+            Keymap::from_json(6, 7, DEFAULT_LAYERS, layers.as_array().unwrap(), 6).unwrap();
+        assert_eq!(current.diff(&keymap).unwrap(), vec![(1, 2, 3), (3, 5, 6)]);
+        let smaller = Keymap::filled(DEFAULT_LAYERS, 6, 6, 0);
+        assert!(current.diff(&smaller).is_err());
+        assert!(update_keymap(&simulator, &current, &smaller).is_err());
+
+        let mut trace = Vec::new();
+        update_keymap(&Recorder::new(&simulator, &mut trace), &current, &keymap).unwrap();
+        let trace = String::from_utf8(trace).unwrap();
+        assert_eq!(trace.matches("CMD_VIA_KEYMAP_SET_BUFFER").count(), 2 * 2);
+        assert_eq!(
+            load_layers_keys(&simulator, DEFAULT_LAYERS, 6, 7).unwrap(),
+            keymap
+        );
+
+        let mut trace = Vec::new();
+        update_keymap(&Recorder::new(&simulator, &mut trace), &keymap, &keymap).unwrap();
+        assert!(trace.is_empty());
+    }
+
+    #[test]
+    fn test_simulator_lock_unlock() {
+        let simulator = Simulator::new(&meta()).unwrap();
+        assert!(!get_locked_status(&simulator).unwrap().locked);
+        set_locked(&simulator).unwrap();
+        let status = get_locked_status(&simulator).unwrap();
+        assert!(status.locked);
+        assert_eq!(status.unlock_buttons, vec![(0, 0)]);
+
+        start_unlock(&simulator).unwrap();
+        assert!(get_locked_status(&simulator).unwrap().unlock_in_progress);
+        let mut polls = 0;
+        while !unlock_poll(&simulator).unwrap().0 {
+            polls += 1;
+        }
+        assert_eq!(polls, UNLOCK_POLLS - 1);
+        assert!(!get_locked_status(&simulator).unwrap().locked);
+    }
+}
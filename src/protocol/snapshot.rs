@@ -0,0 +1,849 @@
+use crate::keymap;
+use crate::protocol::vial_config::{LayoutKey, Section, VialConfig};
+use crate::protocol::{
+    AltRepeat, Capabilities, Combo, Encoder, KeyOverride, Keymap, Macro, ProtocolError, QmkValue,
+    TapDance, Transport, VIAL_PROTOCOL_QMK_SETTINGS, alt_repeats_to_config, combos_to_config,
+    encoders_to_config, get_locked_status, key_overrides_to_config, load_alt_repeats,
+    load_alt_repeats_from_config, load_combos, load_combos_from_config, load_encoder,
+    load_encoders_from_config, load_key_overrides, load_key_overrides_from_config,
+    load_layers_keys, load_layout_options, load_macros, load_macros_from_config, load_qmk_settings,
+    load_qmk_settings_from_config, load_tap_dances, load_tap_dances_from_config, load_uid,
+    macros_to_config, qmk_settings_to_config, set_alt_repeat, set_combo, set_encoder,
+    set_key_override, set_layout_options, set_macros, set_qmk_value, set_tap_dance,
+    tap_dances_to_config, update_keymap,
+};
+use serde_json::{Map, Value};
+use std::cmp::max;
+use std::collections::{HashMap, HashSet};
+
+/// Whole keyboard configuration, the same content as .vil file has.
+#[derive(Debug, PartialEq)]
+pub struct Snapshot {
+    pub uid: u64,
+    pub via_version: u8,
+    pub vial_version: u32,
+    pub keys: Keymap,
+    pub encoders: Vec<Vec<Encoder>>,
+    pub combos: Vec<Combo>,
+    pub tap_dances: Vec<TapDance>,
+    pub macros: Vec<Macro>,
+    pub key_overrides: Vec<KeyOverride>,
+    pub alt_repeats: Vec<AltRepeat>,
+    pub qmk_settings: HashMap<u16, QmkValue>,
+    /// -1 when keyboard has no layout options
+    pub layout_options: i64,
+}
+
+/// Numbers of entries written by Snapshot::apply.
+#[derive(Debug, Default, PartialEq)]
+pub struct ApplyReport {
+    pub keys: usize,
+    pub encoders: usize,
+    pub combos: usize,
+    pub tap_dances: usize,
+    pub macros: usize,
+    pub key_overrides: usize,
+    pub alt_repeats: usize,
+    pub settings: usize,
+    pub layout_options: bool,
+}
+
+/// Typed entries of changed section, entries equal to `before` keep their form from `original`
+/// file. Number of entries in file is kept unless entries beyond it are set.
+fn merged_entries<T: PartialEq, E: Clone>(
+    typed: Vec<E>,
+    entries: &[T],
+    before: &[T],
+    original: &Option<Vec<E>>,
+    is_empty: fn(&T) -> bool,
+) -> Vec<E> {
+    let original = original.as_deref().unwrap_or_default();
+    let used = entries
+        .iter()
+        .rposition(|e| !is_empty(e))
+        .map_or(0, |i| i + 1);
+    let mut result: Vec<E> = typed
+        .into_iter()
+        .enumerate()
+        .map(|(i, entry)| match original.get(i) {
+            Some(original) if entries.get(i) == before.get(i) => original.clone(),
+            _ => entry,
+        })
+        .collect();
+    // keyboard reports macros only up to the last one in buffer
+    if result.len() < original.len() {
+        result.extend_from_slice(&original[result.len()..]);
+    }
+    result.truncate(max(used, original.len()));
+    result
+}
+
+/// Entries of `new` which differ from entries with the same index in `current`, `name` of
+/// entries is used in error when `new` has more entries than device supports.
+fn changed_entries<'a, T: PartialEq>(
+    current: &[T],
+    new: &'a [T],
+    name: &str,
+) -> Result<Vec<&'a T>, ProtocolError> {
+    if new.len() > current.len() {
+        return Err(ProtocolError::General(format!(
+            "configuration has {} {}, keyboard supports {}",
+            new.len(),
+            name,
+            current.len()
+        )));
+    }
+    Ok(new
+        .iter()
+        .zip(current)
+        .filter(|(entry, current)| entry != current)
+        .map(|(entry, _)| entry)
+        .collect())
+}
+
+impl Snapshot {
+    pub fn load(
+        device: &impl Transport,
+        capabilities: &Capabilities,
+        meta: &Value,
+    ) -> Result<Snapshot, Box<dyn std::error::Error>> {
+        let uid: u64 = load_uid(device)?;
+        let cols = meta["matrix"]["cols"]
+            .as_u64()
+            .ok_or("matrix/cols not found in meta")? as u8;
+        let rows = meta["matrix"]["rows"]
+            .as_u64()
+            .ok_or("matrix/rows not found in meta")? as u8;
+
+        let encoders_count = keymap::get_encoders_count(&meta["layouts"]["keymap"])?;
+        let mut encoders = Vec::new();
+        for layer_number in 0..capabilities.layer_count {
+            let mut layer_encoders = Vec::new();
+            for encoder_index in 0..encoders_count {
+                layer_encoders.push(load_encoder(device, layer_number, encoder_index)?);
+            }
+            encoders.push(layer_encoders);
+        }
+
+        let keys = load_layers_keys(device, capabilities.layer_count, rows, cols)?;
+        let combos = match capabilities.combo_count {
+            0 => Vec::new(),
+            _ => load_combos(device, capabilities.combo_count)?,
+        };
+        let tap_dances = match capabilities.tap_dance_count {
+            0 => Vec::new(),
+            _ => load_tap_dances(device, capabilities.tap_dance_count)?,
+        };
+        let macros = load_macros(
+            device,
+            capabilities.macro_count,
+            capabilities.macro_buffer_size,
+        )?;
+        let key_overrides = match capabilities.key_override_count {
+            0 => Vec::new(),
+            _ => load_key_overrides(device, capabilities.key_override_count)?,
+        };
+        let alt_repeats = match capabilities.alt_repeat_key_count {
+            0 => Vec::new(),
+            _ => load_alt_repeats(device, capabilities.alt_repeat_key_count)?,
+        };
+        let qmk_settings = if capabilities.vial_version >= VIAL_PROTOCOL_QMK_SETTINGS {
+            load_qmk_settings(device)?
+        } else {
+            HashMap::new()
+        };
+        let layout_options = match &meta["layouts"]["labels"] {
+            Value::Null => -1,
+            _ => load_layout_options(device)? as i64,
+        };
+
+        Ok(Snapshot {
+            uid,
+            via_version: capabilities.via_version,
+            vial_version: capabilities.vial_version,
+            keys,
+            encoders,
+            combos,
+            tap_dances,
+            macros,
+            key_overrides,
+            alt_repeats,
+            qmk_settings,
+            layout_options,
+        })
+    }
+
+    /// Parses content of .vil file, missing sections are treated as empty.
+    #[allow(dead_code)]
+    pub fn from_json(root: &Value) -> Result<Snapshot, Box<dyn std::error::Error>> {
+        let config = VialConfig::from_json(root)?;
+        let vial_version = config.vial_protocol.unwrap_or(0);
+        Snapshot::from_config(&config, vial_version)
+    }
+
+    /// Converts typed .vil content, keycodes are parsed according to `vial_version`.
+    pub fn from_config(
+        config: &VialConfig,
+        vial_version: u32,
+    ) -> Result<Snapshot, Box<dyn std::error::Error>> {
+        Ok(Snapshot {
+            uid: config.uid,
+            via_version: config.via_protocol.unwrap_or(0),
+            vial_version,
+            keys: Keymap::from_config(&config.layout, vial_version)?,
+            encoders: load_encoders_from_config(
+                config.encoder_layout.as_deref().unwrap_or_default(),
+                vial_version,
+            )?,
+            combos: load_combos_from_config(
+                config.combo.as_deref().unwrap_or_default(),
+                vial_version,
+            )?,
+            tap_dances: load_tap_dances_from_config(
+                config.tap_dance.as_deref().unwrap_or_default(),
+                vial_version,
+            )?,
+            macros: load_macros_from_config(
+                config.macros.as_deref().unwrap_or_default(),
+                vial_version,
+            )?,
+            key_overrides: load_key_overrides_from_config(
+                config.key_override.as_deref().unwrap_or_default(),
+                vial_version,
+            )?,
+            alt_repeats: load_alt_repeats_from_config(
+                config.alt_repeat_key.as_deref().unwrap_or_default(),
+                vial_version,
+            )?,
+            qmk_settings: config
+                .settings
+                .as_ref()
+                .map(load_qmk_settings_from_config)
+                .unwrap_or_default(),
+            layout_options: config.layout_options.unwrap_or(-1),
+        })
+    }
+
+    /// Writes into device only entries which differ from its current state.
+    pub fn apply(
+        &self,
+        device: &impl Transport,
+        capabilities: &Capabilities,
+        meta: &Value,
+    ) -> Result<ApplyReport, Box<dyn std::error::Error>> {
+        self.apply_sections(device, capabilities, meta, &HashSet::from(Section::ALL))
+    }
+
+    /// The same as apply, but other sections than listed in `sections` are left untouched.
+    pub fn apply_sections(
+        &self,
+        device: &impl Transport,
+        capabilities: &Capabilities,
+        meta: &Value,
+        sections: &HashSet<Section>,
+    ) -> Result<ApplyReport, Box<dyn std::error::Error>> {
+        let cols = meta["matrix"]["cols"]
+            .as_u64()
+            .ok_or("matrix/cols not found in meta")? as u8;
+        let rows = meta["matrix"]["rows"]
+            .as_u64()
+            .ok_or("matrix/rows not found in meta")? as u8;
+        let (layers, file_rows, file_cols) = self.keys.dimensions();
+        if sections.contains(&Section::Keys)
+            && (layers, file_rows, file_cols) != (capabilities.layer_count, rows, cols)
+        {
+            return Err(ProtocolError::General(format!(
+                "configuration has {} layers of {}x{} keys, keyboard has {} layers of {}x{} keys",
+                layers, file_rows, file_cols, capabilities.layer_count, rows, cols
+            ))
+            .into());
+        }
+        let encoders_count = keymap::get_encoders_count(&meta["layouts"]["keymap"])?;
+        if sections.contains(&Section::Encoders) {
+            if self.encoders.len() > capabilities.layer_count as usize {
+                return Err(ProtocolError::General(format!(
+                    "configuration has encoders for {} layers, keyboard has {}",
+                    self.encoders.len(),
+                    capabilities.layer_count
+                ))
+                .into());
+            }
+            if let Some(encoder) = self
+                .encoders
+                .iter()
+                .flatten()
+                .find(|encoder| encoder.index >= encoders_count)
+            {
+                return Err(ProtocolError::General(format!(
+                    "configuration has encoder {}, keyboard has {} encoders",
+                    encoder.index, encoders_count
+                ))
+                .into());
+            }
+        }
+        let mut report = ApplyReport::default();
+
+        if sections.contains(&Section::Macros) {
+            let current_macros = load_macros(
+                device,
+                capabilities.macro_count,
+                capabilities.macro_buffer_size,
+            )?;
+            for idx in 0..max(current_macros.len(), self.macros.len()) {
+                let changed = match (current_macros.get(idx), self.macros.get(idx)) {
+                    (Some(current), Some(new)) => current != new,
+                    (Some(m), None) | (None, Some(m)) => !m.is_empty(),
+                    (None, None) => false,
+                };
+                if changed {
+                    report.macros += 1;
+                }
+            }
+        }
+
+        // entries are compared before anything is written, so configuration with more entries
+        // than keyboard supports doesn't leave it partially updated
+        let mut changed_key_overrides = Vec::new();
+        if sections.contains(&Section::KeyOverrides) {
+            let current_key_overrides = match capabilities.key_override_count {
+                0 => Vec::new(),
+                _ => load_key_overrides(device, capabilities.key_override_count)?,
+            };
+            changed_key_overrides =
+                changed_entries(&current_key_overrides, &self.key_overrides, "key overrides")?;
+        }
+        let mut changed_alt_repeats = Vec::new();
+        if sections.contains(&Section::AltRepeats) {
+            let current_alt_repeats = match capabilities.alt_repeat_key_count {
+                0 => Vec::new(),
+                _ => load_alt_repeats(device, capabilities.alt_repeat_key_count)?,
+            };
+            changed_alt_repeats =
+                changed_entries(&current_alt_repeats, &self.alt_repeats, "alt repeat keys")?;
+        }
+        let mut changed_combos = Vec::new();
+        if sections.contains(&Section::Combos) {
+            let current_combos = match capabilities.combo_count {
+                0 => Vec::new(),
+                _ => load_combos(device, capabilities.combo_count)?,
+            };
+            changed_combos = changed_entries(&current_combos, &self.combos, "combos")?;
+        }
+        let mut changed_tap_dances = Vec::new();
+        if sections.contains(&Section::TapDances) {
+            let current_tap_dances = match capabilities.tap_dance_count {
+                0 => Vec::new(),
+                _ => load_tap_dances(device, capabilities.tap_dance_count)?,
+            };
+            changed_tap_dances =
+                changed_entries(&current_tap_dances, &self.tap_dances, "tap dances")?;
+        }
+
+        if report.macros > 0 && capabilities.vial_version > 0 && get_locked_status(device)?.locked {
+            return Err(ProtocolError::KeyboardLocked.into());
+        }
+
+        if sections.contains(&Section::Layout) && self.layout_options >= 0 {
+            let state = self.layout_options as u32;
+            if load_layout_options(device)? != state {
+                set_layout_options(device, state)?;
+                report.layout_options = true;
+            }
+        }
+
+        if report.macros > 0 {
+            set_macros(device, capabilities, &self.macros)?;
+        }
+
+        for ko in &changed_key_overrides {
+            set_key_override(device, ko)?;
+        }
+        report.key_overrides = changed_key_overrides.len();
+        for ar in &changed_alt_repeats {
+            set_alt_repeat(device, ar)?;
+        }
+        report.alt_repeats = changed_alt_repeats.len();
+        for combo in &changed_combos {
+            set_combo(device, combo)?;
+        }
+        report.combos = changed_combos.len();
+        for td in &changed_tap_dances {
+            set_tap_dance(device, td)?;
+        }
+        report.tap_dances = changed_tap_dances.len();
+
+        if sections.contains(&Section::Encoders) {
+            for (layer_number, layer_encoders) in self.encoders.iter().enumerate() {
+                let layer_number = layer_number as u8;
+                for encoder in layer_encoders {
+                    let current = load_encoder(device, layer_number, encoder.index)?;
+                    if current.ccw != encoder.ccw {
+                        set_encoder(device, layer_number, encoder.index, 0, encoder.ccw)?;
+                    }
+                    if current.cw != encoder.cw {
+                        set_encoder(device, layer_number, encoder.index, 1, encoder.cw)?;
+                    }
+                    if current != *encoder {
+                        report.encoders += 1;
+                    }
+                }
+            }
+        }
+
+        if sections.contains(&Section::Settings)
+            && capabilities.vial_version >= VIAL_PROTOCOL_QMK_SETTINGS
+            && !self.qmk_settings.is_empty()
+        {
+            let current_settings = load_qmk_settings(device)?;
+            for (qsid, value) in &self.qmk_settings {
+                // settings unknown to firmware are skipped
+                if let Some(current) = current_settings.get(qsid)
+                    && current != value
+                {
+                    set_qmk_value(device, *qsid, value.get())?;
+                    report.settings += 1;
+                }
+            }
+        }
+
+        if sections.contains(&Section::Keys) {
+            let current_keys = load_layers_keys(device, layers, rows, cols)?;
+            report.keys = current_keys.diff(&self.keys)?.len();
+            update_keymap(device, &current_keys, &self.keys)?;
+        }
+
+        Ok(report)
+    }
+
+    /// Adds empty combos, tap dances, macros, key overrides and alt repeat keys up to counts
+    /// supported by keyboard, so entries missing in configuration are cleared when it is applied.
+    pub fn fill_empty_entries(&mut self, capabilities: &Capabilities) {
+        for index in self.combos.len()..capabilities.combo_count as usize {
+            self.combos.push(Combo::empty(index as u8));
+        }
+        for index in self.tap_dances.len()..capabilities.tap_dance_count as usize {
+            self.tap_dances.push(TapDance::empty(index as u8));
+        }
+        for index in self.macros.len()..capabilities.macro_count as usize {
+            self.macros.push(Macro {
+                index: index as u8,
+                steps: Vec::new(),
+            });
+        }
+        for index in self.key_overrides.len()..capabilities.key_override_count as usize {
+            self.key_overrides.push(KeyOverride::empty(index as u8));
+        }
+        for index in self.alt_repeats.len()..capabilities.alt_repeat_key_count as usize {
+            self.alt_repeats.push(AltRepeat::empty(index as u8));
+        }
+    }
+
+    /// Renders content of .vil file.
+    #[allow(dead_code)]
+    pub fn to_json(&self) -> Result<Value, Box<dyn std::error::Error>> {
+        self.to_config()?.to_json()
+    }
+
+    /// Writes into .vil content only sections which differ from `before`, so untouched sections,
+    /// unknown fields and positions missing in layout (-1) stay as they are in file. Number of
+    /// entries in file is kept unless entries beyond it are set.
+    pub fn update_config(
+        &self,
+        before: &Snapshot,
+        config: &mut VialConfig,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let vial_version = self.vial_version;
+        if self.keys != before.keys {
+            let mut layout = self.keys.to_config(vial_version);
+            for (layer, rows) in config.layout.iter().enumerate() {
+                for (row, keys) in rows.iter().enumerate() {
+                    for (col, key) in keys.iter().enumerate() {
+                        if let LayoutKey::Number(_) = key
+                            && let Some(position) = layout
+                                .get_mut(layer)
+                                .and_then(|rows| rows.get_mut(row))
+                                .and_then(|keys| keys.get_mut(col))
+                        {
+                            *position = key.clone();
+                        }
+                    }
+                }
+            }
+            config.layout = layout;
+        }
+        if self.encoders != before.encoders {
+            config.encoder_layout = Some(encoders_to_config(&self.encoders, vial_version));
+        }
+        if self.layout_options != before.layout_options {
+            config.layout_options = Some(self.layout_options);
+        }
+        if self.macros != before.macros {
+            config.macros = Some(merged_entries(
+                macros_to_config(&self.macros, vial_version),
+                &self.macros,
+                &before.macros,
+                &config.macros,
+                Macro::is_empty,
+            ));
+        }
+        if self.tap_dances != before.tap_dances {
+            config.tap_dance = Some(merged_entries(
+                tap_dances_to_config(&self.tap_dances, vial_version),
+                &self.tap_dances,
+                &before.tap_dances,
+                &config.tap_dance,
+                TapDance::is_empty,
+            ));
+        }
+        if self.combos != before.combos {
+            config.combo = Some(merged_entries(
+                combos_to_config(&self.combos, vial_version),
+                &self.combos,
+                &before.combos,
+                &config.combo,
+                Combo::is_empty,
+            ));
+        }
+        if self.key_overrides != before.key_overrides {
+            config.key_override = Some(merged_entries(
+                key_overrides_to_config(&self.key_overrides, vial_version),
+                &self.key_overrides,
+                &before.key_overrides,
+                &config.key_override,
+                KeyOverride::is_empty,
+            ));
+        }
+        if self.alt_repeats != before.alt_repeats {
+            config.alt_repeat_key = Some(merged_entries(
+                alt_repeats_to_config(&self.alt_repeats, vial_version),
+                &self.alt_repeats,
+                &before.alt_repeats,
+                &config.alt_repeat_key,
+                AltRepeat::is_empty,
+            ));
+        }
+        if self.qmk_settings != before.qmk_settings {
+            let mut settings = config.settings.take().unwrap_or_default();
+            for (qsid, value) in &self.qmk_settings {
+                if settings.contains_key(qsid) || before.qmk_settings.get(qsid) != Some(value) {
+                    settings.insert(*qsid, value.get());
+                }
+            }
+            config.settings = Some(settings);
+        }
+        Ok(())
+    }
+
+    /// Typed .vil content, empty dynamic entry sections are omitted like Vial GUI does.
+    pub fn to_config(&self) -> Result<VialConfig, Box<dyn std::error::Error>> {
+        let vial_version = self.vial_version;
+        Ok(VialConfig {
+            version: 1,
+            uid: self.uid,
+            layout: self.keys.to_config(vial_version),
+            encoder_layout: Some(encoders_to_config(&self.encoders, vial_version)),
+            layout_options: Some(self.layout_options),
+            macros: Some(macros_to_config(&self.macros, vial_version)),
+            vial_protocol: (vial_version > 0).then_some(vial_version),
+            via_protocol: Some(self.via_version),
+            tap_dance: (!self.tap_dances.is_empty())
+                .then(|| tap_dances_to_config(&self.tap_dances, vial_version)),
+            combo: (!self.combos.is_empty()).then(|| combos_to_config(&self.combos, vial_version)),
+            key_override: (!self.key_overrides.is_empty())
+                .then(|| key_overrides_to_config(&self.key_overrides, vial_version)),
+            alt_repeat_key: (!self.alt_repeats.is_empty())
+                .then(|| alt_repeats_to_config(&self.alt_repeats, vial_version)),
+            settings: (!self.qmk_settings.is_empty())
+                .then(|| qmk_settings_to_config(&self.qmk_settings)),
+            extra: Map::new(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::vial_config::MacroAction;
+    use crate::protocol::{
+        MacroStep, Simulator, scan_capabilities, set_combo, set_keycode, set_macros,
+    };
+    use serde_json::json;
+
+    fn config() -> Value {
+        json!({
+            "version": 1,
+            "via_protocol": 9,
+            "vial_protocol": 6,
+            "uid": 42,
+            "layout": [
+                [["KC_A", "KC_B", "KC_C"], ["KC_D", "KC_E", "KC_F"]],
+                [["KC_TRANSPARENT", "KC_1", "KC_2"], ["KC_3", "KC_4", "KC_5"]]
+            ],
+            "layout_options": 1,
+            "encoder_layout": [[["KC_AUDIO_VOL_DOWN", "KC_AUDIO_VOL_UP"]], [["KC_PAGE_DOWN", "KC_PAGE_UP"]]],
+            "combo": [["KC_A", "KC_B", "KC_NO", "KC_NO", "KC_ESCAPE"]],
+            "tap_dance": [["KC_A", "KC_LEFT_SHIFT", "KC_NO", "KC_NO", 200]],
+            "macro": [[["text", "hello"], ["tap", "KC_ENTER"]]],
+            "settings": {"7": 180}
+        })
+    }
+
+    #[test]
+    fn test_snapshot_json_round_trip() {
+        let snapshot = Snapshot::from_json(&config()).unwrap();
+        assert_eq!(snapshot.keys.dimensions(), (2, 2, 3));
+        assert_eq!(snapshot.combos.len(), 1);
+        assert!(snapshot.key_overrides.is_empty());
+        assert_eq!(snapshot.qmk_settings[&7].get(), 180);
+        assert_eq!(snapshot.to_json().unwrap(), config());
+    }
+
+    #[test]
+    fn test_snapshot_vial_file() {
+        let sample = include_str!("testdata/vial_v6.vil");
+        let config: VialConfig = serde_json::from_str(sample).unwrap();
+        let snapshot = Snapshot::from_config(&config, 6).unwrap();
+        let saved = snapshot.to_config().unwrap();
+        assert_eq!(Snapshot::from_config(&saved, 6).unwrap(), snapshot);
+        assert_eq!(saved.settings, config.settings);
+        assert_eq!(saved.alt_repeat_key, config.alt_repeat_key);
+        let text = saved.to_vil_string().unwrap();
+        let positions: Vec<usize> = [
+            "version",
+            "uid",
+            "layout",
+            "encoder_layout",
+            "layout_options",
+            "macro",
+            "vial_protocol",
+            "via_protocol",
+            "tap_dance",
+            "combo",
+            "key_override",
+            "alt_repeat_key",
+            "settings",
+        ]
+        .iter()
+        .map(|key| text.find(&format!("\"{}\": ", key)).unwrap())
+        .collect();
+        assert!(positions.is_sorted());
+        assert!(text.ends_with(r#""settings": {"1": 0, "2": 5000, "7": 180, "21": 1}}"#));
+    }
+
+    #[test]
+    fn test_snapshot_missing_sections() {
+        let snapshot = Snapshot::from_json(&json!({
+            "layout": [[["KC_ESC"]]]
+        }))
+        .unwrap();
+        assert_eq!(snapshot.vial_version, 0);
+        assert_eq!(snapshot.layout_options, -1);
+        assert!(snapshot.encoders.is_empty());
+        assert!(snapshot.macros.is_empty());
+        assert!(snapshot.qmk_settings.is_empty());
+        assert_eq!(
+            snapshot.to_json().unwrap()["layout"],
+            json!([[["KC_ESCAPE"]]])
+        );
+        assert_eq!(
+            Snapshot::from_json(&json!({})).unwrap().keys.dimensions(),
+            (0, 0, 0)
+        );
+        assert!(Snapshot::from_json(&json!({"layout": {}})).is_err());
+    }
+
+    #[test]
+    fn test_snapshot_ragged_layout() {
+        let mut ragged_row = config();
+        ragged_row["layout"][1][1] = json!(["KC_3", "KC_4"]);
+        let error = Snapshot::from_json(&ragged_row).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "layer 1 row 1 has 2 keys, row 0 of layer 0 has 3"
+        );
+        let mut ragged_layer = config();
+        ragged_layer["layout"][1] = json!([["KC_1", "KC_2", "KC_3"]]);
+        let error = Snapshot::from_json(&ragged_layer).unwrap_err();
+        assert_eq!(error.to_string(), "layer 1 has 1 rows, layer 0 has 2");
+    }
+
+    #[test]
+    fn test_snapshot_load() {
+        let meta = json!({
+            "matrix": {"rows": 2, "cols": 3},
+            "layouts": {
+                "labels": ["Split Backspace"],
+                "keymap": [
+                    ["0,0", "0,1", "0,2"],
+                    ["1,0", "1,1", "1,2", "0,0\n\n\n\n\n\n\n\n\ne", "0,1\n\n\n\n\n\n\n\n\ne"]
+                ]
+            }
+        });
+        let simulator = Simulator::new(&meta).unwrap();
+        simulator.load_config(&config()).unwrap();
+        let capabilities = scan_capabilities(&simulator).unwrap();
+        let snapshot = Snapshot::load(&simulator, &capabilities, &meta).unwrap();
+        let expected = config();
+        let json = snapshot.to_json().unwrap();
+        assert_eq!(json["layout"], expected["layout"]);
+        assert_eq!(json["encoder_layout"], expected["encoder_layout"]);
+        assert_eq!(json["combo"], expected["combo"]);
+        assert_eq!(json["macro"], expected["macro"]);
+        assert_eq!(json["layout_options"], 1);
+        assert_eq!(Snapshot::from_json(&json).unwrap(), snapshot);
+    }
+
+    #[test]
+    fn test_snapshot_update_config() {
+        let sample = include_str!("testdata/vial_v6.vil");
+        let mut root: Value = serde_json::from_str(sample).unwrap();
+        let meta = json!({
+            "matrix": {"rows": 2, "cols": 3},
+            "layouts": {
+                "labels": ["Split Backspace"],
+                "keymap": [
+                    ["0,0", "0,1", "0,2"],
+                    ["1,0", "1,1", "0,0\n\n\n\n\n\n\n\n\ne"]
+                ]
+            }
+        });
+        let simulator = Simulator::new(&meta).unwrap();
+        simulator.load_config(&root).unwrap();
+        let capabilities = scan_capabilities(&simulator).unwrap();
+        let before = Snapshot::load(&simulator, &capabilities, &meta).unwrap();
+
+        // untouched file is written back byte for byte
+        let mut config: VialConfig = serde_json::from_str(sample).unwrap();
+        before.update_config(&before, &mut config).unwrap();
+        assert_eq!(config.to_vil_string().unwrap(), sample);
+
+        root["custom"] = json!({"kept": true});
+        set_keycode(&simulator, 1, 0, 0, 0x04).unwrap();
+        let mut combo = Combo::empty(1);
+        (combo.key1, combo.key2, combo.output) = (0x04, 0x05, 0x06);
+        set_combo(&simulator, &combo).unwrap();
+        let mut macros = Snapshot::load(&simulator, &capabilities, &meta)
+            .unwrap()
+            .macros;
+        macros.push(Macro {
+            index: 2,
+            steps: vec![MacroStep::Tap(0x05)],
+        });
+        set_macros(&simulator, &capabilities, &macros).unwrap();
+        let after = Snapshot::load(&simulator, &capabilities, &meta).unwrap();
+        let mut config = VialConfig::from_json(&root).unwrap();
+        after.update_config(&before, &mut config).unwrap();
+        let original = VialConfig::from_json(&root).unwrap();
+        assert_eq!(config.extra["custom"], json!({"kept": true}));
+        assert_eq!(config.layout[1][0][0], LayoutKey::Name("KC_A".to_string()));
+        assert_eq!(config.layout[0][1][2], LayoutKey::Number(-1));
+        assert_eq!(config.combo.as_ref().unwrap()[1][4], "KC_C");
+        assert_eq!(config.combo.as_ref().unwrap().len(), 2);
+        let macros = config.macros.as_ref().unwrap();
+        let original_macros = original.macros.as_ref().unwrap();
+        assert_eq!(macros.len(), 4);
+        assert_eq!(macros[0], original_macros[0]);
+        assert_eq!(macros[2], vec![MacroAction::Tap(vec!["KC_B".to_string()])]);
+        assert_eq!(macros[3], original_macros[3]);
+        assert_eq!(config.tap_dance, original.tap_dance);
+        assert_eq!(config.settings, original.settings);
+        assert_eq!(config.encoder_layout, original.encoder_layout);
+    }
+
+    #[test]
+    fn test_snapshot_apply_sections() {
+        let meta = json!({"matrix": {"rows": 2, "cols": 3}, "layouts": {"keymap": []}});
+        let simulator = Simulator::new(&meta).unwrap();
+        let capabilities = scan_capabilities(&simulator).unwrap();
+        let before = Snapshot::load(&simulator, &capabilities, &meta).unwrap();
+        let snapshot = Snapshot::from_json(&config()).unwrap();
+        let sections = HashSet::from([Section::Combos, Section::Macros]);
+        let report = snapshot
+            .apply_sections(&simulator, &capabilities, &meta, &sections)
+            .unwrap();
+        assert_eq!((report.keys, report.combos, report.macros), (0, 1, 1));
+        assert_eq!(report.settings, 0);
+        let after = Snapshot::load(&simulator, &capabilities, &meta).unwrap();
+        assert_eq!(after.keys, before.keys);
+        assert_eq!(after.qmk_settings, before.qmk_settings);
+        assert_eq!(after.combos[0], snapshot.combos[0]);
+        assert_eq!(after.macros[0], snapshot.macros[0]);
+
+        let mut overflow = Snapshot::from_json(&config()).unwrap();
+        overflow.fill_empty_entries(&capabilities);
+        overflow.combos[0] = Combo::empty(0);
+        overflow.combos.push(Combo::empty(capabilities.combo_count));
+        let error = overflow
+            .apply_sections(&simulator, &capabilities, &meta, &sections)
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "Error configuration has {} combos, keyboard supports {}",
+                capabilities.combo_count as usize + 1,
+                capabilities.combo_count
+            )
+        );
+        let unchanged = Snapshot::load(&simulator, &capabilities, &meta).unwrap();
+        assert_eq!(unchanged.combos[0], snapshot.combos[0]);
+    }
+
+    #[test]
+    fn test_snapshot_apply_dimensions() {
+        let meta = json!({"matrix": {"rows": 2, "cols": 3}, "layouts": {"keymap": [["0,0", "0,0\n\n\n\n\n\n\n\n\ne"]]}});
+        let simulator = Simulator::new(&meta).unwrap();
+        let capabilities = scan_capabilities(&simulator).unwrap();
+        let mut snapshot = Snapshot::load(&simulator, &capabilities, &meta).unwrap();
+
+        let other_meta = json!({"matrix": {"rows": 3, "cols": 2}, "layouts": {"keymap": []}});
+        let other = Simulator::new(&other_meta).unwrap();
+        let error = snapshot
+            .apply(&other, &capabilities, &other_meta)
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "Error configuration has {0} layers of 2x3 keys, keyboard has {0} layers of 3x2 keys",
+                capabilities.layer_count
+            )
+        );
+        // keys of another matrix don't matter when they aren't applied
+        let sections = HashSet::from([Section::Combos]);
+        assert!(
+            snapshot
+                .apply_sections(&other, &capabilities, &other_meta, &sections)
+                .is_ok()
+        );
+
+        snapshot.encoders[1].push(Encoder {
+            index: 1,
+            ccw: 0,
+            cw: 0,
+        });
+        let error = snapshot
+            .apply(&simulator, &capabilities, &meta)
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Error configuration has encoder 1, keyboard has 1 encoders"
+        );
+    }
+
+    #[test]
+    fn test_snapshot_fill_empty_entries() {
+        let meta = json!({"matrix": {"rows": 2, "cols": 3}, "layouts": {"keymap": []}});
+        let simulator = Simulator::new(&meta).unwrap();
+        let capabilities = scan_capabilities(&simulator).unwrap();
+        let mut snapshot = Snapshot::from_json(&config()).unwrap();
+        snapshot.fill_empty_entries(&capabilities);
+        assert_eq!(snapshot.combos.len(), capabilities.combo_count as usize);
+        assert_eq!(
+            snapshot.tap_dances.len(),
+            capabilities.tap_dance_count as usize
+        );
+        assert_eq!(snapshot.macros.len(), capabilities.macro_count as usize);
+        assert_eq!(snapshot.combos[0].output, 0x29);
+        assert!(snapshot.combos[1].is_empty());
+        assert_eq!(snapshot.tap_dances[1].index, 1);
+    }
+}
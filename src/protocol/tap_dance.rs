@@ -1,9 +1,9 @@
 use crate::keycodes;
+use crate::protocol::vial_config::TapDanceConfig;
 use crate::protocol::{
     CMD_VIA_VIAL_PREFIX, CMD_VIAL_DYNAMIC_ENTRY_OP, DYNAMIC_VIAL_TAP_DANCE_GET,
-    DYNAMIC_VIAL_TAP_DANCE_SET, ProtocolError, VIA_UNHANDLED, send, send_recv,
+    DYNAMIC_VIAL_TAP_DANCE_SET, ProtocolError, Transport, VIA_UNHANDLED, send, send_recv,
 };
-use hidapi::HidDevice;
 use serde_json::{Value, json};
 use thiserror::Error;
 
@@ -11,7 +11,7 @@ use thiserror::Error;
 #[error("{0}")]
 pub struct TapDanceFormatError(pub String);
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub struct TapDance {
     pub index: u8,
     pub tap: u16,
@@ -140,7 +140,7 @@ impl TapDance {
 }
 
 pub fn load_tap_dances(
-    device: &HidDevice,
+    device: &impl Transport,
     count: u8,
 ) -> Result<Vec<TapDance>, Box<dyn std::error::Error>> {
     let mut tapdances: Vec<TapDance> = vec![];
@@ -189,8 +189,28 @@ pub fn load_tap_dances_from_json(
     Ok(result)
 }
 
+pub fn load_tap_dances_from_config(
+    tap_dances: &[TapDanceConfig],
+    vial_version: u32,
+) -> Result<Vec<TapDance>, Box<dyn std::error::Error>> {
+    let mut result = Vec::new();
+    for (i, TapDanceConfig(tap, hold, double_tap, tap_hold, tapping_term)) in
+        tap_dances.iter().enumerate()
+    {
+        result.push(TapDance {
+            index: i as u8,
+            tap: keycodes::name_to_qid(tap, vial_version)?,
+            hold: keycodes::name_to_qid(hold, vial_version)?,
+            double_tap: keycodes::name_to_qid(double_tap, vial_version)?,
+            tap_hold: keycodes::name_to_qid(tap_hold, vial_version)?,
+            tapping_term: *tapping_term,
+        })
+    }
+    Ok(result)
+}
+
 pub fn set_tap_dance(
-    device: &HidDevice,
+    device: &impl Transport,
     tapdance: &TapDance,
 ) -> Result<(), Box<dyn std::error::Error>> {
     match send(
@@ -234,6 +254,21 @@ pub fn tap_dances_to_json(
     Ok(result)
 }
 
+pub fn tap_dances_to_config(tap_dances: &[TapDance], vial_version: u32) -> Vec<TapDanceConfig> {
+    tap_dances
+        .iter()
+        .map(|tap_dance| {
+            TapDanceConfig(
+                keycodes::qid_to_name(tap_dance.tap, vial_version),
+                keycodes::qid_to_name(tap_dance.hold, vial_version),
+                keycodes::qid_to_name(tap_dance.double_tap, vial_version),
+                keycodes::qid_to_name(tap_dance.tap_hold, vial_version),
+                tap_dance.tapping_term,
+            )
+        })
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -241,7 +276,7 @@ mod tests {
 
     #[test]
     fn test_tap_hold() {
-        let tapdance = TapDance::from_string(7, &"KC_V + KC_B ~ 50".to_string(), 6).unwrap();
+        let tapdance = TapDance::from_string(7, "KC_V + KC_B ~ 50", 6).unwrap();
         assert_eq!(tapdance.index, 7);
         assert_eq!(keycodes::qid_to_name(tapdance.tap, 6), "KC_V");
         assert_eq!(keycodes::qid_to_name(tapdance.hold, 6), "KC_B");
@@ -252,7 +287,7 @@ mod tests {
 
     #[test]
     fn test_from_string_one_key() {
-        let td = TapDance::from_string(0, &"KC_A ~ 100".to_string(), 6).unwrap();
+        let td = TapDance::from_string(0, "KC_A ~ 100", 6).unwrap();
         assert_eq!(keycodes::qid_to_name(td.tap, 6), "KC_A");
         assert_eq!(td.hold, 0);
         assert_eq!(td.tapping_term, 100);
@@ -260,7 +295,7 @@ mod tests {
 
     #[test]
     fn test_from_string_four_keys() {
-        let td = TapDance::from_string(1, &"KC_A+KC_B+KC_C+KC_D ~ 200".to_string(), 6).unwrap();
+        let td = TapDance::from_string(1, "KC_A+KC_B+KC_C+KC_D ~ 200", 6).unwrap();
         assert_eq!(keycodes::qid_to_name(td.tap, 6), "KC_A");
         assert_eq!(keycodes::qid_to_name(td.hold, 6), "KC_B");
         assert_eq!(keycodes::qid_to_name(td.double_tap, 6), "KC_C");
@@ -270,16 +305,13 @@ mod tests {
 
     #[test]
     fn test_from_string_errors() {
+        assert!(TapDance::from_string(0, "KC_A", 6).is_err(), "Missing ~");
         assert!(
-            TapDance::from_string(0, &"KC_A".to_string(), 6).is_err(),
-            "Missing ~"
-        );
-        assert!(
-            TapDance::from_string(0, &"KC_A ~ abc".to_string(), 6).is_err(),
+            TapDance::from_string(0, "KC_A ~ abc", 6).is_err(),
             "Invalid tapping term"
         );
         assert!(
-            TapDance::from_string(0, &"INVALID ~ 100".to_string(), 6).is_err(),
+            TapDance::from_string(0, "INVALID ~ 100", 6).is_err(),
             "Invalid keycode"
         );
     }
@@ -332,7 +364,7 @@ mod tests {
         assert!(empty_td.is_empty());
         assert_eq!(empty_td.tapping_term, 0);
 
-        let non_empty_td = TapDance::from_string(1, &"KC_A ~ 100".to_string(), 6).unwrap();
+        let non_empty_td = TapDance::from_string(1, "KC_A ~ 100", 6).unwrap();
         assert!(!non_empty_td.is_empty());
     }
 }
@@ -0,0 +1,1004 @@
+use crate::keymap::{self, Button};
+use crate::protocol::vial_config::{
+    AltRepeatConfig, KeyOverrideConfig, LayoutKey, MacroAction, TapDanceConfig, VialConfig,
+};
+use crate::protocol::{LayoutOptions, ProtocolError};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::collections::{BTreeMap, HashSet};
+use std::fmt::Write;
+
+/// Keycodes which take layer number as the first argument.
+const LAYER_KEYCODES: [&str; 9] = ["TO", "MO", "DF", "PDF", "TG", "OSL", "LM", "TT", "LT"];
+
+const KC_NO: &str = "KC_NO";
+
+const DEFAULT_TAPPING_TERM: u16 = 200;
+
+/// Human editable layout file, keys are listed in physical order and entries are referenced by
+/// name. Entries which are not listed are empty.
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct TomlConfig {
+    uid: Option<String>,
+    vial_protocol: Option<u32>,
+    via_protocol: Option<u8>,
+    layout_options: Option<i64>,
+    #[serde(default)]
+    layer: Vec<TomlLayer>,
+    #[serde(rename = "macro")]
+    macros: Option<Vec<TomlMacro>>,
+    tap_dance: Option<Vec<TomlTapDance>>,
+    combo: Option<Vec<TomlCombo>>,
+    key_override: Option<Vec<KeyOverrideConfig>>,
+    alt_repeat_key: Option<Vec<AltRepeatConfig>>,
+    settings: Option<BTreeMap<String, u32>>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct TomlLayer {
+    name: Option<String>,
+    /// keys in physical order, split into rows only for readability
+    keys: Option<Vec<Vec<String>>>,
+    /// [ccw, cw] of every encoder
+    encoders: Option<Vec<[String; 2]>>,
+    /// keys of matrix positions missing in current layout, by "row,col"
+    #[serde(default)]
+    hidden: BTreeMap<String, String>,
+    /// keys replacing ones listed in `keys`, by "row,col", used by overlays
+    #[serde(default)]
+    set: BTreeMap<String, String>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct TomlMacro {
+    name: Option<String>,
+    steps: Vec<MacroAction>,
+}
+
+fn kc_no() -> String {
+    KC_NO.to_string()
+}
+
+fn default_tapping_term() -> u16 {
+    DEFAULT_TAPPING_TERM
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct TomlTapDance {
+    name: Option<String>,
+    #[serde(default = "kc_no")]
+    tap: String,
+    #[serde(default = "kc_no")]
+    hold: String,
+    #[serde(default = "kc_no")]
+    double_tap: String,
+    #[serde(default = "kc_no")]
+    tap_hold: String,
+    #[serde(default = "default_tapping_term")]
+    tapping_term: u16,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct TomlCombo {
+    name: Option<String>,
+    keys: Vec<String>,
+    output: String,
+}
+
+/// Reference to layer, macro or tap dance inside of keycode name, e.g. "1" in "LT(1,KC_A)".
+struct Reference<'a> {
+    kind: &'static str,
+    prefix: &'a str,
+    arg: &'a str,
+    suffix: &'a str,
+}
+
+impl Reference<'_> {
+    fn parse(key: &str) -> Option<Reference<'_>> {
+        // Vial protocol 5 names macros as M0
+        if let Some(arg) = key.strip_prefix("QK_MACRO_").or_else(|| {
+            key.strip_prefix('M')
+                .filter(|arg| !arg.is_empty() && arg.chars().all(|c| c.is_ascii_digit()))
+        }) {
+            return Some(Reference {
+                kind: "macro",
+                prefix: "QK_MACRO_",
+                arg,
+                suffix: "",
+            });
+        }
+        if let Some(arg) = key
+            .strip_prefix("TD(")
+            .and_then(|rest| rest.strip_suffix(')'))
+        {
+            return Some(Reference {
+                kind: "tap dance",
+                prefix: "TD(",
+                arg,
+                suffix: ")",
+            });
+        }
+        for name in LAYER_KEYCODES {
+            if let Some(rest) = key
+                .strip_prefix(name)
+                .and_then(|rest| rest.strip_prefix('('))
+            {
+                let end = rest.find([',', ')'])?;
+                return Some(Reference {
+                    kind: "layer",
+                    prefix: &key[..name.len() + 1],
+                    arg: rest[..end].trim(),
+                    suffix: &rest[end..],
+                });
+            }
+        }
+        None
+    }
+}
+
+/// Names of layers, macros and tap dances by index, keycodes use them instead of numbers.
+#[derive(Default)]
+struct Names {
+    layers: Vec<Option<String>>,
+    macros: Vec<Option<String>>,
+    tap_dances: Vec<Option<String>>,
+}
+
+impl Names {
+    fn table(&self, kind: &str) -> &Vec<Option<String>> {
+        match kind {
+            "layer" => &self.layers,
+            "macro" => &self.macros,
+            _ => &self.tap_dances,
+        }
+    }
+
+    /// Replaces index in keycode with name, keycode is returned as is for unnamed entries.
+    fn symbolic(&self, key: &str) -> String {
+        if let Some(reference) = Reference::parse(key)
+            && let Ok(index) = reference.arg.parse::<usize>()
+            && let Some(Some(name)) = self.table(reference.kind).get(index)
+        {
+            return format!("{}{}{}", reference.prefix, name, reference.suffix);
+        }
+        key.to_string()
+    }
+
+    /// Replaces name in keycode with index, numbers are kept as is.
+    fn resolve(&self, key: &str) -> Result<String, ProtocolError> {
+        match Reference::parse(key) {
+            Some(reference) if reference.arg.parse::<usize>().is_err() => {
+                let index = self
+                    .table(reference.kind)
+                    .iter()
+                    .position(|name| name.as_deref() == Some(reference.arg))
+                    .ok_or_else(|| {
+                        ProtocolError::General(format!(
+                            "unknown {} {:?} in {}",
+                            reference.kind, reference.arg, key
+                        ))
+                    })?;
+                Ok(format!("{}{}{}", reference.prefix, index, reference.suffix))
+            }
+            _ => Ok(key.to_string()),
+        }
+    }
+}
+
+/// Checks that names of one kind are identifiers and don't repeat.
+fn check_names<'a>(
+    kind: &str,
+    names: impl Iterator<Item = &'a Option<String>>,
+) -> Result<(), ProtocolError> {
+    let mut seen = HashSet::new();
+    for name in names.flatten() {
+        if name.is_empty()
+            || name.chars().all(|c| c.is_ascii_digit())
+            || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        {
+            return Err(ProtocolError::General(format!(
+                "{} name {:?} should consist of letters, digits and underscores and not be a number",
+                kind, name
+            )));
+        }
+        if !seen.insert(name) {
+            return Err(ProtocolError::General(format!(
+                "{} name {:?} is used twice",
+                kind, name
+            )));
+        }
+    }
+    Ok(())
+}
+
+fn map_macro(
+    action: &MacroAction,
+    f: impl Fn(&str) -> Result<String, ProtocolError>,
+) -> Result<MacroAction, ProtocolError> {
+    let keys = |args: &Vec<String>| -> Result<Vec<String>, ProtocolError> {
+        args.iter().map(|arg| f(arg)).collect()
+    };
+    Ok(match action {
+        MacroAction::Tap(args) => MacroAction::Tap(keys(args)?),
+        MacroAction::Down(args) => MacroAction::Down(keys(args)?),
+        MacroAction::Up(args) => MacroAction::Up(keys(args)?),
+        MacroAction::Text(_) | MacroAction::Delay(_) => action.clone(),
+    })
+}
+
+/// Layout keys in physical order for layout options state of file.
+fn layout_buttons(
+    meta: &Value,
+    layout_options: Option<i64>,
+) -> Result<Vec<Button>, Box<dyn std::error::Error>> {
+    let options = LayoutOptions::from_json(
+        layout_options.unwrap_or(0).max(0) as u32,
+        &meta["layouts"]["labels"],
+    )?;
+    keymap::keymap_to_buttons(&meta["layouts"]["keymap"], &options)
+}
+
+fn quote(value: &str) -> String {
+    // JSON string escapes are valid in TOML basic strings
+    Value::String(value.to_string()).to_string()
+}
+
+fn string_array<S: AsRef<str>>(items: impl IntoIterator<Item = S>) -> String {
+    let items: Vec<String> = items.into_iter().map(|item| quote(item.as_ref())).collect();
+    format!("[{}]", items.join(", "))
+}
+
+/// Entries which are merged by name in `merge_toml`.
+const NAMED_ENTRIES: [&str; 3] = ["macro", "tap_dance", "combo"];
+
+/// Merges TOML layout `overlay` into `base`. Layers are merged by index, so empty [[layer]]
+/// keeps base layer, macros, tap dances and combos replace base entries with the same name,
+/// tables are merged key by key and other values are replaced.
+pub fn merge_toml(base: &mut toml::Table, overlay: toml::Table) {
+    for (key, value) in overlay {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(base)), toml::Value::Table(overlay)) => {
+                merge_toml(base, overlay)
+            }
+            (Some(toml::Value::Array(base)), toml::Value::Array(overlay)) if key == "layer" => {
+                for (index, layer) in overlay.into_iter().enumerate() {
+                    match (base.get_mut(index), layer) {
+                        (Some(toml::Value::Table(base)), toml::Value::Table(layer)) => {
+                            merge_toml(base, layer)
+                        }
+                        (Some(base), layer) => *base = layer,
+                        (None, layer) => base.push(layer),
+                    }
+                }
+            }
+            (Some(toml::Value::Array(base)), toml::Value::Array(overlay))
+                if NAMED_ENTRIES.contains(&key.as_str()) =>
+            {
+                for entry in overlay {
+                    let existing = entry.get("name").and_then(|name| {
+                        base.iter_mut().find(|base| base.get("name") == Some(name))
+                    });
+                    match existing {
+                        Some(existing) => *existing = entry,
+                        None => base.push(entry),
+                    }
+                }
+            }
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+impl VialConfig {
+    /// Writes configuration in TOML format with keys of layers in physical order of `meta`
+    /// layout, macros and tap dances get names which are used in keycodes.
+    pub fn to_toml(&self, meta: &Value) -> Result<String, Box<dyn std::error::Error>> {
+        let buttons = layout_buttons(meta, self.layout_options)?;
+        let rows = keymap::physical_rows(&buttons);
+        let shown: HashSet<(usize, usize)> = rows
+            .iter()
+            .flatten()
+            .map(|b| (b.wire_x as usize, b.wire_y as usize))
+            .collect();
+
+        let macros: Option<Vec<(usize, &Vec<MacroAction>)>> = self.macros.as_ref().map(|macros| {
+            macros
+                .iter()
+                .enumerate()
+                .filter(|(_, m)| !m.is_empty())
+                .collect()
+        });
+        let tap_dances: Option<Vec<(usize, &TapDanceConfig)>> =
+            self.tap_dance.as_ref().map(|tap_dances| {
+                tap_dances
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, td)| [&td.0, &td.1, &td.2, &td.3].iter().any(|k| *k != KC_NO))
+                    .collect()
+            });
+        let combos: Option<Vec<(usize, &[String; 5])>> = self.combo.as_ref().map(|combos| {
+            combos
+                .iter()
+                .enumerate()
+                .filter(|(_, c)| c[0] != KC_NO && c[4] != KC_NO)
+                .collect()
+        });
+        let key_overrides: Option<Vec<&KeyOverrideConfig>> =
+            self.key_override.as_ref().map(|key_overrides| {
+                key_overrides
+                    .iter()
+                    .filter(|ko| ko.trigger != KC_NO || ko.options & (1 << 7) != 0)
+                    .collect()
+            });
+        let alt_repeats: Option<Vec<&AltRepeatConfig>> =
+            self.alt_repeat_key.as_ref().map(|alt_repeats| {
+                alt_repeats
+                    .iter()
+                    .filter(|ar| ar.keycode != KC_NO || ar.options & (1 << 3) != 0)
+                    .collect()
+            });
+
+        let mut names = Names::default();
+        for (index, _) in macros.iter().flatten() {
+            names.macros.resize(index + 1, None);
+            names.macros[*index] = Some(format!("macro{}", index));
+        }
+        for (index, _) in tap_dances.iter().flatten() {
+            names.tap_dances.resize(index + 1, None);
+            names.tap_dances[*index] = Some(format!("td{}", index));
+        }
+        let symbolic = |key: &str| Ok(names.symbolic(key));
+
+        let mut out = String::new();
+        writeln!(
+            out,
+            "# Layout of {}, keys of layers are listed in physical order row by row.",
+            meta["name"].as_str().unwrap_or("keyboard")
+        )?;
+        writeln!(
+            out,
+            "# Entries missing in file are empty, layers, macros and tap dances are referenced by name."
+        )?;
+        writeln!(out)?;
+        if self.uid != 0 {
+            writeln!(out, "uid = \"0x{:016X}\"", self.uid)?;
+        }
+        if let Some(vial_protocol) = self.vial_protocol {
+            writeln!(out, "vial_protocol = {}", vial_protocol)?;
+        }
+        if let Some(via_protocol) = self.via_protocol {
+            writeln!(out, "via_protocol = {}", via_protocol)?;
+        }
+        if let Some(layout_options) = self.layout_options {
+            writeln!(out, "layout_options = {}", layout_options)?;
+        }
+        // sections without entries are kept present
+        for (name, empty) in [
+            ("macro", macros.as_ref().is_some_and(|v| v.is_empty())),
+            (
+                "tap_dance",
+                tap_dances.as_ref().is_some_and(|v| v.is_empty()),
+            ),
+            ("combo", combos.as_ref().is_some_and(|v| v.is_empty())),
+            (
+                "key_override",
+                key_overrides.as_ref().is_some_and(|v| v.is_empty()),
+            ),
+            (
+                "alt_repeat_key",
+                alt_repeats.as_ref().is_some_and(|v| v.is_empty()),
+            ),
+        ] {
+            if empty {
+                writeln!(out, "{} = []", name)?;
+            }
+        }
+
+        let encoders = self.encoder_layout.as_ref();
+        let layers_count = self
+            .layout
+            .len()
+            .max(encoders.map_or(0, |encoders| encoders.len()));
+        for layer in 0..layers_count {
+            writeln!(out, "\n# layer {}\n[[layer]]", layer)?;
+            if let Some(keys) = self.layout.get(layer) {
+                let name = |row: usize, col: usize| match keys.get(row).and_then(|r| r.get(col)) {
+                    Some(LayoutKey::Name(name)) => names.symbolic(name),
+                    _ => KC_NO.to_string(),
+                };
+                writeln!(out, "keys = [")?;
+                for row in &rows {
+                    writeln!(
+                        out,
+                        "    {},",
+                        string_array(
+                            row.iter()
+                                .map(|b| name(b.wire_x as usize, b.wire_y as usize))
+                        )
+                    )?;
+                }
+                writeln!(out, "]")?;
+                let mut hidden = Vec::new();
+                for (row, cols) in keys.iter().enumerate() {
+                    for (col, key) in cols.iter().enumerate() {
+                        if let LayoutKey::Name(key) = key
+                            && key != KC_NO
+                            && !shown.contains(&(row, col))
+                        {
+                            hidden.push(format!(
+                                "{} = {}",
+                                quote(&format!("{},{}", row, col)),
+                                quote(&names.symbolic(key))
+                            ));
+                        }
+                    }
+                }
+                if !hidden.is_empty() {
+                    writeln!(out, "hidden = {{ {} }}", hidden.join(", "))?;
+                }
+            }
+            if let Some(layer_encoders) = encoders.and_then(|encoders| encoders.get(layer)) {
+                let items: Vec<String> = layer_encoders
+                    .iter()
+                    .map(|[ccw, cw]| string_array([names.symbolic(ccw), names.symbolic(cw)]))
+                    .collect();
+                writeln!(out, "encoders = [{}]", items.join(", "))?;
+            }
+        }
+
+        for (index, steps) in macros.iter().flatten() {
+            let steps = steps
+                .iter()
+                .map(|step| map_macro(step, symbolic))
+                .collect::<Result<Vec<_>, _>>()?;
+            writeln!(out, "\n[[macro]]")?;
+            writeln!(out, "name = {}", quote(&format!("macro{}", index)))?;
+            let mut items = Vec::new();
+            for step in &steps {
+                // JSON array of strings and numbers is valid TOML
+                let args: Vec<String> = serde_json::to_value(step)?
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .map(|arg| arg.to_string())
+                    .collect();
+                items.push(format!("[{}]", args.join(", ")));
+            }
+            writeln!(out, "steps = [{}]", items.join(", "))?;
+        }
+
+        for (index, td) in tap_dances.iter().flatten() {
+            writeln!(out, "\n[[tap_dance]]")?;
+            writeln!(out, "name = {}", quote(&format!("td{}", index)))?;
+            for (field, key) in [
+                ("tap", &td.0),
+                ("hold", &td.1),
+                ("double_tap", &td.2),
+                ("tap_hold", &td.3),
+            ] {
+                if key != KC_NO {
+                    writeln!(out, "{} = {}", field, quote(&names.symbolic(key)))?;
+                }
+            }
+            writeln!(out, "tapping_term = {}", td.4)?;
+        }
+
+        for (index, combo) in combos.iter().flatten() {
+            writeln!(out, "\n[[combo]]")?;
+            writeln!(out, "name = {}", quote(&format!("combo{}", index)))?;
+            writeln!(
+                out,
+                "keys = {}",
+                string_array(
+                    combo[..4]
+                        .iter()
+                        .filter(|k| *k != KC_NO)
+                        .map(|k| names.symbolic(k))
+                )
+            )?;
+            writeln!(out, "output = {}", quote(&names.symbolic(&combo[4])))?;
+        }
+
+        for ko in key_overrides.iter().flatten() {
+            writeln!(out, "\n[[key_override]]")?;
+            writeln!(out, "trigger = {}", quote(&names.symbolic(&ko.trigger)))?;
+            writeln!(
+                out,
+                "replacement = {}",
+                quote(&names.symbolic(&ko.replacement))
+            )?;
+            writeln!(out, "layers = {}", ko.layers)?;
+            writeln!(out, "trigger_mods = {}", ko.trigger_mods)?;
+            writeln!(out, "negative_mod_mask = {}", ko.negative_mod_mask)?;
+            writeln!(out, "suppressed_mods = {}", ko.suppressed_mods)?;
+            writeln!(out, "options = {}", ko.options)?;
+        }
+
+        for ar in alt_repeats.iter().flatten() {
+            writeln!(out, "\n[[alt_repeat_key]]")?;
+            writeln!(out, "keycode = {}", quote(&names.symbolic(&ar.keycode)))?;
+            writeln!(
+                out,
+                "alt_keycode = {}",
+                quote(&names.symbolic(&ar.alt_keycode))
+            )?;
+            writeln!(out, "allowed_mods = {}", ar.allowed_mods)?;
+            writeln!(out, "options = {}", ar.options)?;
+        }
+
+        if let Some(settings) = &self.settings {
+            writeln!(out, "\n# QMK settings by qsid\n[settings]")?;
+            for (qsid, value) in settings {
+                writeln!(out, "{} = {}", qsid, value)?;
+            }
+        }
+        Ok(out)
+    }
+
+    /// Parses configuration in TOML format, keys of layers are expected in physical order of
+    /// `meta` layout.
+    pub fn from_toml(
+        table: toml::Table,
+        meta: &Value,
+    ) -> Result<VialConfig, Box<dyn std::error::Error>> {
+        let file: TomlConfig = toml::Value::Table(table).try_into()?;
+        let names = Names {
+            layers: file.layer.iter().map(|l| l.name.clone()).collect(),
+            macros: file
+                .macros
+                .iter()
+                .flatten()
+                .map(|m| m.name.clone())
+                .collect(),
+            tap_dances: file
+                .tap_dance
+                .iter()
+                .flatten()
+                .map(|td| td.name.clone())
+                .collect(),
+        };
+        check_names("layer", names.layers.iter())?;
+        check_names("macro", names.macros.iter())?;
+        check_names("tap dance", names.tap_dances.iter())?;
+        check_names("combo", file.combo.iter().flatten().map(|c| &c.name))?;
+        // keycode names have no spaces, they are allowed for readability
+        let resolve = |key: &str| names.resolve(&key.split_whitespace().collect::<String>());
+
+        let cols = meta["matrix"]["cols"]
+            .as_u64()
+            .ok_or("matrix/cols not found in meta")? as usize;
+        let rows = meta["matrix"]["rows"]
+            .as_u64()
+            .ok_or("matrix/rows not found in meta")? as usize;
+        let buttons = layout_buttons(meta, file.layout_options)?;
+        let positions: Vec<(usize, usize)> = keymap::physical_rows(&buttons)
+            .iter()
+            .flatten()
+            .map(|b| (b.wire_x as usize, b.wire_y as usize))
+            .collect();
+
+        let mut layout = Vec::new();
+        if file.layer.iter().any(|layer| layer.keys.is_some()) {
+            for (number, layer) in file.layer.iter().enumerate() {
+                let keys: Vec<&String> = layer
+                    .keys
+                    .as_ref()
+                    .ok_or_else(|| ProtocolError::General(format!("layer {} has no keys", number)))?
+                    .iter()
+                    .flatten()
+                    .collect();
+                if keys.len() != positions.len() {
+                    return Err(ProtocolError::General(format!(
+                        "layer {} has {} keys, layout has {}",
+                        number,
+                        keys.len(),
+                        positions.len()
+                    ))
+                    .into());
+                }
+                let mut matrix = vec![vec![LayoutKey::Number(-1); cols]; rows];
+                for (key, (row, col)) in keys.iter().zip(&positions) {
+                    if *row < rows && *col < cols {
+                        matrix[*row][*col] = LayoutKey::Name(resolve(key)?);
+                    }
+                }
+                for (table, position, key) in layer
+                    .hidden
+                    .iter()
+                    .map(|(position, key)| ("hidden", position, key))
+                    .chain(
+                        layer
+                            .set
+                            .iter()
+                            .map(|(position, key)| ("set", position, key)),
+                    )
+                {
+                    let (row, col) = position
+                        .split_once(',')
+                        .and_then(|(row, col)| {
+                            Some((
+                                row.trim().parse::<usize>().ok()?,
+                                col.trim().parse::<usize>().ok()?,
+                            ))
+                        })
+                        .filter(|(row, col)| *row < rows && *col < cols)
+                        .ok_or_else(|| {
+                            ProtocolError::General(format!(
+                                "{} key position {:?} of layer {} should be 'row,col' within matrix",
+                                table, position, number
+                            ))
+                        })?;
+                    matrix[row][col] = LayoutKey::Name(resolve(key)?);
+                }
+                layout.push(matrix);
+            }
+        } else if let Some(number) = file.layer.iter().position(|layer| !layer.set.is_empty()) {
+            return Err(ProtocolError::General(format!(
+                "keys of layer {} are set, but layers have no keys",
+                number
+            ))
+            .into());
+        }
+
+        let encoder_layout = match file.layer.iter().any(|layer| layer.encoders.is_some()) {
+            true => Some(
+                file.layer
+                    .iter()
+                    .map(|layer| {
+                        layer
+                            .encoders
+                            .iter()
+                            .flatten()
+                            .map(|[ccw, cw]| Ok([resolve(ccw)?, resolve(cw)?]))
+                            .collect::<Result<Vec<_>, ProtocolError>>()
+                    })
+                    .collect::<Result<Vec<_>, _>>()?,
+            ),
+            false => None,
+        };
+
+        let macros = match &file.macros {
+            Some(macros) => Some(
+                macros
+                    .iter()
+                    .map(|m| {
+                        m.steps
+                            .iter()
+                            .map(|step| map_macro(step, resolve))
+                            .collect::<Result<Vec<_>, _>>()
+                    })
+                    .collect::<Result<Vec<_>, _>>()?,
+            ),
+            None => None,
+        };
+
+        let tap_dance = match &file.tap_dance {
+            Some(tap_dances) => Some(
+                tap_dances
+                    .iter()
+                    .map(|td| {
+                        Ok(TapDanceConfig(
+                            resolve(&td.tap)?,
+                            resolve(&td.hold)?,
+                            resolve(&td.double_tap)?,
+                            resolve(&td.tap_hold)?,
+                            td.tapping_term,
+                        ))
+                    })
+                    .collect::<Result<Vec<_>, ProtocolError>>()?,
+            ),
+            None => None,
+        };
+
+        let combo = match &file.combo {
+            Some(combos) => Some(
+                combos
+                    .iter()
+                    .enumerate()
+                    .map(|(index, combo)| {
+                        if combo.keys.is_empty() || combo.keys.len() > 4 {
+                            return Err(ProtocolError::General(format!(
+                                "combo {} should have from 1 to 4 keys",
+                                combo.name.clone().unwrap_or(index.to_string())
+                            )));
+                        }
+                        let mut entry: [String; 5] = Default::default();
+                        for (slot, key) in entry.iter_mut().take(4).enumerate() {
+                            *key = match combo.keys.get(slot) {
+                                Some(key) => resolve(key)?,
+                                None => kc_no(),
+                            };
+                        }
+                        entry[4] = resolve(&combo.output)?;
+                        Ok(entry)
+                    })
+                    .collect::<Result<Vec<_>, _>>()?,
+            ),
+            None => None,
+        };
+
+        let key_override = match &file.key_override {
+            Some(key_overrides) => Some(
+                key_overrides
+                    .iter()
+                    .map(|ko| {
+                        Ok(KeyOverrideConfig {
+                            trigger: resolve(&ko.trigger)?,
+                            replacement: resolve(&ko.replacement)?,
+                            ..ko.clone()
+                        })
+                    })
+                    .collect::<Result<Vec<_>, ProtocolError>>()?,
+            ),
+            None => None,
+        };
+
+        let alt_repeat_key = match &file.alt_repeat_key {
+            Some(alt_repeats) => Some(
+                alt_repeats
+                    .iter()
+                    .map(|ar| {
+                        Ok(AltRepeatConfig {
+                            keycode: resolve(&ar.keycode)?,
+                            alt_keycode: resolve(&ar.alt_keycode)?,
+                            ..ar.clone()
+                        })
+                    })
+                    .collect::<Result<Vec<_>, ProtocolError>>()?,
+            ),
+            None => None,
+        };
+
+        let settings = match &file.settings {
+            Some(settings) => Some(
+                settings
+                    .iter()
+                    .map(|(qsid, value)| {
+                        qsid.parse::<u16>().map(|qsid| (qsid, *value)).map_err(|_| {
+                            ProtocolError::General(format!("setting {:?} should be qsid", qsid))
+                        })
+                    })
+                    .collect::<Result<BTreeMap<_, _>, _>>()?,
+            ),
+            None => None,
+        };
+
+        let uid = match &file.uid {
+            Some(uid) => u64::from_str_radix(uid.trim_start_matches("0x"), 16).map_err(|_| {
+                ProtocolError::General(format!("uid {:?} should be hex number", uid))
+            })?,
+            None => 0,
+        };
+
+        Ok(VialConfig {
+            version: 1,
+            uid,
+            layout,
+            layout_options: file.layout_options,
+            encoder_layout,
+            macros,
+            vial_protocol: file.vial_protocol,
+            via_protocol: file.via_protocol,
+            tap_dance,
+            combo,
+            key_override,
+            alt_repeat_key,
+            settings,
+            extra: Map::new(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const VIAL_SAMPLE: &str = include_str!("testdata/vial_v6.vil");
+
+    fn meta() -> Value {
+        json!({
+            "name": "Sample",
+            "matrix": {"rows": 2, "cols": 3},
+            "layouts": {
+                "labels": ["Split Backspace"],
+                "keymap": [
+                    ["0,0", "0,1", "0,2"],
+                    ["1,0", "1,1", "0,0\n\n\n\n\n\n\n\n\ne", "0,1\n\n\n\n\n\n\n\n\ne"]
+                ]
+            }
+        })
+    }
+
+    #[test]
+    fn test_toml_round_trip() {
+        let config: VialConfig = serde_json::from_str(VIAL_SAMPLE).unwrap();
+        let text = config.to_toml(&meta()).unwrap();
+        assert!(text.contains("keys = [\n    [\"KC_ESCAPE\", \"KC_Q\", \"KC_W\"],\n"));
+        assert!(text.contains("name = \"td0\""));
+        assert!(!text.contains("name = \"td1\""));
+
+        let parsed = VialConfig::from_toml(toml::from_str(&text).unwrap(), &meta()).unwrap();
+        assert_eq!(parsed.uid, config.uid);
+        assert_eq!(parsed.layout, config.layout);
+        assert_eq!(parsed.layout_options, Some(1));
+        assert_eq!(parsed.encoder_layout, config.encoder_layout);
+        assert_eq!(parsed.vial_protocol, Some(6));
+        assert_eq!(parsed.via_protocol, Some(9));
+        // empty entries are dropped
+        assert_eq!(parsed.macros.unwrap(), config.macros.unwrap()[..2]);
+        assert_eq!(parsed.tap_dance.unwrap(), config.tap_dance.unwrap()[..1]);
+        assert_eq!(parsed.combo.unwrap(), config.combo.unwrap()[..1]);
+        assert_eq!(
+            parsed.key_override.unwrap(),
+            config.key_override.unwrap()[..1]
+        );
+        assert_eq!(
+            parsed.alt_repeat_key.unwrap(),
+            config.alt_repeat_key.unwrap()[..1]
+        );
+        assert_eq!(parsed.settings, config.settings);
+    }
+
+    #[test]
+    fn test_toml_names() {
+        let text = r#"
+            # layers are referenced by name
+            [[layer]]
+            name = "base"
+            keys = [
+                ["TD(esc_caps)", "QK_MACRO_hello", "MO(nav)"],
+                ["LT(nav, KC_SPACE)", "TG(2)"],  # numbers stay as is
+            ]
+            hidden = { "1,2" = "KC_B" }
+
+            [[layer]]
+            name = "nav"
+            keys = [["KC_TRNS", "KC_TRNS", "KC_TRNS"], ["KC_TRNS", "TO(base)"]]
+
+            [[macro]]
+            name = "bye"
+            steps = [["text", "bye"]]
+
+            [[macro]]
+            name = "hello"
+            steps = [["text", "hello"], ["tap", "TD(esc_caps)"]]
+
+            [[tap_dance]]
+            name = "esc_caps"
+            tap = "KC_ESCAPE"
+            double_tap = "KC_CAPS_LOCK"
+
+            [[combo]]
+            keys = ["KC_Q", "KC_W"]
+            output = "MO(nav)"
+        "#;
+        let config = VialConfig::from_toml(toml::from_str(text).unwrap(), &meta()).unwrap();
+        let name = |key: &str| LayoutKey::Name(key.to_string());
+        assert_eq!(
+            config.layout[0],
+            vec![
+                vec![name("TD(0)"), name("QK_MACRO_1"), name("MO(1)")],
+                vec![name("LT(1,KC_SPACE)"), name("TG(2)"), name("KC_B")],
+            ]
+        );
+        assert_eq!(config.layout[1][1][2], LayoutKey::Number(-1));
+        assert!(config.encoder_layout.is_none());
+        assert_eq!(
+            config.macros.as_ref().unwrap()[1][1],
+            MacroAction::Tap(vec!["TD(0)".to_string()])
+        );
+        assert_eq!(
+            config.tap_dance.as_ref().unwrap()[0],
+            TapDanceConfig(
+                "KC_ESCAPE".to_string(),
+                "KC_NO".to_string(),
+                "KC_CAPS_LOCK".to_string(),
+                "KC_NO".to_string(),
+                200
+            )
+        );
+        assert_eq!(
+            config.combo.as_ref().unwrap()[0],
+            ["KC_Q", "KC_W", "KC_NO", "KC_NO", "MO(1)"].map(String::from)
+        );
+        assert!(config.key_override.is_none());
+
+        // names are written back for macros and tap dances
+        let text = config.to_toml(&meta()).unwrap();
+        assert!(text.contains("[\"TD(td0)\", \"QK_MACRO_macro1\", \"MO(1)\"]"));
+        assert!(text.contains("hidden = { \"1,2\" = \"KC_B\" }"));
+    }
+
+    #[test]
+    fn test_toml_errors() {
+        let error = |text: &str| {
+            VialConfig::from_toml(toml::from_str(text).unwrap(), &meta())
+                .unwrap_err()
+                .to_string()
+        };
+        assert_eq!(
+            error("[[layer]]\nkeys = [[\"KC_A\"]]"),
+            "Error layer 0 has 1 keys, layout has 5"
+        );
+        assert_eq!(
+            error("[[layer]]\nkeys = [[\"KC_A\", \"KC_B\", \"KC_C\", \"KC_D\", \"TD(x)\"]]"),
+            "Error unknown tap dance \"x\" in TD(x)"
+        );
+        assert_eq!(
+            error("[[tap_dance]]\nname = \"a\"\n[[tap_dance]]\nname = \"a\""),
+            "Error tap dance name \"a\" is used twice"
+        );
+        assert!(error("[[layer]]\nname = \"1\"").contains("not be a number"));
+        assert!(error("unknown = 1").contains("unknown field"));
+    }
+
+    #[test]
+    fn test_merge_toml() {
+        let mut base: toml::Table = toml::from_str(
+            r#"
+            vial_protocol = 6
+            [[layer]]
+            name = "base"
+            keys = [["KC_A", "KC_B", "KC_C"], ["KC_D", "TD(esc_caps)"]]
+            [[layer]]
+            keys = [["KC_1", "KC_2", "KC_3"], ["KC_4", "KC_5"]]
+            [[tap_dance]]
+            name = "esc_caps"
+            tap = "KC_ESCAPE"
+            [[combo]]
+            name = "jk"
+            keys = ["KC_J", "KC_K"]
+            output = "KC_ESCAPE"
+            [settings]
+            2 = 5000
+            7 = 180
+            "#,
+        )
+        .unwrap();
+        let overlay: toml::Table = toml::from_str(
+            r#"
+            [[layer]]
+            set = { "0,1" = "KC_Z" }
+            [[layer]]
+            keys = [["KC_F1", "KC_F2", "KC_F3"], ["KC_F4", "KC_F5"]]
+            [[tap_dance]]
+            name = "esc_caps"
+            tap = "KC_ESCAPE"
+            hold = "KC_LCTL"
+            [[combo]]
+            name = "df"
+            keys = ["KC_D", "KC_F"]
+            output = "KC_TAB"
+            [settings]
+            7 = 150
+            "#,
+        )
+        .unwrap();
+        merge_toml(&mut base, overlay);
+        let config = VialConfig::from_toml(base, &meta()).unwrap();
+        let name = |key: &str| LayoutKey::Name(key.to_string());
+        assert_eq!(
+            config.layout[0][0],
+            vec![name("KC_A"), name("KC_Z"), name("KC_C")]
+        );
+        assert_eq!(config.layout[0][1][1], name("TD(0)"));
+        assert_eq!(config.layout[1][0][0], name("KC_F1"));
+        assert_eq!(config.tap_dance.as_ref().unwrap()[0].1, "KC_LCTL");
+        let combos = config.combo.unwrap();
+        assert_eq!(combos.len(), 2);
+        assert_eq!(combos[1][4], "KC_TAB");
+        assert_eq!(
+            config.settings.unwrap(),
+            BTreeMap::from([(2, 5000), (7, 150)])
+        );
+        assert_eq!(config.vial_protocol, Some(6));
+    }
+}
@@ -0,0 +1,386 @@
+use crate::protocol::{
+    CMD_VIA_GET_KEYBOARD_VALUE, CMD_VIA_GET_LAYER_COUNT, CMD_VIA_GET_PROTOCOL_VERSION,
+    CMD_VIA_KEYMAP_GET_BUFFER, CMD_VIA_KEYMAP_SET_BUFFER, CMD_VIA_LIGHTING_GET_VALUE,
+    CMD_VIA_LIGHTING_SAVE, CMD_VIA_LIGHTING_SET_VALUE, CMD_VIA_MACRO_GET_BUFFER,
+    CMD_VIA_MACRO_GET_BUFFER_SIZE, CMD_VIA_MACRO_GET_COUNT, CMD_VIA_MACRO_SET_BUFFER,
+    CMD_VIA_SET_KEYBOARD_VALUE, CMD_VIA_SET_KEYCODE, CMD_VIA_VIAL_PREFIX,
+    CMD_VIAL_DYNAMIC_ENTRY_OP, CMD_VIAL_GET_DEFINITION, CMD_VIAL_GET_ENCODER,
+    CMD_VIAL_GET_KEYBOARD_ID, CMD_VIAL_GET_SIZE, CMD_VIAL_GET_UNLOCK_STATUS, CMD_VIAL_LOCK,
+    CMD_VIAL_QMK_SETTINGS_GET, CMD_VIAL_QMK_SETTINGS_QUERY, CMD_VIAL_QMK_SETTINGS_RESET,
+    CMD_VIAL_QMK_SETTINGS_SET, CMD_VIAL_SET_ENCODER, CMD_VIAL_UNLOCK_POLL, CMD_VIAL_UNLOCK_START,
+    DYNAMIC_VIAL_ALT_REPEAT_KEY_GET, DYNAMIC_VIAL_ALT_REPEAT_KEY_SET, DYNAMIC_VIAL_COMBO_GET,
+    DYNAMIC_VIAL_COMBO_SET, DYNAMIC_VIAL_GET_NUMBER_OF_ENTRIES, DYNAMIC_VIAL_KEY_OVERRIDE_GET,
+    DYNAMIC_VIAL_KEY_OVERRIDE_SET, DYNAMIC_VIAL_TAP_DANCE_GET, DYNAMIC_VIAL_TAP_DANCE_SET,
+    HID_LAYERS_IN, ProtocolError, Transport, VIALRGB_DIRECT_FASTSET, VIALRGB_GET_INFO,
+    VIALRGB_GET_MODE, VIALRGB_GET_NUMBER_LEDS, VIALRGB_GET_SUPPORTED, VIALRGB_SET_MODE,
+};
+use hidapi::{HidError, HidResult};
+use serde_json::{Value, json};
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::io::{BufRead, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const DIRECTION_REQUEST: &str = "request";
+const DIRECTION_RESPONSE: &str = "response";
+const DIRECTION_ERROR: &str = "error";
+
+/// Human readable name of a request, `data` is a message without report id.
+pub fn command_name(data: &[u8]) -> &'static str {
+    let byte = |i: usize| data.get(i).copied().unwrap_or(0);
+    match byte(0) {
+        CMD_VIA_GET_PROTOCOL_VERSION => "CMD_VIA_GET_PROTOCOL_VERSION",
+        CMD_VIA_GET_KEYBOARD_VALUE => "CMD_VIA_GET_KEYBOARD_VALUE",
+        CMD_VIA_SET_KEYBOARD_VALUE => "CMD_VIA_SET_KEYBOARD_VALUE",
+        CMD_VIA_SET_KEYCODE => "CMD_VIA_SET_KEYCODE",
+        CMD_VIA_LIGHTING_SET_VALUE => match byte(1) {
+            VIALRGB_SET_MODE => "CMD_VIA_LIGHTING_SET_VALUE/VIALRGB_SET_MODE",
+            VIALRGB_DIRECT_FASTSET => "CMD_VIA_LIGHTING_SET_VALUE/VIALRGB_DIRECT_FASTSET",
+            _ => "CMD_VIA_LIGHTING_SET_VALUE",
+        },
+        CMD_VIA_LIGHTING_GET_VALUE => match byte(1) {
+            VIALRGB_GET_INFO => "CMD_VIA_LIGHTING_GET_VALUE/VIALRGB_GET_INFO",
+            VIALRGB_GET_MODE => "CMD_VIA_LIGHTING_GET_VALUE/VIALRGB_GET_MODE",
+            VIALRGB_GET_SUPPORTED => "CMD_VIA_LIGHTING_GET_VALUE/VIALRGB_GET_SUPPORTED",
+            VIALRGB_GET_NUMBER_LEDS => "CMD_VIA_LIGHTING_GET_VALUE/VIALRGB_GET_NUMBER_LEDS",
+            _ => "CMD_VIA_LIGHTING_GET_VALUE",
+        },
+        CMD_VIA_LIGHTING_SAVE => "CMD_VIA_LIGHTING_SAVE",
+        CMD_VIA_MACRO_GET_COUNT => "CMD_VIA_MACRO_GET_COUNT",
+        CMD_VIA_MACRO_GET_BUFFER_SIZE => "CMD_VIA_MACRO_GET_BUFFER_SIZE",
+        CMD_VIA_MACRO_GET_BUFFER => "CMD_VIA_MACRO_GET_BUFFER",
+        CMD_VIA_MACRO_SET_BUFFER => "CMD_VIA_MACRO_SET_BUFFER",
+        CMD_VIA_GET_LAYER_COUNT => "CMD_VIA_GET_LAYER_COUNT",
+        CMD_VIA_KEYMAP_GET_BUFFER => "CMD_VIA_KEYMAP_GET_BUFFER",
+        CMD_VIA_KEYMAP_SET_BUFFER => "CMD_VIA_KEYMAP_SET_BUFFER",
+        HID_LAYERS_IN => "HID_LAYERS_IN",
+        CMD_VIA_VIAL_PREFIX => match byte(1) {
+            CMD_VIAL_GET_KEYBOARD_ID => "CMD_VIAL_GET_KEYBOARD_ID",
+            CMD_VIAL_GET_SIZE => "CMD_VIAL_GET_SIZE",
+            CMD_VIAL_GET_DEFINITION => "CMD_VIAL_GET_DEFINITION",
+            CMD_VIAL_GET_ENCODER => "CMD_VIAL_GET_ENCODER",
+            CMD_VIAL_SET_ENCODER => "CMD_VIAL_SET_ENCODER",
+            CMD_VIAL_GET_UNLOCK_STATUS => "CMD_VIAL_GET_UNLOCK_STATUS",
+            CMD_VIAL_UNLOCK_START => "CMD_VIAL_UNLOCK_START",
+            CMD_VIAL_UNLOCK_POLL => "CMD_VIAL_UNLOCK_POLL",
+            CMD_VIAL_LOCK => "CMD_VIAL_LOCK",
+            CMD_VIAL_QMK_SETTINGS_QUERY => "CMD_VIAL_QMK_SETTINGS_QUERY",
+            CMD_VIAL_QMK_SETTINGS_GET => "CMD_VIAL_QMK_SETTINGS_GET",
+            CMD_VIAL_QMK_SETTINGS_SET => "CMD_VIAL_QMK_SETTINGS_SET",
+            CMD_VIAL_QMK_SETTINGS_RESET => "CMD_VIAL_QMK_SETTINGS_RESET",
+            CMD_VIAL_DYNAMIC_ENTRY_OP => match byte(2) {
+                DYNAMIC_VIAL_GET_NUMBER_OF_ENTRIES => {
+                    "CMD_VIAL_DYNAMIC_ENTRY_OP/DYNAMIC_VIAL_GET_NUMBER_OF_ENTRIES"
+                }
+                DYNAMIC_VIAL_TAP_DANCE_GET => {
+                    "CMD_VIAL_DYNAMIC_ENTRY_OP/DYNAMIC_VIAL_TAP_DANCE_GET"
+                }
+                DYNAMIC_VIAL_TAP_DANCE_SET => {
+                    "CMD_VIAL_DYNAMIC_ENTRY_OP/DYNAMIC_VIAL_TAP_DANCE_SET"
+                }
+                DYNAMIC_VIAL_COMBO_GET => "CMD_VIAL_DYNAMIC_ENTRY_OP/DYNAMIC_VIAL_COMBO_GET",
+                DYNAMIC_VIAL_COMBO_SET => "CMD_VIAL_DYNAMIC_ENTRY_OP/DYNAMIC_VIAL_COMBO_SET",
+                DYNAMIC_VIAL_KEY_OVERRIDE_GET => {
+                    "CMD_VIAL_DYNAMIC_ENTRY_OP/DYNAMIC_VIAL_KEY_OVERRIDE_GET"
+                }
+                DYNAMIC_VIAL_KEY_OVERRIDE_SET => {
+                    "CMD_VIAL_DYNAMIC_ENTRY_OP/DYNAMIC_VIAL_KEY_OVERRIDE_SET"
+                }
+                DYNAMIC_VIAL_ALT_REPEAT_KEY_GET => {
+                    "CMD_VIAL_DYNAMIC_ENTRY_OP/DYNAMIC_VIAL_ALT_REPEAT_KEY_GET"
+                }
+                DYNAMIC_VIAL_ALT_REPEAT_KEY_SET => {
+                    "CMD_VIAL_DYNAMIC_ENTRY_OP/DYNAMIC_VIAL_ALT_REPEAT_KEY_SET"
+                }
+                _ => "CMD_VIAL_DYNAMIC_ENTRY_OP",
+            },
+            _ => "CMD_VIA_VIAL_PREFIX",
+        },
+        _ => "UNKNOWN",
+    }
+}
+
+fn to_hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(hex: &str) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    if !hex.len().is_multiple_of(2) {
+        return Err(ProtocolError::General(format!("odd length hex string {:?}", hex)).into());
+    }
+    let mut result = Vec::new();
+    for i in (0..hex.len()).step_by(2) {
+        result.push(u8::from_str_radix(&hex[i..i + 2], 16)?);
+    }
+    Ok(result)
+}
+
+fn timestamp() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or(0)
+}
+
+/// Transport wrapper writing every request and response into `trace` as json lines.
+pub struct Recorder<T: Transport, W: Write> {
+    device: T,
+    trace: RefCell<W>,
+    last_command: RefCell<&'static str>,
+}
+
+impl<T: Transport, W: Write> Recorder<T, W> {
+    pub fn new(device: T, trace: W) -> Recorder<T, W> {
+        Recorder {
+            device,
+            trace: RefCell::new(trace),
+            last_command: RefCell::new("UNKNOWN"),
+        }
+    }
+
+    fn log(&self, record: Value) -> HidResult<()> {
+        writeln!(self.trace.borrow_mut(), "{}", record).map_err(|e| HidError::HidApiError {
+            message: format!("failed to write trace: {}", e),
+        })
+    }
+}
+
+impl<T: Transport, W: Write> Transport for Recorder<T, W> {
+    fn write(&self, data: &[u8]) -> HidResult<usize> {
+        let command = command_name(data.get(1..).unwrap_or_default());
+        *self.last_command.borrow_mut() = command;
+        self.log(json!({
+            "ts": timestamp(),
+            "dir": DIRECTION_REQUEST,
+            "command": command,
+            "data": to_hex(data),
+        }))?;
+        self.device.write(data)
+    }
+
+    fn read_timeout(&self, buf: &mut [u8], timeout: i32) -> HidResult<usize> {
+        let command = *self.last_command.borrow();
+        match self.device.read_timeout(buf, timeout) {
+            Ok(size) => {
+                self.log(json!({
+                    "ts": timestamp(),
+                    "dir": DIRECTION_RESPONSE,
+                    "command": command,
+                    "data": to_hex(&buf[..size]),
+                }))?;
+                Ok(size)
+            }
+            Err(e) => {
+                self.log(json!({
+                    "ts": timestamp(),
+                    "dir": DIRECTION_ERROR,
+                    "command": command,
+                    "error": e.to_string(),
+                }))?;
+                Err(e)
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+enum TraceRecord {
+    Request(String, Vec<u8>),
+    Response(Vec<u8>),
+    Error(String),
+}
+
+/// Transport answering requests with responses from trace written by `Recorder`.
+///
+/// Requests should match the trace byte to byte, divergence is reported as error.
+#[derive(Debug)]
+pub struct Replayer {
+    records: RefCell<VecDeque<TraceRecord>>,
+}
+
+impl Replayer {
+    pub fn new(trace: impl BufRead) -> Result<Replayer, Box<dyn std::error::Error>> {
+        let mut records = VecDeque::new();
+        for (line_number, line) in trace.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let record: Value = serde_json::from_str(&line)?;
+            let dir = record["dir"]
+                .as_str()
+                .ok_or(format!("line {}: dir should be a string", line_number + 1))?;
+            let data = || -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+                from_hex(
+                    record["data"]
+                        .as_str()
+                        .ok_or(format!("line {}: data should be a string", line_number + 1))?,
+                )
+            };
+            records.push_back(match dir {
+                DIRECTION_REQUEST => TraceRecord::Request(
+                    record["command"].as_str().unwrap_or("UNKNOWN").to_string(),
+                    data()?,
+                ),
+                DIRECTION_RESPONSE => TraceRecord::Response(data()?),
+                DIRECTION_ERROR => TraceRecord::Error(
+                    record["error"]
+                        .as_str()
+                        .unwrap_or("recorded error")
+                        .to_string(),
+                ),
+                _ => {
+                    return Err(ProtocolError::General(format!(
+                        "line {}: unknown direction {:?}",
+                        line_number + 1,
+                        dir
+                    ))
+                    .into());
+                }
+            });
+        }
+        Ok(Replayer {
+            records: RefCell::new(records),
+        })
+    }
+
+    /// Number of records not consumed by replay yet.
+    pub fn remaining(&self) -> usize {
+        self.records.borrow().len()
+    }
+}
+
+impl Transport for Replayer {
+    fn write(&self, data: &[u8]) -> HidResult<usize> {
+        let mut records = self.records.borrow_mut();
+        match records.pop_front() {
+            Some(TraceRecord::Request(_, expected)) if expected == data => Ok(data.len()),
+            Some(TraceRecord::Request(command, expected)) => Err(HidError::HidApiError {
+                message: format!(
+                    "replay diverged, trace has {} {} but got {} {}",
+                    command,
+                    to_hex(&expected),
+                    command_name(data.get(1..).unwrap_or_default()),
+                    to_hex(data)
+                ),
+            }),
+            Some(record) => Err(HidError::HidApiError {
+                message: format!("replay diverged, trace has {:?} but got request", record),
+            }),
+            None => Err(HidError::HidApiError {
+                message: format!(
+                    "replay trace is over but got {} {}",
+                    command_name(data.get(1..).unwrap_or_default()),
+                    to_hex(data)
+                ),
+            }),
+        }
+    }
+
+    fn read_timeout(&self, buf: &mut [u8], _timeout: i32) -> HidResult<usize> {
+        let mut records = self.records.borrow_mut();
+        match records.front() {
+            Some(TraceRecord::Response(data)) => {
+                let size = data.len().min(buf.len());
+                buf[..size].copy_from_slice(&data[..size]);
+                records.pop_front();
+                Ok(size)
+            }
+            Some(TraceRecord::Error(message)) => {
+                let message = message.clone();
+                records.pop_front();
+                Err(HidError::HidApiError { message })
+            }
+            // nothing was read at this point during recording
+            _ => Ok(0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{
+        Combo, Simulator, load_combos, load_layers_keys, load_uid, load_vial_meta,
+        scan_capabilities, set_combo,
+    };
+    use std::io::Cursor;
+
+    fn meta() -> Value {
+        json!({
+            "name": "Traced",
+            "matrix": {"rows": 1, "cols": 2},
+            "layouts": {"keymap": [["0,0", "0,1"]]}
+        })
+    }
+
+    #[test]
+    fn test_command_name() {
+        assert_eq!(command_name(&[0x12, 0, 0, 28]), "CMD_VIA_KEYMAP_GET_BUFFER");
+        assert_eq!(command_name(&[0xFE, 0x02, 0, 0]), "CMD_VIAL_GET_DEFINITION");
+        assert_eq!(
+            command_name(&[0xFE, 0x0D, 0x04, 1]),
+            "CMD_VIAL_DYNAMIC_ENTRY_OP/DYNAMIC_VIAL_COMBO_SET"
+        );
+        assert_eq!(command_name(&[0x42]), "UNKNOWN");
+        assert_eq!(command_name(&[]), "UNKNOWN");
+    }
+
+    #[test]
+    fn test_hex() {
+        assert_eq!(to_hex(&[0x00, 0xFE, 0x0d]), "00fe0d");
+        assert_eq!(from_hex("00fe0d").unwrap(), vec![0x00, 0xFE, 0x0D]);
+        assert!(from_hex("0").is_err());
+        assert!(from_hex("zz").is_err());
+    }
+
+    #[test]
+    fn test_record_and_replay() {
+        let simulator = Simulator::new(&meta()).unwrap();
+        let mut trace = Vec::new();
+        {
+            let recorder = Recorder::new(&simulator, &mut trace);
+            let combo = Combo::from_string(1, "KC_A + KC_B = KC_C", 6).unwrap();
+            set_combo(&recorder, &combo).unwrap();
+            assert_eq!(load_uid(&recorder).unwrap(), load_uid(&simulator).unwrap());
+            assert_eq!(load_vial_meta(&recorder).unwrap(), meta());
+        }
+        let first = String::from_utf8(trace.clone()).unwrap();
+        let first: Value = serde_json::from_str(first.lines().next().unwrap()).unwrap();
+        assert_eq!(first["dir"], "request");
+        assert_eq!(
+            first["command"],
+            "CMD_VIAL_DYNAMIC_ENTRY_OP/DYNAMIC_VIAL_COMBO_SET"
+        );
+
+        let replayer = Replayer::new(Cursor::new(&trace)).unwrap();
+        let combo = Combo::from_string(1, "KC_A + KC_B = KC_C", 6).unwrap();
+        set_combo(&replayer, &combo).unwrap();
+        assert_eq!(load_uid(&replayer).unwrap(), load_uid(&simulator).unwrap());
+        assert_eq!(load_vial_meta(&replayer).unwrap(), meta());
+        assert_eq!(replayer.remaining(), 0);
+        assert!(load_uid(&replayer).is_err());
+    }
+
+    #[test]
+    fn test_replay_diverged() {
+        let simulator = Simulator::new(&meta()).unwrap();
+        let mut trace = Vec::new();
+        {
+            let recorder = Recorder::new(&simulator, &mut trace);
+            let capabilities = scan_capabilities(&recorder).unwrap();
+            load_layers_keys(&recorder, capabilities.layer_count, 1, 2).unwrap();
+            load_combos(&recorder, 2).unwrap();
+        }
+
+        let replayer = Replayer::new(Cursor::new(&trace)).unwrap();
+        let capabilities = scan_capabilities(&replayer).unwrap();
+        load_layers_keys(&replayer, capabilities.layer_count, 1, 2).unwrap();
+        let error = load_combos(&replayer, 3).unwrap_err().to_string();
+        assert!(error.contains("replay trace is over"), "{}", error);
+
+        let replayer = Replayer::new(Cursor::new(&trace)).unwrap();
+        let error = load_uid(&replayer).unwrap_err().to_string();
+        assert!(error.contains("replay diverged"), "{}", error);
+    }
+}
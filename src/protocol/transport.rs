@@ -0,0 +1,116 @@
+use hidapi::{HidDevice, HidResult};
+
+/// Raw report channel used by all protocol functions.
+///
+/// `write` receives a report id byte followed by at most `MESSAGE_LENGTH` bytes of payload,
+/// `read_timeout` fills `MESSAGE_LENGTH` bytes of response, exactly as `HidDevice` does.
+pub trait Transport {
+    fn write(&self, data: &[u8]) -> HidResult<usize>;
+    fn read_timeout(&self, buf: &mut [u8], timeout: i32) -> HidResult<usize>;
+}
+
+impl Transport for HidDevice {
+    fn write(&self, data: &[u8]) -> HidResult<usize> {
+        HidDevice::write(self, data)
+    }
+
+    fn read_timeout(&self, buf: &mut [u8], timeout: i32) -> HidResult<usize> {
+        HidDevice::read_timeout(self, buf, timeout)
+    }
+}
+
+impl<T: Transport + ?Sized> Transport for &T {
+    fn write(&self, data: &[u8]) -> HidResult<usize> {
+        (**self).write(data)
+    }
+
+    fn read_timeout(&self, buf: &mut [u8], timeout: i32) -> HidResult<usize> {
+        (**self).read_timeout(buf, timeout)
+    }
+}
+
+impl<T: Transport + ?Sized> Transport for Box<T> {
+    fn write(&self, data: &[u8]) -> HidResult<usize> {
+        (**self).write(data)
+    }
+
+    fn read_timeout(&self, buf: &mut [u8], timeout: i32) -> HidResult<usize> {
+        (**self).read_timeout(buf, timeout)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{Combo, Keymap, MESSAGE_LENGTH, load_uid, set_combo, set_keymap};
+    use serde_json::json;
+    use std::cell::RefCell;
+    use std::collections::VecDeque;
+
+    struct ScriptedTransport {
+        written: RefCell<Vec<Vec<u8>>>,
+        responses: RefCell<VecDeque<[u8; MESSAGE_LENGTH]>>,
+    }
+
+    impl Transport for ScriptedTransport {
+        fn write(&self, data: &[u8]) -> HidResult<usize> {
+            self.written.borrow_mut().push(data.to_vec());
+            Ok(data.len())
+        }
+
+        fn read_timeout(&self, buf: &mut [u8], _timeout: i32) -> HidResult<usize> {
+            let response = self.responses.borrow_mut().pop_front().unwrap_or_default();
+            buf.copy_from_slice(&response);
+            Ok(buf.len())
+        }
+    }
+
+    #[test]
+    fn test_protocol_over_scripted_transport() {
+        let mut response = [0u8; MESSAGE_LENGTH];
+        response[4..12].copy_from_slice(&0x0102030405060708u64.to_le_bytes());
+        let transport = ScriptedTransport {
+            written: RefCell::new(Vec::new()),
+            responses: RefCell::new(VecDeque::from([response])),
+        };
+        assert_eq!(load_uid(&transport).unwrap(), 0x0102030405060708);
+
+        let combo = Combo::from_string(3, "KC_A + KC_B = KC_C", 6).unwrap();
+        set_combo(&transport, &combo).unwrap();
+
+        let written = transport.written.borrow();
+        assert_eq!(written.len(), 2);
+        // report id followed by full length message
+        assert_eq!(written[0].len(), MESSAGE_LENGTH + 1);
+        assert_eq!(written[0][0..2], [0x00, 0xFE]);
+        assert_eq!(
+            written[1][1..9],
+            [0xFE, 0x0D, 0x04, 3, 0x04, 0x00, 0x05, 0x00]
+        );
+    }
+
+    #[test]
+    fn test_set_keymap_verification() {
+        let layers = json!([[["KC_A", "KC_B"]]]);
+        let keymap = Keymap::from_json(1, 2, 1, layers.as_array().unwrap(), 6).unwrap();
+        let mut ack = [0u8; MESSAGE_LENGTH];
+        ack[0..8].copy_from_slice(&[0x13, 0, 0, 4, 0, 4, 0, 5]);
+
+        // device acknowledges write but keeps old content
+        let transport = ScriptedTransport {
+            written: RefCell::new(Vec::new()),
+            responses: RefCell::new(VecDeque::from([ack, [0u8; MESSAGE_LENGTH]])),
+        };
+        let error = set_keymap(&transport, &keymap).unwrap_err().to_string();
+        assert_eq!(error, "keymap verification failed at layer=0, row=0, col=0");
+        assert_eq!(transport.written.borrow()[0][1..9], ack[0..8]);
+
+        // device doesn't echo request
+        let transport = ScriptedTransport {
+            written: RefCell::new(Vec::new()),
+            responses: RefCell::new(VecDeque::from([[0u8; MESSAGE_LENGTH]])),
+        };
+        let error = set_keymap(&transport, &keymap).unwrap_err().to_string();
+        assert_eq!(error, "keymap write at offset 0 is not acknowledged");
+    }
+}
@@ -0,0 +1,536 @@
+use crate::protocol::ProtocolError;
+use serde::de::{self, Deserializer};
+use serde::ser::{SerializeSeq, Serializer};
+use serde::{Deserialize, Serialize};
+use serde_json::ser::Formatter;
+use serde_json::{Map, Value};
+use std::collections::{BTreeMap, HashSet};
+use std::io;
+
+/// Content of .vil file in the same format as Vial GUI saves it.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct VialConfig {
+    #[serde(default = "default_version")]
+    pub version: u32,
+    #[serde(default)]
+    pub uid: u64,
+    /// empty when file has only some of sections
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub layout: Vec<Vec<Vec<LayoutKey>>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encoder_layout: Option<Vec<Vec<[String; 2]>>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub layout_options: Option<i64>,
+    #[serde(default, rename = "macro", skip_serializing_if = "Option::is_none")]
+    pub macros: Option<Vec<Vec<MacroAction>>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vial_protocol: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub via_protocol: Option<u8>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tap_dance: Option<Vec<TapDanceConfig>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub combo: Option<Vec<[String; 5]>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key_override: Option<Vec<KeyOverrideConfig>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub alt_repeat_key: Option<Vec<AltRepeatConfig>>,
+    /// QMK settings by qsid
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub settings: Option<BTreeMap<u16, u32>>,
+    /// fields unknown to vitaly, kept to write them back untouched
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+fn default_version() -> u32 {
+    1
+}
+
+/// Keycode name of layout position, Vial writes -1 for positions missing in layout.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum LayoutKey {
+    Name(String),
+    Number(i64),
+}
+
+/// Tap dance as [tap, hold, double tap, tap hold, tapping term].
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TapDanceConfig(pub String, pub String, pub String, pub String, pub u16);
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct KeyOverrideConfig {
+    pub trigger: String,
+    pub replacement: String,
+    pub layers: u16,
+    pub trigger_mods: u8,
+    pub negative_mod_mask: u8,
+    pub suppressed_mods: u8,
+    pub options: u8,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AltRepeatConfig {
+    pub keycode: String,
+    pub alt_keycode: String,
+    pub allowed_mods: u8,
+    pub options: u8,
+}
+
+/// Macro action encoded as array with action name followed by arguments, e.g. ["tap", "KC_A", "KC_B"].
+#[derive(Debug, Clone, PartialEq)]
+pub enum MacroAction {
+    Text(Vec<String>),
+    Tap(Vec<String>),
+    Down(Vec<String>),
+    Up(Vec<String>),
+    Delay(Vec<u16>),
+}
+
+impl Serialize for MacroAction {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(None)?;
+        match self {
+            MacroAction::Text(args) => {
+                seq.serialize_element("text")?;
+                for arg in args {
+                    seq.serialize_element(arg)?;
+                }
+            }
+            MacroAction::Tap(args) | MacroAction::Down(args) | MacroAction::Up(args) => {
+                seq.serialize_element(match self {
+                    MacroAction::Tap(_) => "tap",
+                    MacroAction::Down(_) => "down",
+                    _ => "up",
+                })?;
+                for arg in args {
+                    seq.serialize_element(arg)?;
+                }
+            }
+            MacroAction::Delay(args) => {
+                seq.serialize_element("delay")?;
+                for arg in args {
+                    seq.serialize_element(arg)?;
+                }
+            }
+        }
+        seq.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for MacroAction {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let items = Vec::<Value>::deserialize(deserializer)?;
+        let (action, args) = items
+            .split_first()
+            .ok_or(de::Error::custom("macro action should not be empty"))?;
+        let strings = || -> Result<Vec<String>, D::Error> {
+            args.iter()
+                .map(|arg| {
+                    arg.as_str().map(|s| s.to_string()).ok_or(de::Error::custom(
+                        "macro action argument should be a string",
+                    ))
+                })
+                .collect()
+        };
+        match action.as_str() {
+            Some("text") => Ok(MacroAction::Text(strings()?)),
+            Some("tap") => Ok(MacroAction::Tap(strings()?)),
+            Some("down") => Ok(MacroAction::Down(strings()?)),
+            Some("up") => Ok(MacroAction::Up(strings()?)),
+            Some("delay") => Ok(MacroAction::Delay(
+                args.iter()
+                    .map(|arg| {
+                        arg.as_u64()
+                            .and_then(|v| u16::try_from(v).ok())
+                            .ok_or(de::Error::custom("delay argument should be a number"))
+                    })
+                    .collect::<Result<_, _>>()?,
+            )),
+            _ => Err(de::Error::custom(format!(
+                "unknown macro action {}",
+                action
+            ))),
+        }
+    }
+}
+
+/// Formats json the same way as Python json.dumps used by Vial GUI: with ", " and ": "
+/// separators and characters out of printable ASCII escaped as \uXXXX.
+struct VilFormatter;
+
+impl Formatter for VilFormatter {
+    fn begin_array_value<W: ?Sized + io::Write>(
+        &mut self,
+        writer: &mut W,
+        first: bool,
+    ) -> io::Result<()> {
+        if first {
+            Ok(())
+        } else {
+            writer.write_all(b", ")
+        }
+    }
+
+    fn begin_object_key<W: ?Sized + io::Write>(
+        &mut self,
+        writer: &mut W,
+        first: bool,
+    ) -> io::Result<()> {
+        if first {
+            Ok(())
+        } else {
+            writer.write_all(b", ")
+        }
+    }
+
+    fn begin_object_value<W: ?Sized + io::Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(b": ")
+    }
+
+    fn write_string_fragment<W: ?Sized + io::Write>(
+        &mut self,
+        writer: &mut W,
+        fragment: &str,
+    ) -> io::Result<()> {
+        for c in fragment.chars() {
+            if (' '..='~').contains(&c) {
+                writer.write_all(&[c as u8])?;
+            } else {
+                for unit in c.encode_utf16(&mut [0; 2]) {
+                    write!(writer, "\\u{:04x}", unit)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Part of configuration which could be saved or loaded separately.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Section {
+    Keys,
+    Layout,
+    Encoders,
+    Combos,
+    Macros,
+    TapDances,
+    KeyOverrides,
+    AltRepeats,
+    Settings,
+}
+
+impl Section {
+    pub const ALL: [Section; 9] = [
+        Section::Keys,
+        Section::Layout,
+        Section::Encoders,
+        Section::Combos,
+        Section::Macros,
+        Section::TapDances,
+        Section::KeyOverrides,
+        Section::AltRepeats,
+        Section::Settings,
+    ];
+
+    /// Name used on command line, the same as name of subcommand editing the section.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Section::Keys => "keys",
+            Section::Layout => "layout",
+            Section::Encoders => "encoders",
+            Section::Combos => "combos",
+            Section::Macros => "macros",
+            Section::TapDances => "tapdances",
+            Section::KeyOverrides => "keyoverrides",
+            Section::AltRepeats => "altrepeats",
+            Section::Settings => "settings",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Result<Section, Box<dyn std::error::Error>> {
+        Section::ALL
+            .into_iter()
+            .find(|section| section.name() == name.trim())
+            .ok_or_else(|| {
+                ProtocolError::General(format!(
+                    "unknown section {:?}, expected one of {}",
+                    name,
+                    Section::ALL.map(|section| section.name()).join(", ")
+                ))
+                .into()
+            })
+    }
+
+    /// Sections listed in comma separated `only` without ones listed in `except`, all by default.
+    pub fn select(
+        only: &Option<String>,
+        except: &Option<String>,
+    ) -> Result<HashSet<Section>, Box<dyn std::error::Error>> {
+        let mut result = match only {
+            Some(names) => names
+                .split(',')
+                .map(Section::from_name)
+                .collect::<Result<HashSet<_>, _>>()?,
+            None => HashSet::from(Section::ALL),
+        };
+        if let Some(names) = except {
+            for name in names.split(',') {
+                result.remove(&Section::from_name(name)?);
+            }
+        }
+        Ok(result)
+    }
+}
+
+/// Merges .vil `overlay` into `base`. Arrays are merged by index where null keeps base entry,
+/// keys of layout and encoders of layers could be replaced one by one, other entries are replaced
+/// as a whole. Settings are merged by qsid, other values are replaced.
+pub fn merge_json(base: &mut Map<String, Value>, overlay: Map<String, Value>) {
+    for (key, value) in overlay {
+        let depth = match key.as_str() {
+            "layout" => 3,
+            "encoder_layout" => 2,
+            _ => 1,
+        };
+        match (base.get_mut(&key), value) {
+            (_, Value::Null) => {}
+            (Some(Value::Array(base)), Value::Array(overlay)) => {
+                merge_entries(base, overlay, depth)
+            }
+            (Some(Value::Object(base)), Value::Object(overlay)) => base.extend(overlay),
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+fn merge_entries(base: &mut Vec<Value>, overlay: Vec<Value>, depth: usize) {
+    for (index, value) in overlay.into_iter().enumerate() {
+        match (base.get_mut(index), value) {
+            (_, Value::Null) => {}
+            (Some(Value::Array(base)), Value::Array(overlay)) if depth > 1 => {
+                merge_entries(base, overlay, depth - 1)
+            }
+            (Some(base), value) => *base = value,
+            (None, value) => base.push(value),
+        }
+    }
+}
+
+impl VialConfig {
+    pub fn from_json(root: &Value) -> Result<VialConfig, Box<dyn std::error::Error>> {
+        Ok(VialConfig::deserialize(root)?)
+    }
+
+    pub fn to_json(&self) -> Result<Value, Box<dyn std::error::Error>> {
+        Ok(serde_json::to_value(self)?)
+    }
+
+    /// Content of .vil file, keys are kept in the order Vial GUI writes them.
+    pub fn to_vil_string(&self) -> Result<String, Box<dyn std::error::Error>> {
+        let mut out = Vec::new();
+        self.serialize(&mut serde_json::Serializer::with_formatter(
+            &mut out,
+            VilFormatter,
+        ))?;
+        Ok(String::from_utf8(out)?)
+    }
+
+    /// Sections defined in file.
+    pub fn sections(&self) -> HashSet<Section> {
+        let present = [
+            (Section::Keys, !self.layout.is_empty()),
+            (Section::Layout, self.layout_options.is_some()),
+            (Section::Encoders, self.encoder_layout.is_some()),
+            (Section::Combos, self.combo.is_some()),
+            (Section::Macros, self.macros.is_some()),
+            (Section::TapDances, self.tap_dance.is_some()),
+            (Section::KeyOverrides, self.key_override.is_some()),
+            (Section::AltRepeats, self.alt_repeat_key.is_some()),
+            (Section::Settings, self.settings.is_some()),
+        ];
+        present
+            .into_iter()
+            .filter(|(_, present)| *present)
+            .map(|(section, _)| section)
+            .collect()
+    }
+
+    /// Drops sections which are not listed in `sections`.
+    pub fn retain(&mut self, sections: &HashSet<Section>) {
+        if !sections.contains(&Section::Keys) {
+            self.layout.clear();
+        }
+        if !sections.contains(&Section::Layout) {
+            self.layout_options = None;
+        }
+        if !sections.contains(&Section::Encoders) {
+            self.encoder_layout = None;
+        }
+        if !sections.contains(&Section::Combos) {
+            self.combo = None;
+        }
+        if !sections.contains(&Section::Macros) {
+            self.macros = None;
+        }
+        if !sections.contains(&Section::TapDances) {
+            self.tap_dance = None;
+        }
+        if !sections.contains(&Section::KeyOverrides) {
+            self.key_override = None;
+        }
+        if !sections.contains(&Section::AltRepeats) {
+            self.alt_repeat_key = None;
+        }
+        if !sections.contains(&Section::Settings) {
+            self.settings = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    // Vial GUI format for keyboard with encoder, layout options and all dynamic entries
+    const VIAL_SAMPLE: &str = include_str!("testdata/vial_v6.vil");
+    // older Vial GUI format, without key overrides, alt repeat keys and settings
+    const VIAL_SAMPLE_V5: &str = include_str!("testdata/vial_v5.vil");
+
+    #[test]
+    fn test_vial_config_round_trip() {
+        for sample in [VIAL_SAMPLE, VIAL_SAMPLE_V5] {
+            let root: Value = serde_json::from_str(sample).unwrap();
+            let config = VialConfig::from_json(&root).unwrap();
+            assert_eq!(config.to_json().unwrap(), root);
+            let text = serde_json::to_string(&config).unwrap();
+            assert_eq!(serde_json::from_str::<VialConfig>(&text).unwrap(), config);
+        }
+    }
+
+    #[test]
+    fn test_vial_config_vil_string() {
+        for sample in [VIAL_SAMPLE, VIAL_SAMPLE_V5] {
+            let config: VialConfig = serde_json::from_str(sample).unwrap();
+            assert_eq!(config.to_vil_string().unwrap(), sample);
+        }
+        let root = json!({"layout": [[["KC_A"]]], "custom": "tab\t é 😀"});
+        let text = VialConfig::from_json(&root)
+            .unwrap()
+            .to_vil_string()
+            .unwrap();
+        assert_eq!(
+            text,
+            r#"{"version": 1, "uid": 0, "layout": [[["KC_A"]]], "custom": "tab\t \u00e9 \ud83d\ude00"}"#
+        );
+    }
+
+    #[test]
+    fn test_vial_config_fields() {
+        let config: VialConfig = serde_json::from_str(VIAL_SAMPLE).unwrap();
+        assert_eq!(config.vial_protocol, Some(6));
+        assert_eq!(config.layout.len(), 4);
+        assert_eq!(config.layout[0][1][2], LayoutKey::Number(-1));
+        assert_eq!(
+            config.layout[0][0][0],
+            LayoutKey::Name("KC_ESCAPE".to_string())
+        );
+        let macros = config.macros.as_ref().unwrap();
+        assert_eq!(
+            macros[0],
+            vec![
+                MacroAction::Text(vec!["hello".to_string()]),
+                MacroAction::Tap(vec!["KC_ENTER".to_string(), "KC_ENTER".to_string()]),
+                MacroAction::Delay(vec![100]),
+            ]
+        );
+        assert_eq!(config.tap_dance.as_ref().unwrap()[0].4, 200);
+        assert_eq!(config.key_override.as_ref().unwrap()[0].options, 7);
+        assert_eq!(config.settings.as_ref().unwrap()[&7], 180);
+
+        let old: VialConfig = serde_json::from_str(VIAL_SAMPLE_V5).unwrap();
+        assert!(old.key_override.is_none());
+        assert!(old.settings.is_none());
+        assert!(old.to_json().unwrap().get("settings").is_none());
+    }
+
+    #[test]
+    fn test_vial_config_unknown_fields() {
+        let root = json!({"layout": [[["KC_A"]]], "custom": {"a": 1}});
+        let config = VialConfig::from_json(&root).unwrap();
+        assert_eq!(config.version, 1);
+        assert_eq!(config.extra["custom"], json!({"a": 1}));
+        assert_eq!(config.to_json().unwrap()["custom"], json!({"a": 1}));
+        assert!(VialConfig::from_json(&json!({"layout": [], "macro": [[["beep"]]]})).is_err());
+        let partial = VialConfig::from_json(&json!({"macro": []})).unwrap();
+        assert!(partial.layout.is_empty());
+    }
+
+    #[test]
+    fn test_vial_config_sections() {
+        let all = Section::select(&None, &None).unwrap();
+        assert_eq!(all.len(), Section::ALL.len());
+        let selected = Section::select(&Some("macros, settings".to_string()), &None).unwrap();
+        assert_eq!(
+            selected,
+            HashSet::from([Section::Macros, Section::Settings])
+        );
+        let selected = Section::select(&None, &Some("keys,encoders".to_string())).unwrap();
+        assert!(!selected.contains(&Section::Keys));
+        assert!(selected.contains(&Section::Combos));
+        assert!(Section::select(&Some("macro".to_string()), &None).is_err());
+
+        let mut config: VialConfig = serde_json::from_str(VIAL_SAMPLE).unwrap();
+        assert_eq!(config.sections(), all);
+        config.retain(&HashSet::from([Section::Macros, Section::Combos]));
+        assert_eq!(
+            config.sections(),
+            HashSet::from([Section::Macros, Section::Combos])
+        );
+        let json = config.to_json().unwrap();
+        assert!(json.get("layout").is_none());
+        assert!(json.get("settings").is_none());
+        let partial = VialConfig::from_json(&json).unwrap();
+        assert_eq!(partial, config);
+
+        let old: VialConfig = serde_json::from_str(VIAL_SAMPLE_V5).unwrap();
+        assert!(!old.sections().contains(&Section::KeyOverrides));
+    }
+
+    #[test]
+    fn test_merge_json() {
+        let mut base = json!({
+            "layout": [[["KC_A", "KC_B"], ["KC_C", -1]], [["KC_1", "KC_2"], ["KC_3", -1]]],
+            "encoder_layout": [[["KC_VOLD", "KC_VOLU"], ["KC_LEFT", "KC_RIGHT"]]],
+            "macro": [[["text", "hello"], ["tap", "KC_ENTER"]], []],
+            "settings": {"2": 5000, "7": 180},
+            "vial_protocol": 6
+        });
+        let overlay = json!({
+            "layout": [null, [null, ["KC_Z"]]],
+            "encoder_layout": [[null, ["KC_UP", "KC_DOWN"]]],
+            "macro": [[["text", "bye"]]],
+            "settings": {"7": 150},
+            "uid": 42
+        });
+        let (Value::Object(base_root), Value::Object(overlay)) = (&mut base, overlay) else {
+            panic!("objects expected");
+        };
+        merge_json(base_root, overlay);
+        assert_eq!(
+            base,
+            json!({
+                "layout": [[["KC_A", "KC_B"], ["KC_C", -1]], [["KC_1", "KC_2"], ["KC_Z", -1]]],
+                "encoder_layout": [[["KC_VOLD", "KC_VOLU"], ["KC_UP", "KC_DOWN"]]],
+                "macro": [[["text", "bye"]], []],
+                "settings": {"2": 5000, "7": 150},
+                "vial_protocol": 6,
+                "uid": 42
+            })
+        );
+    }
+}